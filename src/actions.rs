@@ -0,0 +1,153 @@
+use crate::config::{Config, Table, Value};
+use crate::pipeline::{OnFailure, Step};
+use crate::protocol::{Command, Request};
+use crate::rules::Action;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// What an `[action.<name>]` table does when a v2 packet names it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NamedAction {
+    /// A power action, optionally with its own pipeline
+    Power { action: Action, pipeline: Option<String> },
+    /// A shell command run instead of a power action, e.g. to lock the screen
+    Run(Step),
+}
+
+impl NamedAction {
+    fn parse(name: &str, table: &Table, default_timeout: Duration) -> Result<NamedAction, String> {
+        let (mut power, mut pipeline, mut command, mut timeout) = (None, None, None, default_timeout);
+        for (key, value) in table {
+            match key.as_str() {
+                "power" => {
+                    let action = Action::from_str(string(key, value)?, true)
+                        .ok()
+                        .filter(|action| *action != Action::Ignore)
+                        .ok_or("'power' must be suspend, hibernate or poweroff")?;
+                    power = Some(action);
+                }
+                "pipeline" => pipeline = Some(string(key, value)?.to_string()),
+                "run" => command = Some(string(key, value)?.to_string()),
+                "timeout" => {
+                    let secs = value.as_integer().and_then(|i| u64::try_from(i).ok()).filter(|secs| *secs > 0);
+                    timeout = Duration::from_secs(secs.ok_or("'timeout' must be a positive integer")?);
+                }
+                _ => return Err(format!("unknown key '{}'", key)),
+            }
+        }
+        match (power, command) {
+            (Some(action), None) => Ok(NamedAction::Power { action, pipeline }),
+            (None, Some(command)) if pipeline.is_none() => Ok(NamedAction::Run(Step {
+                name: name.to_string(),
+                command,
+                power_profile: None,
+                timeout,
+                on_failure: OnFailure::Abort,
+            })),
+            (None, Some(_)) => Err("'pipeline' only applies to 'power' actions".to_string()),
+            _ => Err("exactly one of 'power' and 'run' is required".to_string()),
+        }
+    }
+}
+
+fn string<'a>(key: &str, value: &'a Value) -> Result<&'a str, String> {
+    value.as_str().ok_or_else(|| format!("'{}' must be a string", key))
+}
+
+/// Actions defined in the config file, by name, that v2 packets can select
+#[derive(Debug, Default)]
+pub struct NamedActions {
+    actions: BTreeMap<String, NamedAction>,
+}
+
+impl NamedActions {
+    /// Build actions from `[action.<name>]` tables. Commands without a `timeout` get
+    /// `default_timeout`.
+    pub fn from_config(config: &Config, default_timeout: Duration) -> Result<Self, String> {
+        let mut actions = BTreeMap::new();
+        for (name, table) in config.subtables("action") {
+            if name == "cancel" || name == "snooze" || Action::from_str(name, false).is_ok() {
+                return Err(format!("action.{}: the name is reserved for the built-in action", name));
+            }
+            let action = NamedAction::parse(name, table, default_timeout).map_err(|e| format!("action.{}: {}", name, e))?;
+            actions.insert(name.to_string(), action);
+        }
+        Ok(NamedActions { actions })
+    }
+
+    /// Pipelines the actions refer to, to check they exist
+    pub fn pipelines(&self) -> impl Iterator<Item = &str> {
+        self.actions.values().filter_map(|action| match action {
+            NamedAction::Power { pipeline, .. } => pipeline.as_deref(),
+            NamedAction::Run(_) => None,
+        })
+    }
+
+    /// Turn a request for a named power action into a plain one, or return the command a
+    /// named command action runs
+    pub fn resolve(&self, command: &mut Command) -> Result<Option<Step>, String> {
+        let Request::Named(name) = &command.request else {
+            return Ok(None);
+        };
+        match self.actions.get(name) {
+            Some(NamedAction::Power { action, pipeline }) => {
+                command.request = Request::Action(*action);
+                if command.pipeline.is_none() {
+                    command.pipeline = pipeline.clone();
+                }
+                Ok(None)
+            }
+            Some(NamedAction::Run(step)) => Ok(Some(step.clone())),
+            None => Err(format!("unknown action '{}'", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[action.nightly]
+power = "hibernate"
+pipeline = "nas"
+
+[action.stop-minecraft]
+run = "systemctl stop minecraft"
+timeout = 90
+"#;
+
+    fn command(action: &str) -> Command {
+        let body = format!(r#"{{"action":"{}","mac":"aa:bb:cc:dd:ee:ff","ts":1}}"#, action);
+        Command::parse(body.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_named_actions() {
+        let actions = NamedActions::from_config(&Config::parse(CONFIG).unwrap(), Duration::from_secs(30)).unwrap();
+        assert_eq!(actions.pipelines().collect::<Vec<_>>(), ["nas"]);
+
+        let mut nightly = command("nightly");
+        assert_eq!(actions.resolve(&mut nightly), Ok(None));
+        assert_eq!((nightly.request, nightly.pipeline.as_deref()), (Request::Action(Action::Hibernate), Some("nas")));
+
+        let step = actions.resolve(&mut command("stop-minecraft")).unwrap().unwrap();
+        assert_eq!((step.command.as_str(), step.timeout), ("systemctl stop minecraft", Duration::from_secs(90)));
+
+        let mut suspend = command("suspend");
+        assert_eq!(actions.resolve(&mut suspend), Ok(None));
+        assert_eq!(suspend.request, Request::Action(Action::Suspend));
+        assert!(actions.resolve(&mut command("missing")).is_err());
+    }
+
+    #[test]
+    fn test_invalid_actions() {
+        let parse = |text| NamedActions::from_config(&Config::parse(text).unwrap(), Duration::from_secs(30));
+        assert!(parse("[action.suspend]\nrun = \"true\"").unwrap_err().contains("reserved"));
+        assert!(parse("[action.x]\npower = \"ignore\"").is_err());
+        assert!(parse("[action.x]\npower = \"suspend\"\nrun = \"true\"").is_err());
+        assert!(parse("[action.x]\nrun = \"true\"\npipeline = \"nas\"").is_err());
+        assert!(parse("[action.x]\ncolor = \"red\"").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::events::{Event, Handled, Listener, Record};
+use crate::protocol::unix_now;
+use sol::sha256;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Hash the first entry is chained to
+const GENESIS: [u8; 32] = [0; 32];
+
+/// Signature field of entries written without a key
+const UNSIGNED: &str = "-";
+
+struct State {
+    file: File,
+    seq: u64,
+    prev: [u8; 32],
+}
+
+/// Append-only record of triggers and actions. Each line is
+/// `SEQ<TAB>UNIX_TIME<TAB>EVENT<TAB>DETAIL<TAB>HASH<TAB>SIGNATURE`, where HASH is the SHA-256
+/// of the previous entry's hash followed by the first four fields, and SIGNATURE is the
+/// HMAC-SHA256 of HASH with the audit key, so editing, removing or reordering entries
+/// breaks the chain.
+pub struct Audit {
+    key: Option<Vec<u8>>,
+    state: Mutex<State>,
+}
+
+impl Audit {
+    /// Open `path` for appending, continuing the chain already in it. Refuses a file that
+    /// fails verification, so tampering is noticed rather than papered over.
+    pub fn open(path: &Path, key: Option<Vec<u8>>) -> Result<Self, String> {
+        let (seq, prev) = match std::fs::read_to_string(path) {
+            Ok(text) => verify(&text, key.as_deref()).map_err(|e| {
+                format!("Audit log {} fails verification: {}; move it aside to start a new one", path.display(), e)
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, GENESIS),
+            Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e)),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        Ok(Audit { key, state: Mutex::new(State { file, seq, prev }) })
+    }
+
+    fn append(&self, event: &str, detail: &str) {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.seq + 1;
+        let (line, hash) = entry(seq, unix_now(), event, detail, &state.prev, self.key.as_deref());
+        let written = state.file.write_all(line.as_bytes()).and_then(|()| state.file.sync_data());
+        match written {
+            Ok(()) => {
+                state.seq = seq;
+                state.prev = hash;
+            }
+            Err(e) => error!("Failed to write audit entry: {}", e),
+        }
+    }
+}
+
+impl Listener for Audit {
+    fn handle<'a>(&'a self, event: &'a Event) -> Handled<'a> {
+        Box::pin(async move {
+            if !matches!(event, Event::PacketReceived { .. }) {
+                self.append(event.name(), &event.detail());
+            }
+        })
+    }
+}
+
+/// Build the line for one entry and return it with the entry's hash
+fn entry(seq: u64, time: u64, event: &str, detail: &str, prev: &[u8; 32], key: Option<&[u8]>) -> (String, [u8; 32]) {
+    let detail = detail.replace(['\t', '\n', '\r'], " ");
+    let record = format!("{}\t{}\t{}\t{}", seq, time, event, detail);
+    let hash = chain(prev, &record);
+    let signature = key.map_or(UNSIGNED.to_string(), |key| hex(&sha256::hmac(key, &hash)));
+    (format!("{}\t{}\t{}\n", record, hex(&hash), signature), hash)
+}
+
+fn chain(prev: &[u8; 32], record: &str) -> [u8; 32] {
+    let mut hasher = sha256::Sha256::default();
+    hasher.update(prev);
+    hasher.update(record.as_bytes());
+    hasher.finish()
+}
+
+/// Check every entry's sequence number, hash and, with a key, signature. Returns the last
+/// sequence number and hash.
+pub fn verify(text: &str, key: Option<&[u8]>) -> Result<(u64, [u8; 32]), String> {
+    let (mut seq, mut prev) = (0, GENESIS);
+    for (number, line) in text.lines().enumerate() {
+        let fail = |what: &str| format!("line {}: {}", number + 1, what);
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [entry_seq, _, _, _, hash, signature] = fields[..] else {
+            return Err(fail("malformed entry"));
+        };
+        if entry_seq.parse() != Ok(seq + 1) {
+            return Err(fail(&format!("expected entry {}", seq + 1)));
+        }
+        let record = &line[..line.len() - hash.len() - signature.len() - 2];
+        let expected = chain(&prev, record);
+        if unhex(hash).is_none_or(|hash| !sha256::equal(&hash, &expected)) {
+            return Err(fail("hash does not match the chain"));
+        }
+        if let Some(key) = key {
+            let valid = unhex(signature).is_some_and(|signature| sha256::equal(&signature, &sha256::hmac(key, &expected)));
+            if !valid {
+                return Err(fail("bad signature"));
+            }
+        }
+        (seq, prev) = (seq + 1, expected);
+    }
+    Ok((seq, prev))
+}
+
+/// The time, event and detail of each entry, to fill the event history on startup
+pub fn records(text: &str) -> Vec<Record> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t').skip(1);
+            let time = fields.next()?.parse().ok()?;
+            Some(Record { time, event: fields.next()?.to_string(), detail: fields.next()?.to_string() })
+        })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<[u8; 32]> {
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"audit";
+
+    fn log(key: Option<&[u8]>) -> String {
+        let mut prev = GENESIS;
+        let mut text = String::new();
+        let entries = [
+            ("validated", "sender=10.0.0.5:40000 mac=aa:bb:cc:dd:ee:ff"),
+            ("action-started", "suspend"),
+            ("resumed", "suspend"),
+        ];
+        for (seq, (event, detail)) in entries.into_iter().enumerate() {
+            let (line, hash) = entry(seq as u64 + 1, 1_760_000_000 + seq as u64, event, detail, &prev, key);
+            text += &line;
+            prev = hash;
+        }
+        text
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let text = log(Some(KEY));
+        assert_eq!(verify(&text, Some(KEY)).unwrap().0, 3);
+        assert_eq!(verify(&log(None), None).unwrap().0, 3);
+        assert!(verify(&log(None), Some(KEY)).unwrap_err().contains("bad signature"));
+        assert!(verify(&text, Some(b"other")).is_err());
+    }
+
+    #[test]
+    fn test_detect_tampering() {
+        let text = log(Some(KEY));
+        let edited = text.replace("10.0.0.5", "10.0.0.6");
+        assert!(verify(&edited, Some(KEY)).unwrap_err().contains("line 1"));
+
+        let lines: Vec<&str> = text.lines().collect();
+        let removed = format!("{}\n{}\n", lines[0], lines[2]);
+        assert!(verify(&removed, Some(KEY)).unwrap_err().contains("expected entry 2"));
+    }
+
+    #[test]
+    fn test_records() {
+        let records = records(&log(None));
+        assert_eq!(records.len(), 3);
+        assert_eq!((records[0].time, records[0].event.as_str()), (1_760_000_000, "validated"));
+        assert_eq!(records[0].detail, "sender=10.0.0.5:40000 mac=aa:bb:cc:dd:ee:ff");
+    }
+
+    #[test]
+    fn test_resume_chain() {
+        let dir = std::env::temp_dir().join(format!("sol-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.log");
+        std::fs::write(&path, log(Some(KEY))).unwrap();
+
+        let audit = Audit::open(&path, Some(KEY.to_vec())).unwrap();
+        audit.append("inhibited", "tab\there");
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(verify(&text, Some(KEY)).unwrap().0, 4);
+        assert!(text.contains("\tinhibited\ttab here\t"));
+
+        std::fs::write(&path, text.replace("suspend", "hibernate")).unwrap();
+        assert!(Audit::open(&path, Some(KEY.to_vec())).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How often counters that changed without a ban are written out
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// When to ban a sender, in seconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BanPolicy {
+    /// Rejected packets within `window` that earn a ban
+    pub limit: usize,
+    pub window: u64,
+    pub duration: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Record {
+    /// Unix times of rejected packets still inside the window
+    strikes: Vec<u64>,
+    banned_until: Option<u64>,
+}
+
+#[derive(Default)]
+struct Inner {
+    records: HashMap<IpAddr, Record>,
+    dirty: bool,
+}
+
+/// Senders banned for sending too many rejected packets, with the counters that lead to a
+/// ban. Kept in a state file so restarting the daemon does not give a scanner a clean slate.
+pub struct Bans {
+    policy: Option<BanPolicy>,
+    path: Option<PathBuf>,
+    inner: Mutex<Inner>,
+}
+
+impl Bans {
+    /// Load the state file at `path`, if any. Without a policy nobody is banned, but bans
+    /// already in the file can still be listed and cleared.
+    pub fn new(policy: Option<BanPolicy>, path: Option<PathBuf>) -> Self {
+        let mut inner = Inner::default();
+        if let Some(path) = &path {
+            match std::fs::read_to_string(path) {
+                Ok(text) => inner.records = parse(&text),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Cannot read ban state {}: {}", path.display(), e),
+            }
+        }
+        Bans { policy, path, inner: Mutex::new(inner) }
+    }
+
+    pub fn is_banned(&self, ip: IpAddr, now: u64) -> bool {
+        let ip = ip.to_canonical();
+        self.inner.lock().unwrap().records.get(&ip).and_then(|r| r.banned_until).is_some_and(|until| until > now)
+    }
+
+    /// Count a rejected packet from `ip`. Returns true if this packet got it banned.
+    pub fn strike(&self, ip: IpAddr, now: u64) -> bool {
+        let Some(policy) = self.policy else {
+            return false;
+        };
+        let ip = ip.to_canonical();
+        let mut inner = self.inner.lock().unwrap();
+        let record = inner.records.entry(ip).or_default();
+        record.strikes.retain(|at| now.saturating_sub(*at) < policy.window);
+        record.strikes.push(now);
+        let banned = record.strikes.len() >= policy.limit;
+        if banned {
+            record.strikes.clear();
+            record.banned_until = Some(now + policy.duration);
+        }
+        inner.dirty = true;
+        drop(inner);
+        if banned {
+            self.save();
+        }
+        banned
+    }
+
+    /// Active bans with their remaining seconds, longest first
+    pub fn list(&self, now: u64) -> Vec<(IpAddr, u64)> {
+        let inner = self.inner.lock().unwrap();
+        let mut bans: Vec<(IpAddr, u64)> = inner
+            .records
+            .iter()
+            .filter_map(|(ip, record)| Some((*ip, record.banned_until?.checked_sub(now).filter(|left| *left > 0)?)))
+            .collect();
+        bans.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        bans
+    }
+
+    /// Forget the bans and counters of `ip`, or of everyone. Returns how many bans were lifted.
+    pub fn clear(&self, ip: Option<IpAddr>, now: u64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let active = |record: &Record| record.banned_until.is_some_and(|until| until > now);
+        let lifted = match ip {
+            Some(ip) => inner.records.remove(&ip.to_canonical()).filter(active).map_or(0, |_| 1),
+            None => inner.records.drain().filter(|(_, record)| active(record)).count(),
+        };
+        inner.dirty = true;
+        drop(inner);
+        self.save();
+        lifted
+    }
+
+    /// Write the state file if anything changed, dropping expired entries
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let now = crate::protocol::unix_now();
+        let text = {
+            let mut inner = self.inner.lock().unwrap();
+            if !inner.dirty {
+                return;
+            }
+            inner.dirty = false;
+            let window = self.policy.map_or(0, |policy| policy.window);
+            inner.records.retain(|_, record| {
+                record.strikes.retain(|at| now.saturating_sub(*at) < window);
+                record.banned_until.is_some_and(|until| until > now) || !record.strikes.is_empty()
+            });
+            format(&inner.records)
+        };
+        let tmp = path.with_extension("tmp");
+        let written = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(&tmp, text))
+            .and_then(|()| std::fs::rename(&tmp, path));
+        if let Err(e) = written {
+            error!("Failed to write ban state {}: {}", path.display(), e);
+        }
+    }
+
+    /// Write changed counters every half minute, so they survive a crash or reboot
+    pub fn flush_periodically(self: &std::sync::Arc<Self>) {
+        let bans = std::sync::Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(FLUSH_INTERVAL).await;
+                bans.save();
+            }
+        });
+    }
+}
+
+/// One line per address: `ADDRESS BANNED_UNTIL STRIKE...`, with `-` for no ban
+fn format(records: &HashMap<IpAddr, Record>) -> String {
+    let mut lines: Vec<String> = records
+        .iter()
+        .map(|(ip, record)| {
+            let until = record.banned_until.map_or("-".to_string(), |until| until.to_string());
+            let mut line = format!("{} {}", ip, until);
+            for at in &record.strikes {
+                line += &format!(" {}", at);
+            }
+            line
+        })
+        .collect();
+    lines.sort();
+    lines.into_iter().map(|line| line + "\n").collect()
+}
+
+/// Lines that do not parse are skipped, so a damaged file loses entries rather than
+/// stopping the daemon
+fn parse(text: &str) -> HashMap<IpAddr, Record> {
+    let mut records = HashMap::new();
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        let (Some(ip), Some(until)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let Ok(ip) = ip.parse::<IpAddr>() else {
+            continue;
+        };
+        let banned_until = if until == "-" { None } else { until.parse().ok() };
+        let strikes = words.filter_map(|at| at.parse().ok()).collect();
+        records.insert(ip, Record { strikes, banned_until });
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: BanPolicy = BanPolicy { limit: 3, window: 60, duration: 600 };
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ban_after_limit() {
+        let bans = Bans::new(Some(POLICY), None);
+        let scanner = ip("203.0.113.9");
+        assert!(!bans.strike(scanner, 1000));
+        assert!(!bans.strike(scanner, 1070));
+        assert!(!bans.strike(scanner, 1080));
+        assert!(bans.strike(scanner, 1090));
+        assert!(bans.is_banned(ip("::ffff:203.0.113.9"), 1100));
+        assert!(!bans.is_banned(scanner, 1690));
+        assert_eq!(bans.list(1100), [(scanner, 590)]);
+
+        assert!(!Bans::new(None, None).strike(scanner, 1000));
+    }
+
+    #[test]
+    fn test_clear() {
+        let bans = Bans::new(Some(POLICY), None);
+        for addr in ["10.0.0.1", "10.0.0.2"] {
+            for at in 0..3 {
+                bans.strike(ip(addr), at);
+            }
+        }
+        assert_eq!(bans.clear(Some(ip("10.0.0.1")), 10), 1);
+        assert_eq!(bans.clear(Some(ip("10.0.0.1")), 10), 0);
+        assert_eq!(bans.clear(None, 10), 1);
+        assert!(bans.list(10).is_empty());
+    }
+
+    #[test]
+    fn test_state_survives_restart() {
+        let dir = std::env::temp_dir().join(format!("sol-bans-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bans");
+        let now = crate::protocol::unix_now();
+
+        let bans = Bans::new(Some(POLICY), Some(path.clone()));
+        for at in 0..3 {
+            bans.strike(ip("198.51.100.7"), now + at);
+        }
+        bans.strike(ip("198.51.100.8"), now);
+        bans.save();
+
+        let restarted = Bans::new(Some(POLICY), Some(path.clone()));
+        assert!(restarted.is_banned(ip("198.51.100.7"), now + 5));
+        assert!(!restarted.strike(ip("198.51.100.8"), now + 1));
+        assert!(restarted.strike(ip("198.51.100.8"), now + 2));
+        assert_eq!(parse("garbage\n10.0.0.1 - 5 x 6\n").get(&ip("10.0.0.1")).unwrap().strikes, [5, 6]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
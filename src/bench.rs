@@ -0,0 +1,181 @@
+use crate::control;
+use crate::mac::MacAddr;
+use crate::selftest;
+use sol::packet;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// How often a batch of packets is sent
+const TICK: Duration = Duration::from_millis(10);
+
+/// How long to wait for outstanding replies once sending stops
+const DRAIN: Duration = Duration::from_secs(1);
+
+/// How fast to send, for how long, and how many of the packets are invalid
+#[derive(Clone, Copy, Debug)]
+pub struct Load {
+    /// Packets per second
+    pub rate: u32,
+    pub duration: Duration,
+    /// Percentage of packets that are invalid
+    pub invalid: u8,
+}
+
+/// What a bench run sent and what came back
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Test packets, each of which the daemon answers
+    pub valid: u64,
+    /// Packets with a WoL header and a broken MAC repetition, which the daemon rejects
+    pub invalid: u64,
+    /// Packets the local network stack refused to send
+    pub send_failed: u64,
+    pub answered: u64,
+    pub elapsed: Duration,
+    /// Growth of the daemon's rejected packet counter, when its control socket was reachable
+    pub rejected: Option<u32>,
+    pub flood: Option<bool>,
+}
+
+impl Report {
+    pub fn render(&self) -> String {
+        let secs = self.elapsed.as_secs_f64().max(0.001);
+        let sent = self.valid + self.invalid;
+        let mut lines = vec![
+            format!("sent: {} packets in {:.1}s ({:.0}/s), {} valid, {} invalid", sent, secs, sent as f64 / secs, self.valid, self.invalid),
+            format!("answered: {} of {} valid ({} dropped)", self.answered, self.valid, self.valid.saturating_sub(self.answered)),
+        ];
+        if self.send_failed > 0 {
+            lines.push(format!("send failed: {}", self.send_failed));
+        }
+        match self.rejected {
+            Some(rejected) => lines.push(format!(
+                "rejected: {} of {} invalid ({} dropped before validation)",
+                rejected,
+                self.invalid,
+                self.invalid.saturating_sub(rejected.into())
+            )),
+            None => lines.push("rejected: unknown (control socket not reachable)".to_string()),
+        }
+        if let Some(flood) = self.flood {
+            lines.push(format!("flood protection: {}", if flood { "active" } else { "inactive" }));
+        }
+        lines.join("\n")
+    }
+}
+
+/// An invalid packet the daemon still has to parse: a WoL header followed by a MAC that is
+/// not repeated
+fn invalid_packet(mac: &MacAddr) -> Vec<u8> {
+    let mut bytes = packet::encode(&packet::MAGIC_HEADER, &mac.0).to_vec();
+    if let Some(last) = bytes.last_mut() {
+        *last ^= 0xff;
+    }
+    bytes
+}
+
+/// The daemon's rejected packet counter and flood state
+async fn counters(socket: &Path) -> Option<(u32, bool)> {
+    let report = control::request(socket, "status").await.ok()?;
+    let value = |key: &str| report.lines().find_map(|line| line.strip_prefix(key)?.strip_prefix(": "));
+    Some((value("rejected packets")?.parse().ok()?, value("flood protection")? == "active"))
+}
+
+/// Send test packets and invalid packets for `mac` to `destination` at the given load, and
+/// count the answers. With the daemon's control `socket`, also count what it rejected.
+pub async fn run(destination: SocketAddr, mac: &MacAddr, load: Load, socket: Option<&Path>) -> Result<Report, String> {
+    let (valid, invalid) = (selftest::probe_packet(mac), invalid_packet(mac));
+    let bind: SocketAddr = if destination.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+    let udp = UdpSocket::bind(bind).await.map_err(|e| e.to_string())?;
+    udp.set_broadcast(true).map_err(|e| e.to_string())?;
+    let before = match socket {
+        Some(socket) => counters(socket).await,
+        None => None,
+    };
+
+    let mut report = Report::default();
+    let mut tick = tokio::time::interval(TICK);
+    let mut buf = [0u8; 512];
+    let start = Instant::now();
+    let mut sent = 0u64;
+    loop {
+        tokio::select! {
+            _ = tick.tick() => {
+                let elapsed = start.elapsed().min(load.duration);
+                let due = (elapsed.as_secs_f64() * f64::from(load.rate)) as u64;
+                for n in sent..due {
+                    let is_invalid = (n + 1) * u64::from(load.invalid) / 100 > n * u64::from(load.invalid) / 100;
+                    match udp.send_to(if is_invalid { &invalid } else { &valid }, destination).await {
+                        Ok(_) if is_invalid => report.invalid += 1,
+                        Ok(_) => report.valid += 1,
+                        Err(_) => report.send_failed += 1,
+                    }
+                }
+                sent = sent.max(due);
+                if elapsed >= load.duration {
+                    break;
+                }
+            }
+            received = udp.recv(&mut buf) => {
+                if received.is_ok() {
+                    report.answered += 1;
+                }
+            }
+        }
+    }
+    report.elapsed = start.elapsed();
+
+    let drain = tokio::time::sleep(DRAIN);
+    tokio::pin!(drain);
+    while report.answered < report.valid {
+        tokio::select! {
+            received = udp.recv(&mut buf) => {
+                if received.is_ok() {
+                    report.answered += 1;
+                }
+            }
+            _ = &mut drain => break,
+        }
+    }
+
+    if let (Some(socket), Some((rejected, _))) = (socket, before)
+        && let Some((now, flood)) = counters(socket).await
+    {
+        report.rejected = Some(now.wrapping_sub(rejected));
+        report.flood = Some(flood);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bench_counts() {
+        let daemon = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let destination = daemon.local_addr().unwrap();
+        let mac = MacAddr([0xAA; 6]);
+        // Answers test packets and ignores the rest, like the daemon
+        tokio::spawn(async move {
+            let mut buf = [0u8; 128];
+            while let Ok((len, from)) = daemon.recv_from(&mut buf).await {
+                if packet::parse(&buf[..len], &selftest::PROBE_PACKET_HEADER).is_ok() {
+                    daemon.send_to(b"ok", from).await.unwrap();
+                } else {
+                    assert_eq!(packet::parse(&buf[..len], &packet::MAGIC_HEADER), Err(packet::Error::Repetition));
+                }
+            }
+        });
+
+        let load = Load { rate: 200, duration: Duration::from_millis(300), invalid: 25 };
+        let report = run(destination, &mac, load, None).await.unwrap();
+        assert_eq!(report.valid + report.invalid, 60);
+        assert_eq!(report.invalid, 15);
+        assert_eq!(report.answered, report.valid);
+        assert!(report.render().contains("answered: 45 of 45 valid (0 dropped)"));
+        assert!(report.render().ends_with("rejected: unknown (control socket not reachable)"));
+    }
+}
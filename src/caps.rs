@@ -0,0 +1,90 @@
+use std::fs;
+
+pub const CAP_NET_BIND_SERVICE: u32 = 10;
+const CAP_SETPCAP: u32 = 8;
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Parse the effective capability mask from a /proc status file
+fn parse_effective(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("CapEff:"))?;
+    u64::from_str_radix(line["CapEff:".len()..].trim(), 16).ok()
+}
+
+/// Whether the calling thread holds `cap` in its effective set
+pub fn has(cap: u32) -> bool {
+    fs::read_to_string("/proc/thread-self/status")
+        .ok()
+        .and_then(|status| parse_effective(&status))
+        .is_some_and(|mask| mask & (1 << cap) != 0)
+}
+
+/// Clear every capability of the calling thread, including the bounding and ambient sets, so
+/// programs it runs later cannot regain them either. Only affects the calling thread and the
+/// threads and processes it creates afterwards.
+pub fn drop_all() -> Result<(), String> {
+    // SAFETY: prctl and capset only read the arguments passed
+    unsafe {
+        if libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_CLEAR_ALL, 0, 0, 0) != 0 {
+            return Err(format!("clearing ambient capabilities failed: {}", std::io::Error::last_os_error()));
+        }
+        // Shrinking the bounding set needs CAP_SETPCAP, which only root normally has
+        if has(CAP_SETPCAP) {
+            let last = fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+                .ok()
+                .and_then(|s| s.trim().parse::<u32>().ok())
+                .unwrap_or(40);
+            for cap in 0..=last {
+                if libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) != 0 {
+                    return Err(format!("dropping capability {} failed: {}", cap, std::io::Error::last_os_error()));
+                }
+            }
+        }
+        let header = CapHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+        let data = [CapData::default(); 2];
+        if libc::syscall(libc::SYS_capset, &header as *const CapHeader, data.as_ptr()) != 0 {
+            return Err(format!("clearing capabilities failed: {}", std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_effective() {
+        let status = "Name:\tsol\nCapInh:\t0000000000000000\nCapEff:\t0000000000000400\n";
+        let mask = parse_effective(status).unwrap();
+        assert_ne!(mask & (1 << CAP_NET_BIND_SERVICE), 0);
+        assert_eq!(mask & (1 << CAP_SETPCAP), 0);
+        assert_eq!(parse_effective("Name:\tsol\n"), None);
+    }
+
+    #[test]
+    fn test_drop_all_clears_thread() {
+        // Capabilities are per thread, so this leaves the test harness untouched
+        std::thread::spawn(|| {
+            drop_all().unwrap();
+            assert!(!has(CAP_NET_BIND_SERVICE));
+            assert!(!has(CAP_SETPCAP));
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,378 @@
+use crate::logfile;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// pcap's link type for packets that start with an IPv4 or IPv6 header
+const LINKTYPE_RAW: u32 = 101;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+/// Raw IP as some BSDs number it
+const LINKTYPE_RAW_BSD: u32 = 12;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const IPV4_HEADER: usize = 20;
+const IPV6_HEADER: usize = 40;
+const UDP_HEADER: usize = 8;
+
+/// Writes every datagram the listeners receive to a pcap file, rotated to `<path>.1`,
+/// `<path>.2`, ... once it grows past `max_size` bytes
+pub struct Capture {
+    pub path: PathBuf,
+    /// Bytes kept of each packet, counting the IP and UDP headers
+    pub snaplen: u32,
+    pub max_size: u64,
+    /// Number of rotated files kept
+    pub keep: usize,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl Capture {
+    /// Start a new capture at `path`, rotating away what an earlier run left there
+    pub fn open(path: PathBuf, snaplen: u32, max_size: u64, keep: usize) -> Result<Self, String> {
+        if std::fs::metadata(&path).is_ok_and(|metadata| metadata.len() > 0) {
+            logfile::shift(&path, keep).map_err(|e| format!("rotating {} failed: {}", path.display(), e))?;
+        }
+        let (file, size) = create(&path, snaplen)?;
+        Ok(Capture { path, snaplen, max_size, keep, file, size })
+    }
+
+    /// Record `payload` as a UDP datagram from `peer` to `local`
+    pub fn write(&mut self, peer: SocketAddr, local: SocketAddr, payload: &[u8], time: SystemTime) -> Result<(), String> {
+        let packet = datagram(peer, local, payload);
+        let kept = &packet[..packet.len().min(self.snaplen as usize)];
+        let record = 16 + kept.len() as u64;
+        if self.size + record > self.max_size && self.size > 24 {
+            self.rotate()?;
+        }
+        let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut header = Vec::with_capacity(16);
+        header.extend_from_slice(&(time.as_secs() as u32).to_le_bytes());
+        header.extend_from_slice(&time.subsec_micros().to_le_bytes());
+        header.extend_from_slice(&(kept.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        self.file
+            .write_all(&header)
+            .and_then(|()| self.file.write_all(kept))
+            .and_then(|()| self.file.flush())
+            .map_err(|e| format!("writing {} failed: {}", self.path.display(), e))?;
+        self.size += record;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        logfile::shift(&self.path, self.keep).map_err(|e| format!("rotating {} failed: {}", self.path.display(), e))?;
+        (self.file, self.size) = create(&self.path, self.snaplen)?;
+        Ok(())
+    }
+}
+
+/// Create `path` and write the pcap file header
+fn create(path: &PathBuf, snaplen: u32) -> Result<(BufWriter<File>, u64), String> {
+    let file = File::create(path).map_err(|e| format!("creating {} failed: {}", path.display(), e))?;
+    let mut file = BufWriter::new(file);
+    let mut header = Vec::with_capacity(24);
+    header.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&snaplen.to_le_bytes());
+    header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    file.write_all(&header)
+        .and_then(|()| file.flush())
+        .map_err(|e| format!("writing {} failed: {}", path.display(), e))?;
+    Ok((file, header.len() as u64))
+}
+
+/// IP and UDP headers for `payload`. The daemon only sees the payload, so the headers are
+/// made up from the two addresses; a wildcard listener shows as the unspecified address.
+fn datagram(peer: SocketAddr, local: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (UDP_HEADER + payload.len()) as u16;
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend_from_slice(&peer.port().to_be_bytes());
+    udp.extend_from_slice(&local.port().to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+
+    let (source, destination) = (peer.ip().to_canonical(), local.ip().to_canonical());
+    let mut packet = match (source, destination) {
+        (IpAddr::V4(source), destination) => {
+            let destination = match destination {
+                IpAddr::V4(destination) => destination,
+                IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+            };
+            let mut pseudo = [0u8; 12];
+            pseudo[..4].copy_from_slice(&source.octets());
+            pseudo[4..8].copy_from_slice(&destination.octets());
+            pseudo[9] = libc::IPPROTO_UDP as u8;
+            pseudo[10..].copy_from_slice(&udp_len.to_be_bytes());
+            set_udp_checksum(&mut udp, &pseudo);
+
+            let mut header = [0u8; IPV4_HEADER];
+            header[0] = 0x45;
+            header[2..4].copy_from_slice(&(IPV4_HEADER as u16 + udp_len).to_be_bytes());
+            header[6] = 0x40;
+            header[8] = 64;
+            header[9] = libc::IPPROTO_UDP as u8;
+            header[12..16].copy_from_slice(&source.octets());
+            header[16..20].copy_from_slice(&destination.octets());
+            let sum = !checksum(&[&header]);
+            header[10..12].copy_from_slice(&sum.to_be_bytes());
+            header.to_vec()
+        }
+        (IpAddr::V6(source), destination) => {
+            let destination = match destination {
+                IpAddr::V6(destination) => destination,
+                IpAddr::V4(_) => Ipv6Addr::UNSPECIFIED,
+            };
+            let mut pseudo = [0u8; 40];
+            pseudo[..16].copy_from_slice(&source.octets());
+            pseudo[16..32].copy_from_slice(&destination.octets());
+            pseudo[34..36].copy_from_slice(&udp_len.to_be_bytes());
+            pseudo[39] = libc::IPPROTO_UDP as u8;
+            set_udp_checksum(&mut udp, &pseudo);
+
+            let mut header = [0u8; IPV6_HEADER];
+            header[0] = 0x60;
+            header[4..6].copy_from_slice(&udp_len.to_be_bytes());
+            header[6] = libc::IPPROTO_UDP as u8;
+            header[7] = 64;
+            header[8..24].copy_from_slice(&source.octets());
+            header[24..40].copy_from_slice(&destination.octets());
+            header.to_vec()
+        }
+    };
+    packet.extend_from_slice(&udp);
+    packet
+}
+
+fn set_udp_checksum(udp: &mut [u8], pseudo: &[u8]) {
+    // A computed zero is sent as all ones, since zero means "no checksum"
+    let sum = match !checksum(&[pseudo, udp]) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    udp[6..8].copy_from_slice(&sum.to_be_bytes());
+}
+
+/// The ones' complement sum of the 16-bit words in `parts`, taken as one buffer
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    let mut odd = None;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        match odd.take() {
+            Some(high) => sum += u32::from(u16::from_be_bytes([high, *byte])),
+            None => odd = Some(*byte),
+        }
+    }
+    if let Some(high) = odd {
+        sum += u32::from(high) << 8;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// A UDP datagram read back from a capture
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Captured {
+    /// Time since the Unix epoch
+    pub time: Duration,
+    pub peer: SocketAddr,
+    pub local: SocketAddr,
+    /// The payload as captured, which is short if the capture was truncated
+    pub payload: Vec<u8>,
+}
+
+/// Read the UDP datagrams in a pcap file, as written by `--capture` or by tcpdump on an
+/// Ethernet, "any" or raw IP interface. Everything else in the file is skipped.
+pub fn read(bytes: &[u8]) -> Result<Vec<Captured>, String> {
+    let magic = bytes.get(..4).ok_or("not a pcap file")?;
+    let (big_endian, nanos) = match magic {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+        [0x0a, 0x0d, 0x0d, 0x0a] => return Err("pcapng files are not supported; convert with `editcap -F pcap`".to_string()),
+        _ => return Err("not a pcap file".to_string()),
+    };
+    let word = |at: usize| {
+        bytes.get(at..at + 4).map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) }
+        })
+    };
+    let linktype = word(20).ok_or("truncated pcap header")? & 0x0fff_ffff;
+
+    let mut datagrams = Vec::new();
+    let mut at = 24;
+    while at < bytes.len() {
+        let (Some(secs), Some(frac), Some(kept)) = (word(at), word(at + 4), word(at + 8)) else {
+            return Err(format!("truncated record at offset {}", at));
+        };
+        let data = bytes
+            .get(at + 16..at + 16 + kept as usize)
+            .ok_or_else(|| format!("truncated record at offset {}", at))?;
+        at += 16 + kept as usize;
+        let frac = if nanos { Duration::from_nanos(frac.into()) } else { Duration::from_micros(frac.into()) };
+        let time = Duration::from_secs(secs.into()) + frac;
+        if let Some(ip) = network_layer(linktype, data)
+            && let Some((peer, local, payload)) = udp(ip)
+        {
+            datagrams.push(Captured { time, peer, local, payload: payload.to_vec() });
+        }
+    }
+    Ok(datagrams)
+}
+
+/// The IP packet inside a link-layer frame, if it carries one
+fn network_layer(linktype: u32, frame: &[u8]) -> Option<&[u8]> {
+    let ethertype = |at: usize| frame.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    let (ethertype, start) = match linktype {
+        LINKTYPE_RAW | LINKTYPE_RAW_BSD | LINKTYPE_IPV4 | LINKTYPE_IPV6 => return Some(frame),
+        // The address family, in the capturing host's byte order
+        LINKTYPE_NULL => return frame.get(4..),
+        LINKTYPE_ETHERNET => match ethertype(12)? {
+            0x8100 => (ethertype(16)?, 18),
+            ethertype => (ethertype, 14),
+        },
+        LINKTYPE_LINUX_SLL => (ethertype(14)?, 16),
+        LINKTYPE_LINUX_SLL2 => (ethertype(0)?, 20),
+        _ => return None,
+    };
+    matches!(ethertype, 0x0800 | 0x86dd).then(|| frame.get(start..)).flatten()
+}
+
+/// Sender, destination and payload of a UDP datagram in an IP packet. Fragments and IPv6
+/// extension headers are not followed.
+fn udp(ip: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (source, destination, segment): (IpAddr, IpAddr, &[u8]) = match ip.first()? >> 4 {
+        4 => {
+            let header = usize::from(ip.first()? & 0x0f) * 4;
+            let fragment = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]) & 0x3fff;
+            if header < IPV4_HEADER || ip.get(9) != Some(&(libc::IPPROTO_UDP as u8)) || fragment != 0 {
+                return None;
+            }
+            let source: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (source.into(), destination.into(), ip.get(header..)?)
+        }
+        6 => {
+            if ip.get(6) != Some(&(libc::IPPROTO_UDP as u8)) {
+                return None;
+            }
+            let source: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            (source.into(), destination.into(), ip.get(IPV6_HEADER..)?)
+        }
+        _ => return None,
+    };
+    let port = |at: usize| Some(u16::from_be_bytes([*segment.get(at)?, *segment.get(at + 1)?]));
+    let length = usize::from(port(4)?).max(UDP_HEADER);
+    let payload = segment.get(UDP_HEADER..length.min(segment.len()))?;
+    Some((SocketAddr::new(source, port(0)?), SocketAddr::new(destination, port(2)?), payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_datagram_headers() {
+        let peer: SocketAddr = "[::ffff:192.168.1.5]:40000".parse().unwrap();
+        let local: SocketAddr = "[::]:9".parse().unwrap();
+        let packet = datagram(peer, local, b"abc");
+        assert_eq!(packet.len(), IPV4_HEADER + UDP_HEADER + 3);
+        assert_eq!(packet[0], 0x45);
+        assert_eq!(&packet[12..16], &[192, 168, 1, 5]);
+        assert_eq!(&packet[16..20], &[0, 0, 0, 0]);
+        // A header with a valid checksum sums to all ones
+        assert_eq!(checksum(&[&packet[..IPV4_HEADER]]), 0xffff);
+        assert_eq!(&packet[20..24], &[0x9c, 0x40, 0, 9]);
+
+        let peer: SocketAddr = "[fe80::1]:5000".parse().unwrap();
+        let packet = datagram(peer, "[fe80::2]:9".parse().unwrap(), b"abcd");
+        assert_eq!(packet.len(), IPV6_HEADER + UDP_HEADER + 4);
+        assert_eq!((packet[0], packet[6], &packet[4..6]), (0x60, 17, &[0u8, 12][..]));
+        let mut pseudo = packet[8..40].to_vec();
+        pseudo.extend_from_slice(&[0, 0, 0, 12, 0, 0, 0, 17]);
+        assert_eq!(checksum(&[&pseudo, &packet[IPV6_HEADER..]]), 0xffff);
+    }
+
+    #[test]
+    fn test_snaplen_and_rotation() {
+        let dir = std::env::temp_dir().join(format!("sol-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sol.pcap");
+        let (peer, local) = ("10.0.0.9:5000".parse().unwrap(), "0.0.0.0:9".parse().unwrap());
+        let time = UNIX_EPOCH + Duration::from_micros(1_760_000_000_250_000);
+
+        let mut capture = Capture::open(path.clone(), 40, 120, 1).unwrap();
+        capture.write(peer, local, &[0xff; 102], time).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(&bytes[20..24], &LINKTYPE_RAW.to_le_bytes());
+        assert_eq!(&bytes[24..28], &1_760_000_000u32.to_le_bytes());
+        assert_eq!(&bytes[28..32], &250_000u32.to_le_bytes());
+        assert_eq!(&bytes[32..40], &[40, 0, 0, 0, 130, 0, 0, 0]);
+        assert_eq!(bytes.len(), 24 + 16 + 40);
+
+        // The next record would pass max_size, so the file is rotated first
+        capture.write(peer, local, b"x", time).unwrap();
+        capture.write(peer, local, b"y", time).unwrap();
+        assert_eq!(std::fs::read(dir.join("sol.pcap.1")).unwrap(), bytes);
+        assert_eq!(std::fs::read(&path).unwrap().len(), 24 + 2 * (16 + 29));
+
+        // A restart keeps the earlier capture
+        drop(capture);
+        Capture::open(path.clone(), 40, 120, 1).unwrap();
+        assert_eq!(std::fs::read(dir.join("sol.pcap.1")).unwrap().len(), 24 + 2 * (16 + 29));
+        assert_eq!(std::fs::read(&path).unwrap().len(), 24);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_back() {
+        let dir = std::env::temp_dir().join(format!("sol-capture-read-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sol.pcap");
+        let (peer, local) = ("[fe80::1]:5000".parse().unwrap(), "[::]:9".parse().unwrap());
+        let time = UNIX_EPOCH + Duration::from_micros(1_760_000_000_250_000);
+        let mut capture = Capture::open(path.clone(), 65535, 1 << 20, 1).unwrap();
+        capture.write(peer, local, b"hello", time).unwrap();
+        capture.write("10.0.0.9:7".parse().unwrap(), "0.0.0.0:9".parse().unwrap(), b"", time).unwrap();
+        drop(capture);
+
+        let datagrams = read(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(datagrams[0], Captured { time: Duration::from_micros(1_760_000_000_250_000), peer, local, payload: b"hello".to_vec() });
+        assert_eq!(datagrams[1].peer, "10.0.0.9:7".parse().unwrap());
+        assert!(datagrams[1].payload.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // An Ethernet frame as tcpdump writes it, in big-endian byte order
+        let mut file = vec![0xa1, 0xb2, 0xc3, 0xd4, 0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 0, 1];
+        let mut frame = vec![0xff; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&datagram("192.168.1.5:40000".parse().unwrap(), "192.168.1.255:9".parse().unwrap(), b"wol"));
+        file.extend_from_slice(&[0, 0, 0, 10, 0, 0, 0, 0]);
+        file.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        file.extend_from_slice(&frame);
+        let datagrams = read(&file).unwrap();
+        assert_eq!(datagrams[0].local, "192.168.1.255:9".parse().unwrap());
+        assert_eq!((datagrams[0].time, datagrams[0].payload.as_slice()), (Duration::from_secs(10), &b"wol"[..]));
+
+        assert!(read(&file[..30]).is_err());
+        assert!(read(b"junk").is_err());
+    }
+}
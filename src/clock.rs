@@ -0,0 +1,177 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Broken-down local wall-clock time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalTime {
+    pub second: u32,
+    pub minute: u32,
+    pub hour: u32,
+    /// Day of the month, 1-31
+    pub day: u32,
+    /// Month, 1-12
+    pub month: u32,
+    pub year: i32,
+    /// Day of the week, 0 = Sunday
+    pub weekday: u32,
+}
+
+impl LocalTime {
+    pub fn now() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        LocalTime::from_unix(secs as libc::time_t)
+    }
+
+    pub fn from_unix(secs: libc::time_t) -> Self {
+        // SAFETY: localtime_r only writes to the tm we pass in
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        unsafe { libc::localtime_r(&secs, &mut tm) };
+
+        LocalTime {
+            second: tm.tm_sec as u32,
+            minute: tm.tm_min as u32,
+            hour: tm.tm_hour as u32,
+            day: tm.tm_mday as u32,
+            month: tm.tm_mon as u32 + 1,
+            year: tm.tm_year + 1900,
+            weekday: tm.tm_wday as u32,
+        }
+    }
+
+    /// Minutes since local midnight
+    pub fn minute_of_day(&self) -> u32 {
+        self.hour * 60 + self.minute
+    }
+}
+
+impl std::fmt::Display for LocalTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// A daily time window such as `08:00-18:00`; windows may wrap past midnight
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HourRange {
+    start: u32,
+    end: u32,
+}
+
+impl HourRange {
+    pub fn contains(&self, time: &LocalTime) -> bool {
+        let minute = time.minute_of_day();
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl std::str::FromStr for HourRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid time range '{}' (expected HH:MM-HH:MM)", s))?;
+        Ok(HourRange {
+            start: parse_hhmm(start.trim())?,
+            end: parse_hhmm(end.trim())?,
+        })
+    }
+}
+
+fn parse_hhmm(s: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid time '{}' (expected HH:MM)", s);
+    let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+    let hour: u32 = hour.parse().map_err(|_| invalid())?;
+    let minute: u32 = minute.parse().map_err(|_| invalid())?;
+    if hour > 24 || minute > 59 || (hour == 24 && minute > 0) {
+        return Err(invalid());
+    }
+    Ok(hour * 60 + minute)
+}
+
+/// A duration such as `90s`, `45m`, `2h`, `1d` or `1h30m`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration '{}' (expected e.g. 45m, 2h or 1h30m)", s);
+    let (mut secs, mut digits) = (0u64, String::new());
+    for c in s.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                digits.push(c);
+                continue;
+            }
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(invalid()),
+        };
+        let count: u64 = std::mem::take(&mut digits).parse().map_err(|_| invalid())?;
+        secs = secs.saturating_add(count.saturating_mul(unit));
+    }
+    if s.is_empty() || !digits.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> LocalTime {
+        LocalTime { second: 0, minute, hour, day: 1, month: 1, year: 2024, weekday: 1 }
+    }
+
+    #[test]
+    fn test_daytime_range() {
+        let range: HourRange = "08:00-18:00".parse().unwrap();
+        assert!(range.contains(&at(8, 0)));
+        assert!(range.contains(&at(17, 59)));
+        assert!(!range.contains(&at(18, 0)));
+        assert!(!range.contains(&at(7, 59)));
+    }
+
+    #[test]
+    fn test_overnight_range() {
+        let range: HourRange = "22:30-06:00".parse().unwrap();
+        assert!(range.contains(&at(23, 0)));
+        assert!(range.contains(&at(2, 0)));
+        assert!(!range.contains(&at(6, 0)));
+        assert!(!range.contains(&at(12, 0)));
+    }
+
+    #[test]
+    fn test_display() {
+        let time = LocalTime { second: 5, ..at(9, 3) };
+        assert_eq!(time.to_string(), "2024-01-01 09:03:05");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45m"), Ok(Duration::from_secs(2700)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_duration("45").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("2 h").is_err());
+    }
+
+    #[test]
+    fn test_invalid_ranges() {
+        assert!("08:00".parse::<HourRange>().is_err());
+        assert!("25:00-06:00".parse::<HourRange>().is_err());
+        assert!("08:60-09:00".parse::<HourRange>().is_err());
+        assert!("8-9".parse::<HourRange>().is_err());
+    }
+}
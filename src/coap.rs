@@ -0,0 +1,374 @@
+use crate::control::{Ask, Control, Refusal, Trigger};
+use crate::mac::MacAddr;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+const CON: u8 = 0;
+const NON: u8 = 1;
+const ACK: u8 = 2;
+const RST: u8 = 3;
+
+const GET: u8 = 0x01;
+const POST: u8 = 0x02;
+const CHANGED: u8 = 0x44;
+const CONTENT: u8 = 0x45;
+const FORBIDDEN: u8 = 0x83;
+const NOT_FOUND: u8 = 0x84;
+const METHOD_NOT_ALLOWED: u8 = 0x85;
+const CONFLICT: u8 = 0x89;
+
+const URI_PATH: u16 = 11;
+const CONTENT_FORMAT: u16 = 12;
+const TEXT_PLAIN: u8 = 0;
+const LINK_FORMAT: u8 = 40;
+
+/// How long a confirmable request's response is kept to answer retransmissions
+const EXCHANGE_LIFETIME: Duration = Duration::from_secs(247);
+const MAX_REMEMBERED: usize = 64;
+
+/// A CoAP message (RFC 7252)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Message {
+    kind: u8,
+    code: u8,
+    id: u16,
+    token: Vec<u8>,
+    /// Options in ascending number order
+    options: Vec<(u16, Vec<u8>)>,
+    payload: Vec<u8>,
+}
+
+/// Read an extended option delta or length
+fn extended(nibble: u8, buf: &mut &[u8]) -> Result<u16, String> {
+    let truncated = || "truncated option".to_string();
+    match nibble {
+        0..=12 => Ok(nibble as u16),
+        13 => {
+            let (&b, rest) = buf.split_first().ok_or_else(truncated)?;
+            *buf = rest;
+            Ok(b as u16 + 13)
+        }
+        14 => {
+            let bytes = buf.get(..2).ok_or_else(truncated)?;
+            let value = u16::from_be_bytes([bytes[0], bytes[1]]);
+            *buf = &buf[2..];
+            value.checked_add(269).ok_or_else(|| "option too large".to_string())
+        }
+        _ => Err("reserved option nibble".to_string()),
+    }
+}
+
+impl Message {
+    fn parse(buf: &[u8]) -> Result<Message, String> {
+        if buf.len() < 4 || buf[0] >> 6 != 1 {
+            return Err("not a CoAP version 1 message".to_string());
+        }
+        let tkl = (buf[0] & 0x0F) as usize;
+        if tkl > 8 {
+            return Err("invalid token length".to_string());
+        }
+        let token = buf.get(4..4 + tkl).ok_or("truncated token")?.to_vec();
+        let mut message = Message {
+            kind: (buf[0] >> 4) & 0x03,
+            code: buf[1],
+            id: u16::from_be_bytes([buf[2], buf[3]]),
+            token,
+            ..Message::default()
+        };
+
+        let mut rest = &buf[4 + tkl..];
+        let mut number = 0u16;
+        while let Some((&header, tail)) = rest.split_first() {
+            rest = tail;
+            if header == 0xFF {
+                if rest.is_empty() {
+                    return Err("payload marker without payload".to_string());
+                }
+                message.payload = rest.to_vec();
+                break;
+            }
+            let delta = extended(header >> 4, &mut rest)?;
+            let len = extended(header & 0x0F, &mut rest)? as usize;
+            number = number.checked_add(delta).ok_or("option number too large")?;
+            let value = rest.get(..len).ok_or("truncated option")?;
+            message.options.push((number, value.to_vec()));
+            rest = &rest[len..];
+        }
+        Ok(message)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![0x40 | (self.kind << 4) | self.token.len() as u8, self.code];
+        buf.extend_from_slice(&self.id.to_be_bytes());
+        buf.extend_from_slice(&self.token);
+
+        let nibble = |value: usize, extra: &mut Vec<u8>| match value {
+            0..=12 => value as u8,
+            13..=268 => {
+                extra.push((value - 13) as u8);
+                13
+            }
+            _ => {
+                extra.extend_from_slice(&((value - 269) as u16).to_be_bytes());
+                14
+            }
+        };
+        let mut previous = 0;
+        for (number, value) in &self.options {
+            let mut extra = Vec::new();
+            let delta = nibble((number - previous) as usize, &mut extra);
+            let len = nibble(value.len(), &mut extra);
+            buf.push(delta << 4 | len);
+            buf.extend_from_slice(&extra);
+            buf.extend_from_slice(value);
+            previous = *number;
+        }
+        if !self.payload.is_empty() {
+            buf.push(0xFF);
+            buf.extend_from_slice(&self.payload);
+        }
+        buf
+    }
+
+    fn path(&self) -> String {
+        let segments: Vec<String> = self
+            .options
+            .iter()
+            .filter(|(number, _)| *number == URI_PATH)
+            .map(|(_, value)| String::from_utf8_lossy(value).into_owned())
+            .collect();
+        segments.join("/")
+    }
+}
+
+/// Serves `sleep` and `status` resources to CoAP clients
+pub struct Coap {
+    pub control: Control,
+    /// What `POST /sleep` triggers for
+    pub mac: MacAddr,
+}
+
+impl Coap {
+    /// The response code and payload for a request, with the content format if there is a body
+    async fn resource(&self, request: &Message, peer: SocketAddr, local: SocketAddr) -> (u8, Option<u8>, String) {
+        match (request.path().as_str(), request.code) {
+            (".well-known/core", GET) => (CONTENT, Some(LINK_FORMAT), "</sleep>,</status>".to_string()),
+            ("status", GET) => (CONTENT, Some(TEXT_PLAIN), self.control.respond("status").await),
+            ("sleep", POST) => {
+                let trigger = Trigger { ask: Ask::Sleep, via: "CoAP", peer, local, mac: self.mac };
+                match self.control.trigger(trigger).await {
+                    Ok(verdict) => (CHANGED, Some(TEXT_PLAIN), verdict),
+                    Err(Refusal::Forbidden(reason)) => (FORBIDDEN, Some(TEXT_PLAIN), reason),
+                    Err(Refusal::Conflict(reason)) => (CONFLICT, Some(TEXT_PLAIN), reason),
+                }
+            }
+            (".well-known/core" | "status" | "sleep", _) => (METHOD_NOT_ALLOWED, None, String::new()),
+            _ => (NOT_FOUND, None, String::new()),
+        }
+    }
+
+    /// The reply to a datagram, if it needs one
+    async fn respond(&self, request: &Message, peer: SocketAddr, local: SocketAddr, next_id: u16) -> Option<Message> {
+        let reply = |kind, code| Message {
+            kind,
+            code,
+            id: if kind == ACK || kind == RST { request.id } else { next_id },
+            token: request.token.clone(),
+            ..Message::default()
+        };
+        match (request.kind, request.code) {
+            (ACK | RST, _) => None,
+            // An empty confirmable message is a ping
+            (CON, 0) => Some(Message { token: Vec::new(), ..reply(RST, 0) }),
+            (_, code) if code >> 5 != 0 || code == 0 => None,
+            (kind, _) => {
+                let (code, format, body) = self.resource(request, peer, local).await;
+                info!("CoAP {} /{} from {}: {}", method(request.code), request.path(), peer, code_name(code));
+                let mut response = reply(if kind == CON { ACK } else { NON }, code);
+                if let Some(format) = format {
+                    response.options.push((CONTENT_FORMAT, if format == 0 { Vec::new() } else { vec![format] }));
+                }
+                response.payload = body.into_bytes();
+                Some(response)
+            }
+        }
+    }
+}
+
+fn method(code: u8) -> &'static str {
+    match code {
+        GET => "GET",
+        POST => "POST",
+        0x03 => "PUT",
+        0x04 => "DELETE",
+        _ => "?",
+    }
+}
+
+fn code_name(code: u8) -> String {
+    format!("{}.{:02}", code >> 5, code & 0x1F)
+}
+
+/// Responses to recent confirmable requests, so a retransmission is answered without
+/// triggering twice
+#[derive(Default)]
+struct Exchanges {
+    recent: VecDeque<(SocketAddr, u16, Vec<u8>, Instant)>,
+}
+
+impl Exchanges {
+    fn get(&mut self, peer: SocketAddr, id: u16, now: Instant) -> Option<Vec<u8>> {
+        self.recent.retain(|(_, _, _, at)| now.duration_since(*at) < EXCHANGE_LIFETIME);
+        self.recent
+            .iter()
+            .find(|(p, i, _, _)| *p == peer && *i == id)
+            .map(|(_, _, response, _)| response.clone())
+    }
+
+    fn insert(&mut self, peer: SocketAddr, id: u16, response: Vec<u8>, now: Instant) {
+        if self.recent.len() == MAX_REMEMBERED {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((peer, id, response, now));
+    }
+}
+
+/// Bind `addr` and serve CoAP requests in the background
+pub async fn listen(addr: SocketAddr, coap: Coap) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(addr).await?;
+    let local = socket.local_addr()?;
+    let coap = Arc::new(coap);
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1152];
+        let mut exchanges = Exchanges::default();
+        let mut next_id = std::process::id() as u16;
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("Receiving CoAP request failed: {}", e);
+                    return;
+                }
+            };
+            let request = match Message::parse(&buf[..len]) {
+                Ok(request) => request,
+                Err(e) => {
+                    verbose!("Ignoring malformed CoAP message from {}: {}", peer, e);
+                    continue;
+                }
+            };
+
+            let now = Instant::now();
+            let response = match exchanges.get(peer, request.id, now) {
+                Some(response) => response,
+                None => {
+                    next_id = next_id.wrapping_add(1);
+                    let Some(response) = coap.respond(&request, peer, local, next_id).await else { continue };
+                    let response = response.encode();
+                    if request.kind == CON {
+                        exchanges.insert(peer, request.id, response.clone(), now);
+                    }
+                    response
+                }
+            };
+            if let Err(e) = socket.send_to(&response, peer).await {
+                warn!("Failed to answer CoAP request from {}: {}", peer, e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bans::Bans;
+    use crate::inhibit::Inhibitors;
+    use crate::maintenance::Maintenance;
+    use crate::pause::Paused;
+    use crate::peers::Peers;
+    use crate::policy::{Policy, Profiles};
+
+    fn coap(verdict: Result<String, Refusal>) -> Coap {
+        let policy = Policy {
+            quorum: 1,
+            quorum_window: 10,
+            confirm: false,
+            grace: 0,
+            warn_users: false,
+            inhibit_share_clients: false,
+            inhibit_ports: Vec::new(),
+            ignore_desktop_inhibitors: true,
+            inhibit_session_activity: false,
+            min_idle: None,
+        };
+        let mut control = Control::new(
+            Maintenance::new(std::env::temp_dir().join(format!("sol-coap-test-{}", std::process::id()))),
+            Paused::default(),
+            Arc::new(Inhibitors::default()),
+            Arc::new(Profiles::new(policy)),
+            Arc::new(Peers::default()),
+            Arc::new(Bans::new(None, None)),
+        );
+        let (tx, mut triggers) = tokio::sync::mpsc::channel::<(Trigger, crate::control::Verdict)>(1);
+        tokio::spawn(async move {
+            while let Some((_, answer)) = triggers.recv().await {
+                let _ = answer.send(verdict.clone());
+            }
+        });
+        control.triggers = Some(tx);
+        Coap { control, mac: MacAddr::ZERO }
+    }
+
+    fn request(kind: u8, code: u8, path: &str) -> Message {
+        Message {
+            kind,
+            code,
+            id: 0x1234,
+            token: vec![0xAB, 0xCD],
+            options: path.split('/').map(|segment| (URI_PATH, segment.as_bytes().to_vec())).collect(),
+            payload: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut message = request(CON, GET, ".well-known/core");
+        message.options.push((300, vec![1; 20]));
+        message.payload = b"x".to_vec();
+        let encoded = message.encode();
+        assert_eq!(&encoded[..6], &[0x42, 0x01, 0x12, 0x34, 0xAB, 0xCD]);
+        assert_eq!(Message::parse(&encoded), Ok(message));
+
+        assert!(Message::parse(&[0x40, 0x01, 0x00]).is_err());
+        assert!(Message::parse(&[0x40, 0x01, 0x00, 0x01, 0xFF]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_responses() {
+        let inhibited = coap(Err(Refusal::Conflict("inhibited (snooze)".to_string())));
+        let coap = coap(Err(Refusal::Forbidden("ignored by rule".to_string())));
+        let peer: SocketAddr = "192.168.1.5:40000".parse().unwrap();
+        let local: SocketAddr = "192.168.1.2:5683".parse().unwrap();
+
+        let status = coap.respond(&request(CON, GET, "status"), peer, local, 7).await.unwrap();
+        assert_eq!((status.kind, status.code, status.id, &status.token[..]), (ACK, CONTENT, 0x1234, &[0xAB, 0xCD][..]));
+        assert!(String::from_utf8(status.payload).unwrap().contains("maintenance: off"));
+
+        let sleep = coap.respond(&request(NON, POST, "sleep"), peer, local, 7).await.unwrap();
+        assert_eq!((sleep.kind, sleep.code, sleep.id), (NON, FORBIDDEN, 7));
+        let sleep = inhibited.respond(&request(CON, POST, "sleep"), peer, local, 7).await.unwrap();
+        assert_eq!((sleep.code, &sleep.payload[..]), (CONFLICT, &b"inhibited (snooze)"[..]));
+
+        assert_eq!(coap.respond(&request(CON, GET, "sleep"), peer, local, 7).await.unwrap().code, METHOD_NOT_ALLOWED);
+        assert_eq!(coap.respond(&request(CON, GET, "reboot"), peer, local, 7).await.unwrap().code, NOT_FOUND);
+
+        let ping = Message { kind: CON, id: 9, ..Message::default() };
+        assert_eq!(coap.respond(&ping, peer, local, 7).await.unwrap().kind, RST);
+        assert_eq!(coap.respond(&Message { kind: ACK, ..ping }, peer, local, 7).await, None);
+    }
+}
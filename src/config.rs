@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+pub type Table = BTreeMap<String, Value>;
+
+/// A parsed configuration file, in the TOML subset the daemon needs: `[table]` and
+/// `[table.sub]` headers with `key = value` pairs whose values are strings, integers,
+/// booleans or single-line arrays. Keys before the first header live in the root table `""`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub tables: BTreeMap<String, Table>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Config::default();
+        let mut current = String::new();
+        config.tables.insert(current.clone(), Table::new());
+
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let result = if let Some(header) = line.strip_prefix('[') {
+                parse_header(header).map(|name| {
+                    current = name;
+                    config.tables.entry(current.clone()).or_default();
+                })
+            } else {
+                parse_pair(line).and_then(|(key, value)| {
+                    let table = config.tables.entry(current.clone()).or_default();
+                    match table.insert(key.clone(), value) {
+                        Some(_) => Err(format!("duplicate key '{}'", key)),
+                        None => Ok(()),
+                    }
+                })
+            };
+
+            result.map_err(|e| format!("line {}: {}", number + 1, e))?;
+        }
+
+        Ok(config)
+    }
+
+    /// Tables named `<prefix>.<name>`, yielded as `(name, table)`
+    pub fn subtables<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a Table)> + 'a {
+        self.tables.iter().filter_map(move |(name, table)| {
+            name.strip_prefix(prefix)
+                .and_then(|rest| rest.strip_prefix('.'))
+                .map(|rest| (rest, table))
+        })
+    }
+}
+
+/// Drop a trailing `# comment`, ignoring `#` inside strings
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_header(header: &str) -> Result<String, String> {
+    let name = header
+        .strip_suffix(']')
+        .ok_or("unterminated table header")?
+        .trim();
+    if name.starts_with('[') {
+        return Err("arrays of tables are not supported".to_string());
+    }
+    if name.is_empty() || !name.split('.').all(is_bare_key) {
+        return Err(format!("invalid table name '{}'", name));
+    }
+    Ok(name.to_string())
+}
+
+fn parse_pair(line: &str) -> Result<(String, Value), String> {
+    let (key, value) = line.split_once('=').ok_or("expected key = value")?;
+    let key = key.trim();
+    if !is_bare_key(key) {
+        return Err(format!("invalid key '{}'", key));
+    }
+    let (value, rest) = parse_value(value.trim())?;
+    if !rest.trim().is_empty() {
+        return Err(format!("unexpected trailing characters '{}'", rest.trim()));
+    }
+    Ok((key.to_string(), value))
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Parse one value from the front of `input`, returning it with the unparsed remainder
+fn parse_value(input: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = input.strip_prefix('"') {
+        return parse_string(rest);
+    }
+
+    if let Some(mut rest) = input.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), after));
+            }
+            let (value, after) = parse_value(rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.starts_with(']') {
+                return Err("expected ',' or ']' in array".to_string());
+            }
+        }
+    }
+
+    let end = input
+        .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+        .unwrap_or(input.len());
+    let (token, rest) = input.split_at(end);
+    let value = match token {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => Value::Integer(
+            token
+                .replace('_', "")
+                .parse()
+                .map_err(|_| format!("invalid value '{}'", token))?,
+        ),
+    };
+    Ok((value, rest))
+}
+
+fn parse_string(input: &str) -> Result<(Value, &str), String> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((Value::String(value), &input[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                other => return Err(format!("invalid escape '\\{}'", other.unwrap_or(' '))),
+            },
+            _ => value.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tables_and_values() {
+        let config = Config::parse(
+            r#"
+            port = 9  # comment
+            name = "sol # not a comment"
+
+            [profile.day]
+            hours = "08:00-18:00"
+            quorum = 3
+            inhibit_share_clients = true
+            containers = ["db:60", "web"]
+            "#,
+        )
+        .unwrap();
+
+        let root = &config.tables[""];
+        assert_eq!(root["port"], Value::Integer(9));
+        assert_eq!(root["name"].as_str(), Some("sol # not a comment"));
+
+        let profiles: Vec<_> = config.subtables("profile").collect();
+        assert_eq!(profiles.len(), 1);
+        let (name, day) = profiles[0];
+        assert_eq!(name, "day");
+        assert_eq!(day["quorum"].as_integer(), Some(3));
+        assert_eq!(day["inhibit_share_clients"].as_bool(), Some(true));
+        assert_eq!(
+            day["containers"],
+            Value::Array(vec![
+                Value::String("db:60".to_string()),
+                Value::String("web".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let config = Config::parse(r#"command = "echo \"hi\"\tthere""#).unwrap();
+        assert_eq!(config.tables[""]["command"].as_str(), Some("echo \"hi\"\tthere"));
+    }
+
+    #[test]
+    fn test_errors_report_line() {
+        assert_eq!(Config::parse("a = 1\nb =").unwrap_err(), "line 2: invalid value ''");
+        assert_eq!(Config::parse("a = 1\na = 2").unwrap_err(), "line 2: duplicate key 'a'");
+        assert!(Config::parse("[[servers]]").unwrap_err().contains("not supported"));
+        assert!(Config::parse("name = \"open").unwrap_err().contains("unterminated"));
+        assert!(Config::parse("a = 1 2").unwrap_err().contains("trailing"));
+    }
+}
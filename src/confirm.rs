@@ -0,0 +1,123 @@
+use crate::mac::MacAddr;
+use sol::packet;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+pub const TOKEN_SIZE: usize = 6;
+
+/// Pending suspends armed by a first packet, waiting for a second packet carrying the token
+pub struct Confirmations {
+    timeout: Duration,
+    pending: HashMap<MacAddr, ([u8; TOKEN_SIZE], Instant)>,
+}
+
+impl Confirmations {
+    pub fn new(timeout: Duration) -> Self {
+        Confirmations {
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Arm a pending suspend for `mac`, replacing any previous one, and return its token
+    pub fn arm(&mut self, mac: MacAddr, now: Instant) -> std::io::Result<[u8; TOKEN_SIZE]> {
+        let token = generate_token()?;
+        self.pending.insert(mac, (token, now));
+        Ok(token)
+    }
+
+    /// Whether a suspend for `mac` is waiting for its token, expired or not
+    pub fn is_pending(&self, mac: &MacAddr) -> bool {
+        self.pending.contains_key(mac)
+    }
+
+    /// Drop any pending suspend for `mac`
+    pub fn cancel(&mut self, mac: &MacAddr) {
+        self.pending.remove(mac);
+    }
+
+    /// Consume the pending suspend for `mac` if `token` matches and it has not expired
+    pub fn confirm(&mut self, mac: &MacAddr, token: &[u8], now: Instant) -> bool {
+        match self.pending.get(mac) {
+            Some((_, armed)) if now.duration_since(*armed) > self.timeout => {
+                self.pending.remove(mac);
+                false
+            }
+            Some((expected, _)) if expected.as_slice() == token => {
+                self.pending.remove(mac);
+                true
+            }
+            Some(_) => false,
+            None => false,
+        }
+    }
+}
+
+/// Extract the confirmation token appended after the 102-byte magic packet, if present
+pub fn packet_token(packet: &[u8]) -> Option<&[u8]> {
+    packet.get(packet::PACKET_SIZE..packet::PACKET_SIZE + TOKEN_SIZE)
+}
+
+/// Build the reply sent to the sender: the original magic packet with the token appended,
+/// so the sender can confirm by echoing it back verbatim
+pub fn confirmation_packet(mac: &MacAddr, token: &[u8; TOKEN_SIZE]) -> Vec<u8> {
+    let mut packet = packet::encode(&packet::MAGIC_HEADER, &mac.0).to_vec();
+    packet.extend_from_slice(token);
+    packet
+}
+
+fn generate_token() -> std::io::Result<[u8; TOKEN_SIZE]> {
+    let mut token = [0u8; TOKEN_SIZE];
+    File::open("/dev/urandom")?.read_exact(&mut token)?;
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+    #[test]
+    fn test_confirm_with_matching_token() {
+        let mut confirmations = Confirmations::new(Duration::from_secs(30));
+        let now = Instant::now();
+        let token = confirmations.arm(MAC, now).unwrap();
+
+        assert!(confirmations.confirm(&MAC, &token, now + Duration::from_secs(5)));
+        // Token is single-use
+        assert!(!confirmations.confirm(&MAC, &token, now + Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn test_confirm_rejects_wrong_token() {
+        let mut confirmations = Confirmations::new(Duration::from_secs(30));
+        let now = Instant::now();
+        let token = confirmations.arm(MAC, now).unwrap();
+        let mut wrong = token;
+        wrong[0] ^= 0xFF;
+
+        assert!(!confirmations.confirm(&MAC, &wrong, now));
+        assert!(confirmations.confirm(&MAC, &token, now));
+    }
+
+    #[test]
+    fn test_confirm_expires() {
+        let mut confirmations = Confirmations::new(Duration::from_secs(30));
+        let now = Instant::now();
+        let token = confirmations.arm(MAC, now).unwrap();
+
+        assert!(!confirmations.confirm(&MAC, &token, now + Duration::from_secs(31)));
+    }
+
+    #[test]
+    fn test_confirmation_packet_roundtrip() {
+        let token = [1, 2, 3, 4, 5, 6];
+        let packet = confirmation_packet(&MAC, &token);
+        assert_eq!(packet.len(), packet::PACKET_SIZE + TOKEN_SIZE);
+        assert_eq!(packet_token(&packet), Some(&token[..]));
+        assert_eq!(packet_token(&packet[..packet::PACKET_SIZE]), None);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::inhibit::{self, COMMAND_TIMEOUT, Inhibitor};
+use std::io::ErrorKind;
+use std::process::Command;
+
+const TCP_TABLES: [&str; 2] = ["/proc/net/tcp", "/proc/net/tcp6"];
+const TCP_ESTABLISHED: &str = "01";
+
+/// Ports served by NFS (2049) and SMB (445, 139)
+pub const SHARE_PORTS: [u16; 3] = [2049, 445, 139];
+
+/// Count established TCP connections whose local port is in `ports`
+pub fn established_connections(ports: &[u16]) -> Result<usize, String> {
+    Ok(established_ports(ports)?.len())
+}
+
+/// Local ports of the established TCP connections whose local port is in `ports`, one
+/// entry per connection
+pub fn established_ports(ports: &[u16]) -> Result<Vec<u16>, String> {
+    let mut found = Vec::new();
+    for table in TCP_TABLES {
+        match std::fs::read_to_string(table) {
+            Ok(contents) => found.extend(established(&contents, ports)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("{}: {}", table, e)),
+        }
+    }
+    Ok(found)
+}
+
+/// Established connections in a /proc/net/tcp{,6} table, as their local ports
+fn established<'a>(table: &'a str, ports: &'a [u16]) -> impl Iterator<Item = u16> + 'a {
+    table.lines().skip(1).filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (Some(local), Some(state)) = (fields.get(1), fields.get(3)) else {
+            return None;
+        };
+        let port = local
+            .rsplit_once(':')
+            .and_then(|(_, port)| u16::from_str_radix(port, 16).ok());
+        port.filter(|port| *state == TCP_ESTABLISHED && ports.contains(port))
+    })
+}
+
+/// Blocks suspend while NFS/SMB clients are connected to this machine
+pub struct ShareClients;
+
+impl Inhibitor for ShareClients {
+    fn name(&self) -> &str {
+        "share-clients"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        let connections = established_connections(&SHARE_PORTS)?;
+        if connections > 0 {
+            return Ok(Some(format!("{} NFS/SMB client connection(s)", connections)));
+        }
+
+        let sessions = smb_sessions()?;
+        if sessions > 0 {
+            return Ok(Some(format!("{} Samba session(s)", sessions)));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Blocks suspend while any of `ports` has an established connection, e.g. VNC, a media
+/// server or a game server
+pub struct PortClients {
+    pub ports: Vec<u16>,
+}
+
+impl Inhibitor for PortClients {
+    fn name(&self) -> &str {
+        "ports"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        let mut connected = established_ports(&self.ports)?;
+        if connected.is_empty() {
+            return Ok(None);
+        }
+        let count = connected.len();
+        connected.sort_unstable();
+        connected.dedup();
+        let ports: Vec<String> = connected.iter().map(u16::to_string).collect();
+        Ok(Some(format!("{} connection(s) on port {}", count, ports.join(", "))))
+    }
+}
+
+/// Count Samba sessions reported by `smbstatus -b`, or zero when Samba is not installed
+fn smb_sessions() -> Result<usize, String> {
+    let output = match inhibit::output(Command::new("smbstatus").arg("-b"), COMMAND_TIMEOUT) {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(format!("smbstatus: {}", e)),
+    };
+
+    if !output.status.success() {
+        return Err(format!(
+            "smbstatus failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(count_smb_sessions(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Session rows follow the dashed separator line in `smbstatus -b` output
+fn count_smb_sessions(output: &str) -> usize {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("---"))
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCP_TABLE: &str = "\
+  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:0801 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1000 1
+   1: 0101A8C0:0801 0501A8C0:03FF 01 00000000:00000000 00:00000000 00000000     0        0 1001 1
+   2: 0101A8C0:01BD 0601A8C0:D431 01 00000000:00000000 00:00000000 00000000     0        0 1002 1
+   3: 0101A8C0:0016 0601A8C0:D432 01 00000000:00000000 00:00000000 00000000     0        0 1003 1
+   4: 0101A8C0:01BD 0701A8C0:D433 06 00000000:00000000 00:00000000 00000000     0        0 1004 1
+";
+
+    #[test]
+    fn test_established_ports() {
+        // Listening socket and TIME_WAIT are ignored, SSH (port 22) is not a share port
+        assert_eq!(established(TCP_TABLE, &SHARE_PORTS).count(), 2);
+        assert_eq!(established(TCP_TABLE, &[22]).count(), 1);
+        assert_eq!(established(TCP_TABLE, &[80]).count(), 0);
+        assert_eq!(established(TCP_TABLE, &[445, 22, 2049]).collect::<Vec<_>>(), [2049, 445, 22]);
+    }
+
+    #[test]
+    fn test_count_smb_sessions() {
+        let output = "\
+Samba version 4.19.5
+PID     Username     Group        Machine                                   Protocol Version  Encryption           Signing
+----------------------------------------------------------------------------------------------------------------------------------------
+4242    alice        users        192.168.1.5 (ipv4:192.168.1.5:51234)      SMB3_11           -                    partial(AES-128-CMAC)
+4243    bob          users        192.168.1.6 (ipv4:192.168.1.6:51240)      SMB3_11           -                    partial(AES-128-CMAC)
+
+";
+        assert_eq!(count_smb_sessions(output), 2);
+        assert_eq!(count_smb_sessions("Samba version 4.19.5\nPID Username\n-----\n\n"), 0);
+    }
+}
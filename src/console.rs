@@ -0,0 +1,129 @@
+use crate::control::{Ask, Control, Trigger};
+use crate::mac::MacAddr;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const HELP: &str = "commands: status, pause, resume, suspend, quit, or any control socket command";
+
+/// Answers commands typed on the terminal the daemon runs in
+pub struct Console {
+    pub control: Control,
+    /// What `suspend` triggers for
+    pub mac: Option<MacAddr>,
+}
+
+impl Console {
+    /// The reply to one line, or None to quit
+    async fn handle(&self, line: &str) -> Option<String> {
+        let reply = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [] => return Some(String::new()),
+            ["quit" | "exit"] => return None,
+            ["help"] => HELP.to_string(),
+            [command @ ("pause" | "resume")] => {
+                let pause = *command == "pause";
+                if self.control.paused.set(pause) {
+                    info!("Trigger processing {} via console", if pause { "paused" } else { "resumed" });
+                }
+                format!("paused: {}", if pause { "yes" } else { "no" })
+            }
+            // A real trigger from loopback, so rules, inhibitors, quorum and the grace period all apply
+            ["suspend"] => match self.mac {
+                Some(mac) => {
+                    let local = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+                    match self.control.trigger(Trigger { ask: Ask::Sleep, via: "console", peer: local, local, mac }).await {
+                        Ok(verdict) => verdict,
+                        Err(e) => format!("error: {}", e),
+                    }
+                }
+                None => "error: no network interface with a MAC address to trigger".to_string(),
+            },
+            _ => self.control.respond(line).await,
+        };
+        Some(reply)
+    }
+}
+
+/// Read commands from stdin in the background when it is a terminal. Replies go to stderr,
+/// so stdout keeps carrying only log lines or events.
+pub fn start(console: Console) -> bool {
+    // SAFETY: isatty only inspects the descriptor
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return false;
+    }
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            match console.handle(&line).await {
+                Some(reply) if reply.is_empty() => {}
+                Some(reply) => eprintln!("{}", reply),
+                None => {
+                    info!("Quit via console");
+                    console.control.bans.save();
+                    std::process::exit(0);
+                }
+            }
+        }
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bans::Bans;
+    use crate::inhibit::Inhibitors;
+    use crate::maintenance::Maintenance;
+    use crate::pause::Paused;
+    use crate::peers::Peers;
+    use crate::control::Refusal;
+    use crate::policy::{Policy, Profiles};
+    use std::sync::Arc;
+
+    fn console(mac: Option<MacAddr>) -> Console {
+        let policy = Policy {
+            quorum: 1,
+            quorum_window: 10,
+            confirm: false,
+            grace: 0,
+            warn_users: false,
+            inhibit_share_clients: false,
+            inhibit_ports: Vec::new(),
+            ignore_desktop_inhibitors: true,
+            inhibit_session_activity: false,
+            min_idle: None,
+        };
+        let control = Control::new(
+            Maintenance::new(std::env::temp_dir().join(format!("sol-console-test-{}", std::process::id()))),
+            Paused::default(),
+            Arc::new(Inhibitors::default()),
+            Arc::new(Profiles::new(policy)),
+            Arc::new(Peers::default()),
+            Arc::new(Bans::new(None, None)),
+        );
+        Console { control, mac }
+    }
+
+    #[tokio::test]
+    async fn test_commands() {
+        let mac = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let mut console = console(Some(mac));
+        let (tx, mut triggers) = tokio::sync::mpsc::channel(1);
+        console.control.triggers = Some(tx);
+
+        assert_eq!(console.handle("pause").await.as_deref(), Some("paused: yes"));
+        assert!(console.control.paused.is_paused());
+        assert!(console.handle("status").await.unwrap().contains("paused: yes"));
+        assert_eq!(console.handle("resume").await.as_deref(), Some("paused: no"));
+        assert_eq!(console.handle("  ").await.as_deref(), Some(""));
+        assert!(console.handle("reboot").await.unwrap().starts_with("error: unknown command"));
+        assert_eq!(console.handle("quit").await, None);
+
+        let daemon = async {
+            let (trigger, verdict): (Trigger, crate::control::Verdict) = triggers.recv().await.unwrap();
+            assert_eq!((trigger.via, trigger.mac), ("console", mac));
+            verdict.send(Err(Refusal::Conflict("inhibited (snooze)".to_string()))).unwrap();
+        };
+        let (reply, ()) = tokio::join!(console.handle("suspend"), daemon);
+        assert_eq!(reply.as_deref(), Some("error: inhibited (snooze)"));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::presuspend::run_step;
+use clap::ValueEnum;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Extra time allowed for the runtime CLI itself on top of a container's stop timeout
+const RUNTIME_SLACK: Duration = Duration::from_secs(10);
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Runtime {
+    Docker,
+    Podman,
+}
+
+impl Runtime {
+    /// The runtime's CLI, looked up in PATH
+    pub fn program(self) -> PathBuf {
+        PathBuf::from(match self {
+            Runtime::Docker => "docker",
+            Runtime::Podman => "podman",
+        })
+    }
+}
+
+/// What happens to containers before suspending
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContainerMode {
+    /// Freeze with `pause` and `unpause` after resume
+    Pause,
+    /// Gracefully `stop` and `start` after resume
+    Stop,
+}
+
+/// A container name with an optional per-container timeout, written `NAME[:SECS]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContainerSpec {
+    pub name: String,
+    pub timeout: Option<u64>,
+}
+
+impl FromStr for ContainerSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, timeout) = match s.rsplit_once(':') {
+            Some((name, secs)) => {
+                let secs = secs.parse().map_err(|_| format!("Invalid container timeout: {}", secs))?;
+                (name, Some(secs))
+            }
+            None => (s, None),
+        };
+
+        if name.is_empty() {
+            return Err("Container name cannot be empty".to_string());
+        }
+
+        Ok(ContainerSpec {
+            name: name.to_string(),
+            timeout,
+        })
+    }
+}
+
+/// Containers paused or stopped around a suspend
+pub struct Containers {
+    pub specs: Vec<ContainerSpec>,
+    /// The runtime CLI, normally `Runtime::program`
+    pub program: PathBuf,
+    pub mode: ContainerMode,
+    pub default_timeout: u64,
+}
+
+impl Containers {
+    /// Pause or stop every container. On failure, containers already handled are resumed.
+    pub async fn prepare(&self) -> Result<(), String> {
+        for (i, spec) in self.specs.iter().enumerate() {
+            let (cmd, timeout) = self.prepare_command(spec);
+            if let Err(e) = run_step(&spec.name, cmd, timeout).await {
+                self.restore_specs(&self.specs[..i]).await;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unpause or start every container, in reverse order, logging failures without stopping
+    pub async fn restore(&self) {
+        self.restore_specs(&self.specs).await;
+    }
+
+    async fn restore_specs(&self, specs: &[ContainerSpec]) {
+        for spec in specs.iter().rev() {
+            let (cmd, timeout) = self.restore_command(spec);
+            if let Err(e) = run_step(&spec.name, cmd, timeout).await {
+                error!("Failed to restore container: {}", e);
+            }
+        }
+    }
+
+    fn timeout(&self, spec: &ContainerSpec) -> u64 {
+        spec.timeout.unwrap_or(self.default_timeout)
+    }
+
+    fn prepare_command(&self, spec: &ContainerSpec) -> (Command, Duration) {
+        let mut cmd = Command::new(&self.program);
+        let secs = self.timeout(spec);
+        match self.mode {
+            ContainerMode::Pause => {
+                cmd.args(["pause", &spec.name]);
+                (cmd, Duration::from_secs(secs))
+            }
+            ContainerMode::Stop => {
+                cmd.args(["stop", "-t", &secs.to_string(), &spec.name]);
+                (cmd, Duration::from_secs(secs) + RUNTIME_SLACK)
+            }
+        }
+    }
+
+    fn restore_command(&self, spec: &ContainerSpec) -> (Command, Duration) {
+        let mut cmd = Command::new(&self.program);
+        match self.mode {
+            ContainerMode::Pause => cmd.args(["unpause", &spec.name]),
+            ContainerMode::Stop => cmd.args(["start", &spec.name]),
+        };
+        (cmd, Duration::from_secs(self.timeout(spec)) + RUNTIME_SLACK)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<String> {
+        let std = cmd.as_std();
+        std::iter::once(std.get_program())
+            .chain(std.get_args())
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_container_spec() {
+        assert_eq!(
+            "postgres".parse(),
+            Ok(ContainerSpec { name: "postgres".to_string(), timeout: None })
+        );
+        assert_eq!(
+            "postgres:60".parse(),
+            Ok(ContainerSpec { name: "postgres".to_string(), timeout: Some(60) })
+        );
+        assert!("postgres:soon".parse::<ContainerSpec>().is_err());
+        assert!(":60".parse::<ContainerSpec>().is_err());
+    }
+
+    #[test]
+    fn test_stop_uses_per_container_timeout() {
+        let containers = Containers {
+            specs: vec![],
+            program: Runtime::Podman.program(),
+            mode: ContainerMode::Stop,
+            default_timeout: 10,
+        };
+        let spec = "db:45".parse().unwrap();
+
+        let (cmd, timeout) = containers.prepare_command(&spec);
+        assert_eq!(args(&cmd), ["podman", "stop", "-t", "45", "db"]);
+        assert_eq!(timeout, Duration::from_secs(45) + RUNTIME_SLACK);
+
+        let (cmd, _) = containers.restore_command(&spec);
+        assert_eq!(args(&cmd), ["podman", "start", "db"]);
+    }
+
+    #[test]
+    fn test_pause_commands() {
+        let containers = Containers {
+            specs: vec![],
+            program: Runtime::Docker.program(),
+            mode: ContainerMode::Pause,
+            default_timeout: 10,
+        };
+        let spec = "db".parse().unwrap();
+
+        let (cmd, timeout) = containers.prepare_command(&spec);
+        assert_eq!(args(&cmd), ["docker", "pause", "db"]);
+        assert_eq!(timeout, Duration::from_secs(10));
+
+        let (cmd, _) = containers.restore_command(&spec);
+        assert_eq!(args(&cmd), ["docker", "unpause", "db"]);
+    }
+}
@@ -0,0 +1,735 @@
+use crate::bans::Bans;
+use crate::clock::LocalTime;
+use crate::events::{Query, Record};
+use crate::inhibit::{self, Inhibitors};
+use crate::mac::MacAddr;
+use crate::maintenance::Maintenance;
+use crate::neighbors;
+use crate::pause::{self, Paused, Snooze};
+use crate::peers::{self, Peers};
+use crate::policy::Profiles;
+use crate::power::{self, MemSleep};
+use crate::verify::Checks;
+use sol::packet;
+use std::fs::{self, Permissions};
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UdpSocket, UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot};
+
+pub const DEFAULT_SOCKET: &str = "/run/sol/control.sock";
+
+/// Daemon state reported by the `status` control command
+#[derive(Default, Debug)]
+pub struct Status {
+    pub listen: String,
+    pub suspend_pending: bool,
+    pub last_trigger: Option<String>,
+    /// Actions that returned success but the system never slept
+    pub missed_sleeps: u32,
+    /// Whether flood protection is degrading packet processing
+    pub flood: bool,
+    pub rejected_packets: u32,
+    /// Datagrams the kernel dropped before the daemon could read them
+    pub kernel_drops: u32,
+    /// Smallest receive buffer among the trigger sockets, as the kernel accounts it
+    pub recv_buffer: usize,
+    /// The suspend variant selected with --mem-sleep
+    pub mem_sleep: Option<MemSleep>,
+}
+
+/// What a trigger handed in through `Control` asks for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ask {
+    /// Put this machine to sleep, as a WoL packet for the MAC would
+    Sleep,
+    /// Only check that the sender may act on the MAC; the caller then carries out the request
+    Admit,
+}
+
+/// A trigger that came in over HTTP, CoAP, QUIC or the console rather than as a packet. The
+/// receive loop checks it like a packet from `peer` arriving on `local`, so bans, the sender
+/// checks and rules apply to the real sender.
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    pub ask: Ask,
+    /// What carried it, such as `HTTP`, for the log
+    pub via: &'static str,
+    pub peer: SocketAddr,
+    pub local: SocketAddr,
+    pub mac: MacAddr,
+}
+
+/// Why the receive loop turned a `Trigger` down
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Refusal {
+    /// The sender may not do this: it is banned, failed a sender check or a rule ignores it
+    Forbidden(String),
+    /// The trigger is fine but cannot go ahead, such as while an inhibitor is active
+    Conflict(String),
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Refusal::Forbidden(reason) | Refusal::Conflict(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// Where the receive loop sends its verdict on a `Trigger`
+pub type Verdict = oneshot::Sender<Result<String, Refusal>>;
+
+/// Shared handle used by the control socket to inspect and change daemon state
+#[derive(Clone)]
+pub struct Control {
+    pub status: Arc<Mutex<Status>>,
+    pub maintenance: Maintenance,
+    pub paused: Paused,
+    pub inhibitors: Arc<Inhibitors>,
+    pub profiles: Arc<Profiles>,
+    pub peers: Arc<Peers>,
+    pub bans: Arc<Bans>,
+    /// Feeds the `events` command
+    pub events: crate::events::Stream,
+    /// Answers the `history` command
+    pub history: crate::events::History,
+    pub snooze: Snooze,
+    /// Confirms that woken peers came up
+    pub checks: Option<Checks>,
+    /// Hands triggers to the receive loop; unset until the loop runs
+    pub triggers: Option<mpsc::Sender<(Trigger, Verdict)>>,
+    /// Listed in status while the D-Bus wake-lock service runs
+    #[cfg(feature = "dbus")]
+    pub wake_locks: Option<crate::wakelock::WakeLocks>,
+}
+
+impl Control {
+    pub fn new(
+        maintenance: Maintenance,
+        paused: Paused,
+        inhibitors: Arc<Inhibitors>,
+        profiles: Arc<Profiles>,
+        peers: Arc<Peers>,
+        bans: Arc<Bans>,
+    ) -> Self {
+        Control {
+            status: Arc::new(Mutex::new(Status::default())),
+            maintenance,
+            paused,
+            inhibitors,
+            profiles,
+            peers,
+            bans,
+            events: crate::events::Stream::default(),
+            history: crate::events::History::default(),
+            snooze: Snooze::default(),
+            checks: None,
+            triggers: None,
+            #[cfg(feature = "dbus")]
+            wake_locks: None,
+        }
+    }
+
+    /// Hand `trigger` to the receive loop and wait for its verdict
+    pub async fn trigger(&self, trigger: Trigger) -> Result<String, Refusal> {
+        let closed = || Refusal::Conflict("the daemon is not taking triggers".to_string());
+        let triggers = self.triggers.as_ref().ok_or_else(closed)?;
+        let (verdict, answer) = oneshot::channel();
+        triggers.send((trigger, verdict)).await.map_err(|_| closed())?;
+        answer.await.unwrap_or_else(|_| Err(Refusal::Conflict("the trigger was dropped".to_string())))
+    }
+
+    /// Handle one command line and return the response. Failed commands start with `error:`.
+    pub fn handle(&self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["maintenance"] => format!("maintenance: {}", on_off(self.maintenance.enabled())),
+            ["maintenance", state @ ("on" | "off")] => {
+                let enabled = *state == "on";
+                match self.maintenance.set(enabled) {
+                    Ok(()) => {
+                        info!("Maintenance mode {} via control socket", on_off(enabled));
+                        format!("maintenance: {}", on_off(enabled))
+                    }
+                    Err(e) => format!("error: failed to change maintenance mode: {}", e),
+                }
+            }
+            ["profile"] => self.profile_line(),
+            ["profile", name] => {
+                let selection = (*name != "auto").then_some(*name);
+                match self.profiles.select(selection) {
+                    Ok(()) => {
+                        info!("Profile set to {} via control socket", name);
+                        self.profile_line()
+                    }
+                    Err(e) => format!("error: {}", e),
+                }
+            }
+            ["peers"] => {
+                let peers: Vec<String> = self.peers.list().iter().map(|peer| peer.to_string()).collect();
+                if peers.is_empty() {
+                    "no peers configured".to_string()
+                } else {
+                    peers.join("\n")
+                }
+            }
+            ["wake", name] => match self.peers.wake(name) {
+                Ok(destination) => {
+                    info!("Woke peer {} via control socket ({})", name, destination);
+                    let mut reply = format!("wake packet sent to {} ({})", name, destination);
+                    if let (Some(checks), Some(peer)) = (&self.checks, self.peers.get(name))
+                        && checks.spawn(name.to_string(), peer.target.mac, true)
+                    {
+                        reply += &format!(", verifying within {}s", checks.timeout.as_secs());
+                    }
+                    reply
+                }
+                Err(e) => format!("error: {}", e),
+            },
+            ["wake", _, "wait"] => "error: waiting for a wake is only available on the control socket".to_string(),
+            ["bans"] => {
+                let bans = self.bans.list(crate::protocol::unix_now());
+                if bans.is_empty() {
+                    "no bans".to_string()
+                } else {
+                    let lines: Vec<String> = bans.iter().map(|(ip, left)| format!("{} banned for {}s", ip, left)).collect();
+                    lines.join("\n")
+                }
+            }
+            ["bans", "clear", rest @ ..] => {
+                let ip = match rest {
+                    [] => None,
+                    [ip] => match ip.parse::<IpAddr>() {
+                        Ok(ip) => Some(ip),
+                        Err(_) => return format!("error: invalid address '{}'", ip),
+                    },
+                    _ => return format!("error: unknown command: {}", line.trim()),
+                };
+                let lifted = self.bans.clear(ip, crate::protocol::unix_now());
+                info!("Cleared bans for {} via control socket", ip.map_or("everyone".to_string(), |ip| ip.to_string()));
+                format!("lifted {} ban{}", lifted, if lifted == 1 { "" } else { "s" })
+            }
+            ["history", rest @ ..] => self.history_report(rest),
+            ["snooze"] => self.snooze_line(),
+            ["snooze", length] => match pause::parse_snooze(length) {
+                Ok(length) => {
+                    self.snooze.set(length);
+                    match self.snooze.describe() {
+                        Some(left) => info!("Snoozed via control socket, {}", left),
+                        None => info!("Snooze ended via control socket"),
+                    }
+                    self.snooze_line()
+                }
+                Err(e) => format!("error: {}", e),
+            },
+            _ => format!("error: unknown command: {}", line.trim()),
+        }
+    }
+
+    /// Handle one command line, including those that wait on the network
+    pub async fn respond(&self, line: &str) -> String {
+        match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["wake", name, "wait"] => self.wake_and_wait(name).await,
+            ["wake", host] if self.peers.get(host).is_none() => self.wake_neighbor(host).await,
+            ["status"] => self.status_report().await,
+            ["neighbors"] => neighbors::format_table(&neighbors::table().await),
+            ["neighbors", host] => match neighbors::resolve(host).await {
+                Ok((ip, mac)) => format!("{} ({}) is at {}", host, ip, mac),
+                Err(e) => format!("error: {}", e),
+            },
+            _ => self.handle(line),
+        }
+    }
+
+    /// Wake `host`, a hostname or address that is not a known peer, by the MAC the neighbor
+    /// table has for it; the packet goes where a peer without a target's would
+    pub async fn wake_neighbor(&self, host: &str) -> String {
+        let (ip, mac) = match neighbors::resolve(host).await {
+            Ok(found) => found,
+            Err(e) => return format!("error: unknown peer '{}', and {}", host, e),
+        };
+        match peers::send_wol(&peers::broadcast_target(mac)) {
+            Ok(destination) => {
+                info!("Woke {} ({}, {} from the neighbor table) via control socket ({})", host, ip, mac, destination);
+                format!("wake packet for {} sent to {} ({})", mac, host, destination)
+            }
+            Err(e) => format!("error: {}", e),
+        }
+    }
+
+    /// Wake the named peer and answer once a probe shows it came up, or the verification
+    /// timeout passes
+    pub async fn wake_and_wait(&self, name: &str) -> String {
+        let destination = match self.peers.wake(name) {
+            Ok(destination) => destination,
+            Err(e) => return format!("error: {}", e),
+        };
+        info!("Woke peer {} via control socket ({}), waiting for it", name, destination);
+        let sent = format!("wake packet sent to {} ({})", name, destination);
+        let mac = self.peers.get(name).map(|peer| peer.target.mac);
+        let result = match (&self.checks, mac) {
+            (Some(checks), Some(mac)) => checks.wait(name, &mac, true).await,
+            _ => None,
+        };
+        match result {
+            Some(Ok(detail)) => format!("{}\n{}", sent, detail),
+            Some(Err(detail)) => format!("error: {}; {}", sent, detail),
+            None => format!("error: {}, but there is no --verify-wake target to probe", sent),
+        }
+    }
+
+    /// Past events matching `KEY=VALUE` filters, one per line or, after `json`, as a JSON array
+    fn history_report(&self, words: &[&str]) -> String {
+        let (json, pairs) = match words.split_last() {
+            Some((&"json", pairs)) => (true, pairs),
+            _ => (false, words),
+        };
+        let query = match Query::parse(pairs.iter().copied(), crate::protocol::unix_now()) {
+            Ok(query) => query,
+            Err(e) => return format!("error: {}", e),
+        };
+        let records = self.history.query(&query);
+        if json {
+            format!("[{}]", records.iter().map(Record::json).collect::<Vec<_>>().join(","))
+        } else if records.is_empty() {
+            "no matching events".to_string()
+        } else {
+            let lines: Vec<String> = records
+                .iter()
+                .map(|record| format!("{}  {}  {}", LocalTime::from_unix(record.time as libc::time_t), record.event, record.detail))
+                .collect();
+            lines.join("\n")
+        }
+    }
+
+    fn snooze_line(&self) -> String {
+        format!("snoozed: {}", self.snooze.describe().as_deref().unwrap_or("no"))
+    }
+
+    /// Every held wake lock, or None when the wake-lock service is not running
+    #[cfg(feature = "dbus")]
+    fn wake_lock_line(&self) -> Option<String> {
+        let now = std::time::Instant::now();
+        let locks = crate::wakelock::describe(&self.wake_locks.as_ref()?.active(now), now);
+        Some(format!("wake locks: {}", if locks.is_empty() { "none".to_string() } else { locks.join("; ") }))
+    }
+
+    #[cfg(not(feature = "dbus"))]
+    fn wake_lock_line(&self) -> Option<String> {
+        None
+    }
+
+    fn profile_line(&self) -> String {
+        let (name, _) = self.profiles.current(&LocalTime::now());
+        let how = if self.profiles.is_selected() { "selected" } else { "auto" };
+        format!("profile: {} ({})", name, how)
+    }
+
+    /// The first active inhibitor, including those the current profile enables
+    pub async fn inhibited(&self) -> Option<String> {
+        let (_, policy) = self.profiles.current(&LocalTime::now());
+        inhibit::first_active(self.inhibitors.clone(), policy, false).await
+    }
+
+    async fn status_report(&self) -> String {
+        let inhibited = self.inhibited().await;
+        let profile = self.profile_line();
+        let status = self.status.lock().unwrap();
+        let mut lines = vec![
+            format!("listening: {}", status.listen),
+            profile,
+            format!("maintenance: {}", on_off(self.maintenance.enabled())),
+            format!("paused: {}", if self.paused.is_paused() { "yes" } else { "no" }),
+            self.snooze_line(),
+            format!("inhibited: {}", inhibited.as_deref().unwrap_or("no")),
+        ];
+        lines.extend(self.wake_lock_line());
+        lines.extend([
+            format!("suspend pending: {}", if status.suspend_pending { "yes" } else { "no" }),
+            format!("last trigger: {}", status.last_trigger.as_deref().unwrap_or("none")),
+            format!("missed sleeps: {}", status.missed_sleeps),
+            format!("flood protection: {}", if status.flood { "active" } else { "inactive" }),
+            format!("rejected packets: {}", status.rejected_packets),
+            format!("kernel drops: {}", status.kernel_drops),
+            format!("receive buffer: {} bytes", status.recv_buffer),
+            mem_sleep_line(status.mem_sleep, Path::new("/sys/power")),
+        ]);
+        lines.join("\n")
+    }
+}
+
+fn mem_sleep_line(selected: Option<MemSleep>, sys_power: &Path) -> String {
+    let mut line = match power::mem_sleep_modes(sys_power) {
+        Some((current, modes)) => format!("mem sleep: {} (available: {})", current, modes.join(", ")),
+        None => "mem sleep: unsupported".to_string(),
+    };
+    if let Some(mode) = selected {
+        line += &format!(", {} when suspending", mode.as_str());
+    }
+    line
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled { "on" } else { "off" }
+}
+
+/// Bind the control socket and serve it in the background. With `group`, members of that
+/// group may use it too.
+pub fn listen(path: &Path, group: Option<u32>, control: Control) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    let listener = UnixListener::bind(path)?;
+    match group {
+        Some(gid) => {
+            std::os::unix::fs::chown(path, None, Some(gid))?;
+            fs::set_permissions(path, Permissions::from_mode(0o660))?;
+        }
+        None => fs::set_permissions(path, Permissions::from_mode(0o600))?,
+    }
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let control = control.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, control).await {
+                            error!("Control connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Failed to accept control connection: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn serve(stream: UnixStream, control: Control) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim() == "events" {
+            return stream_events(lines, writer, control).await;
+        }
+        let response = control.respond(&line).await;
+        writer.write_all(response.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+    }
+
+    Ok(())
+}
+
+/// Write events as they happen until the client closes its end
+async fn stream_events(
+    mut lines: Lines<BufReader<OwnedReadHalf>>,
+    mut writer: OwnedWriteHalf,
+    control: Control,
+) -> std::io::Result<()> {
+    let mut events = control.events.subscribe();
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            line = lines.next_line() => match line? {
+                Some(_) => continue,
+                None => return Ok(()),
+            },
+        };
+        match event {
+            Ok(line) => {
+                writer.write_all(line.as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+            }
+            Err(RecvError::Lagged(missed)) => {
+                writer.write_all(format!("{}\tmissed\t{}\n", crate::protocol::unix_now(), missed).as_bytes()).await?;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+/// Look up a group's ID by name
+pub fn group_id(name: &str) -> Result<u32, String> {
+    let c_name = std::ffi::CString::new(name).map_err(|_| format!("Invalid group name '{}'", name))?;
+    // SAFETY: c_name is NUL-terminated; the returned entry is read before any other call
+    let group = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if group.is_null() {
+        return Err(format!("Unknown group '{}'", name));
+    }
+    // SAFETY: group is non-null and points to a valid entry
+    Ok(unsafe { (*group).gr_gid })
+}
+
+/// Send a single command to a running daemon and return its response
+pub async fn request(path: &Path, command: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut stream = UnixStream::connect(path)
+        .await
+        .map_err(|e| format!("Cannot connect to daemon at {}: {}", path.display(), e))?;
+
+    stream.write_all(command.as_bytes()).await?;
+    stream.write_all(b"\n").await?;
+    stream.shutdown().await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    if let Some(error) = response.strip_prefix("error: ") {
+        return Err(error.trim().to_string().into());
+    }
+
+    Ok(response.trim_end().to_string())
+}
+
+/// How long a loopback sender waits for the daemon's verdict
+const LOCAL_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Send a packet with `header` for `mac` to the daemon's own trigger port on loopback and wait
+/// for its verdict. The daemon answers loopback senders with an `ok:` or `error:` line; an
+/// error line or no answer is an error.
+pub async fn send_local(header: &[u8; 6], port: u16, mac: &MacAddr) -> Result<String, String> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.map_err(|e| e.to_string())?;
+    socket.send_to(&packet::encode(header, &mac.0), ("127.0.0.1", port)).await.map_err(|e| e.to_string())?;
+
+    let mut buf = [0u8; 512];
+    let deadline = tokio::time::Instant::now() + LOCAL_REPLY_TIMEOUT;
+    loop {
+        let len = match tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await {
+            Ok(received) => received.map_err(|e| e.to_string())?,
+            Err(_) => return Err(format!("no answer from the daemon on port {}", port)),
+        };
+        let Ok(line) = std::str::from_utf8(&buf[..len]).map(str::trim_end) else {
+            continue;
+        };
+        if let Some(verdict) = line.strip_prefix("ok: ") {
+            return Ok(verdict.to_string());
+        }
+        if let Some(error) = line.strip_prefix("error: ") {
+            return Err(error.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Policy;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("sol-control-test-{}-{}", std::process::id(), name))
+    }
+
+    fn policy() -> Policy {
+        Policy {
+            quorum: 1,
+            quorum_window: 10,
+            confirm: false,
+            grace: 0,
+            warn_users: false,
+            inhibit_share_clients: false,
+            inhibit_ports: Vec::new(),
+            // Keep the tests independent of the host's desktop sessions
+            ignore_desktop_inhibitors: true,
+            inhibit_session_activity: false,
+            min_idle: None,
+        }
+    }
+
+    fn control(name: &str) -> Control {
+        Control::new(
+            Maintenance::new(temp_path(name)),
+            Paused::default(),
+            Arc::new(Inhibitors::default()),
+            Arc::new(Profiles::new(policy())),
+            Arc::new(Peers::default()),
+            Arc::new(Bans::new(None, None)),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_status_report() {
+        let control = control("status-maintenance");
+        control.status.lock().unwrap().listen = "0.0.0.0:10".to_string();
+
+        let report = control.respond("status").await;
+        assert!(report.contains("listening: 0.0.0.0:10"));
+        assert!(report.contains("profile: default (auto)"));
+        assert!(report.contains("maintenance: off"));
+        assert!(report.contains("paused: no"));
+        assert!(report.contains("snoozed: no"));
+        assert!(report.contains("inhibited: no"));
+        assert!(!report.contains("wake locks"));
+        assert!(report.contains("last trigger: none"));
+        assert!(report.contains("missed sleeps: 0"));
+        assert!(report.contains("\nmem sleep: "));
+    }
+
+    #[cfg(feature = "dbus")]
+    #[tokio::test]
+    async fn test_status_lists_wake_locks() {
+        let mut control = control("status-wake-locks-maintenance");
+        let locks = crate::wakelock::WakeLocks::default();
+        control.wake_locks = Some(locks.clone());
+        assert!(control.respond("status").await.contains("\nwake locks: none\n"));
+
+        locks.acquire("nightly backup".to_string(), 1000, std::time::Duration::from_secs(600), std::time::Instant::now());
+        let report = control.respond("status").await;
+        assert!(report.contains("\nwake locks: 1 \"nightly backup\" by uid 1000, held 0 minute(s), 10 minute(s) left\n"), "{}", report);
+    }
+
+    #[test]
+    fn test_mem_sleep_line() {
+        let dir = std::env::temp_dir().join(format!("sol-control-mem-sleep-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(mem_sleep_line(None, &dir), "mem sleep: unsupported");
+        fs::write(dir.join("mem_sleep"), "[s2idle] deep\n").unwrap();
+        assert_eq!(
+            mem_sleep_line(Some(MemSleep::Deep), &dir),
+            "mem sleep: s2idle (available: s2idle, deep), deep when suspending"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_profile_commands() {
+        let control = control("profile-maintenance");
+        assert_eq!(control.handle("profile"), "profile: default (auto)");
+        assert_eq!(control.handle("profile default"), "profile: default (selected)");
+        assert_eq!(control.handle("profile auto"), "profile: default (auto)");
+        assert!(control.handle("profile night").starts_with("error: unknown profile"));
+    }
+
+    #[test]
+    fn test_peer_commands() {
+        let control = control("peers-maintenance");
+        assert_eq!(control.handle("peers"), "no peers configured");
+        assert_eq!(control.handle("wake nas"), "error: unknown peer 'nas'");
+    }
+
+    #[tokio::test]
+    async fn test_wake_neighbor() {
+        let control = control("neighbors-maintenance");
+        let reply = control.respond("wake 127.0.0.1").await;
+        assert!(reply.starts_with("error: unknown peer '127.0.0.1', and no neighbor table entry for 127.0.0.1"), "{}", reply);
+        assert_eq!(control.respond("peers").await, "no peers configured");
+    }
+
+    #[test]
+    fn test_ban_commands() {
+        let control = control("bans-maintenance");
+        assert_eq!(control.handle("bans"), "no bans");
+        assert_eq!(control.handle("bans clear"), "lifted 0 bans");
+        assert_eq!(control.handle("bans clear 10.0.0.1"), "lifted 0 bans");
+        assert!(control.handle("bans clear nowhere").starts_with("error:"));
+    }
+
+    #[test]
+    fn test_history_command() {
+        let control = control("history-maintenance");
+        assert_eq!(control.handle("history"), "no matching events");
+        assert_eq!(control.handle("history json"), "[]");
+        control.history.push(Record { time: 100, event: "rejected".to_string(), detail: "sender=10.0.0.9:9 reason=x".to_string() });
+        control.history.push(Record { time: 200, event: "action-started".to_string(), detail: "suspend".to_string() });
+        assert!(control.handle("history outcome=rejected").ends_with("  rejected  sender=10.0.0.9:9 reason=x"));
+        assert_eq!(control.handle("history last=1 json"), r#"[{"time":200,"event":"action-started","detail":"suspend"}]"#);
+        assert!(control.handle("history mac=nope").starts_with("error: Invalid MAC address"));
+    }
+
+    #[tokio::test]
+    async fn test_snooze_commands() {
+        let control = control("snooze-maintenance");
+        assert_eq!(control.handle("snooze"), "snoozed: no");
+        assert_eq!(control.handle("snooze 45m"), "snoozed: 45 minute(s) left");
+        assert!(control.respond("status").await.contains("\nsnoozed: 45 minute(s) left\n"));
+        assert_eq!(control.handle("snooze off"), "snoozed: no");
+        assert!(control.handle("snooze soon").starts_with("error: Invalid duration"));
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let control = control("unknown-maintenance");
+        assert!(control.handle("reboot").starts_with("error:"));
+    }
+
+    #[tokio::test]
+    async fn test_socket_roundtrip() {
+        let socket = temp_path("socket");
+        let flag = temp_path("roundtrip-maintenance");
+        // Our own primary group is one we may always hand the socket to
+        // SAFETY: getgid cannot fail
+        let gid = unsafe { libc::getgid() };
+        let control = control("roundtrip-maintenance");
+        listen(&socket, Some(gid), control.clone()).unwrap();
+        assert_eq!(fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o660);
+
+        assert_eq!(request(&socket, "maintenance on").await.unwrap(), "maintenance: on");
+        assert!(flag.exists());
+        assert_eq!(request(&socket, "maintenance off").await.unwrap(), "maintenance: off");
+        assert!(!flag.exists());
+        assert!(request(&socket, "bogus").await.is_err());
+
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        stream.write_all(b"events\n").await.unwrap();
+        // Give the server a moment to subscribe before publishing
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let event = crate::events::Event::Resumed { action: crate::rules::Action::Suspend };
+        crate::events::Listener::handle(&control.events, &event).await;
+        let mut lines = BufReader::new(stream).lines();
+        assert!(lines.next_line().await.unwrap().unwrap().ends_with("\tresumed\tsuspend"));
+
+        fs::remove_file(&socket).unwrap();
+        assert!(group_id("no-such-group-sol").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_trigger_waits_for_verdict() {
+        let mac = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let trigger = Trigger {
+            ask: Ask::Sleep,
+            via: "HTTP",
+            peer: "192.168.1.5:40000".parse().unwrap(),
+            local: "192.168.1.2:8080".parse().unwrap(),
+            mac,
+        };
+        let mut control = control("trigger");
+        assert!(control.trigger(trigger.clone()).await.is_err());
+
+        let (triggers, mut handed) = mpsc::channel(1);
+        control.triggers = Some(triggers);
+        let daemon = async {
+            let (trigger, verdict): (Trigger, Verdict) = handed.recv().await.unwrap();
+            verdict.send(Err(Refusal::Forbidden("banned".to_string()))).unwrap();
+            trigger
+        };
+        let (verdict, seen) = tokio::join!(control.trigger(trigger), daemon);
+        assert_eq!(verdict, Err(Refusal::Forbidden("banned".to_string())));
+        assert_eq!(seen.peer, "192.168.1.5:40000".parse().unwrap());
+
+        let daemon = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = daemon.local_addr().unwrap().port();
+        let answer = async {
+            let mut buf = [0u8; 128];
+            let (len, sender) = daemon.recv_from(&mut buf).await.unwrap();
+            daemon.send_to(b"ok: no suspend is pending", sender).await.unwrap();
+            len
+        };
+        let (verdict, sent) = tokio::join!(send_local(&packet::CANCEL_HEADER, port, &mac), answer);
+        assert_eq!(verdict, Ok("no suspend is pending".to_string()));
+        assert_eq!(sent, packet::PACKET_SIZE);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::inhibit::COMMAND_TIMEOUT;
+#[cfg(not(feature = "dbus"))]
+use crate::inhibit;
+#[cfg(not(feature = "dbus"))]
+use std::process::Command;
+
+/// Which bus a call goes to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Bus {
+    System,
+    /// The session bus of the given user
+    User(String),
+}
+
+/// Call a method that takes at most one `u32` and returns a single boolean
+pub fn call_bool(bus: &Bus, dest: &str, path: &str, iface: &str, method: &str, arg: Option<u32>) -> Result<bool, String> {
+    call(bus, dest, path, iface, method, arg)
+}
+
+/// Call a method that takes no arguments and returns a single string
+pub fn call_string(bus: &Bus, dest: &str, path: &str, iface: &str, method: &str) -> Result<String, String> {
+    call(bus, dest, path, iface, method, None)
+}
+
+/// Call a method returning `a(ssssuu)`, such as logind's ListInhibitors, and keep the four
+/// strings of each entry: what, who, why and mode
+pub fn call_locks(bus: &Bus, dest: &str, path: &str, iface: &str, method: &str) -> Result<Vec<[String; 4]>, String> {
+    let locks: Vec<(String, String, String, String, u32, u32)> = call(bus, dest, path, iface, method, None)?;
+    Ok(locks.into_iter().map(|(what, who, why, mode, _, _)| [what, who, why, mode]).collect())
+}
+
+/// Run a D-Bus call from one of the runtime's blocking threads on the runtime itself, giving up
+/// after `COMMAND_TIMEOUT`
+#[cfg(feature = "dbus")]
+fn block_on<T>(call: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+    let runtime = tokio::runtime::Handle::try_current().map_err(|e| e.to_string())?;
+    runtime
+        .block_on(async { tokio::time::timeout(COMMAND_TIMEOUT, call).await })
+        .unwrap_or_else(|_| Err(format!("D-Bus call timed out after {}s", COMMAND_TIMEOUT.as_secs())))
+}
+
+#[cfg(feature = "dbus")]
+async fn connect(bus: &Bus) -> Result<zbus::Connection, String> {
+    let connection = match bus {
+        Bus::System => zbus::Connection::system().await,
+        Bus::User(user) => {
+            let address = format!("unix:path=/run/user/{}/bus", user_id(user)?);
+            zbus::connection::Builder::address(address.as_str()).map_err(|e| e.to_string())?.build().await
+        }
+    };
+    connection.map_err(|e| format!("Cannot connect to the {} bus: {}", describe(bus), e))
+}
+
+#[cfg(feature = "dbus")]
+fn describe(bus: &Bus) -> String {
+    match bus {
+        Bus::System => "system".to_string(),
+        Bus::User(user) => format!("{}'s session", user),
+    }
+}
+
+/// Look up a user's ID by name, to find their session bus
+#[cfg(feature = "dbus")]
+fn user_id(name: &str) -> Result<u32, String> {
+    let c_name = std::ffi::CString::new(name).map_err(|_| format!("Invalid user name '{}'", name))?;
+    // SAFETY: c_name is NUL-terminated; the returned entry is read before any other call
+    let user = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if user.is_null() {
+        return Err(format!("Unknown user '{}'", name));
+    }
+    // SAFETY: user is non-null and points to a valid entry
+    Ok(unsafe { (*user).pw_uid })
+}
+
+#[cfg(feature = "dbus")]
+fn call<R>(bus: &Bus, dest: &str, path: &str, iface: &str, method: &str, arg: Option<u32>) -> Result<R, String>
+where
+    R: zbus::zvariant::Type + zbus::export::serde::de::DeserializeOwned,
+{
+    block_on(async {
+        let connection = connect(bus).await?;
+        let reply = match arg {
+            Some(arg) => connection.call_method(Some(dest), path, Some(iface), method, &(arg,)).await,
+            None => connection.call_method(Some(dest), path, Some(iface), method, &()).await,
+        };
+        let reply = reply.map_err(|e| format!("{}.{} failed: {}", iface, method, e))?;
+        reply.body().deserialize().map_err(|e| format!("Unexpected reply from {}.{}: {}", iface, method, e))
+    })
+}
+
+/// Read a string property
+#[cfg(feature = "dbus")]
+pub fn get_string(bus: &Bus, dest: &str, path: &str, iface: &str, property: &str) -> Result<String, String> {
+    block_on(async {
+        let connection = connect(bus).await?;
+        let reply = connection
+            .call_method(Some(dest), path, Some("org.freedesktop.DBus.Properties"), "Get", &(iface, property))
+            .await
+            .map_err(|e| format!("Reading {} failed: {}", property, e))?;
+        let value: zbus::zvariant::OwnedValue = reply.body().deserialize().map_err(|e| e.to_string())?;
+        String::try_from(value).map_err(|e| format!("Unexpected {}: {}", property, e))
+    })
+}
+
+/// Set a string property
+#[cfg(feature = "dbus")]
+pub fn set_string(bus: &Bus, dest: &str, path: &str, iface: &str, property: &str, value: &str) -> Result<(), String> {
+    block_on(async {
+        let connection = connect(bus).await?;
+        let value = zbus::zvariant::Value::from(value);
+        connection
+            .call_method(Some(dest), path, Some("org.freedesktop.DBus.Properties"), "Set", &(iface, property, value))
+            .await
+            .map_err(|e| format!("Setting {} failed: {}", property, e))?;
+        Ok(())
+    })
+}
+
+/// What a busctl reply can be read as, for builds without the `dbus` feature
+#[cfg(not(feature = "dbus"))]
+trait Reply: Sized {
+    /// Read the reply from busctl's tokens, starting with its signature
+    fn parse(tokens: &[String]) -> Option<Self>;
+}
+
+#[cfg(not(feature = "dbus"))]
+impl Reply for bool {
+    fn parse(tokens: &[String]) -> Option<Self> {
+        match tokens {
+            [sig, value] if sig == "b" => Some(value == "true"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(feature = "dbus"))]
+impl Reply for String {
+    fn parse(tokens: &[String]) -> Option<Self> {
+        match tokens {
+            [sig, value] if sig == "s" => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(feature = "dbus"))]
+impl Reply for Vec<(String, String, String, String, u32, u32)> {
+    fn parse(tokens: &[String]) -> Option<Self> {
+        let [sig, count, fields @ ..] = tokens else {
+            return None;
+        };
+        let count: usize = count.parse().ok().filter(|_| sig == "a(ssssuu)")?;
+        if fields.len() != count * 6 {
+            return None;
+        }
+        fields
+            .chunks(6)
+            .map(|entry| {
+                let (uid, pid) = (entry[4].parse().ok()?, entry[5].parse().ok()?);
+                Some((entry[0].clone(), entry[1].clone(), entry[2].clone(), entry[3].clone(), uid, pid))
+            })
+            .collect()
+    }
+}
+
+/// A `busctl` command talking to `bus`
+#[cfg(not(feature = "dbus"))]
+fn command(bus: &Bus) -> Command {
+    let mut cmd = Command::new("busctl");
+    match bus {
+        Bus::System => cmd.arg("--system"),
+        Bus::User(user) => cmd.arg("--user").arg(format!("--machine={}@.host", user)),
+    };
+    cmd
+}
+
+/// Call a D-Bus method through `busctl`
+#[cfg(not(feature = "dbus"))]
+fn call<R: Reply>(bus: &Bus, dest: &str, path: &str, iface: &str, method: &str, arg: Option<u32>) -> Result<R, String> {
+    let mut cmd = command(bus);
+    cmd.args(["call", dest, path, iface, method]);
+    if let Some(arg) = arg {
+        cmd.arg("u").arg(arg.to_string());
+    }
+    let reply = busctl(cmd, &format!("call {}.{}", iface, method))?;
+    R::parse(&reply).ok_or_else(|| format!("Unexpected reply from {}.{}: {:?}", iface, method, reply))
+}
+
+/// Read a string property through `busctl get-property`
+#[cfg(not(feature = "dbus"))]
+pub fn get_string(bus: &Bus, dest: &str, path: &str, iface: &str, property: &str) -> Result<String, String> {
+    let mut cmd = command(bus);
+    cmd.args(["get-property", dest, path, iface, property]);
+    let reply = busctl(cmd, &format!("get-property {}", property))?;
+    String::parse(&reply).ok_or_else(|| format!("Unexpected {}: {:?}", property, reply))
+}
+
+/// Set a string property through `busctl set-property`
+#[cfg(not(feature = "dbus"))]
+pub fn set_string(bus: &Bus, dest: &str, path: &str, iface: &str, property: &str, value: &str) -> Result<(), String> {
+    let mut cmd = command(bus);
+    cmd.args(["set-property", dest, path, iface, property, "s", value]);
+    busctl(cmd, &format!("set-property {}", property)).map(|_| ())
+}
+
+/// Run `cmd` and split its output into tokens. Callers run on the blocking pool, and a hung
+/// busctl is killed after `COMMAND_TIMEOUT`.
+#[cfg(not(feature = "dbus"))]
+fn busctl(mut cmd: Command, what: &str) -> Result<Vec<String>, String> {
+    let output = inhibit::output(&mut cmd, COMMAND_TIMEOUT).map_err(|e| format!("busctl: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("busctl {} failed: {}", what, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(tokenize(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Split busctl's text output into whitespace-separated tokens, honouring double quotes
+#[cfg(not(feature = "dbus"))]
+fn tokenize(output: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = output.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut token = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some(escaped) = chars.next() {
+                            token.push(escaped);
+                        }
+                    }
+                    '"' => break,
+                    _ => token.push(c),
+                }
+            }
+            tokens.push(token);
+        } else {
+            let mut token = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "dbus")]
+    #[test]
+    fn test_user_id() {
+        assert_eq!(user_id("root"), Ok(0));
+        assert!(user_id("no-such-user-sol").is_err());
+    }
+
+    #[cfg(feature = "dbus")]
+    #[test]
+    fn test_call_needs_runtime() {
+        assert!(call_string(&Bus::System, "org.freedesktop.login1", "/org/freedesktop/login1", "org.freedesktop.login1.Manager", "CanSuspend").is_err());
+    }
+
+    #[cfg(not(feature = "dbus"))]
+    #[test]
+    fn test_tokenize_quoted_strings() {
+        let output = "a(ssssuu) 1 \"sleep\" \"Totem\" \"Playing \\\"a film\\\"\" \"block\" 1000 4242\n";
+        assert_eq!(
+            tokenize(output),
+            ["a(ssssuu)", "1", "sleep", "Totem", "Playing \"a film\"", "block", "1000", "4242"]
+        );
+    }
+
+    #[cfg(not(feature = "dbus"))]
+    #[test]
+    fn test_tokenize_bool() {
+        assert_eq!(tokenize("b true\n"), ["b", "true"]);
+        assert_eq!(tokenize(""), Vec::<String>::new());
+    }
+
+    #[cfg(not(feature = "dbus"))]
+    #[test]
+    fn test_parse_replies() {
+        assert_eq!(bool::parse(&tokenize("b true\n")), Some(true));
+        assert_eq!(bool::parse(&tokenize("s \"yes\"\n")), None);
+        assert_eq!(String::parse(&tokenize("s \"challenge\"\n")).as_deref(), Some("challenge"));
+
+        let locks = tokenize(
+            "a(ssssuu) 2 \"sleep\" \"NetworkManager\" \"NetworkManager needs to turn off networks\" \"delay\" 0 933 \
+             \"sleep:idle\" \"Videos\" \"Playing a video\" \"block\" 1000 4242",
+        );
+        let locks = Vec::<(String, String, String, String, u32, u32)>::parse(&locks).unwrap();
+        assert_eq!(locks.len(), 2);
+        assert_eq!((locks[1].1.as_str(), locks[1].3.as_str(), locks[1].5), ("Videos", "block", 4242));
+        assert_eq!(Vec::<(String, String, String, String, u32, u32)>::parse(&tokenize("a(ssssuu) 1 \"sleep\"")), None);
+    }
+}
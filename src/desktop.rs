@@ -0,0 +1,189 @@
+use crate::dbus::{self, Bus};
+use crate::inhibit::{self, COMMAND_TIMEOUT, Inhibitor};
+use std::process::Command;
+
+/// A logind inhibitor lock as returned by ListInhibitors
+#[derive(Debug, PartialEq, Eq)]
+struct InhibitorLock {
+    what: String,
+    who: String,
+    why: String,
+    mode: String,
+}
+
+impl InhibitorLock {
+    fn blocks_sleep(&self) -> bool {
+        self.mode == "block" && self.what.split(':').any(|what| what == "sleep" || what == "idle")
+    }
+}
+
+/// Blocks suspend while an application holds a sleep/idle inhibit, either through logind
+/// or through the desktop session (GNOME session manager, KDE PowerManagement, which
+/// back the XDG portal and org.freedesktop.ScreenSaver inhibit calls)
+pub struct DesktopInhibitors;
+
+impl Inhibitor for DesktopInhibitors {
+    fn name(&self) -> &str {
+        "desktop"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        if let Some(lock) = logind_locks()?.into_iter().find(InhibitorLock::blocks_sleep) {
+            return Ok(Some(format!("{} is inhibiting sleep ({})", lock.who, lock.why)));
+        }
+
+        for user in graphical_users()? {
+            if session_inhibited(&user) {
+                return Ok(Some(format!("an application in {}'s session is inhibiting idle", user)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn logind_locks() -> Result<Vec<InhibitorLock>, String> {
+    let locks = dbus::call_locks(
+        &Bus::System,
+        "org.freedesktop.login1",
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+        "ListInhibitors",
+    )?;
+    Ok(locks.into_iter().map(|[what, who, why, mode]| InhibitorLock { what, who, why, mode }).collect())
+}
+
+/// A logged-in X11 or Wayland session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphicalSession {
+    pub user: String,
+    pub kind: String,
+    /// X11 display, e.g. `:0` (empty for Wayland sessions)
+    pub display: Option<String>,
+}
+
+/// IDs of all sessions known to logind
+pub fn session_ids() -> Result<Vec<String>, String> {
+    Ok(loginctl(&["list-sessions", "--no-legend"])?
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect())
+}
+
+/// All X11 and Wayland sessions known to logind
+pub fn graphical_sessions() -> Result<Vec<GraphicalSession>, String> {
+    let mut sessions = Vec::new();
+    for session in session_ids()? {
+        let properties = loginctl(&["show-session", &session, "-p", "Type", "-p", "Name", "-p", "Display"])?;
+        if let (Some(kind @ ("x11" | "wayland")), Some(user)) =
+            (property(&properties, "Type"), property(&properties, "Name"))
+        {
+            sessions.push(GraphicalSession {
+                user: user.to_string(),
+                kind: kind.to_string(),
+                display: property(&properties, "Display")
+                    .filter(|display| !display.is_empty())
+                    .map(str::to_string),
+            });
+        }
+    }
+    Ok(sessions)
+}
+
+/// Users with an X11 or Wayland session
+pub fn graphical_users() -> Result<Vec<String>, String> {
+    let mut users: Vec<String> = Vec::new();
+    for session in graphical_sessions()? {
+        if !users.contains(&session.user) {
+            users.push(session.user);
+        }
+    }
+    Ok(users)
+}
+
+pub fn loginctl(args: &[&str]) -> Result<String, String> {
+    let output = inhibit::output(Command::new("loginctl").args(args), COMMAND_TIMEOUT)
+        .map_err(|e| format!("loginctl: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "loginctl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Look up `key` in `Key=value` lines as printed by `loginctl show-*`
+pub fn property<'a>(properties: &'a str, key: &str) -> Option<&'a str> {
+    properties.lines().find_map(|line| {
+        line.split_once('=')
+            .filter(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+    })
+}
+
+/// Ask the user's session manager whether anything holds a suspend or idle inhibit.
+/// Session managers that are not running are simply skipped.
+fn session_inhibited(user: &str) -> bool {
+    let bus = Bus::User(user.to_string());
+
+    // GsmInhibitorFlag: 4 = suspend, 8 = idle
+    let gnome = dbus::call_bool(
+        &bus,
+        "org.gnome.SessionManager",
+        "/org/gnome/SessionManager",
+        "org.gnome.SessionManager",
+        "IsInhibited",
+        Some(12),
+    );
+    let kde = dbus::call_bool(
+        &bus,
+        "org.freedesktop.PowerManagement.Inhibit",
+        "/org/freedesktop/PowerManagement/Inhibit",
+        "org.freedesktop.PowerManagement.Inhibit",
+        "HasInhibit",
+        None,
+    );
+
+    gnome.unwrap_or(false) || kde.unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sleep_locks() {
+        let lock = |what: &str, mode: &str| InhibitorLock {
+            what: what.to_string(),
+            who: "Videos".to_string(),
+            why: "Playing a video".to_string(),
+            mode: mode.to_string(),
+        };
+        assert!(!lock("sleep", "delay").blocks_sleep());
+        assert!(lock("sleep:idle", "block").blocks_sleep());
+        assert!(lock("idle", "block").blocks_sleep());
+    }
+
+    #[test]
+    fn test_shutdown_lock_does_not_block_sleep() {
+        let lock = InhibitorLock {
+            what: "shutdown".to_string(),
+            who: "Unattended Upgrades Shutdown".to_string(),
+            why: "Stop ongoing upgrades".to_string(),
+            mode: "block".to_string(),
+        };
+        assert!(!lock.blocks_sleep());
+    }
+
+    #[test]
+    fn test_property() {
+        let properties = "Type=wayland\nName=alice\n";
+        assert_eq!(property(properties, "Type"), Some("wayland"));
+        assert_eq!(property(properties, "Name"), Some("alice"));
+        assert_eq!(property(properties, "Active"), None);
+    }
+}
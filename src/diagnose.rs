@@ -0,0 +1,284 @@
+use crate::control;
+use crate::dbus::{self, Bus};
+use crate::mac::MacAddr;
+use crate::netif;
+use crate::nicwol;
+use crate::online;
+use crate::power::{Backend, LOGIND, LOGIND_MANAGER, LOGIND_PATH};
+use crate::rules::Action;
+use crate::selftest;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+
+const SYS_POWER_STATE: &str = "/sys/power/state";
+
+/// How much a finding matters; the report lists the worst first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something in the chain is broken
+    Fail,
+    /// Something may get in the way
+    Warn,
+    Pass,
+}
+
+/// The result of one check, with what to do about it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// The link of the chain it is about, e.g. `wol`
+    pub area: &'static str,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn new(severity: Severity, area: &'static str, message: impl Into<String>) -> Self {
+        Finding { severity, area, message: message.into(), fix: None }
+    }
+
+    fn fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+/// What the checks look at, taken from the command line and config file
+pub struct Setup {
+    pub ports: Vec<u16>,
+    pub binds: Vec<IpAddr>,
+    pub control_socket: PathBuf,
+    pub backend: Backend,
+    /// Power actions that rules and schedules can ask for
+    pub actions: Vec<Action>,
+    /// A local MAC to address probes to
+    pub mac: Option<MacAddr>,
+    /// What loading the config file the way the daemon does came to
+    pub config: Result<String, String>,
+}
+
+/// Check every link from a packet arriving to the machine sleeping and waking again,
+/// returning the findings worst first
+pub async fn run(setup: &Setup) -> Vec<Finding> {
+    let mut findings = vec![match &setup.config {
+        Ok(summary) => Finding::new(Severity::Pass, "config", summary.clone()),
+        Err(e) => Finding::new(Severity::Fail, "config", e.clone()).fix("the daemon refuses to start until this is fixed"),
+    }];
+    if let Err(e) = online::ready(&netif::interfaces()) {
+        findings.push(Finding::new(Severity::Fail, "network", e).fix("bring up an interface, or start the daemon with --wait-for-network"));
+    }
+    match control::request(&setup.control_socket, "status").await {
+        Ok(report) => {
+            let listening = report.lines().find_map(|line| line.strip_prefix("listening: ")).unwrap_or("unknown");
+            findings.push(Finding::new(Severity::Pass, "daemon", format!("running, listening on {}", listening)));
+            findings.extend(reachability(setup).await);
+        }
+        Err(e) => {
+            findings.push(
+                Finding::new(Severity::Warn, "daemon", format!("not answering on its control socket: {}", e))
+                    .fix("start it (systemctl start sol), or pass the --control-socket it uses; reachability is not probed"),
+            );
+            findings.extend(binding(setup));
+        }
+    }
+    // ethtool and the logind queries block, so they run on the blocking pool
+    findings.extend(tokio::task::spawn_blocking(nic_wol).await.unwrap_or_default());
+    let (kind, actions) = (setup.backend, setup.actions.clone());
+    findings.extend(tokio::task::spawn_blocking(move || backend(kind, &actions)).await.unwrap_or_default());
+    findings.sort_by_key(|finding| finding.severity);
+    findings
+}
+
+/// Whether the trigger ports can be bound, for when the daemon is not running
+fn binding(setup: &Setup) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for addr in &setup.binds {
+        for port in &setup.ports {
+            let addr = SocketAddr::new(*addr, *port);
+            findings.push(match UdpSocket::bind(addr) {
+                Ok(_) => Finding::new(Severity::Pass, "bind", format!("UDP {} can be bound", addr)),
+                Err(e) if e.kind() == ErrorKind::AddrInUse => {
+                    Finding::new(Severity::Fail, "bind", format!("UDP {} is in use by another program", addr))
+                        .fix(format!("find it with `ss -ulpn 'sport = :{}'`", port))
+                }
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                    Finding::new(Severity::Fail, "bind", format!("this account cannot bind UDP {}: {}", addr, e)).fix(
+                        "ports below 1024 need root or CAP_NET_BIND_SERVICE (AmbientCapabilities=CAP_NET_BIND_SERVICE in the unit)",
+                    )
+                }
+                Err(e) if e.kind() == ErrorKind::AddrNotAvailable => {
+                    Finding::new(Severity::Fail, "bind", format!("cannot bind UDP {}: {}", addr, e))
+                        .fix("no interface has this address; use --freebind if it is configured later")
+                }
+                Err(e) => Finding::new(Severity::Fail, "bind", format!("cannot bind UDP {}: {}", addr, e)),
+            });
+        }
+    }
+    findings
+}
+
+/// Whether probes reach the running daemon over loopback and broadcast
+async fn reachability(setup: &Setup) -> Vec<Finding> {
+    let (Some(mac), Some(port)) = (setup.mac, setup.ports.first()) else {
+        return vec![Finding::new(Severity::Warn, "firewall", "no interface with a MAC to address probes to")];
+    };
+    selftest::probe_all(*port, &mac)
+        .await
+        .into_iter()
+        .map(|(name, destination, result)| match result {
+            Ok(reply) if !reply.starts_with("error") => {
+                Finding::new(Severity::Pass, "firewall", format!("probe via {} ({}) answered: {}", name, destination, reply))
+            }
+            Ok(reply) => Finding::new(Severity::Warn, "firewall", format!("probe via {} ({}) rejected: {}", name, destination, reply)),
+            Err(e) => Finding::new(Severity::Fail, "firewall", format!("probe via {} ({}): {}", name, destination, e))
+                .fix(format!("check --bind and that the firewall accepts UDP port {}; `sol generate firewall` prints rules", port)),
+        })
+        .collect()
+}
+
+/// Whether each interface will wake on a magic packet
+fn nic_wol() -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for iface in netif::interfaces() {
+        if iface.is_loopback() || !iface.is_up() || iface.mac.is_none() {
+            continue;
+        }
+        findings.push(match nicwol::settings(&iface.name) {
+            Ok(Some(settings)) if settings.magic_enabled() => {
+                Finding::new(Severity::Pass, "wol", format!("magic-packet wake is enabled on {}", iface.name))
+            }
+            Ok(Some(settings)) if settings.supports_magic() => {
+                Finding::new(Severity::Fail, "wol", format!("magic-packet wake is disabled on {} (wake-on: {})", iface.name, settings.enabled))
+                    .fix(format!("`ethtool -s {} wol g`, or run the daemon with --fix", iface.name))
+            }
+            Ok(Some(settings)) => Finding::new(
+                Severity::Warn,
+                "wol",
+                format!("{} does not support magic-packet wake (supports: {})", iface.name, settings.supported),
+            ),
+            Ok(None) => Finding::new(Severity::Warn, "wol", format!("{} reports no Wake-on-LAN support", iface.name)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                findings.push(Finding::new(Severity::Warn, "wol", "ethtool is not installed; cannot check Wake-on-LAN"));
+                break;
+            }
+            Err(e) => Finding::new(Severity::Warn, "wol", format!("cannot run ethtool on {}: {}", iface.name, e)),
+        });
+    }
+    findings
+}
+
+/// Whether the backend can carry out each action
+fn backend(kind: Backend, actions: &[Action]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let state = std::fs::read_to_string(SYS_POWER_STATE);
+    for action in actions {
+        let (method, state_word) = match action {
+            Action::Suspend => ("CanSuspend", Some("mem")),
+            Action::Hibernate => ("CanHibernate", Some("disk")),
+            Action::Poweroff => ("CanPowerOff", None),
+            Action::Ignore => continue,
+        };
+        let name = action.as_str();
+        if kind == Backend::Sysfs {
+            findings.push(match (&state, state_word) {
+                (_, None) => Finding::new(Severity::Pass, "backend", format!("{} runs the poweroff command", name)),
+                (Ok(state), Some(word)) if state.split_whitespace().any(|offered| offered == word) => {
+                    Finding::new(Severity::Pass, "backend", format!("{} offers {} for {}", SYS_POWER_STATE, word, name))
+                }
+                (Ok(state), Some(word)) => {
+                    Finding::new(Severity::Fail, "backend", format!("{} offers '{}', not {} for {}", SYS_POWER_STATE, state.trim(), word, name))
+                }
+                (Err(e), _) => Finding::new(Severity::Fail, "backend", format!("{}: {}", SYS_POWER_STATE, e)),
+            });
+            continue;
+        }
+        let answer = match dbus::call_string(&Bus::System, LOGIND, LOGIND_PATH, LOGIND_MANAGER, method) {
+            Ok(answer) => answer,
+            Err(e) => {
+                findings.push(
+                    Finding::new(Severity::Fail, "backend", format!("cannot ask logind whether it can {}: {}", name, e))
+                        .fix("is systemd-logind running? Without systemd, use --backend sysfs"),
+                );
+                break;
+            }
+        };
+        findings.push(match answer.as_str() {
+            "yes" => Finding::new(Severity::Pass, "backend", format!("logind can {}", name)),
+            // systemctl run by root skips polkit, while the logind backend always asks it
+            "challenge" if kind == Backend::Systemd => {
+                Finding::new(Severity::Warn, "backend", format!("logind would ask this account to authenticate to {}", name))
+                    .fix("fine if the daemon runs as root; otherwise see `sol generate polkit-rule`")
+            }
+            "challenge" => Finding::new(Severity::Fail, "backend", format!("logind would ask for authentication to {}", name))
+                .fix("install a polkit rule for the daemon's account: `sol generate polkit-rule --user ACCOUNT`"),
+            _ => {
+                let fix = match action {
+                    Action::Hibernate => "hibernation needs swap at least as large as memory and a resume= kernel parameter",
+                    _ => "check the kernel log and /sys/power/state",
+                };
+                Finding::new(Severity::Fail, "backend", format!("logind cannot {} (answered '{}')", name, answer)).fix(fix)
+            }
+        });
+    }
+    findings
+}
+
+/// The findings as a report, worst first, with a closing count
+pub fn render(findings: &[Finding]) -> String {
+    let mut out = String::new();
+    for finding in findings {
+        let label = match finding.severity {
+            Severity::Fail => "FAIL",
+            Severity::Warn => "WARN",
+            Severity::Pass => "PASS",
+        };
+        out += &format!("{} {:<8} {}\n", label, finding.area, finding.message);
+        if let Some(fix) = &finding.fix {
+            out += &format!("              fix: {}\n", fix);
+        }
+    }
+    let count = |severity| findings.iter().filter(|finding| finding.severity == severity).count();
+    out += &format!("\n{} problem(s), {} warning(s), {} passed\n", count(Severity::Fail), count(Severity::Warn), count(Severity::Pass));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut findings = vec![
+            Finding::new(Severity::Pass, "config", "no config file"),
+            Finding::new(Severity::Warn, "wol", "eth1 reports no Wake-on-LAN support"),
+            Finding::new(Severity::Fail, "wol", "magic-packet wake is disabled on eth0 (wake-on: d)").fix("`ethtool -s eth0 wol g`"),
+        ];
+        findings.sort_by_key(|finding| finding.severity);
+        let report = render(&findings);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "FAIL wol      magic-packet wake is disabled on eth0 (wake-on: d)");
+        assert_eq!(lines[1], "              fix: `ethtool -s eth0 wol g`");
+        assert!(lines[2].starts_with("WARN wol "));
+        assert!(lines[3].starts_with("PASS config "));
+        assert_eq!(lines[5], "1 problem(s), 1 warning(s), 1 passed");
+    }
+
+    #[test]
+    fn test_binding() {
+        let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let setup = Setup {
+            ports: vec![taken.local_addr().unwrap().port()],
+            binds: vec![IpAddr::from([127, 0, 0, 1])],
+            control_socket: PathBuf::from("/nonexistent/sol.sock"),
+            backend: Backend::Sysfs,
+            actions: Vec::new(),
+            mac: None,
+            config: Ok("no config file".to_string()),
+        };
+        let findings = binding(&setup);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Fail);
+        assert!(findings[0].message.ends_with("is in use by another program"), "{}", findings[0].message);
+    }
+}
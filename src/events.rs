@@ -0,0 +1,406 @@
+use crate::json::quote;
+use crate::listen::Arrival;
+use crate::mac::MacAddr;
+use crate::rules::Action;
+use std::future::Future;
+use std::io::Write;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+/// Something that happened in the daemon, published to every listener
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A datagram arrived on a trigger port
+    PacketReceived { peer: SocketAddr, len: usize },
+    /// A valid trigger for one of this machine's MACs, with where it arrived when known
+    Validated { sender: String, mac: MacAddr, arrival: Option<Arrival> },
+    /// A trigger or schedule was blocked by an inhibitor
+    Inhibited { reason: String },
+    /// The steps before the power action are starting
+    ActionStarted { action: Action },
+    /// The power action was carried out
+    ActionFinished { action: Action },
+    /// A step stopped the action
+    ActionFailed { detail: String },
+    /// The system came back from `action`
+    Resumed { action: Action },
+    /// The action succeeded but the system never slept
+    MissedSleep { detail: String },
+    /// A probe confirmed, or failed to confirm, that a woken peer came up or a group member went down
+    Verified { peer: String, ok: bool, detail: String },
+}
+
+impl Event {
+    /// Short name for logs and notifications
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::PacketReceived { .. } => "packet-received",
+            Event::Validated { .. } => "validated",
+            Event::Inhibited { .. } => "inhibited",
+            Event::ActionStarted { .. } => "action-started",
+            Event::ActionFinished { .. } => "action-finished",
+            Event::ActionFailed { .. } => "action-failed",
+            Event::Resumed { .. } => "resumed",
+            Event::MissedSleep { .. } => "missed-sleep",
+            Event::Verified { ok: true, .. } => "peer-verified",
+            Event::Verified { ok: false, .. } => "peer-unverified",
+        }
+    }
+
+    /// The event's fields on one line
+    pub fn detail(&self) -> String {
+        match self {
+            Event::PacketReceived { peer, len } => format!("peer={} len={}", peer, len),
+            Event::Validated { sender, mac, arrival } => match arrival {
+                Some(arrival) => format!(
+                    "sender={} mac={} interface={} destination={}",
+                    sender, mac, arrival.interface, arrival.destination
+                ),
+                None => format!("sender={} mac={}", sender, mac),
+            },
+            Event::Inhibited { reason } => reason.clone(),
+            Event::ActionStarted { action } | Event::ActionFinished { action } | Event::Resumed { action } => {
+                action.as_str().to_string()
+            }
+            Event::ActionFailed { detail } | Event::MissedSleep { detail } | Event::Verified { detail, .. } => detail.clone(),
+        }
+    }
+
+    /// The event's fields as JSON object members, without the braces
+    fn json_fields(&self) -> String {
+        match self {
+            Event::PacketReceived { peer, len } => format!("\"peer\":{},\"len\":{}", quote(&peer.to_string()), len),
+            Event::Validated { sender, mac, arrival } => {
+                let mut fields = format!("\"sender\":{},\"mac\":{}", quote(sender), quote(&mac.to_string()));
+                if let Some(arrival) = arrival {
+                    fields += &format!(
+                        ",\"interface\":{},\"destination\":{}",
+                        quote(&arrival.interface),
+                        quote(&arrival.destination.to_string())
+                    );
+                }
+                fields
+            }
+            Event::Inhibited { reason } => format!("\"reason\":{}", quote(reason)),
+            Event::ActionStarted { action } | Event::ActionFinished { action } | Event::Resumed { action } => {
+                format!("\"action\":{}", quote(action.as_str()))
+            }
+            Event::ActionFailed { detail } | Event::MissedSleep { detail } => format!("\"detail\":{}", quote(detail)),
+            Event::Verified { peer, ok, detail } => format!("\"peer\":{},\"ok\":{},\"detail\":{}", quote(peer), ok, quote(detail)),
+        }
+    }
+
+    /// The event as one line of JSON, stamped with `time` in Unix seconds
+    pub fn json(&self, time: u64) -> String {
+        format!("{{\"time\":{},\"event\":{},{}}}", time, quote(self.name()), self.json_fields())
+    }
+}
+
+pub type Handled<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// Something that reacts to events, such as a logger, notifier or metrics exporter
+pub trait Listener: Send + Sync {
+    fn handle<'a>(&'a self, event: &'a Event) -> Handled<'a>;
+}
+
+impl<T: Listener> Listener for Arc<T> {
+    fn handle<'a>(&'a self, event: &'a Event) -> Handled<'a> {
+        (**self).handle(event)
+    }
+}
+
+/// Listeners registered at startup. Publishing waits for each listener in turn, so an
+/// event announcing a suspend is delivered before the system goes to sleep; listeners
+/// that talk to the network must bound their own time.
+#[derive(Default)]
+pub struct Bus {
+    listeners: Vec<Box<dyn Listener>>,
+}
+
+impl Bus {
+    pub fn subscribe(&mut self, listener: Box<dyn Listener>) {
+        self.listeners.push(listener);
+    }
+
+    pub async fn publish(&self, event: Event) {
+        for listener in &self.listeners {
+            listener.handle(&event).await;
+        }
+    }
+}
+
+/// Logs every event at debug level
+pub struct Log;
+
+impl Listener for Log {
+    fn handle<'a>(&'a self, event: &'a Event) -> Handled<'a> {
+        Box::pin(async move { debug!("Event {}: {:?}", event.name(), event) })
+    }
+}
+
+/// How many events a slow `events` subscriber may fall behind before it misses some
+const STREAM_BACKLOG: usize = 256;
+
+/// Hands events to clients of the control socket's `events` command, as lines of
+/// `UNIX_TIME<TAB>EVENT<TAB>DETAIL`
+#[derive(Clone)]
+pub struct Stream(tokio::sync::broadcast::Sender<String>);
+
+impl Default for Stream {
+    fn default() -> Self {
+        Stream(tokio::sync::broadcast::channel(STREAM_BACKLOG).0)
+    }
+}
+
+impl Stream {
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<String> {
+        self.0.subscribe()
+    }
+}
+
+impl Listener for Stream {
+    fn handle<'a>(&'a self, event: &'a Event) -> Handled<'a> {
+        Box::pin(async move {
+            let detail = event.detail().replace(['\t', '\n', '\r'], " ");
+            // Failing only means nobody is subscribed
+            let _ = self.0.send(format!("{}\t{}\t{}", crate::protocol::unix_now(), event.name(), detail));
+        })
+    }
+}
+
+/// Writes every event to stdout as one line of JSON, for `--output ndjson`
+pub struct Ndjson;
+
+impl Listener for Ndjson {
+    fn handle<'a>(&'a self, event: &'a Event) -> Handled<'a> {
+        Box::pin(async move {
+            let line = event.json(crate::protocol::unix_now());
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(stdout, "{}", line).and_then(|_| stdout.flush());
+        })
+    }
+}
+
+/// How many events `History` keeps for queries
+const HISTORY_SIZE: usize = 1000;
+
+/// An event as kept by `History`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Unix seconds
+    pub time: u64,
+    /// An event name, or `rejected` for a rejected packet
+    pub event: String,
+    pub detail: String,
+}
+
+impl Record {
+    pub fn json(&self) -> String {
+        format!("{{\"time\":{},\"event\":{},\"detail\":{}}}", self.time, quote(&self.event), quote(&self.detail))
+    }
+
+    /// Whether the `sender=` field names `sender`, as a host name, an address or an address
+    /// and port
+    fn sent_by(&self, sender: &str) -> bool {
+        let Some((_, rest)) = self.detail.split_once("sender=") else {
+            return false;
+        };
+        let mut words = rest.split(' ');
+        let first = words.next().unwrap_or_default();
+        // A resolved sender reads `NAME (ADDRESS:PORT)`
+        let address = match words.next() {
+            Some(word) if word.starts_with('(') && word.ends_with(')') => &word[1..word.len() - 1],
+            _ => first,
+        };
+        first == sender || address == sender || address.parse::<SocketAddr>().is_ok_and(|address| address.ip().to_string() == sender)
+    }
+
+    fn for_mac(&self, mac: &MacAddr) -> bool {
+        self.detail.split(' ').filter_map(|word| word.strip_prefix("mac=")).any(|value| value.parse().ok() == Some(*mac))
+    }
+}
+
+/// What `History::query` returns
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    /// Unix seconds
+    pub since: Option<u64>,
+    /// Event name, or `rejected`
+    pub outcome: Option<String>,
+    pub sender: Option<String>,
+    pub mac: Option<MacAddr>,
+    /// Only the newest this many of the matching events
+    pub last: Option<usize>,
+}
+
+impl Query {
+    /// Build a query from `KEY=VALUE` pairs. `since` is Unix seconds, or a duration before
+    /// `now` such as `30m`, `2h` or `1h30m`.
+    pub fn parse<'a>(pairs: impl IntoIterator<Item = &'a str>, now: u64) -> Result<Query, String> {
+        let mut query = Query::default();
+        for pair in pairs {
+            let (key, value) = pair.split_once('=').ok_or_else(|| format!("expected KEY=VALUE, not '{}'", pair))?;
+            match key {
+                "since" => query.since = Some(since(value, now).ok_or_else(|| format!("invalid time '{}'", value))?),
+                "outcome" => query.outcome = Some(value.to_string()),
+                "sender" => query.sender = Some(value.to_string()),
+                "mac" => query.mac = Some(value.parse()?),
+                "last" => query.last = Some(value.parse().map_err(|_| format!("invalid count '{}'", value))?),
+                _ => return Err(format!("unknown parameter '{}'", key)),
+            }
+        }
+        Ok(query)
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        self.since.is_none_or(|since| record.time >= since)
+            && self.outcome.as_ref().is_none_or(|outcome| record.event == *outcome)
+            && self.sender.as_ref().is_none_or(|sender| record.sent_by(sender))
+            && self.mac.as_ref().is_none_or(|mac| record.for_mac(mac))
+    }
+}
+
+/// Unix seconds from either Unix seconds or a duration before `now`
+fn since(value: &str, now: u64) -> Option<u64> {
+    match value.parse() {
+        Ok(time) => Some(time),
+        Err(_) => Some(now.saturating_sub(crate::clock::parse_duration(value).ok()?.as_secs())),
+    }
+}
+
+/// The latest events and rejected packets, for the control socket's `history` command and
+/// `GET /events`. Packets that merely arrived are left out, as in the audit log.
+#[derive(Clone, Default)]
+pub struct History(Arc<Mutex<VecDeque<Record>>>);
+
+impl History {
+    /// Keep `record`, dropping the oldest once full
+    pub fn push(&self, record: Record) {
+        let mut records = self.0.lock().unwrap();
+        if records.len() == HISTORY_SIZE {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Keep an event that happened just now
+    pub fn record(&self, event: &str, detail: &str) {
+        self.push(Record { time: crate::protocol::unix_now(), event: event.to_string(), detail: detail.to_string() });
+    }
+
+    /// Matching records, oldest first
+    pub fn query(&self, query: &Query) -> Vec<Record> {
+        let records = self.0.lock().unwrap();
+        let newest = records.iter().rev().filter(|record| query.matches(record));
+        let mut matching: Vec<Record> = newest.take(query.last.unwrap_or(usize::MAX)).cloned().collect();
+        matching.reverse();
+        matching
+    }
+}
+
+impl Listener for History {
+    fn handle<'a>(&'a self, event: &'a Event) -> Handled<'a> {
+        Box::pin(async move {
+            if !matches!(event, Event::PacketReceived { .. }) {
+                self.record(event.name(), &event.detail());
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<&'static str>>);
+
+    impl Listener for Recorder {
+        fn handle<'a>(&'a self, event: &'a Event) -> Handled<'a> {
+            Box::pin(async move { self.0.lock().unwrap().push(event.name()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_every_listener_sees_events_in_order() {
+        let (first, second) = (Arc::new(Recorder::default()), Arc::new(Recorder::default()));
+        let mut bus = Bus::default();
+        bus.subscribe(Box::new(Arc::clone(&first)));
+        bus.subscribe(Box::new(Arc::clone(&second)));
+
+        bus.publish(Event::ActionStarted { action: Action::Suspend }).await;
+        bus.publish(Event::Resumed { action: Action::Suspend }).await;
+
+        for recorder in [first, second] {
+            assert_eq!(*recorder.0.lock().unwrap(), ["action-started", "resumed"]);
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        let event = Event::Verified { peer: "nas".to_string(), ok: false, detail: "nas is not \"up\"".to_string() };
+        let json = crate::json::Json::parse(&event.json(1760000000)).unwrap();
+        assert_eq!(json.get("event").and_then(|event| event.as_str()), Some("peer-unverified"));
+        assert_eq!(json.get("time").and_then(|time| time.as_u64()), Some(1760000000));
+        assert_eq!(json.get("ok"), Some(&crate::json::Json::Bool(false)));
+        assert_eq!(json.get("detail").and_then(|detail| detail.as_str()), Some("nas is not \"up\""));
+        let received = Event::PacketReceived { peer: "192.168.1.5:40000".parse().unwrap(), len: 102 };
+        assert_eq!(received.json(5), r#"{"time":5,"event":"packet-received","peer":"192.168.1.5:40000","len":102}"#);
+        let arrival = Arrival { index: 3, interface: "eth1".to_string(), destination: "192.168.20.255".parse().unwrap() };
+        let validated = Event::Validated { sender: "192.168.20.7".to_string(), mac: MacAddr::ZERO, arrival: Some(arrival) };
+        assert_eq!(validated.detail(), "sender=192.168.20.7 mac=00:00:00:00:00:00 interface=eth1 destination=192.168.20.255");
+        assert!(validated.json(5).ends_with(r#","interface":"eth1","destination":"192.168.20.255"}"#));
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let stream = Stream::default();
+        stream.handle(&Event::Resumed { action: Action::Suspend }).await;
+        let mut lines = stream.subscribe();
+        stream.handle(&Event::Inhibited { reason: "wake-lock: held by\tbackup".to_string() }).await;
+        let line = lines.recv().await.unwrap();
+        assert!(line.ends_with("\tinhibited\twake-lock: held by backup"), "{}", line);
+    }
+
+    #[test]
+    fn test_history_query() {
+        let history = History::default();
+        let record = |time: u64, event: &str, detail: &str| Record { time, event: event.to_string(), detail: detail.to_string() };
+        history.push(record(100, "rejected", "sender=192.168.1.77:40000 reason=Invalid packet: bad header"));
+        history.push(record(200, "validated", "sender=nas (192.168.1.5:40000) mac=aa:bb:cc:dd:ee:ff"));
+        history.push(record(300, "action-started", "suspend"));
+        history.push(record(400, "rejected", "sender=[fe80::1]:9 reason=Banned: too many rejected packets"));
+
+        let events = |pairs: &[&str]| -> Vec<u64> {
+            history.query(&Query::parse(pairs.iter().copied(), 1000).unwrap()).iter().map(|record| record.time).collect()
+        };
+        assert_eq!(events(&[]), [100, 200, 300, 400]);
+        assert_eq!(events(&["outcome=rejected"]), [100, 400]);
+        assert_eq!(events(&["outcome=rejected", "last=1"]), [400]);
+        assert_eq!(events(&["since=250"]), [300, 400]);
+        assert_eq!(events(&["since=12m"]), [300, 400]);
+        assert_eq!(events(&["sender=192.168.1.5"]), [200]);
+        assert_eq!(events(&["sender=nas"]), [200]);
+        assert_eq!(events(&["sender=192.168.1.77:40000"]), [100]);
+        assert_eq!(events(&["sender=fe80::1"]), [400]);
+        assert_eq!(events(&["mac=AA-BB-CC-DD-EE-FF"]), [200]);
+        assert_eq!(events(&["mac=aa:bb:cc:dd:ee:ff", "outcome=rejected"]), Vec::<u64>::new());
+        assert!(Query::parse(["since=yesterday"], 0).is_err());
+        assert!(Query::parse(["color=red"], 0).is_err());
+        assert_eq!(history.query(&Query::default())[2].json(), r#"{"time":300,"event":"action-started","detail":"suspend"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_history_is_bounded() {
+        let history = History::default();
+        for _ in 0..HISTORY_SIZE + 5 {
+            history.handle(&Event::Inhibited { reason: "lock".to_string() }).await;
+        }
+        history.handle(&Event::PacketReceived { peer: "192.168.1.5:40000".parse().unwrap(), len: 102 }).await;
+        let records = history.query(&Query::default());
+        assert_eq!(records.len(), HISTORY_SIZE);
+        assert_eq!(records.last().unwrap().event, "inhibited");
+    }
+}
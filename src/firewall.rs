@@ -0,0 +1,170 @@
+use crate::netif::{IpNetwork, NetworkInterface};
+use crate::rules::{Action, Rules, Sender};
+use clap::ValueEnum;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Which firewall to write rules for
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Nftables,
+    Iptables,
+}
+
+/// The trigger ports and who may send to them; `sources` of None means anyone
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Allowed {
+    pub ports: Vec<u16>,
+    pub sources: Option<Vec<IpNetwork>>,
+}
+
+impl Allowed {
+    /// Everyone when packets from unknown senders get an action, otherwise the senders of the
+    /// rules that do not ignore. `--local-only` narrows "everyone" to the local subnets.
+    /// Hostnames count with the addresses they resolved to.
+    pub fn from_rules(ports: &[u16], rules: &Rules, local_only: Option<&[NetworkInterface]>) -> Self {
+        let mut sources = Some(Vec::new());
+        if rules.default != Action::Ignore {
+            sources = None;
+        }
+        for rule in rules.rules.iter().filter(|rule| rule.action != Action::Ignore) {
+            let (Some(list), Some(from)) = (sources.as_mut(), &rule.from) else {
+                sources = None;
+                break;
+            };
+            match from {
+                Sender::Subnet(net) => list.push(*net),
+                Sender::Host(name) => list.extend(rules.hosts.addresses(name).into_iter().map(IpNetwork::from)),
+            }
+        }
+        if sources.is_none()
+            && let Some(interfaces) = local_only
+        {
+            let subnets = interfaces.iter().flat_map(|iface| &iface.ips).filter_map(|net| IpNetwork::new(net.network(), net.prefix()).ok());
+            sources = Some(subnets.collect());
+        }
+        if let Some(list) = sources.as_mut() {
+            list.sort();
+            list.dedup();
+        }
+        Allowed { ports: ports.to_vec(), sources }
+    }
+
+    fn sources(&self, v6: bool) -> Option<Vec<String>> {
+        let sources = self.sources.as_ref()?;
+        Some(sources.iter().filter(|net| net.is_ipv6() == v6).map(IpNetwork::to_string).collect())
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Nftables => self.nftables(),
+            Format::Iptables => self.iptables(),
+        }
+    }
+
+    fn nftables(&self) -> String {
+        let ports: Vec<String> = self.ports.iter().map(u16::to_string).collect();
+        let ports = format!("{{ {} }}", ports.join(", "));
+        let mut out = String::from("#!/usr/sbin/nft -f\n");
+        out += "# Generated by `sol generate firewall`; regenerate after changing the trigger ports or rules\n";
+        // Declaring the table first lets the delete succeed on the first run
+        out += "table inet sol\ndelete table inet sol\ntable inet sol {\n";
+        let mut chain = Vec::new();
+        match (self.sources(false), self.sources(true)) {
+            (Some(v4), Some(v6)) => {
+                for (name, kind, family, elements) in [("allowed4", "ipv4_addr", "ip", v4), ("allowed6", "ipv6_addr", "ip6", v6)] {
+                    out += &format!("\tset {} {{\n\t\ttype {}\n\t\tflags interval\n", name, kind);
+                    if !elements.is_empty() {
+                        out += &format!("\t\telements = {{ {} }}\n", elements.join(", "));
+                    }
+                    out += "\t}\n\n";
+                    chain.push(format!("udp dport {} {} saddr @{} accept", ports, family, name));
+                }
+                chain.push(format!("udp dport {} drop", ports));
+            }
+            _ => chain.push(format!("udp dport {} accept", ports)),
+        }
+        out += "\tchain input {\n\t\ttype filter hook input priority filter - 1; policy accept;\n";
+        for rule in chain {
+            out += &format!("\t\t{}\n", rule);
+        }
+        out += "\t}\n}\n";
+        out
+    }
+
+    fn iptables(&self) -> String {
+        let ports: Vec<String> = self.ports.iter().map(u16::to_string).collect();
+        let jump = format!("INPUT -p udp -m multiport --dports {} -j sol", ports.join(","));
+        let mut out = String::from("#!/bin/sh\n");
+        out += "# Generated by `sol generate firewall`; regenerate after changing the trigger ports or rules\n";
+        out += "set -e\n";
+        for (command, v6) in [("iptables", false), ("ip6tables", true)] {
+            out += &format!("\n{0} -N sol 2>/dev/null || {0} -F sol\n", command);
+            match self.sources(v6) {
+                Some(sources) => {
+                    for source in sources {
+                        out += &format!("{} -A sol -s {} -j ACCEPT\n", command, source);
+                    }
+                    out += &format!("{} -A sol -j DROP\n", command);
+                }
+                None => out += &format!("{} -A sol -j ACCEPT\n", command),
+            }
+            out += &format!("{0} -C {1} 2>/dev/null || {0} -I {1}\n", command, jump);
+        }
+        out
+    }
+}
+
+/// Load the generated rules with `nft -f -`, or run the iptables script with `sh`
+pub fn apply(script: &str, format: Format) -> Result<(), String> {
+    let (program, args): (&str, &[&str]) = match format {
+        Format::Nftables => ("nft", &["-f", "-"]),
+        Format::Iptables => ("sh", &["-s"]),
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Cannot run {}: {}", program, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(script.as_bytes()).map_err(|e| format!("Cannot write to {}: {}", program, e))?;
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("{} failed ({})", program, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hosts::Hosts;
+    use crate::supply::Supply;
+
+    fn rules(rules: &[&str], default: Action) -> Rules {
+        let rules = rules.iter().map(|rule| rule.parse().unwrap()).collect();
+        Rules::new(rules, default, Supply::new(None), Hosts::default())
+    }
+
+    #[test]
+    fn test_allowed_sources() {
+        let open = Allowed::from_rules(&[9], &rules(&["ignore:from=10.0.0.0/8"], Action::Suspend), None);
+        assert_eq!(open.sources, None);
+        assert_eq!(open.render(Format::Nftables).lines().filter(|line| line.contains("udp dport")).collect::<Vec<_>>(), ["\t\tudp dport { 9 } accept"]);
+
+        let rules = rules(&["ignore:from=192.168.1.13", "suspend:from=192.168.1.0/24", "poweroff:from=fd00::/8,port=10"], Action::Ignore);
+        let allowed = Allowed::from_rules(&[9, 10], &rules, None);
+        assert_eq!(allowed.sources.as_ref().unwrap().len(), 2);
+        let nft = allowed.render(Format::Nftables);
+        assert!(nft.contains("\t\telements = { 192.168.1.0/24 }\n"));
+        assert!(nft.contains("\t\tudp dport { 9, 10 } ip6 saddr @allowed6 accept\n\t\tudp dport { 9, 10 } drop\n"));
+
+        let iptables = allowed.render(Format::Iptables);
+        assert!(iptables.contains("\niptables -N sol 2>/dev/null || iptables -F sol\niptables -A sol -s 192.168.1.0/24 -j ACCEPT\niptables -A sol -j DROP\n"));
+        assert!(iptables.contains("ip6tables -A sol -s fd00::/8 -j ACCEPT\n"));
+        assert!(iptables.ends_with(
+            "ip6tables -C INPUT -p udp -m multiport --dports 9,10 -j sol 2>/dev/null || ip6tables -I INPUT -p udp -m multiport --dports 9,10 -j sol\n"
+        ));
+    }
+}
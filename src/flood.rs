@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+/// During a flood, one rejection in this many is logged
+const LOG_SAMPLE: u64 = 100;
+
+const SECOND: Duration = Duration::from_secs(1);
+
+/// When flood protection kicks in and when it stands down
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FloodPolicy {
+    /// Rejected packets per second that start flood protection
+    pub threshold: u32,
+    /// How long the rate must stay under half the threshold before it stops
+    pub recover: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    /// A flood started; holds the rate that triggered it
+    Started(u32),
+    /// The flood is over; holds the number of rejections that were not logged
+    Ended(u64),
+}
+
+/// Tracks the rate of rejected packets and switches into a degraded mode under a storm
+#[derive(Debug)]
+pub struct Flood {
+    policy: Option<FloodPolicy>,
+    /// Start of the current one-second bucket and the rejections in it
+    bucket: Instant,
+    count: u32,
+    active: bool,
+    /// Since when the rate has been low enough to recover
+    calm_since: Option<Instant>,
+    /// Rejections seen during the current flood
+    seen: u64,
+}
+
+impl Flood {
+    /// Without a policy there is never a flood
+    pub fn new(policy: Option<FloodPolicy>, now: Instant) -> Self {
+        Flood { policy, bucket: now, count: 0, active: false, calm_since: None, seen: 0 }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Count a rejected packet. Returns whether to log it and whether a flood started.
+    pub fn reject(&mut self, now: Instant) -> (bool, Option<Transition>) {
+        let transition = self.tick(now);
+        let Some(policy) = self.policy else {
+            return (true, transition);
+        };
+        self.count += 1;
+        if self.active {
+            self.seen += 1;
+            return (self.seen % LOG_SAMPLE == 1, transition);
+        }
+        if self.count >= policy.threshold {
+            self.active = true;
+            self.calm_since = None;
+            self.seen = 1;
+            return (true, Some(Transition::Started(self.count)));
+        }
+        (true, transition)
+    }
+
+    /// Close finished one-second buckets and check for recovery. Needs calling about once a
+    /// second during a flood, since packets the kernel drops never reach `reject`.
+    pub fn tick(&mut self, now: Instant) -> Option<Transition> {
+        let policy = self.policy?;
+        while now.duration_since(self.bucket) >= SECOND {
+            if self.active {
+                if self.count < policy.threshold.div_ceil(2) {
+                    self.calm_since.get_or_insert(self.bucket + SECOND);
+                } else {
+                    self.calm_since = None;
+                }
+            }
+            self.count = 0;
+            self.bucket += SECOND;
+            // Skip idle seconds in one step rather than one by one
+            if now.duration_since(self.bucket) >= SECOND * 2 {
+                let idle = now.duration_since(self.bucket).as_secs() - 1;
+                if self.active {
+                    self.calm_since.get_or_insert(self.bucket);
+                }
+                self.bucket += SECOND * idle as u32;
+            }
+        }
+        let calm = self.calm_since?;
+        if self.active && now.duration_since(calm) >= policy.recover {
+            self.active = false;
+            self.calm_since = None;
+            let unlogged = self.seen - self.seen.div_ceil(LOG_SAMPLE);
+            return Some(Transition::Ended(unlogged));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: FloodPolicy = FloodPolicy { threshold: 10, recover: Duration::from_secs(5) };
+
+    #[test]
+    fn test_flood_starts_and_samples() {
+        let start = Instant::now();
+        let mut flood = Flood::new(Some(POLICY), start);
+        for _ in 0..9 {
+            assert_eq!(flood.reject(start), (true, None));
+        }
+        assert_eq!(flood.reject(start), (true, Some(Transition::Started(10))));
+        assert!(flood.active());
+
+        let logged = (0..300).filter(|_| flood.reject(start + SECOND).0).count();
+        assert_eq!(logged, 3);
+    }
+
+    #[test]
+    fn test_recovery_needs_a_calm_period() {
+        let start = Instant::now();
+        let mut flood = Flood::new(Some(POLICY), start);
+        for _ in 0..10 {
+            flood.reject(start);
+        }
+        // Still busy in the second second, so recovery has not started
+        for _ in 0..8 {
+            flood.reject(start + SECOND);
+        }
+        assert_eq!(flood.tick(start + SECOND * 6), None);
+        assert!(flood.active());
+        assert_eq!(flood.tick(start + SECOND * 7), Some(Transition::Ended(8)));
+        assert!(!flood.active());
+    }
+
+    #[test]
+    fn test_no_policy() {
+        let start = Instant::now();
+        let mut flood = Flood::new(None, start);
+        for _ in 0..1000 {
+            assert_eq!(flood.reject(start), (true, None));
+        }
+        assert!(!flood.active());
+    }
+}
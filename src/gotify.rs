@@ -0,0 +1,85 @@
+use crate::heartbeat::post;
+use crate::json::quote;
+use sol::notifier::{Notification, Notifier, Priority, Sent, Target};
+
+/// Sends messages to a Gotify server, given as `http://HOST[:PORT]`, with an application token
+pub struct Gotify {
+    pub url: String,
+    pub token: String,
+}
+
+fn priority(priority: Priority) -> u8 {
+    match priority {
+        Priority::Min => 0,
+        Priority::Low => 2,
+        Priority::Default => 5,
+        Priority::High => 8,
+        Priority::Urgent => 10,
+    }
+}
+
+/// The JSON body of `POST /message`
+fn body(notification: &Notification) -> String {
+    let extras = match &notification.click {
+        Some(click) => format!(",\"extras\":{{\"client::notification\":{{\"click\":{{\"url\":{}}}}}}}", quote(click)),
+        None => String::new(),
+    };
+    format!(
+        "{{\"title\":{},\"message\":{},\"priority\":{}{}}}",
+        quote(&notification.title),
+        quote(&notification.message),
+        priority(notification.priority),
+        extras
+    )
+}
+
+/// The backend for a `type = "gotify"` table
+pub fn build(target: Target) -> Result<Box<dyn Notifier>, String> {
+    match target {
+        Target { url, token: Some(token), user: None } => Ok(Box::new(Gotify { url, token })),
+        Target { token: None, .. } => Err("'token' is required for gotify".to_string()),
+        Target { user: Some(_), .. } => Err("'user' only applies to pushover".to_string()),
+    }
+}
+
+impl Notifier for Gotify {
+    fn describe(&self) -> String {
+        format!("gotify {}", self.url)
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> Sent<'a> {
+        Box::pin(async move {
+            let url = format!("{}/message", self.url.trim_end_matches('/'));
+            let headers = [("Content-Type", "application/json"), ("X-Gotify-Key", self.token.as_str())];
+            post(&url, &headers, &body(notification)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::Json;
+
+    #[test]
+    fn test_body() {
+        let mut notification = Notification {
+            event: "resumed",
+            host: "nas".to_string(),
+            title: "nas: resumed".to_string(),
+            message: "nas resumed from \"suspend\"".to_string(),
+            priority: Priority::High,
+            tag: "sunny",
+            click: None,
+        };
+        let json = Json::parse(&body(&notification)).unwrap();
+        assert_eq!(json.get("message").and_then(Json::as_str), Some("nas resumed from \"suspend\""));
+        assert_eq!(json.get("priority").and_then(Json::as_u64), Some(8));
+        assert!(json.get("extras").is_none());
+
+        notification.click = Some("http://nas.lan/".to_string());
+        let json = Json::parse(&body(&notification)).unwrap();
+        let click = json.get("extras").and_then(|extras| extras.get("client::notification")).and_then(|n| n.get("click"));
+        assert_eq!(click.and_then(|click| click.get("url")).and_then(Json::as_str), Some("http://nas.lan/"));
+    }
+}
@@ -0,0 +1,325 @@
+use crate::mac::MacAddr;
+use crate::peers::wol_packet;
+use crate::relay::{RelayEntry, RelayTarget};
+use crate::verify::Checks;
+use clap::ValueEnum;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+/// Header of the reply a daemon sends when it accepts a trigger
+pub const ACK_PACKET_HEADER: [u8; 6] = [0x55; 6];
+
+/// Header of the packet asking a member whether it is ready to sleep
+pub const PREPARE_PACKET_HEADER: [u8; 6] = [0x50; 6];
+
+/// Header of a member's answer that it is ready to sleep
+pub const READY_PACKET_HEADER: [u8; 6] = [0x52; 6];
+
+/// Header of a member's answer that it is not ready, followed by the reason
+pub const BUSY_PACKET_HEADER: [u8; 6] = [0x42; 6];
+
+/// Port of a member's daemon unless the member names one
+pub const DEFAULT_MEMBER_PORT: u16 = 10;
+
+/// Build an acknowledgement for a trigger targeting `mac`
+pub fn ack_packet(mac: &MacAddr) -> Vec<u8> {
+    let mut packet = wol_packet(mac);
+    packet[..6].copy_from_slice(&ACK_PACKET_HEADER);
+    packet
+}
+
+/// Build a request asking the member with `mac` whether it is ready to sleep
+pub fn prepare_packet(mac: &MacAddr) -> Vec<u8> {
+    let mut packet = wol_packet(mac);
+    packet[..6].copy_from_slice(&PREPARE_PACKET_HEADER);
+    packet
+}
+
+/// Build a member's answer to a prepare request: ready, or busy with the reason appended
+pub fn readiness_packet(mac: &MacAddr, readiness: &Result<(), String>) -> Vec<u8> {
+    let mut packet = wol_packet(mac);
+    match readiness {
+        Ok(()) => packet[..6].copy_from_slice(&READY_PACKET_HEADER),
+        Err(reason) => {
+            packet[..6].copy_from_slice(&BUSY_PACKET_HEADER);
+            packet.extend_from_slice(reason.as_bytes());
+        }
+    }
+    packet
+}
+
+/// The readiness a member reported for `mac`, if `packet` is its answer to a prepare request
+fn readiness_for(packet: &[u8], mac: &MacAddr) -> Option<Result<(), String>> {
+    if packet.len() < 12 || packet[6..12] != mac.0 {
+        return None;
+    }
+    match packet[..6].try_into().unwrap() {
+        READY_PACKET_HEADER => Some(Ok(())),
+        BUSY_PACKET_HEADER => Some(Err(String::from_utf8_lossy(packet.get(102..).unwrap_or_default()).into_owned())),
+        _ => None,
+    }
+}
+
+/// What to do about members that do not answer a prepare request in time
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Unanswered {
+    /// Stay awake, as if the member had said it was busy
+    #[default]
+    Abort,
+    /// Sleep anyway; a member that is already down cannot be using this machine
+    Proceed,
+}
+
+/// Whether `packet` acknowledges a trigger for `mac`
+pub fn is_ack_for(packet: &[u8], mac: &MacAddr) -> bool {
+    packet.len() >= 12 && packet[..6] == ACK_PACKET_HEADER && packet[6..12] == mac.0
+}
+
+/// Parse a member as `MAC@TARGET[:PORT]`, defaulting to the daemon's port
+pub fn parse_member(s: &str) -> Result<RelayEntry, String> {
+    match s.rsplit_once('@') {
+        Some((_, target)) if !target.contains(':') => format!("{}:{}", s, DEFAULT_MEMBER_PORT).parse(),
+        _ => s.parse(),
+    }
+}
+
+/// Peer daemons that are put to sleep before this machine
+#[derive(Clone, Debug)]
+pub struct SleepGroup {
+    pub members: Vec<RelayEntry>,
+    pub wait_ack: bool,
+    pub timeout: Duration,
+    /// Ask members whether they are ready before anything is put to sleep
+    pub prepare: bool,
+    pub unanswered: Unanswered,
+    /// Probes confirming that members went down before this machine follows
+    pub checks: Option<Checks>,
+}
+
+impl SleepGroup {
+    /// Whether `addr` is a member's address; triggers from members are not propagated
+    /// back into the group so that two daemons listing each other cannot loop
+    pub fn is_member(&self, addr: IpAddr) -> bool {
+        self.members
+            .iter()
+            .any(|member| matches!(member.target, RelayTarget::Address(a) if IpAddr::V4(a) == addr))
+    }
+
+    /// Ask every member whether it is ready to sleep and wait up to the timeout for all of
+    /// them. Returns why the group is not ready: a member that is busy, or one that stayed
+    /// silent when unanswered requests abort.
+    pub async fn prepare(&self) -> Result<(), String> {
+        if !self.prepare || self.members.is_empty() {
+            return Ok(());
+        }
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+        socket.set_broadcast(true).map_err(|e| e.to_string())?;
+
+        let mut waiting: Vec<(&RelayEntry, SocketAddr)> = Vec::new();
+        for member in &self.members {
+            let destination = member.destination()?;
+            socket
+                .send_to(&prepare_packet(&member.mac), destination)
+                .await
+                .map_err(|e| format!("failed to ask group member {}: {}", destination, e))?;
+            waiting.push((member, destination));
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = [0u8; 512];
+        while !waiting.is_empty() {
+            let received = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await;
+            let Ok(Ok((len, _))) = received else {
+                break;
+            };
+            let answer = waiting.iter().enumerate().find_map(|(i, (member, _))| Some((i, readiness_for(&buf[..len], &member.mac)?)));
+            if let Some((i, readiness)) = answer {
+                let (_, destination) = waiting.remove(i);
+                match readiness {
+                    Ok(()) => info!("Group member {} is ready to sleep", destination),
+                    Err(reason) => return Err(format!("group member {} is busy ({})", destination, reason)),
+                }
+            }
+        }
+
+        for (_, destination) in &waiting {
+            warn!("No answer from group member {} within {}s", destination, self.timeout.as_secs());
+        }
+        match waiting.first() {
+            Some((_, destination)) if self.unanswered == Unanswered::Abort => {
+                Err(format!("group member {} did not say it was ready", destination))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Send a sleep packet to every member and, if configured, wait for their acknowledgements
+    pub async fn propagate(&self) {
+        if self.members.is_empty() {
+            return;
+        }
+        if let Err(e) = self.send_and_wait().await {
+            error!("Failed to propagate sleep to group: {}", e);
+        }
+        if let Some(checks) = &self.checks {
+            // Members are probed concurrently, so the wait is bounded by one timeout
+            let waits: Vec<_> = self
+                .members
+                .iter()
+                .map(|member| {
+                    let (checks, mac) = (checks.clone(), member.mac);
+                    tokio::spawn(async move { checks.wait(&mac.to_string(), &mac, false).await })
+                })
+                .collect();
+            for wait in waits {
+                let _ = wait.await;
+            }
+        }
+    }
+
+    async fn send_and_wait(&self) -> Result<(), String> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+        socket.set_broadcast(true).map_err(|e| e.to_string())?;
+
+        let mut waiting: Vec<(&RelayEntry, SocketAddr)> = Vec::new();
+        for member in &self.members {
+            let destination = member.destination()?;
+            match socket.send_to(&wol_packet(&member.mac), destination).await {
+                Ok(_) => {
+                    info!("Sleep packet sent to group member {}", destination);
+                    waiting.push((member, destination));
+                }
+                Err(e) => error!("Failed to send sleep packet to {}: {}", destination, e),
+            }
+        }
+
+        if !self.wait_ack {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = [0u8; 128];
+        while !waiting.is_empty() {
+            let received = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await;
+            let Ok(Ok((len, from))) = received else {
+                break;
+            };
+            if let Some(i) = waiting.iter().position(|(member, _)| is_ack_for(&buf[..len], &member.mac)) {
+                let (_, destination) = waiting.remove(i);
+                info!("Group member {} acknowledged (from {})", destination, from);
+            }
+        }
+
+        for (_, destination) in waiting {
+            error!("No acknowledgement from group member {} within {}s", destination, self.timeout.as_secs());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const MAC: MacAddr = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+    #[test]
+    fn test_parse_member() {
+        let member = parse_member("aa:bb:cc:dd:ee:ff@192.168.1.5").unwrap();
+        assert_eq!(member.port, DEFAULT_MEMBER_PORT);
+        assert_eq!(parse_member("aa:bb:cc:dd:ee:ff@192.168.1.5:99").unwrap().port, 99);
+        assert!(parse_member("aa:bb:cc:dd:ee:ff").is_err());
+
+        let group = SleepGroup {
+            members: vec![member],
+            wait_ack: false,
+            timeout: Duration::ZERO,
+            prepare: false,
+            unanswered: Unanswered::Abort,
+            checks: None,
+        };
+        assert!(group.is_member(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 5))));
+        assert!(!group.is_member(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 6))));
+    }
+
+    #[tokio::test]
+    async fn test_propagate_waits_for_ack() {
+        let member = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = member.local_addr().unwrap().port();
+        let group = SleepGroup {
+            members: vec![parse_member(&format!("aa:bb:cc:dd:ee:ff@127.0.0.1:{}", port)).unwrap()],
+            wait_ack: true,
+            timeout: Duration::from_secs(5),
+            prepare: false,
+            unanswered: Unanswered::Abort,
+            checks: None,
+        };
+
+        let responder = tokio::spawn(async move {
+            let mut buf = [0u8; 128];
+            let (len, from) = member.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], wol_packet(&MAC).as_slice());
+            member.send_to(&ack_packet(&MAC), from).await.unwrap();
+        });
+
+        let started = std::time::Instant::now();
+        group.propagate().await;
+        responder.await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    /// A group of one member that answers the prepare request with `readiness`, or stays silent
+    async fn prepared(readiness: Option<Result<(), String>>, unanswered: Unanswered) -> Result<(), String> {
+        let member = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = member.local_addr().unwrap().port();
+        let group = SleepGroup {
+            members: vec![parse_member(&format!("aa:bb:cc:dd:ee:ff@127.0.0.1:{}", port)).unwrap()],
+            wait_ack: false,
+            timeout: Duration::from_millis(200),
+            prepare: true,
+            unanswered,
+            checks: None,
+        };
+        let responder = tokio::spawn(async move {
+            let mut buf = [0u8; 128];
+            let (len, from) = member.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], prepare_packet(&MAC).as_slice());
+            if let Some(readiness) = readiness {
+                member.send_to(&readiness_packet(&MAC, &readiness), from).await.unwrap();
+            }
+        });
+        let result = group.prepare().await;
+        responder.await.unwrap();
+        result
+    }
+
+    #[tokio::test]
+    async fn test_prepare() {
+        assert_eq!(prepared(Some(Ok(())), Unanswered::Abort).await, Ok(()));
+        let busy = prepared(Some(Err("inhibited (rsync)".to_string())), Unanswered::Proceed).await.unwrap_err();
+        assert!(busy.ends_with("is busy (inhibited (rsync))"), "{}", busy);
+        assert!(prepared(None, Unanswered::Abort).await.unwrap_err().ends_with("did not say it was ready"));
+        assert_eq!(prepared(None, Unanswered::Proceed).await, Ok(()));
+    }
+
+    #[test]
+    fn test_readiness_packet() {
+        assert_eq!(readiness_for(&readiness_packet(&MAC, &Ok(())), &MAC), Some(Ok(())));
+        let busy = readiness_packet(&MAC, &Err("session open".to_string()));
+        assert_eq!(readiness_for(&busy, &MAC), Some(Err("session open".to_string())));
+        assert_eq!(readiness_for(&busy, &MacAddr::ZERO), None);
+        assert_eq!(readiness_for(&prepare_packet(&MAC), &MAC), None);
+    }
+
+    #[test]
+    fn test_ack_packet() {
+        let ack = ack_packet(&MAC);
+        assert_eq!(ack.len(), 102);
+        assert!(is_ack_for(&ack, &MAC));
+        assert!(!is_ack_for(&wol_packet(&MAC), &MAC));
+        assert!(!is_ack_for(&ack, &MacAddr::ZERO));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::control::Control;
+use crate::portmap::split_url;
+use crate::rules::Action;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// How long one beat may take to deliver
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where heartbeats go: `http://HOST[:PORT]/PATH` for a JSON POST, or `udp://HOST:PORT` for
+/// a JSON datagram
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Monitor {
+    Http(String),
+    Udp(String),
+}
+
+impl std::str::FromStr for Monitor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("http://") {
+            split_url(s)?;
+            Ok(Monitor::Http(s.to_string()))
+        } else if let Some(addr) = s.strip_prefix("udp://") {
+            match addr.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(Monitor::Udp(addr.to_string())),
+                _ => Err(format!("Invalid monitor '{}' (expected udp://HOST:PORT)", s)),
+            }
+        } else {
+            Err(format!("Invalid monitor '{}' (expected http://HOST[:PORT]/PATH or udp://HOST:PORT)", s))
+        }
+    }
+}
+
+impl std::fmt::Display for Monitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Monitor::Http(url) => f.write_str(url),
+            Monitor::Udp(addr) => write!(f, "udp://{}", addr),
+        }
+    }
+}
+
+/// Tells a monitoring endpoint the daemon is alive, and that a sleep is planned rather than
+/// a crash
+#[derive(Clone)]
+pub struct Heartbeat {
+    pub monitor: Monitor,
+    pub interval: Duration,
+    pub control: Control,
+    pub started: Instant,
+}
+
+impl Heartbeat {
+    /// The beat as one line of JSON. `sleeping` names the action about to be carried out.
+    fn body(&self, sleeping: Option<Action>) -> String {
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+        let system_uptime = std::fs::read_to_string("/proc/uptime")
+            .ok()
+            .and_then(|uptime| uptime.split('.').next()?.parse::<u64>().ok())
+            .unwrap_or_default();
+        let state = match sleeping {
+            Some(action) => action.as_str(),
+            None if self.control.maintenance.enabled() => "maintenance",
+            None if self.control.paused.is_paused() => "paused",
+            None => "awake",
+        };
+        let pending = self.control.status.lock().unwrap().suspend_pending;
+        format!(
+            "{{\"host\":\"{}\",\"state\":\"{}\",\"uptime\":{},\"system_uptime\":{},\"suspend_pending\":{},\"time\":{}}}",
+            hostname.trim().replace(['"', '\\'], ""),
+            state,
+            self.started.elapsed().as_secs(),
+            system_uptime,
+            pending,
+            crate::protocol::unix_now()
+        )
+    }
+
+    async fn deliver(&self, body: &str) -> Result<(), String> {
+        let send = async {
+            match &self.monitor {
+                Monitor::Udp(addr) => {
+                    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+                    socket.send_to(body.as_bytes(), addr).await.map_err(|e| e.to_string())?;
+                    Ok(())
+                }
+                Monitor::Http(url) => post(url, &[("Content-Type", "application/json")], body).await,
+            }
+        };
+        tokio::time::timeout(SEND_TIMEOUT, send).await.map_err(|_| "timed out".to_string())?
+    }
+
+    /// Send one beat; `sleeping` marks the final beat before a power action
+    pub async fn beat(&self, sleeping: Option<Action>) {
+        if let Err(e) = self.deliver(&self.body(sleeping)).await {
+            warn!("Heartbeat to {} failed: {}", self.monitor, e);
+        }
+    }
+
+    /// Beat now and then every interval, in the background
+    pub fn start(&self) {
+        let heartbeat = self.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(heartbeat.interval);
+            loop {
+                tick.tick().await;
+                heartbeat.beat(None).await;
+            }
+        });
+    }
+}
+
+/// POST `body` to `url` with extra `headers` and expect a 2xx status
+pub async fn post(url: &str, headers: &[(&str, &str)], body: &str) -> Result<(), String> {
+    let (host, path) = split_url(url)?;
+    let mut stream = TcpStream::connect(&host).await.map_err(|e| e.to_string())?;
+    let headers: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        headers,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.map_err(|e| e.to_string())?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.split_whitespace().nth(1).unwrap_or_default();
+    if !status.starts_with('2') {
+        return Err(format!("HTTP status {}", if status.is_empty() { "missing" } else { status }));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bans::Bans;
+    use crate::inhibit::Inhibitors;
+    use crate::maintenance::Maintenance;
+    use crate::pause::Paused;
+    use crate::peers::Peers;
+    use crate::policy::{Policy, Profiles};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    fn heartbeat(monitor: Monitor) -> Heartbeat {
+        let policy = Policy {
+            quorum: 1,
+            quorum_window: 10,
+            confirm: false,
+            grace: 0,
+            warn_users: false,
+            inhibit_share_clients: false,
+            inhibit_ports: Vec::new(),
+            ignore_desktop_inhibitors: true,
+            inhibit_session_activity: false,
+            min_idle: None,
+        };
+        let control = Control::new(
+            Maintenance::new(std::env::temp_dir().join(format!("sol-heartbeat-test-{}", std::process::id()))),
+            Paused::default(),
+            Arc::new(Inhibitors::default()),
+            Arc::new(Profiles::new(policy)),
+            Arc::new(Peers::default()),
+            Arc::new(Bans::new(None, None)),
+        );
+        Heartbeat { monitor, interval: Duration::from_secs(60), control, started: Instant::now() }
+    }
+
+    #[test]
+    fn test_parse_monitor() {
+        assert_eq!("udp://monitor.lan:9999".parse(), Ok(Monitor::Udp("monitor.lan:9999".to_string())));
+        assert_eq!("http://monitor.lan/beat".parse::<Monitor>().unwrap().to_string(), "http://monitor.lan/beat");
+        assert!("udp://monitor.lan".parse::<Monitor>().is_err());
+        assert!("https://monitor.lan/beat".parse::<Monitor>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_beats() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let udp = heartbeat(Monitor::Udp(receiver.local_addr().unwrap().to_string()));
+        udp.beat(Some(Action::Suspend)).await;
+        let mut buf = [0u8; 512];
+        let len = receiver.recv(&mut buf).await.unwrap();
+        let beat = crate::json::Json::parse(std::str::from_utf8(&buf[..len]).unwrap()).unwrap();
+        assert_eq!(beat.get("state").and_then(|state| state.as_str()), Some("suspend"));
+        assert_eq!(beat.get("uptime").and_then(|uptime| uptime.as_u64()), Some(0));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/beat", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 1024];
+            let len = stream.read(&mut request).await.unwrap();
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+            String::from_utf8_lossy(&request[..len]).into_owned()
+        });
+        let http = heartbeat(Monitor::Http(url));
+        assert_eq!(http.deliver(&http.body(None)).await, Ok(()));
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /beat HTTP/1.1\r\n"));
+        assert!(request.contains("\"state\":\"awake\""));
+    }
+}
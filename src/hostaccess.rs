@@ -0,0 +1,152 @@
+use crate::power::Backend;
+use clap::ValueEnum;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// How a daemon running in a container reaches the host to suspend it
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostAccess {
+    /// Call the host's logind over its system bus socket, mounted into the container
+    Dbus,
+    /// Run the power command in the host's namespaces with `nsenter -t 1`
+    Nsenter,
+}
+
+/// Where power commands go once the prerequisites of a `HostAccess` mode are checked
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Host {
+    /// The host's system bus socket
+    Bus(PathBuf),
+    Nsenter,
+}
+
+/// `program`, run in the host's namespaces with nsenter if needed
+pub fn command(host: Option<&Host>, program: &str) -> Command {
+    match host {
+        Some(Host::Nsenter) => {
+            let mut cmd = Command::new("nsenter");
+            cmd.args(["-t", "1", "-m", "-u", "-i", "-n", "-p", "--", program]);
+            cmd
+        }
+        _ => Command::new(program),
+    }
+}
+
+/// busctl's option selecting the system bus
+pub fn bus_option(host: Option<&Host>) -> String {
+    match host {
+        Some(Host::Bus(path)) => format!("--address=unix:path={}", path.display()),
+        _ => "--system".to_string(),
+    }
+}
+
+/// The kind of container the daemon runs in, if any, judged from the marker files under
+/// `root` and the `container` variable systemd-nspawn, podman and LXC set
+pub fn detect(root: &Path, variable: Option<String>) -> Option<String> {
+    if let Some(kind) = variable.filter(|kind| !kind.is_empty()) {
+        return Some(kind);
+    }
+    if root.join("run/.containerenv").exists() {
+        return Some("podman".to_string());
+    }
+    if root.join(".dockerenv").exists() {
+        return Some("docker".to_string());
+    }
+    let cgroup = std::fs::read_to_string(root.join("proc/1/cgroup")).unwrap_or_default();
+    ["kubepods", "docker", "lxc"].iter().find(|kind| cgroup.contains(*kind)).map(|kind| kind.to_string())
+}
+
+/// Check what `mode` needs and return where power commands go. Without a mode, only warn
+/// when the daemon seems to be in a container, where the power action would fail.
+pub async fn prepare(mode: Option<HostAccess>, bus: &Path, backend: Backend) -> Result<Option<Host>, String> {
+    let container = detect(Path::new("/"), std::env::var("container").ok());
+    let Some(mode) = mode else {
+        if let Some(kind) = container
+            && backend != Backend::Sysfs
+        {
+            warn!("Running in a {} container; suspending the host needs --host-access", kind);
+        }
+        return Ok(None);
+    };
+    if container.is_none() {
+        warn!("--host-access is set, but this does not look like a container");
+    }
+    match mode {
+        HostAccess::Dbus => {
+            if backend != Backend::Logind {
+                return Err("--host-access dbus needs --backend logind".to_string());
+            }
+            let is_socket = std::fs::metadata(bus).is_ok_and(|metadata| metadata.file_type().is_socket());
+            if !is_socket {
+                return Err(format!(
+                    "{} is not a socket; mount the host's /run/dbus/system_bus_socket there",
+                    bus.display()
+                ));
+            }
+            let host = Host::Bus(bus.to_path_buf());
+            let output = Command::new("busctl")
+                .args([&bus_option(Some(&host)), "call", "org.freedesktop.DBus", "/org/freedesktop/DBus"])
+                .args(["org.freedesktop.DBus", "NameHasOwner", "s", "org.freedesktop.login1"])
+                .output()
+                .await
+                .map_err(|e| format!("Cannot run busctl: {}", e))?;
+            if String::from_utf8_lossy(&output.stdout).trim() != "b true" {
+                let error = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("logind is not reachable through {}: {}", bus.display(), error.trim()));
+            }
+            Ok(Some(host))
+        }
+        HostAccess::Nsenter => {
+            if backend == Backend::Sysfs {
+                return Err("--backend sysfs writes /sys/power directly and does not need --host-access".to_string());
+            }
+            let init = std::fs::read_to_string("/proc/1/comm").unwrap_or_default();
+            if std::process::id() == 1 || !matches!(init.trim(), "systemd" | "init") {
+                return Err(format!(
+                    "PID 1 is {}, not the host's init; run the container in the host's PID namespace (--pid=host)",
+                    init.trim()
+                ));
+            }
+            let status = command(Some(&Host::Nsenter), "true")
+                .status()
+                .await
+                .map_err(|e| format!("Cannot run nsenter: {}", e))?;
+            if !status.success() {
+                return Err("nsenter into the host failed; the container needs --privileged".to_string());
+            }
+            Ok(Some(Host::Nsenter))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let root = std::env::temp_dir().join(format!("sol-hostaccess-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("proc/1")).unwrap();
+        assert_eq!(detect(&root, None), None);
+        assert_eq!(detect(&root, Some("systemd-nspawn".to_string())).as_deref(), Some("systemd-nspawn"));
+        std::fs::write(root.join("proc/1/cgroup"), "0::/kubepods/besteffort/pod1\n").unwrap();
+        assert_eq!(detect(&root, Some(String::new())).as_deref(), Some("kubepods"));
+        std::fs::write(root.join(".dockerenv"), "").unwrap();
+        assert_eq!(detect(&root, None).as_deref(), Some("docker"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_commands() {
+        let cmd = command(Some(&Host::Nsenter), "systemctl");
+        let args: Vec<_> = cmd.as_std().get_args().collect();
+        assert_eq!(cmd.as_std().get_program(), "nsenter");
+        assert_eq!(args.last().unwrap().to_str(), Some("systemctl"));
+        assert_eq!(command(None, "systemctl").as_std().get_program(), "systemctl");
+
+        let bus = Host::Bus(PathBuf::from("/host/run/dbus/system_bus_socket"));
+        assert_eq!(bus_option(Some(&bus)), "--address=unix:path=/host/run/dbus/system_bus_socket");
+        assert_eq!(bus_option(Some(&Host::Nsenter)), "--system");
+    }
+}
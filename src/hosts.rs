@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::process::Command;
+
+/// How long one forward lookup may take
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Addresses of the hostnames used in `from=` rule matchers, kept fresh in the background so
+/// DHCP clients stay matched when their address changes
+#[derive(Clone, Debug, Default)]
+pub struct Hosts {
+    resolved: Arc<RwLock<HashMap<String, Vec<IpAddr>>>>,
+}
+
+impl Hosts {
+    /// Whether `ip` is one of the addresses `name` last resolved to
+    pub fn contains(&self, name: &str, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.resolved.read().unwrap().get(name).is_some_and(|addrs| addrs.contains(&ip))
+    }
+
+    /// The addresses `name` last resolved to
+    pub fn addresses(&self, name: &str) -> Vec<IpAddr> {
+        self.resolved.read().unwrap().get(name).cloned().unwrap_or_default()
+    }
+
+    /// Look up every name once. A failed lookup keeps the addresses from the last success,
+    /// so a flaky resolver does not lock out a known sender.
+    pub async fn resolve(&self, names: &[String]) {
+        for name in names {
+            match tokio::time::timeout(LOOKUP_TIMEOUT, lookup(name)).await.ok().flatten() {
+                Some(addrs) => {
+                    let previous = self.resolved.write().unwrap().insert(name.clone(), addrs.clone());
+                    if previous.as_ref() != Some(&addrs) {
+                        let list: Vec<String> = addrs.iter().map(IpAddr::to_string).collect();
+                        verbose!("{} resolves to {}", name, list.join(", "));
+                    }
+                }
+                None => verbose!("Failed to resolve {}", name),
+            }
+        }
+    }
+
+    /// Resolve `names` again every `interval`
+    pub fn refresh(&self, names: Vec<String>, interval: Duration) {
+        let hosts = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                hosts.resolve(&names).await;
+            }
+        });
+    }
+}
+
+/// Forward lookup through NSS, so /etc/hosts, DNS and mDNS all apply
+async fn lookup(name: &str) -> Option<Vec<IpAddr>> {
+    let output = Command::new("getent").args(["ahosts", name]).kill_on_drop(true).output().await.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(parse_ahosts(&String::from_utf8_lossy(&output.stdout))).filter(|addrs| !addrs.is_empty())
+}
+
+/// `getent ahosts` prints each address once per socket type
+fn parse_ahosts(output: &str) -> Vec<IpAddr> {
+    let mut addrs = Vec::new();
+    for addr in output.lines().filter_map(|line| line.split_whitespace().next()?.parse::<IpAddr>().ok()) {
+        let addr = addr.to_canonical();
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    addrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ahosts() {
+        let output = "192.168.1.42    STREAM my-phone.lan\n192.168.1.42    DGRAM  \n192.168.1.42    RAW    \n\
+                      fe80::1c2d      STREAM \n::ffff:10.0.0.9 STREAM \n";
+        let addrs: Vec<IpAddr> = ["192.168.1.42", "fe80::1c2d", "10.0.0.9"].iter().map(|a| a.parse().unwrap()).collect();
+        assert_eq!(parse_ahosts(output), addrs);
+
+        let hosts = Hosts::default();
+        hosts.resolved.write().unwrap().insert("my-phone.lan".to_string(), addrs);
+        assert!(hosts.contains("my-phone.lan", "::ffff:192.168.1.42".parse().unwrap()));
+        assert!(!hosts.contains("my-phone.lan", "192.168.1.43".parse().unwrap()));
+        assert!(!hosts.contains("other.lan", "192.168.1.42".parse().unwrap()));
+    }
+}
@@ -0,0 +1,365 @@
+use crate::control::{Ask, Control, Refusal, Trigger};
+use crate::events::{Query, Record};
+use crate::mac::MacAddr;
+use crate::neighbors;
+use crate::peers;
+use crate::relay::{DEFAULT_RELAY_PORT, RelayEntry};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Longest request head read; the body is ignored
+const MAX_HEAD: usize = 8192;
+
+/// How long a client may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves `sleep`, `wol`, `wake`, `snooze`, `neighbors`, `events` and `status` resources to HTTP clients
+pub struct Http {
+    pub control: Control,
+    /// What `POST /sleep` triggers for
+    pub mac: MacAddr,
+    /// `USER:PASSWORD` that requests must present with HTTP Basic authentication
+    pub auth: Option<String>,
+}
+
+impl Http {
+    /// Whether the request head carries the configured credentials, or none are configured
+    fn authorized(&self, head: &str) -> bool {
+        let Some(auth) = &self.auth else {
+            return true;
+        };
+        let expected = format!("Basic {}", base64(auth.as_bytes()));
+        head.lines().skip(1).any(|line| match line.split_once(':') {
+            Some((name, value)) if name.trim().eq_ignore_ascii_case("authorization") => {
+                let value = value.trim().as_bytes();
+                // Compare in constant time so the response time does not leak the password
+                value.len() == expected.len() && value.iter().zip(expected.as_bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+            }
+            _ => false,
+        })
+    }
+
+    /// Hand the daemon a trigger from `peer`, which reached us on `local`
+    async fn trigger(&self, ask: Ask, peer: SocketAddr, local: SocketAddr, mac: MacAddr) -> Result<String, Refusal> {
+        self.control.trigger(Trigger { ask, via: "HTTP", peer, local, mac }).await
+    }
+
+    /// The status code and plain text body for a request from `peer` that reached us on `local`
+    async fn respond(&self, method: &str, target: &str, peer: SocketAddr, local: SocketAddr) -> (u16, String) {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        match (method, segments.as_slice()) {
+            ("GET", ["status"]) => (200, self.control.respond("status").await),
+            ("GET", ["neighbors"]) => (200, neighbors::format_table(&neighbors::table().await)),
+            ("GET", ["events"]) => {
+                // Addresses and MACs arrive with their colons escaped
+                let query = query.replace("%3A", ":").replace("%3a", ":");
+                match Query::parse(query.split('&').filter(|pair| !pair.is_empty()), crate::protocol::unix_now()) {
+                    Ok(query) => {
+                        let records: Vec<String> = self.control.history.query(&query).iter().map(Record::json).collect();
+                        (200, format!("[{}]", records.join(",")))
+                    }
+                    Err(e) => (400, e),
+                }
+            }
+            ("POST", ["sleep"]) => match self.trigger(Ask::Sleep, peer, local, self.mac).await {
+                Ok(verdict) => (200, verdict),
+                Err(Refusal::Forbidden(reason)) => (403, reason),
+                Err(Refusal::Conflict(reason)) => (409, reason),
+            },
+            ("POST", ["wol", mac]) => {
+                let target = match wol_target(mac, query) {
+                    Ok(target) => target,
+                    Err(e) => return (400, e),
+                };
+                if let Err(e) = self.trigger(Ask::Admit, peer, local, target.mac).await {
+                    return (403, e.to_string());
+                }
+                match peers::send_wol(&target) {
+                    Ok(destination) => (200, format!("magic packet for {} sent to {}", target.mac, destination)),
+                    Err(e) => (500, e),
+                }
+            }
+            ("POST", ["wake", host]) => {
+                // A known peer by name, or else a hostname or address from the neighbor table
+                let target = match self.control.peers.get(host) {
+                    Some(peer) => peer.target,
+                    None => match neighbors::resolve(host).await {
+                        Ok((_, mac)) => peers::broadcast_target(mac),
+                        Err(e) => return (404, e),
+                    },
+                };
+                if let Err(e) = self.trigger(Ask::Admit, peer, local, target.mac).await {
+                    return (403, e.to_string());
+                }
+                match peers::send_wol(&target) {
+                    Ok(destination) => (200, format!("magic packet for {} sent to {}", target.mac, destination)),
+                    Err(e) => (500, e),
+                }
+            }
+            ("POST", ["snooze", length]) => {
+                if let Err(e) = self.trigger(Ask::Admit, peer, local, self.mac).await {
+                    return (403, e.to_string());
+                }
+                let reply = self.control.handle(&format!("snooze {}", length));
+                (if reply.starts_with("error:") { 400 } else { 200 }, reply)
+            }
+            (_, ["status" | "sleep" | "neighbors" | "events"] | ["wol" | "wake" | "snooze", _]) => (405, String::new()),
+            _ => (404, String::new()),
+        }
+    }
+}
+
+/// Where `POST /wol/MAC` sends its packet: the limited broadcast address on port 9 unless
+/// the query names a `broadcast` address (or interface) and `port`
+fn wol_target(mac: &str, query: &str) -> Result<RelayEntry, String> {
+    let (mut broadcast, mut port) = (Ipv4Addr::BROADCAST.to_string(), DEFAULT_RELAY_PORT.to_string());
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        match pair.split_once('=') {
+            Some(("broadcast", value)) => broadcast = value.to_string(),
+            Some(("port", value)) => port = value.to_string(),
+            _ => return Err(format!("unknown parameter '{}'", pair)),
+        }
+    }
+    format!("{}@{}:{}", mac.replace("%3A", ":").replace("%3a", ":"), broadcast, port).parse()
+}
+
+/// Standard base64 with padding, as used by HTTP Basic authentication
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Read one request, answer it and close the connection
+async fn serve(mut stream: TcpStream, peer: SocketAddr, http: &Http) -> std::io::Result<()> {
+    let local = stream.local_addr()?;
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    let read = async {
+        while !head.windows(4).any(|window| window == b"\r\n\r\n") && head.len() < MAX_HEAD {
+            let len = stream.read(&mut buf).await?;
+            if len == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..len]);
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    tokio::time::timeout(READ_TIMEOUT, read)
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "request timed out"))??;
+
+    let head = String::from_utf8_lossy(&head);
+    let request_line = head.lines().next().unwrap_or_default();
+    let (status, body, json) = match request_line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [method, target, version] if version.starts_with("HTTP/1.") => {
+            let (status, body) = if http.authorized(&head) {
+                http.respond(method, target, peer, local).await
+            } else {
+                (401, "authentication required".to_string())
+            };
+            info!("HTTP {} {} from {}: {}", method, target, peer, status);
+            // The event history is the one resource answered in JSON
+            (status, body, status == 200 && target.split('?').next() == Some("/events"))
+        }
+        _ => (400, "malformed request".to_string(), false),
+    };
+    let body = if body.is_empty() { String::new() } else { format!("{}\n", body) };
+    let challenge = if status == 401 { "WWW-Authenticate: Basic realm=\"sol\"\r\n" } else { "" };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\n{}Content-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        challenge,
+        if json { "application/json" } else { "text/plain; charset=utf-8" },
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Bind `addr` and serve HTTP requests in the background
+pub async fn listen(addr: SocketAddr, http: Http) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let http = Arc::new(http);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let http = Arc::clone(&http);
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, peer, &http).await {
+                            verbose!("HTTP connection from {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => error!("Failed to accept HTTP connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bans::Bans;
+    use crate::inhibit::Inhibitors;
+    use crate::maintenance::Maintenance;
+    use crate::pause::Paused;
+    use crate::peers::{Peers, wol_packet};
+    use crate::policy::{Policy, Profiles};
+    use tokio::net::UdpSocket;
+
+    fn http() -> Http {
+        let policy = Policy {
+            quorum: 1,
+            quorum_window: 10,
+            confirm: false,
+            grace: 0,
+            warn_users: false,
+            inhibit_share_clients: false,
+            inhibit_ports: Vec::new(),
+            ignore_desktop_inhibitors: true,
+            inhibit_session_activity: false,
+            min_idle: None,
+        };
+        let control = Control::new(
+            Maintenance::new(std::env::temp_dir().join(format!("sol-http-test-{}", std::process::id()))),
+            Paused::default(),
+            Arc::new(Inhibitors::default()),
+            Arc::new(Profiles::new(policy)),
+            Arc::new(Peers::default()),
+            Arc::new(Bans::new(None, None)),
+        );
+        Http { control, mac: MacAddr::ZERO, auth: None }
+    }
+
+    /// Stand in for the receive loop, answering every trigger with `verdict`
+    fn answer_triggers(http: &mut Http, verdict: Result<String, Refusal>) {
+        let (tx, mut triggers) = tokio::sync::mpsc::channel::<(Trigger, crate::control::Verdict)>(1);
+        http.control.triggers = Some(tx);
+        tokio::spawn(async move {
+            while let Some((_, answer)) = triggers.recv().await {
+                let _ = answer.send(verdict.clone());
+            }
+        });
+    }
+
+    #[test]
+    fn test_wol_target() {
+        let target = wol_target("aa:bb:cc:dd:ee:ff", "").unwrap();
+        assert_eq!(target.destination(), Ok(SocketAddr::from(([255, 255, 255, 255], 9))));
+        let target = wol_target("aa%3Abb%3Acc%3Add%3Aee%3Aff", "broadcast=192.168.20.255&port=7").unwrap();
+        assert_eq!(target.destination(), Ok(SocketAddr::from(([192, 168, 20, 255], 7))));
+        assert!(wol_target("aa:bb:cc:dd:ee", "").is_err());
+        assert!(wol_target("aa:bb:cc:dd:ee:ff", "port=x").is_err());
+        assert!(wol_target("aa:bb:cc:dd:ee:ff", "ttl=4").is_err());
+    }
+
+    #[test]
+    fn test_authorized() {
+        assert_eq!(base64(b"admin:s3cret"), "YWRtaW46czNjcmV0");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+        let mut server = http();
+        assert!(server.authorized("GET /status HTTP/1.1\r\n"));
+        server.auth = Some("admin:s3cret".to_string());
+        assert!(server.authorized("GET /status HTTP/1.1\r\nauthorization: Basic YWRtaW46czNjcmV0\r\n"));
+        assert!(!server.authorized("GET /status HTTP/1.1\r\nAuthorization: Basic YWRtaW46d3Jvbmc=\r\n"));
+        assert!(!server.authorized("GET /status HTTP/1.1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_responses() {
+        let peer: SocketAddr = "192.168.1.5:40000".parse().unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let local: SocketAddr = "192.168.1.2:8080".parse().unwrap();
+        let mut server = http();
+        answer_triggers(&mut server, Ok("admitted".to_string()));
+
+        let (status, body) = server.respond("POST", &format!("/wol/aa:bb:cc:dd:ee:ff?broadcast=127.0.0.1&port={}", port), peer, local).await;
+        assert_eq!((status, body), (200, format!("magic packet for aa:bb:cc:dd:ee:ff sent to 127.0.0.1:{}", port)));
+        let mut buf = [0u8; 128];
+        let len = receiver.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], wol_packet(&MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF])).as_slice());
+
+        assert_eq!(server.respond("POST", "/wol/nas", peer, local).await.0, 400);
+        assert_eq!(server.respond("GET", "/wol/aa:bb:cc:dd:ee:ff", peer, local).await.0, 405);
+        assert!(server.respond("GET", "/status", peer, local).await.1.contains("maintenance: off"));
+        assert_eq!(server.respond("GET", "/reboot", peer, local).await.0, 404);
+        server.control.history.record("rejected", "sender=192.168.1.77:40000 reason=Invalid packet: bad header");
+        server.control.history.record("action-started", "suspend");
+        let (status, body) = server.respond("GET", "/events?outcome=rejected&sender=192.168.1.77&last=5", peer, local).await;
+        assert_eq!(status, 200);
+        assert!(body.starts_with(r#"[{"time":"#), "{}", body);
+        assert!(body.ends_with(r#""event":"rejected","detail":"sender=192.168.1.77:40000 reason=Invalid packet: bad header"}]"#), "{}", body);
+        assert_eq!(server.respond("GET", "/events?since=soon", peer, local).await.0, 400);
+        assert_eq!(server.respond("POST", "/events", peer, local).await.0, 405);
+        assert_eq!(server.respond("POST", "/snooze/45m", peer, local).await, (200, "snoozed: 45 minute(s) left".to_string()));
+        assert_eq!(server.respond("POST", "/snooze/never", peer, local).await.0, 400);
+        assert_eq!(server.respond("GET", "/snooze/off", peer, local).await.0, 405);
+        assert_eq!(server.respond("GET", "/wake/nas", peer, local).await.0, 405);
+        let (status, body) = server.respond("POST", "/wake/127.0.0.1", peer, local).await;
+        assert_eq!(status, 404);
+        assert!(body.starts_with("no neighbor table entry for 127.0.0.1"), "{}", body);
+
+        let mut ignoring = http();
+        answer_triggers(&mut ignoring, Err(Refusal::Forbidden("ignored by rule".to_string())));
+        assert_eq!(ignoring.respond("POST", "/sleep", peer, local).await, (403, "ignored by rule".to_string()));
+        assert_eq!(ignoring.respond("POST", "/wol/aa:bb:cc:dd:ee:ff", peer, local).await.0, 403);
+        assert_eq!(ignoring.respond("POST", "/snooze/45m", peer, local).await.0, 403);
+
+        let mut inhibited = http();
+        answer_triggers(&mut inhibited, Err(Refusal::Conflict("inhibited (snooze)".to_string())));
+        assert_eq!(inhibited.respond("POST", "/sleep", peer, local).await, (409, "inhibited (snooze)".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+            serve(stream, peer, &http()).await.unwrap();
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET /nothing HTTP/1.1\r\nHost: nas\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        server.await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("Content-Length: 0\r\nConnection: close\r\n\r\n"));
+    }
+}
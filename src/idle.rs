@@ -0,0 +1,98 @@
+use crate::desktop::{self, property};
+use crate::inhibit::{self, COMMAND_TIMEOUT, Inhibitor};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Blocks suspend until every local (non-remote) session has been idle for at least `min_idle`
+pub struct UserIdle {
+    pub min_idle: Duration,
+}
+
+impl Inhibitor for UserIdle {
+    fn name(&self) -> &str {
+        "user-idle"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?;
+
+        for session in desktop::session_ids()? {
+            let properties = desktop::loginctl(&[
+                "show-session", &session,
+                "-p", "Name", "-p", "Remote", "-p", "Type", "-p", "Display",
+                "-p", "IdleHint", "-p", "IdleSinceHint",
+            ])?;
+
+            if property(&properties, "Remote") == Some("yes") {
+                continue;
+            }
+            let user = property(&properties, "Name").unwrap_or("unknown");
+
+            let mut idle = logind_idle(&properties, now);
+            if property(&properties, "Type") == Some("x11")
+                && let Some(display) = property(&properties, "Display")
+                && let Some(x11_idle) = xprintidle(user, display)
+            {
+                idle = x11_idle;
+            }
+
+            if idle < self.min_idle {
+                return Ok(Some(format!(
+                    "{} was active {}s ago (need {}s idle)",
+                    user,
+                    idle.as_secs(),
+                    self.min_idle.as_secs()
+                )));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Idle time according to logind's IdleHint and IdleSinceHint (realtime microseconds)
+fn logind_idle(properties: &str, now: Duration) -> Duration {
+    if property(properties, "IdleHint") != Some("yes") {
+        return Duration::ZERO;
+    }
+
+    property(properties, "IdleSinceHint")
+        .and_then(|since| since.parse().ok())
+        .map(|since| now.saturating_sub(Duration::from_micros(since)))
+        .unwrap_or(Duration::ZERO)
+}
+
+/// X11 idle time from the XScreenSaver extension, via xprintidle
+fn xprintidle(user: &str, display: &str) -> Option<Duration> {
+    let mut cmd = Command::new("runuser");
+    cmd.args(["-u", user, "--", "env"]).arg(format!("DISPLAY={}", display)).arg("xprintidle");
+    let output = inhibit::output(&mut cmd, COMMAND_TIMEOUT).ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logind_idle() {
+        let now = Duration::from_secs(1_700_000_600);
+        let idle = "IdleHint=yes\nIdleSinceHint=1700000000000000\n";
+        let active = "IdleHint=no\nIdleSinceHint=0\n";
+
+        assert_eq!(logind_idle(idle, now), Duration::from_secs(600));
+        assert_eq!(logind_idle(active, now), Duration::ZERO);
+        assert_eq!(logind_idle("IdleHint=yes\n", now), Duration::ZERO);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::policy::Policy;
+use std::io::{self, Read};
+use std::process::{Command, Output, Stdio};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How long a command run by an inhibitor check may take before it is killed
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A condition that vetoes suspend while it holds
+pub trait Inhibitor: Send + Sync {
+    /// Short name used in log messages
+    fn name(&self) -> &str;
+
+    /// Return a reason if suspend should currently be blocked
+    fn check(&self) -> Result<Option<String>, String>;
+}
+
+/// All configured inhibitors, checked in order. Hard ones, such as maintenance mode, are
+/// checked first and hold even against a `force=true` rule.
+#[derive(Default)]
+pub struct Inhibitors {
+    hard: Vec<Box<dyn Inhibitor>>,
+    inhibitors: Vec<Box<dyn Inhibitor>>,
+}
+
+impl Inhibitors {
+    pub fn push(&mut self, inhibitor: Box<dyn Inhibitor>) {
+        self.inhibitors.push(inhibitor);
+    }
+
+    /// Add an inhibitor that a forced trigger cannot override
+    pub fn push_hard(&mut self, inhibitor: Box<dyn Inhibitor>) {
+        self.hard.push(inhibitor);
+    }
+
+    /// Return the first active inhibitor's reason. Inhibitors that fail to run are
+    /// logged and skipped so a missing tool does not block suspend forever.
+    pub fn active(&self) -> Option<String> {
+        first(self.hard.iter().chain(&self.inhibitors))
+    }
+
+    /// Like `active`, but only the hard inhibitors
+    pub fn active_hard(&self) -> Option<String> {
+        first(self.hard.iter())
+    }
+}
+
+fn first<'a>(inhibitors: impl Iterator<Item = &'a Box<dyn Inhibitor>>) -> Option<String> {
+    for inhibitor in inhibitors {
+        match inhibitor.check() {
+            Ok(Some(reason)) => return Some(format!("{}: {}", inhibitor.name(), reason)),
+            Ok(None) => {}
+            Err(e) => warn!("Inhibitor {} failed: {}", inhibitor.name(), e),
+        }
+    }
+    None
+}
+
+/// The first active inhibitor among `always` and then those `policy` enables; with `force`,
+/// only the hard ones. The checks run commands and open connections, so they run on the
+/// blocking pool rather than the caller's task.
+pub async fn first_active(always: Arc<Inhibitors>, policy: Policy, force: bool) -> Option<String> {
+    tokio::task::spawn_blocking(move || match force {
+        true => always.active_hard(),
+        false => always.active().or_else(|| policy.inhibitors().active()),
+    })
+    .await
+    .unwrap_or_default()
+}
+
+/// Run `cmd` to completion and collect its output like `Command::output`, but kill it once it
+/// has run for `timeout`. A hung tool then fails its check instead of holding up every later one.
+pub fn output(cmd: &mut Command, timeout: Duration) -> io::Result<Output> {
+    let mut child = cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let (stdout, stderr) = (drain(child.stdout.take()), drain(child.stderr.take()));
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out after {}s and was killed", timeout.as_secs()),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    Ok(Output { status, stdout: stdout.join().unwrap_or_default(), stderr: stderr.join().unwrap_or_default() })
+}
+
+/// Read a child's pipe to the end on its own thread, so a chatty command can't fill it and stall
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str, Result<Option<String>, String>);
+
+    impl Inhibitor for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn check(&self) -> Result<Option<String>, String> {
+            self.1.clone()
+        }
+    }
+
+    #[test]
+    fn test_no_inhibitors() {
+        assert_eq!(Inhibitors::default().active(), None);
+    }
+
+    #[test]
+    fn test_first_active_reason_wins() {
+        let mut inhibitors = Inhibitors::default();
+        inhibitors.push(Box::new(Fixed("broken", Err("not installed".to_string()))));
+        inhibitors.push(Box::new(Fixed("idle", Ok(None))));
+        inhibitors.push(Box::new(Fixed("busy", Ok(Some("2 clients".to_string())))));
+        inhibitors.push(Box::new(Fixed("later", Ok(Some("ignored".to_string())))));
+
+        assert_eq!(inhibitors.active(), Some("busy: 2 clients".to_string()));
+    }
+
+    #[test]
+    fn test_hard_inhibitors() {
+        let mut inhibitors = Inhibitors::default();
+        inhibitors.push(Box::new(Fixed("busy", Ok(Some("2 clients".to_string())))));
+        assert_eq!(inhibitors.active_hard(), None);
+        inhibitors.push_hard(Box::new(Fixed("maintenance", Ok(Some("enabled".to_string())))));
+
+        assert_eq!(inhibitors.active(), Some("maintenance: enabled".to_string()));
+        assert_eq!(inhibitors.active_hard(), Some("maintenance: enabled".to_string()));
+    }
+
+    #[test]
+    fn test_output_timeout() {
+        let done = output(Command::new("sh").args(["-c", "echo out; echo err >&2"]), COMMAND_TIMEOUT).unwrap();
+        assert!(done.status.success());
+        assert_eq!((&done.stdout[..], &done.stderr[..]), (&b"out\n"[..], &b"err\n"[..]));
+
+        let started = Instant::now();
+        let e = output(Command::new("sleep").arg("5"), Duration::from_millis(100)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(output(&mut Command::new("sol-no-such-tool"), COMMAND_TIMEOUT).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::mac::MacAddr;
+use crate::netif::{self, IpNetwork, NetworkInterface};
+use std::net::IpAddr;
+use std::process::Command;
+
+/// What `sol list-interfaces` reports about one interface
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceInfo {
+    pub name: String,
+    pub up: bool,
+    /// The address currently in use, which may have been changed from the hardware one
+    pub mac: Option<MacAddr>,
+    /// The address burned into the NIC, as reported by `ethtool -P`
+    pub permanent: Option<MacAddr>,
+    pub monitored: bool,
+}
+
+/// Whether packets for this interface's MAC are accepted as triggers
+pub fn monitored(iface: &NetworkInterface) -> bool {
+    iface.mac.is_some()
+}
+
+/// Whether `peer` is on the subnet of the interface holding `bound`, or of any interface
+/// when `bound` is unspecified
+pub fn on_link(interfaces: &[NetworkInterface], bound: IpAddr, peer: IpAddr) -> bool {
+    let peer = peer.to_canonical();
+    interfaces
+        .iter()
+        .filter(|iface| bound.is_unspecified() || iface.ips.iter().any(|net| net.ip() == bound))
+        .flat_map(|iface| &iface.ips)
+        .any(|net| net.contains(peer))
+}
+
+/// Whether `destination` is a broadcast or multicast address: the limited broadcast address,
+/// the broadcast address of a local IPv4 subnet, or any multicast group
+pub fn is_broadcast(interfaces: &[NetworkInterface], destination: IpAddr) -> bool {
+    match destination.to_canonical() {
+        IpAddr::V4(ip) => {
+            ip.is_broadcast()
+                || ip.is_multicast()
+                || interfaces.iter().flat_map(|iface| &iface.ips).any(|net| match net {
+                    // /31 and /32 subnets have no broadcast address
+                    IpNetwork::V4(v4) => net.prefix() < 31 && v4.broadcast() == ip,
+                    IpNetwork::V6(_) => false,
+                })
+        }
+        IpAddr::V6(ip) => ip.is_multicast(),
+    }
+}
+
+/// Check that `mac` belongs to the interface with `index`. Loopback passes, as the daemon's
+/// own HTTP, CoAP and console triggers arrive there.
+pub fn owns(interfaces: &[NetworkInterface], index: u32, mac: &MacAddr) -> Result<(), String> {
+    match interfaces.iter().find(|iface| iface.index == index) {
+        Some(iface) if iface.is_loopback() || iface.mac == Some(*mac) => Ok(()),
+        Some(iface) => Err(format!("MAC address {} is not that of receiving interface {}", mac, iface.name)),
+        None => Err(format!("receiving interface {} is gone", index)),
+    }
+}
+
+/// Parse `ethtool -P IFACE`; virtual devices report an all-zero address
+fn parse_permanent(output: &str) -> Option<MacAddr> {
+    let (_, mac) = output.trim().split_once("Permanent address:")?;
+    let mac: MacAddr = mac.trim().parse().ok()?;
+    (mac != MacAddr::ZERO).then_some(mac)
+}
+
+fn permanent_mac(name: &str) -> Option<MacAddr> {
+    let output = Command::new("ethtool").args(["-P", name]).output().ok()?;
+    parse_permanent(&String::from_utf8_lossy(&output.stdout))
+}
+
+pub fn list() -> Vec<InterfaceInfo> {
+    netif::interfaces()
+        .iter()
+        .map(|iface| InterfaceInfo {
+            name: iface.name.clone(),
+            up: iface.is_up(),
+            mac: iface.mac,
+            permanent: if iface.is_loopback() { None } else { permanent_mac(&iface.name) },
+            monitored: monitored(iface),
+        })
+        .collect()
+}
+
+fn mac_or_dash(mac: Option<MacAddr>) -> String {
+    mac.map_or_else(|| "-".to_string(), |mac| mac.to_string())
+}
+
+fn yes_no(b: bool) -> &'static str {
+    if b { "yes" } else { "no" }
+}
+
+pub fn table(interfaces: &[InterfaceInfo]) -> String {
+    let width = interfaces.iter().map(|i| i.name.len()).max().unwrap_or(0).max("INTERFACE".len());
+    let mut lines = vec![format!("{:width$}  {:17}  {:17}  {:4}  MONITORED", "INTERFACE", "MAC", "PERMANENT", "UP")];
+    for iface in interfaces {
+        lines.push(format!(
+            "{:width$}  {:17}  {:17}  {:4}  {}",
+            iface.name,
+            mac_or_dash(iface.mac),
+            mac_or_dash(iface.permanent),
+            yes_no(iface.up),
+            yes_no(iface.monitored)
+        ));
+    }
+    lines.join("\n")
+}
+
+fn json_mac(mac: Option<MacAddr>) -> String {
+    mac.map_or_else(|| "null".to_string(), |mac| crate::json::quote(&mac.to_string()))
+}
+
+pub fn json(interfaces: &[InterfaceInfo]) -> String {
+    let entries: Vec<String> = interfaces
+        .iter()
+        .map(|iface| {
+            format!(
+                "{{\"name\":{},\"up\":{},\"mac\":{},\"permanent_mac\":{},\"monitored\":{}}}",
+                crate::json::quote(&iface.name),
+                iface.up,
+                json_mac(iface.mac),
+                json_mac(iface.permanent),
+                iface.monitored
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eth0() -> InterfaceInfo {
+        InterfaceInfo {
+            name: "eth0".to_string(),
+            up: true,
+            mac: Some(MacAddr([0x02, 0, 0, 0, 0, 1])),
+            permanent: Some(MacAddr([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff])),
+            monitored: true,
+        }
+    }
+
+    #[test]
+    fn test_on_link() {
+        let iface = |name: &str, ips: &[&str]| NetworkInterface {
+            name: name.to_string(),
+            index: 0,
+            mac: None,
+            ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            flags: 0,
+        };
+        let interfaces = [iface("lo", &["127.0.0.1/8"]), iface("eth0", &["192.168.1.10/24", "fe80::1/64"])];
+        let any: IpAddr = "0.0.0.0".parse().unwrap();
+        let eth0: IpAddr = "192.168.1.10".parse().unwrap();
+
+        assert!(on_link(&interfaces, any, "192.168.1.77".parse().unwrap()));
+        assert!(on_link(&interfaces, any, "::ffff:192.168.1.77".parse().unwrap()));
+        assert!(on_link(&interfaces, any, "fe80::2".parse().unwrap()));
+        assert!(!on_link(&interfaces, any, "10.0.0.5".parse().unwrap()));
+        assert!(on_link(&interfaces, eth0, "192.168.1.77".parse().unwrap()));
+        assert!(!on_link(&interfaces, eth0, "127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_is_broadcast() {
+        let eth0 = NetworkInterface {
+            name: "eth0".to_string(),
+            index: 2,
+            mac: None,
+            ips: vec!["192.168.1.10/24".parse().unwrap(), "10.0.0.1/32".parse().unwrap()],
+            flags: 0,
+        };
+        let interfaces = [eth0];
+        assert!(is_broadcast(&interfaces, "255.255.255.255".parse().unwrap()));
+        assert!(is_broadcast(&interfaces, "192.168.1.255".parse().unwrap()));
+        assert!(is_broadcast(&interfaces, "239.255.255.250".parse().unwrap()));
+        assert!(is_broadcast(&interfaces, "ff02::1".parse().unwrap()));
+        assert!(!is_broadcast(&interfaces, "192.168.1.10".parse().unwrap()));
+        assert!(!is_broadcast(&interfaces, "10.0.0.1".parse().unwrap()));
+        assert!(!is_broadcast(&interfaces, "192.168.2.255".parse().unwrap()));
+        assert!(!is_broadcast(&interfaces, "fe80::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_owns() {
+        let iface = |name: &str, index: u32, mac: Option<MacAddr>, flags: u32| NetworkInterface {
+            name: name.to_string(),
+            index,
+            mac,
+            ips: Vec::new(),
+            flags,
+        };
+        let eth0 = MacAddr([0x02, 0, 0, 0, 0, 1]);
+        let eth1 = MacAddr([0x02, 0, 0, 0, 0, 2]);
+        let interfaces = [
+            iface("lo", 1, Some(MacAddr::ZERO), libc::IFF_LOOPBACK as u32),
+            iface("eth0", 2, Some(eth0), 0),
+            iface("eth1", 3, Some(eth1), 0),
+        ];
+        assert_eq!(owns(&interfaces, 2, &eth0), Ok(()));
+        assert_eq!(owns(&interfaces, 1, &eth1), Ok(()));
+        assert_eq!(
+            owns(&interfaces, 3, &eth0),
+            Err("MAC address 02:00:00:00:00:01 is not that of receiving interface eth1".to_string())
+        );
+        assert!(owns(&interfaces, 9, &eth0).is_err());
+    }
+
+    #[test]
+    fn test_parse_permanent() {
+        assert_eq!(
+            parse_permanent("Permanent address: aa:bb:cc:dd:ee:ff\n"),
+            Some(MacAddr([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]))
+        );
+        assert_eq!(parse_permanent("Permanent address: 00:00:00:00:00:00\n"), None);
+        assert_eq!(parse_permanent("Cannot read permanent address\n"), None);
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let lo = InterfaceInfo { name: "lo\"".to_string(), mac: None, permanent: None, ..eth0() };
+        assert_eq!(
+            json(&[eth0(), lo]),
+            "[{\"name\":\"eth0\",\"up\":true,\"mac\":\"02:00:00:00:00:01\",\"permanent_mac\":\"aa:bb:cc:dd:ee:ff\",\"monitored\":true},\
+             {\"name\":\"lo\\\"\",\"up\":true,\"mac\":null,\"permanent_mac\":null,\"monitored\":true}]"
+        );
+        assert_eq!(
+            table(&[eth0()]).lines().nth(1).unwrap(),
+            "eth0       02:00:00:00:00:01  aa:bb:cc:dd:ee:ff  yes   yes"
+        );
+    }
+}
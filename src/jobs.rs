@@ -0,0 +1,228 @@
+use crate::clock::LocalTime;
+use crate::inhibit::{self, COMMAND_TIMEOUT, Inhibitor};
+use crate::json::Json;
+use crate::schedule::Cron;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// System crontabs, whose lines name the user before the command
+const SYSTEM_CRONTABS: [&str; 2] = ["/etc/crontab", "/etc/cron.d"];
+/// Per-user crontabs: Debian keeps them in `crontabs`, Red Hat directly in the spool
+pub const CRON_SPOOL: &str = "/var/spool/cron";
+/// The RTC alarm, as Unix seconds; 0 clears it
+pub const WAKEALARM: &str = "/sys/class/rtc/rtc0/wakealarm";
+/// How far ahead cron jobs are looked for when setting a wake alarm
+const WAKE_HORIZON: Duration = Duration::from_secs(8 * 24 * 3600);
+
+/// A timer or cron job and the next time it runs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Job {
+    /// The timer unit, or the cron command
+    pub name: String,
+    /// Unix seconds
+    pub next: u64,
+}
+
+/// Timers with a next elapse in the JSON output of `systemctl list-timers --output=json`
+fn parse_timers(output: &str) -> Result<Vec<Job>, String> {
+    let Json::Array(timers) = Json::parse(output)? else {
+        return Err("not a list of timers".to_string());
+    };
+    Ok(timers
+        .iter()
+        .filter_map(|timer| {
+            let next = timer.get("next")?.as_u64().filter(|usec| *usec > 0)?;
+            Some(Job { name: timer.get("unit")?.as_str()?.to_string(), next: next / 1_000_000 })
+        })
+        .collect())
+}
+
+fn timers() -> Result<Vec<Job>, String> {
+    let mut cmd = std::process::Command::new("systemctl");
+    cmd.args(["list-timers", "--all", "--output=json", "--no-pager"]);
+    let output = inhibit::output(&mut cmd, COMMAND_TIMEOUT)
+        .map_err(|e| format!("systemctl: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("systemctl list-timers failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    parse_timers(&String::from_utf8_lossy(&output.stdout)).map_err(|e| format!("Unexpected output from systemctl list-timers: {}", e))
+}
+
+/// The schedule and command of each job in a crontab; `system` crontabs have a user field.
+/// Environment lines, `@reboot` and lines the cron parser does not accept are skipped.
+fn parse_crontab(text: &str, system: bool) -> Vec<(Cron, String)> {
+    let mut jobs = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (schedule, rest) = match split_fields(line, 1) {
+            Some((macro_, rest)) if macro_[0].starts_with('@') => match macro_[0] {
+                "@hourly" => ("0 * * * *".to_string(), rest),
+                "@daily" | "@midnight" => ("0 0 * * *".to_string(), rest),
+                "@weekly" => ("0 0 * * 0".to_string(), rest),
+                "@monthly" => ("0 0 1 * *".to_string(), rest),
+                "@yearly" | "@annually" => ("0 0 1 1 *".to_string(), rest),
+                _ => continue,
+            },
+            _ => match split_fields(line, 5) {
+                Some((fields, rest)) => (fields.join(" "), rest),
+                None => continue,
+            },
+        };
+        let command = if system { split_fields(rest, 1).map_or("", |(_, command)| command) } else { rest };
+        match schedule.parse::<Cron>() {
+            Ok(cron) if !command.is_empty() => jobs.push((cron, command.to_string())),
+            Ok(_) => {}
+            Err(e) => verbose!("Skipping crontab line '{}': {}", line, e),
+        }
+    }
+    jobs
+}
+
+/// The first `n` whitespace-separated fields of `line` and the rest of it, trimmed
+fn split_fields(line: &str, n: usize) -> Option<(Vec<&str>, &str)> {
+    let mut rest = line.trim();
+    let mut fields = Vec::with_capacity(n);
+    for _ in 0..n {
+        let (field, tail) = rest.split_once(char::is_whitespace)?;
+        fields.push(field);
+        rest = tail.trim_start();
+    }
+    Some((fields, rest.trim_end()))
+}
+
+/// Every crontab line from the system crontabs and the cron spool
+fn cron_jobs() -> Vec<(Cron, String)> {
+    let files = |path: &Path| -> Vec<std::path::PathBuf> {
+        match std::fs::read_dir(path) {
+            Ok(entries) => entries.flatten().map(|entry| entry.path()).filter(|path| path.is_file()).collect(),
+            Err(_) => vec![path.to_path_buf()],
+        }
+    };
+    let system = SYSTEM_CRONTABS.iter().flat_map(|path| files(Path::new(path))).map(|path| (path, true));
+    let spool = Path::new(CRON_SPOOL);
+    let users = files(spool).into_iter().chain(files(&spool.join("crontabs"))).map(|path| (path, false));
+    system
+        .chain(users)
+        .filter_map(|(path, system)| Some(parse_crontab(&std::fs::read_to_string(path).ok()?, system)))
+        .flatten()
+        .collect()
+}
+
+/// The first minute after `from` and no later than `until` (Unix seconds) that `cron` matches
+fn next_run(cron: &Cron, from: u64, until: u64) -> Option<u64> {
+    (from / 60 + 1..=until / 60).map(|minute| minute * 60).find(|secs| cron.matches(&LocalTime::from_unix(*secs as libc::time_t)))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Systemd timers, and optionally cron jobs, that should not be slept through
+#[derive(Clone, Debug)]
+pub struct ScheduledJobs {
+    /// How long before a job suspend is refused, and the wake alarm goes off
+    pub window: Duration,
+    pub cron: bool,
+    /// Timer units (with or without `.timer`) or words of cron commands to watch; all when empty
+    pub names: Vec<String>,
+}
+
+impl ScheduledJobs {
+    fn watched(&self, job: &str) -> bool {
+        self.names.is_empty()
+            || self.names.iter().any(|name| {
+                job == name
+                    || job.strip_suffix(".timer") == Some(name)
+                    || (self.cron && job.split_whitespace().any(|word| word == name || word.ends_with(&format!("/{}", name))))
+            })
+    }
+
+    /// The first watched job due within `horizon` from now
+    pub fn next(&self, horizon: Duration) -> Result<Option<Job>, String> {
+        let now = now();
+        let until = now + horizon.as_secs();
+        let mut jobs: Vec<Job> = timers()?.into_iter().filter(|job| job.next > now && job.next <= until).collect();
+        if self.cron {
+            // Watched first, since finding the next run goes minute by minute
+            let watched = cron_jobs().into_iter().filter(|(_, command)| self.watched(command));
+            jobs.extend(watched.filter_map(|(cron, command)| Some(Job { next: next_run(&cron, now, until)?, name: command })));
+        }
+        Ok(jobs.into_iter().filter(|job| self.watched(&job.name)).min_by_key(|job| job.next))
+    }
+
+    /// Set the RTC alarm to go off `window` before the next watched job, returning the job
+    /// and the time the alarm was set for
+    pub fn set_wake_alarm(&self) -> Result<Option<(Job, u64)>, String> {
+        let Some(job) = self.next(WAKE_HORIZON)? else {
+            return Ok(None);
+        };
+        // A forced suspend skips the inhibitor, so the job may be closer than the window
+        let at = job.next.saturating_sub(self.window.as_secs()).max(now() + 60);
+        // The alarm must be cleared before a new one is accepted
+        write_alarm(0)?;
+        write_alarm(at)?;
+        Ok(Some((job, at)))
+    }
+}
+
+/// Write `at` (Unix seconds, or 0 to clear) to the RTC alarm
+fn write_alarm(at: u64) -> Result<(), String> {
+    std::fs::write(WAKEALARM, at.to_string()).map_err(|e| format!("{}: {}", WAKEALARM, e))
+}
+
+impl Inhibitor for ScheduledJobs {
+    fn name(&self) -> &str {
+        "scheduled-jobs"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        Ok(self.next(self.window)?.map(|job| {
+            let minutes = job.next.saturating_sub(now()).div_ceil(60);
+            format!("{} runs in {} minute(s)", job.name, minutes)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timers() {
+        let output = r#"[{"next":1760652000000000,"left":1200000000,"last":null,"passed":null,"unit":"backup.timer","activates":"backup.service"},
+            {"next":null,"left":null,"last":1760000000000000,"passed":5,"unit":"once.timer","activates":"once.service"}]"#;
+        assert_eq!(parse_timers(output).unwrap(), [Job { name: "backup.timer".to_string(), next: 1760652000 }]);
+        assert!(parse_timers("{}").is_err());
+    }
+
+    #[test]
+    fn test_parse_crontab() {
+        let system = "SHELL=/bin/sh\n# m h dom mon dow user command\n17 * * * * root cd / && run-parts --report /etc/cron.hourly\n@daily root /usr/local/bin/backup\n@reboot root /bin/true\n";
+        let jobs: Vec<String> = parse_crontab(system, true).into_iter().map(|(_, command)| command).collect();
+        assert_eq!(jobs, ["cd / && run-parts --report /etc/cron.hourly", "/usr/local/bin/backup"]);
+
+        let user = parse_crontab("30 2 * * 1-5 restic backup /home\n0 0 * * mon names-unsupported\n", false);
+        assert_eq!(user.len(), 1);
+        assert_eq!(user[0].1, "restic backup /home");
+    }
+
+    #[test]
+    fn test_next_run() {
+        let cron: Cron = "* * * * *".parse().unwrap();
+        assert_eq!(next_run(&cron, 1_000_000_030, 1_000_000_200), Some(1_000_000_080));
+        assert_eq!(next_run(&cron, 1_000_000_030, 1_000_000_070), None);
+    }
+
+    #[test]
+    fn test_watched() {
+        let jobs = ScheduledJobs { window: Duration::from_secs(600), cron: true, names: vec!["backup".to_string()] };
+        assert!(jobs.watched("backup.timer"));
+        assert!(jobs.watched("/usr/local/bin/backup --full"));
+        assert!(!jobs.watched("fstrim.timer"));
+        assert!(!jobs.watched("backup-check.timer"));
+        let all = ScheduledJobs { names: Vec::new(), ..jobs };
+        assert!(all.watched("fstrim.timer"));
+    }
+}
@@ -0,0 +1,202 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+/// Every filesystem right of the first Landlock ABI: the above plus removing and creating entries
+const ACCESS_ALL: u64 = (1 << 13) - 1;
+/// The rights that apply to a file rather than a directory
+const ACCESS_FILE: u64 = ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: libc::c_int,
+}
+
+/// What is allowed beneath a path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Execute,
+    Write,
+}
+
+impl Access {
+    fn rights(&self) -> u64 {
+        match self {
+            Access::Read => ACCESS_READ_FILE | ACCESS_READ_DIR,
+            Access::Execute => ACCESS_EXECUTE | ACCESS_READ_FILE | ACCESS_READ_DIR,
+            Access::Write => ACCESS_ALL,
+        }
+    }
+}
+
+/// Files the daemon itself uses, on top of the system paths its helpers need
+#[derive(Clone, Debug, Default)]
+pub struct Paths {
+    pub config: Option<PathBuf>,
+    pub state: Vec<PathBuf>,
+    /// Flag and lock files checked by inhibitors
+    pub watched: Vec<PathBuf>,
+    pub extra: Vec<PathBuf>,
+}
+
+/// Build the allowed paths. Helpers the daemon runs (systemctl, loginctl, container CLIs,
+/// pre-suspend commands, ...) inherit the restriction, so the system binaries and libraries
+/// stay executable and /run, /dev and /tmp writable; everything else, notably /home, /root
+/// and the rest of /var, becomes inaccessible.
+pub fn rules(paths: &Paths) -> Vec<(PathBuf, Access)> {
+    let mut rules: Vec<(PathBuf, Access)> = ["/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64"]
+        .iter()
+        .map(|p| (PathBuf::from(p), Access::Execute))
+        .collect();
+    rules.extend(["/etc", "/proc", "/sys"].iter().map(|p| (PathBuf::from(p), Access::Read)));
+    rules.extend(["/run", "/dev", "/tmp", "/sys/power"].iter().map(|p| (PathBuf::from(p), Access::Write)));
+    // The directory rather than the file, since a rule follows the inode and reloading must
+    // still work after the file is replaced
+    if let Some(dir) = paths.config.as_ref().and_then(|config| config.parent()) {
+        rules.push((dir.to_path_buf(), Access::Read));
+    }
+    // The parent as well, since a flag file may not exist yet
+    for path in &paths.watched {
+        rules.extend(path.parent().map(|dir| (dir.to_path_buf(), Access::Read)));
+        rules.push((path.clone(), Access::Read));
+    }
+    for path in paths.state.iter().chain(&paths.extra) {
+        rules.push((path.clone(), Access::Write));
+    }
+    rules
+}
+
+/// Restrict the calling thread, and any thread or process it later creates, to the paths of
+/// `rules`. Returns Ok(false) when the kernel has no Landlock support. Cannot be undone.
+pub fn apply(rules: &[(PathBuf, Access)]) -> Result<bool, String> {
+    // SAFETY: querying the ABI version takes no pointer
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 1 {
+        return Ok(false);
+    }
+
+    let attr = RulesetAttr { handled_access_fs: ACCESS_ALL };
+    // SAFETY: attr is a valid ruleset_attr of the size passed
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(format!("creating ruleset failed: {}", std::io::Error::last_os_error()));
+    }
+    let ruleset = ruleset as libc::c_int;
+
+    let result = add_rules(ruleset, rules).and_then(|()| restrict(ruleset));
+    // SAFETY: ruleset is an fd we own
+    unsafe { libc::close(ruleset) };
+    result.map(|()| true)
+}
+
+fn add_rules(ruleset: libc::c_int, rules: &[(PathBuf, Access)]) -> Result<(), String> {
+    for (path, access) in rules {
+        // Paths that do not exist (no /lib32, no state directory yet) are skipped
+        let Ok(metadata) = std::fs::metadata(path) else { continue };
+        let mut allowed = access.rights();
+        if !metadata.is_dir() {
+            allowed &= ACCESS_FILE;
+        }
+
+        let fd = open_path(path)?;
+        let attr = PathBeneathAttr { allowed_access: allowed, parent_fd: fd };
+        // SAFETY: attr is a valid path_beneath_attr holding an open O_PATH fd
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset,
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0,
+            )
+        };
+        let error = std::io::Error::last_os_error();
+        // SAFETY: fd was opened above
+        unsafe { libc::close(fd) };
+        if rc != 0 {
+            return Err(format!("allowing {} failed: {}", path.display(), error));
+        }
+    }
+    Ok(())
+}
+
+fn open_path(path: &Path) -> Result<libc::c_int, String> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+    // SAFETY: c_path is a valid NUL-terminated string
+    let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(format!("opening {} failed: {}", path.display(), std::io::Error::last_os_error()));
+    }
+    Ok(fd)
+}
+
+fn restrict(ruleset: libc::c_int) -> Result<(), String> {
+    // SAFETY: prctl and landlock_restrict_self take no pointers
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(format!("setting no_new_privs failed: {}", std::io::Error::last_os_error()));
+        }
+        if libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) != 0 {
+            return Err(format!("restricting failed: {}", std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_include_daemon_paths() {
+        let paths = Paths {
+            config: Some(PathBuf::from("/srv/sol/sol.toml")),
+            state: vec![PathBuf::from("/var/lib/sol")],
+            watched: vec![PathBuf::from("/srv/backup/lock.exclusive")],
+            extra: vec![PathBuf::from("/srv/scripts")],
+        };
+        let rules = rules(&paths);
+        assert!(rules.contains(&(PathBuf::from("/usr"), Access::Execute)));
+        assert!(rules.contains(&(PathBuf::from("/srv/sol"), Access::Read)));
+        assert!(rules.contains(&(PathBuf::from("/var/lib/sol"), Access::Write)));
+        assert!(rules.contains(&(PathBuf::from("/srv/scripts"), Access::Write)));
+        assert!(rules.contains(&(PathBuf::from("/srv/backup"), Access::Read)));
+        assert!(!rules.iter().any(|(path, _)| path.starts_with("/home") || path.starts_with("/root")));
+    }
+
+    #[test]
+    fn test_access_rights() {
+        assert_eq!(Access::Read.rights() & ACCESS_EXECUTE, 0);
+        assert_ne!(Access::Execute.rights() & ACCESS_EXECUTE, 0);
+        assert_eq!(Access::Write.rights(), ACCESS_ALL);
+        assert_eq!(Access::Write.rights() & ACCESS_FILE, ACCESS_FILE);
+    }
+}
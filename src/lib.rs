@@ -0,0 +1,12 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+/// Magic-packet layout shared by the daemon and senders, with no dependencies beyond `core`
+pub mod packet;
+/// SHA-256 and HMAC-SHA256 for signing v2 packets
+pub mod sha256;
+/// Versioned packet framing carrying a signed JSON command
+pub mod v2;
+/// The notification types shared by the daemon's push backends, and the registry they are
+/// built from
+#[cfg(feature = "std")]
+pub mod notifier;
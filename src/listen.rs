@@ -0,0 +1,581 @@
+use crate::caps;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::{AsRawFd, RawFd};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+/// Largest trigger datagram read; longer ones are truncated and fail validation
+const MAX_PACKET: usize = 1024;
+
+/// Bind a UDP socket to `addr`; with `freebind`, the address need not be configured yet
+fn bind_socket(addr: SocketAddr, freebind: bool) -> std::io::Result<std::net::UdpSocket> {
+    if !freebind {
+        return std::net::UdpSocket::bind(addr);
+    }
+    let family = if addr.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 };
+    // SAFETY: socket has no memory arguments; the descriptor is owned by the UdpSocket below
+    let fd = unsafe { libc::socket(family, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: `fd` is a fresh socket that nothing else owns, so dropping the UdpSocket closes it
+    let socket = unsafe { <std::net::UdpSocket as std::os::fd::FromRawFd>::from_raw_fd(fd) };
+    // IP_FREEBIND covers IPv6 sockets as well
+    set_flag(fd, libc::IPPROTO_IP, libc::IP_FREEBIND)?;
+    // SAFETY: all-zero is a valid value for these plain C structs, and bind only reads `len` bytes
+    let rc = unsafe {
+        match addr {
+            SocketAddr::V4(addr) => {
+                let mut sin: libc::sockaddr_in = std::mem::zeroed();
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+                let len = std::mem::size_of_val(&sin) as libc::socklen_t;
+                libc::bind(fd, &sin as *const _ as *const libc::sockaddr, len)
+            }
+            SocketAddr::V6(addr) => {
+                let mut sin6: libc::sockaddr_in6 = std::mem::zeroed();
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_scope_id = addr.scope_id();
+                let len = std::mem::size_of_val(&sin6) as libc::socklen_t;
+                libc::bind(fd, &sin6 as *const _ as *const libc::sockaddr, len)
+            }
+        }
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+fn bind_one(addr: SocketAddr, freebind: bool) -> Result<std::net::UdpSocket, String> {
+    // SAFETY: geteuid cannot fail
+    if addr.port() < 1024 && unsafe { libc::geteuid() } != 0 && caps::has(caps::CAP_NET_BIND_SERVICE) {
+        info!("Binding port {} using CAP_NET_BIND_SERVICE", addr.port());
+    }
+    let socket = bind_socket(addr, freebind).map_err(|e| match e.kind() {
+        ErrorKind::PermissionDenied => format!(
+            "Cannot bind UDP {}: {}. Ports below 1024 need root or CAP_NET_BIND_SERVICE \
+             (AmbientCapabilities=CAP_NET_BIND_SERVICE in the unit, or setcap cap_net_bind_service=+ep on the binary)",
+            addr, e
+        ),
+        ErrorKind::AddrNotAvailable => {
+            format!("Cannot bind UDP {}: {}. Use --freebind for an address that is configured later", addr, e)
+        }
+        _ => format!("Cannot bind UDP {}: {}", addr, e),
+    })?;
+    socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+    enable_info(&socket).map_err(|e| format!("Cannot enable packet info on UDP {}: {}", addr, e))?;
+    Ok(socket)
+}
+
+fn set_flag(fd: RawFd, level: libc::c_int, name: libc::c_int) -> std::io::Result<()> {
+    let one: libc::c_int = 1;
+    // SAFETY: setsockopt only reads the option value
+    let rc = unsafe {
+        libc::setsockopt(fd, level, name, &one as *const _ as *const libc::c_void, std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if rc != 0 { Err(std::io::Error::last_os_error()) } else { Ok(()) }
+}
+
+/// Ask the kernel to report the interface and destination address of every datagram, and
+/// how many datagrams the socket has dropped so far. An IPv6 socket gets both packet info
+/// options, as IPv4-mapped senders are reported as IPv4.
+fn enable_info(socket: &std::net::UdpSocket) -> std::io::Result<()> {
+    let fd = socket.as_raw_fd();
+    if socket.local_addr()?.is_ipv6() {
+        set_flag(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO)?;
+    }
+    set_flag(fd, libc::IPPROTO_IP, libc::IP_PKTINFO)?;
+    set_flag(fd, libc::SOL_SOCKET, libc::SO_RXQ_OVFL)
+}
+
+/// Bind every port on every address. Done before the runtime starts, so that capabilities
+/// can be dropped right after.
+pub fn bind(addrs: &[IpAddr], ports: &[u16], freebind: bool) -> Result<Vec<std::net::UdpSocket>, String> {
+    let mut sockets = Vec::new();
+    for addr in addrs {
+        for port in ports {
+            sockets.push(bind_one(SocketAddr::new(*addr, *port), freebind)?);
+        }
+    }
+    Ok(sockets)
+}
+
+/// Receive buffer of `socket` in bytes, as the kernel accounts it. A size set with
+/// SO_RCVBUF is doubled to leave room for the kernel's own bookkeeping.
+pub fn recv_buffer(socket: &impl AsRawFd) -> std::io::Result<usize> {
+    let mut size: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: getsockopt writes at most `len` bytes into `size`
+    let rc = unsafe {
+        libc::getsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_RCVBUF, &mut size as *mut _ as *mut libc::c_void, &mut len)
+    };
+    if rc != 0 { Err(std::io::Error::last_os_error()) } else { Ok(size as usize) }
+}
+
+fn set_buffer(fd: RawFd, name: libc::c_int, bytes: libc::c_int) -> std::io::Result<()> {
+    // SAFETY: setsockopt only reads the option value
+    let rc = unsafe {
+        libc::setsockopt(fd, libc::SOL_SOCKET, name, &bytes as *const _ as *const libc::c_void, std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if rc != 0 { Err(std::io::Error::last_os_error()) } else { Ok(()) }
+}
+
+/// Ask for a receive buffer of `bytes` on every socket, and return the smallest the kernel
+/// granted, in the same terms. The kernel caps SO_RCVBUF at net.core.rmem_max, so when that
+/// falls short SO_RCVBUFFORCE is tried as well, which needs CAP_NET_ADMIN.
+pub fn set_recv_buffer(sockets: &[std::net::UdpSocket], bytes: usize) -> Result<usize, String> {
+    let requested = libc::c_int::try_from(bytes).map_err(|_| format!("Receive buffer of {} bytes is too large", bytes))?;
+    let mut granted = bytes;
+    for socket in sockets {
+        let fd = socket.as_raw_fd();
+        let error = |e: std::io::Error| format!("Cannot set the receive buffer on {:?}: {}", socket.local_addr(), e);
+        set_buffer(fd, libc::SO_RCVBUF, requested).map_err(error)?;
+        if recv_buffer(socket).map_err(error)? / 2 < bytes {
+            // Without CAP_NET_ADMIN this fails, and the capped size stands
+            let _ = set_buffer(fd, libc::SO_RCVBUFFORCE, requested);
+        }
+        granted = granted.min(recv_buffer(socket).map_err(error)? / 2);
+    }
+    Ok(granted)
+}
+
+/// First file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Datagram sockets passed by systemd socket activation (`LISTEN_PID` and `LISTEN_FDS`),
+/// or none when the daemon was started normally
+pub fn activated() -> Result<Vec<std::net::UdpSocket>, String> {
+    let for_us = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(std::process::id());
+    let count = match std::env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse::<i32>().ok()) {
+        Some(count) if for_us => count,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut sockets = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        let mut kind: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: getsockopt writes at most `len` bytes into `kind`
+        let rc = unsafe {
+            libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE, &mut kind as *mut _ as *mut libc::c_void, &mut len)
+        };
+        if rc != 0 || kind != libc::SOCK_DGRAM {
+            return Err(format!("socket activation passed fd {}, which is not a UDP socket", fd));
+        }
+        // SAFETY: systemd hands these descriptors to this process, which owns them from here on
+        let socket = unsafe { <std::net::UdpSocket as std::os::fd::FromRawFd>::from_raw_fd(fd) };
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        if let Err(e) = enable_info(&socket) {
+            warn!("Cannot enable packet info on fd {}, receiving interfaces will not be known: {}", fd, e);
+        }
+        sockets.push(socket);
+    }
+    Ok(sockets)
+}
+
+/// Shortest datagram any trigger format can be: a v2 header and a one-byte body
+const MIN_TRIGGER: u32 = sol::v2::HEADER_SIZE as u32 + 1;
+
+/// UDP header size; socket filters on UDP sockets see the datagram from its UDP header on
+const UDP_HEADER: u32 = 8;
+
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter { code: code as u16, jt: 0, jf: 0, k }
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code: code as u16, jt, jf, k }
+}
+
+/// Socket filter used during a flood: drop datagrams in the kernel unless they are long
+/// enough and start like a WoL, cancel, test or v2 packet
+fn strict_filter() -> Vec<libc::sock_filter> {
+    let prefixes = [
+        u32::from_be_bytes([0xFF; 4]),
+        u32::from_be_bytes([0x00; 4]),
+        u32::from_be_bytes([crate::selftest::PROBE_PACKET_HEADER[0]; 4]),
+        u32::from_be_bytes([crate::group::PREPARE_PACKET_HEADER[0]; 4]),
+        u32::from_be_bytes(sol::v2::PREFIX),
+    ];
+    let mut filter = vec![
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_LEN, 0),
+        jump(libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K, UDP_HEADER + MIN_TRIGGER, 0, prefixes.len() as u8 + 1),
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, UDP_HEADER),
+    ];
+    for (i, prefix) in prefixes.iter().enumerate() {
+        // On a match, skip the remaining checks and the drop to land on the accept
+        let skip = (prefixes.len() - i) as u8;
+        filter.push(jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, *prefix, skip, 0));
+    }
+    filter.push(stmt(libc::BPF_RET | libc::BPF_K, 0));
+    filter.push(stmt(libc::BPF_RET | libc::BPF_K, u32::MAX));
+    filter
+}
+
+/// Attach the strict filter to `socket`, or detach it
+fn set_strict(socket: &UdpSocket, strict: bool) -> std::io::Result<()> {
+    let fd = socket.as_raw_fd();
+    // SAFETY: setsockopt only reads the option value, and `filter` outlives the call
+    let rc = unsafe {
+        if strict {
+            let filter = strict_filter();
+            let prog = libc::sock_fprog { len: filter.len() as u16, filter: filter.as_ptr() as *mut libc::sock_filter };
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &prog as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+            )
+        } else {
+            let zero: libc::c_int = 0;
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_DETACH_FILTER,
+                &zero as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        }
+    };
+    if rc != 0 {
+        let e = std::io::Error::last_os_error();
+        // Detaching when nothing is attached is fine
+        if strict || e.raw_os_error() != Some(libc::ENOENT) {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// The interface a datagram arrived on and the address it was sent to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Arrival {
+    pub index: u32,
+    pub interface: String,
+    /// The packet's destination, such as a broadcast address or one of ours
+    pub destination: IpAddr,
+}
+
+impl Arrival {
+    fn new(index: u32, destination: IpAddr) -> Self {
+        let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+        // SAFETY: if_indextoname writes at most IF_NAMESIZE bytes, including the NUL
+        let interface = if unsafe { libc::if_indextoname(index, name.as_mut_ptr()) }.is_null() {
+            format!("if{}", index)
+        } else {
+            // SAFETY: on success the buffer holds a NUL-terminated name
+            unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) }.to_string_lossy().into_owned()
+        };
+        Arrival { index, interface, destination: destination.to_canonical() }
+    }
+}
+
+impl std::fmt::Display for Arrival {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.interface, self.destination)
+    }
+}
+
+/// A datagram and the index of the listener it arrived on
+pub struct Received {
+    pub listener: usize,
+    pub packet: Vec<u8>,
+    pub peer: SocketAddr,
+    /// Unknown when the kernel did not attach packet info
+    pub arrival: Option<Arrival>,
+}
+
+/// The socket address in `addr`, as filled in by recvmsg
+fn socket_addr(addr: &libc::sockaddr_storage) -> std::io::Result<SocketAddr> {
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET => {
+            // SAFETY: the family says the storage holds a sockaddr_in
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Ok(SocketAddr::new(IpAddr::V4(ip), u16::from_be(addr.sin_port)))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: the family says the storage holds a sockaddr_in6
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            Ok(SocketAddr::new(IpAddr::V6(ip), u16::from_be(addr.sin6_port)))
+        }
+        family => Err(std::io::Error::other(format!("unexpected address family {}", family))),
+    }
+}
+
+/// What recvmsg returned: the datagram's length and sender, where it arrived, and the
+/// socket's drop counter at the time
+type Info = (usize, SocketAddr, Option<Arrival>, Option<u32>);
+
+/// Receive one datagram with recvmsg, along with the packet info the kernel attached
+fn recv_with_info(fd: RawFd, buf: &mut [u8]) -> std::io::Result<Info> {
+    // SAFETY: all-zero is a valid value for these plain C structs
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() };
+    // u64 elements keep the control buffer aligned for cmsghdr
+    let mut control = [0u64; 16];
+    // SAFETY: as above
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+    // SAFETY: every pointer in `msg` refers to a live buffer of the length given with it
+    let len = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if len < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let peer = socket_addr(&addr)?;
+
+    let (mut arrival, mut drops) = (None, None);
+    // SAFETY: the CMSG macros walk the control buffer recvmsg filled, within msg_controllen
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                    let info = std::ptr::read_unaligned(data as *const libc::in_pktinfo);
+                    let destination = Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr));
+                    arrival = Some(Arrival::new(info.ipi_ifindex as u32, IpAddr::V4(destination)));
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                    let info = std::ptr::read_unaligned(data as *const libc::in6_pktinfo);
+                    arrival = Some(Arrival::new(info.ipi6_ifindex, IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr))));
+                }
+                (libc::SOL_SOCKET, libc::SO_RXQ_OVFL) => drops = Some(std::ptr::read_unaligned(data as *const u32)),
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((len as usize, peer, arrival, drops))
+}
+
+/// Drop counters of the UDP sockets in this network namespace, by socket inode
+fn proc_drops() -> HashMap<u64, u32> {
+    let mut drops = HashMap::new();
+    for path in ["/proc/net/udp", "/proc/net/udp6"] {
+        drops.extend(parse_proc_drops(&std::fs::read_to_string(path).unwrap_or_default()));
+    }
+    drops
+}
+
+/// Lines end in `... uid timeout inode ref pointer drops`
+fn parse_proc_drops(text: &str) -> impl Iterator<Item = (u64, u32)> + '_ {
+    text.lines().skip(1).filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        Some((fields.get(9)?.parse().ok()?, fields.last()?.parse().ok()?))
+    })
+}
+
+fn inode(fd: RawFd) -> Option<u64> {
+    // SAFETY: all-zero is a valid stat, and fstat writes at most one
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    // SAFETY: as above
+    (unsafe { libc::fstat(fd, &mut stat) } == 0).then_some(stat.st_ino as u64)
+}
+
+/// The trigger sockets, read concurrently and merged into one stream of packets
+pub struct Listeners {
+    sockets: Vec<Arc<UdpSocket>>,
+    received: mpsc::Receiver<Received>,
+    /// The drop counter each socket last reported with a datagram
+    drops: Vec<Arc<AtomicU32>>,
+}
+
+impl Listeners {
+    pub fn new(sockets: Vec<std::net::UdpSocket>) -> std::io::Result<Self> {
+        let sockets = sockets
+            .into_iter()
+            .map(|socket| UdpSocket::from_std(socket).map(Arc::new))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let (tx, received) = mpsc::channel(64);
+        let drops: Vec<Arc<AtomicU32>> = sockets.iter().map(|_| Arc::default()).collect();
+        for (listener, socket) in sockets.iter().enumerate() {
+            let (socket, tx, dropped) = (socket.clone(), tx.clone(), drops[listener].clone());
+            tokio::spawn(async move {
+                let mut buf = [0u8; MAX_PACKET];
+                loop {
+                    let fd = socket.as_raw_fd();
+                    match socket.async_io(Interest::READABLE, || recv_with_info(fd, &mut buf)).await {
+                        Ok((len, peer, arrival, drops)) => {
+                            if let Some(drops) = drops {
+                                dropped.store(drops, Ordering::Relaxed);
+                            }
+                            let packet = buf[..len].to_vec();
+                            if tx.send(Received { listener, packet, peer, arrival }).await.is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) => {
+                            error!("Receiving on {:?} failed: {}", socket.local_addr(), e);
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+        Ok(Listeners { sockets, received, drops })
+    }
+
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.sockets.iter().filter_map(|socket| socket.local_addr().ok()).collect()
+    }
+
+    /// The next packet from any listener, or None once every listener has failed
+    pub async fn recv(&mut self) -> Option<Received> {
+        self.received.recv().await
+    }
+
+    pub fn socket(&self, listener: usize) -> &UdpSocket {
+        &self.sockets[listener]
+    }
+
+    /// Datagrams the kernel dropped on all listeners before the daemon read them, usually
+    /// because a receive buffer was full. Taken from `/proc/net/udp`, which is current even
+    /// when nothing has arrived since, or else from the counter sent along with each datagram.
+    pub fn drops(&self) -> u32 {
+        let proc = proc_drops();
+        self.sockets
+            .iter()
+            .zip(&self.drops)
+            .map(|(socket, reported)| {
+                let reported = reported.load(Ordering::Relaxed);
+                inode(socket.as_raw_fd()).and_then(|inode| proc.get(&inode)).map_or(reported, |drops| reported.max(*drops))
+            })
+            .fold(0, u32::wrapping_add)
+    }
+
+    /// The smallest receive buffer among the listeners, as the kernel accounts it
+    pub fn recv_buffer(&self) -> usize {
+        self.sockets.iter().filter_map(|socket| recv_buffer(socket.as_ref()).ok()).min().unwrap_or(0)
+    }
+
+    /// Drop datagrams that cannot be triggers in the kernel, or stop doing so
+    pub fn set_strict(&self, strict: bool) {
+        for socket in &self.sockets {
+            if let Err(e) = set_strict(socket, strict) {
+                error!("Failed to change the socket filter on {:?}: {}", socket.local_addr(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_packets_from_all_listeners() {
+        let loopback = [IpAddr::from([127, 0, 0, 1])];
+        let mut listeners = Listeners::new(bind(&loopback, &[0, 0], false).unwrap()).unwrap();
+        let addresses = listeners.addresses();
+        assert_eq!(addresses.len(), 2);
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.send_to(b"second", addresses[1]).await.unwrap();
+        let received = listeners.recv().await.unwrap();
+        assert_eq!((received.listener, received.packet.as_slice()), (1, &b"second"[..]));
+        let arrival = received.arrival.unwrap();
+        assert_eq!(arrival.destination, IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(arrival.interface, "lo");
+
+        listeners.socket(1).send_to(b"reply", received.peer).await.unwrap();
+        let mut buf = [0u8; 16];
+        let (len, from) = sender.recv_from(&mut buf).await.unwrap();
+        assert_eq!((&buf[..len], from), (&b"reply"[..], addresses[1]));
+    }
+
+    #[tokio::test]
+    async fn test_drops() {
+        let sockets = bind(&[IpAddr::from([127, 0, 0, 1])], &[0], false).unwrap();
+        let small: libc::c_int = 1;
+        // SAFETY: setsockopt only reads the option value
+        unsafe {
+            let size = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            libc::setsockopt(sockets[0].as_raw_fd(), libc::SOL_SOCKET, libc::SO_RCVBUF, &small as *const _ as *const libc::c_void, size);
+        }
+        let address = sockets[0].local_addr().unwrap();
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..64 {
+            sender.send_to(&[0u8; 102], address).unwrap();
+        }
+
+        let mut listeners = Listeners::new(sockets).unwrap();
+        let dropped = listeners.drops();
+        assert!(dropped > 0);
+        // A datagram carries the count from when it was queued, so only one sent now reports the drops
+        'sent: loop {
+            // The queue may still be full, so send again until one gets through
+            sender.send_to(b"after", address).unwrap();
+            while let Ok(received) = tokio::time::timeout(Duration::from_millis(100), listeners.recv()).await {
+                if received.unwrap().packet == b"after" {
+                    break 'sent;
+                }
+            }
+        }
+        assert!(listeners.drops[0].load(Ordering::Relaxed) >= dropped);
+    }
+
+    #[test]
+    fn test_parse_proc_drops() {
+        let udp = "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n\
+                   1042: 00000000:0009 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 48213 2 0000000000000000 17\n";
+        assert_eq!(parse_proc_drops(udp).collect::<Vec<_>>(), [(48213, 17)]);
+    }
+
+    #[test]
+    fn test_freebind() {
+        // TEST-NET-1 is never configured on a real interface
+        let absent = [IpAddr::from([192, 0, 2, 123])];
+        let e = bind(&absent, &[0], false).unwrap_err();
+        assert!(e.ends_with("Use --freebind for an address that is configured later"), "{}", e);
+        let sockets = bind(&absent, &[0], true).unwrap();
+        assert_eq!(sockets[0].local_addr().unwrap().ip(), absent[0]);
+        assert!(bind(&[IpAddr::from([127, 0, 0, 1])], &[0], true).is_ok());
+    }
+
+    #[test]
+    fn test_recv_buffer() {
+        let sockets = bind(&[IpAddr::from([127, 0, 0, 1])], &[0, 0], false).unwrap();
+        assert_eq!(set_recv_buffer(&sockets, 65536), Ok(65536));
+        assert_eq!(recv_buffer(&sockets[1]).unwrap(), 131072);
+        // Past both net.core.rmem_max and what the kernel allows at all
+        assert!(set_recv_buffer(&sockets, 1 << 30).unwrap() < 1 << 30);
+        assert!(set_recv_buffer(&sockets, usize::MAX).unwrap_err().ends_with("is too large"));
+    }
+
+    #[tokio::test]
+    async fn test_strict_filter() {
+        let loopback = [IpAddr::from([127, 0, 0, 1])];
+        let mut listeners = Listeners::new(bind(&loopback, &[0], false).unwrap()).unwrap();
+        let address = listeners.addresses()[0];
+        listeners.set_strict(true);
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let wol = sol::packet::encode(&sol::packet::MAGIC_HEADER, &[1, 2, 3, 4, 5, 6]);
+        sender.send_to(&[0x42; 200], address).await.unwrap();
+        sender.send_to(&wol[..20], address).await.unwrap();
+        sender.send_to(&wol, address).await.unwrap();
+        assert_eq!(listeners.recv().await.unwrap().packet, wol);
+
+        listeners.set_strict(false);
+        sender.send_to(b"junk", address).await.unwrap();
+        assert_eq!(listeners.recv().await.unwrap().packet, b"junk");
+    }
+}
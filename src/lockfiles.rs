@@ -0,0 +1,112 @@
+use crate::inhibit::Inhibitor;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Blocks suspend while a flag file exists or a lock file is held, e.g. a borg repository's
+/// `lock.exclusive` or `/run/unattended-upgrades.lock`
+pub struct LockFiles {
+    /// Paths whose existence blocks; with a trailing `/`, a directory that has entries
+    pub flags: Vec<PathBuf>,
+    /// Files that block while another process holds a `flock` or POSIX lock on them
+    pub locks: Vec<PathBuf>,
+}
+
+impl Inhibitor for LockFiles {
+    fn name(&self) -> &str {
+        "lock-files"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        for path in &self.flags {
+            if flag_present(path)? {
+                return Ok(Some(format!("{} exists", path.display())));
+            }
+        }
+        for path in &self.locks {
+            if lock_held(path)? {
+                return Ok(Some(format!("{} is locked", path.display())));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn flag_present(path: &Path) -> Result<bool, String> {
+    let result = if path.as_os_str().as_encoded_bytes().ends_with(b"/") {
+        std::fs::read_dir(path).map(|mut entries| entries.next().is_some())
+    } else {
+        std::fs::symlink_metadata(path).map(|_| true)
+    };
+    match result {
+        Ok(present) => Ok(present),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+/// Ask for a conflicting POSIX write lock, then try a non-blocking shared `flock`. The shared
+/// lock is only held for an instant, but a holder that polls with `LOCK_NB` could still see it.
+fn lock_held(path: &Path) -> Result<bool, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let fd = file.as_raw_fd();
+    // SAFETY: an all-zero flock is a valid value, and fcntl only writes to it
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    // SAFETY: fd is open and lock is a valid flock
+    if unsafe { libc::fcntl(fd, libc::F_GETLK, &mut lock) } != 0 {
+        return Err(format!("{}: {}", path.display(), std::io::Error::last_os_error()));
+    }
+    if lock.l_type != libc::F_UNLCK as libc::c_short {
+        return Ok(true);
+    }
+    // SAFETY: fd is open; closing the file drops the lock if it was taken
+    if unsafe { libc::flock(fd, libc::LOCK_SH | libc::LOCK_NB) } == 0 {
+        return Ok(false);
+    }
+    let e = std::io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(true),
+        _ => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_and_locks() {
+        let dir = std::env::temp_dir().join(format!("sol-lockfiles-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("locks")).unwrap();
+        let flag = dir.join("lock.exclusive");
+        let lock = dir.join("upgrade.lock");
+        std::fs::write(&lock, "").unwrap();
+        let mut locks_dir = dir.join("locks").into_os_string();
+        locks_dir.push("/");
+        let inhibitor = LockFiles { flags: vec![flag.clone(), locks_dir.into()], locks: vec![lock.clone()] };
+        assert_eq!(inhibitor.check(), Ok(None));
+
+        std::fs::write(dir.join("locks").join("3f2a"), "").unwrap();
+        assert!(inhibitor.check().unwrap().unwrap().ends_with("locks/ exists"));
+        std::fs::remove_file(dir.join("locks").join("3f2a")).unwrap();
+
+        // A lock held by another open file description counts, whichever kind it is
+        let holder = File::open(&lock).unwrap();
+        // SAFETY: holder is open
+        assert_eq!(unsafe { libc::flock(holder.as_raw_fd(), libc::LOCK_EX) }, 0);
+        assert_eq!(inhibitor.check(), Ok(Some(format!("{} is locked", lock.display()))));
+        drop(holder);
+        assert_eq!(inhibitor.check(), Ok(None));
+
+        std::fs::create_dir(&flag).unwrap();
+        assert_eq!(inhibitor.check(), Ok(Some(format!("{} exists", flag.display()))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use crate::clock::LocalTime;
+use clap::ValueEnum;
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// Severity of a log line; lines above the configured level are dropped
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    /// Triggers, actions and startup information, one line per event
+    Info,
+    /// Rejected packets and other routine noise
+    Verbose,
+    Debug,
+}
+
+/// When to color console output
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+/// What the daemon writes to stdout
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    /// Log lines
+    Text,
+    /// One JSON object per event; log lines go to stderr
+    Ndjson,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static COLOR: AtomicBool = AtomicBool::new(false);
+static TIMESTAMPS: AtomicBool = AtomicBool::new(true);
+static STDERR_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Level shown for `-v` given `verbose` times and `-q` given `quiet` times
+pub fn level(verbose: u8, quiet: u8) -> Level {
+    match 2 + verbose as i16 - quiet as i16 {
+        i16::MIN..=0 => Level::Error,
+        1 => Level::Warn,
+        2 => Level::Info,
+        3 => Level::Verbose,
+        _ => Level::Debug,
+    }
+}
+
+pub fn init(level: Level, color: Color, timestamps: bool, output: Output) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    TIMESTAMPS.store(timestamps, Ordering::Relaxed);
+    STDERR_ONLY.store(output == Output::Ndjson, Ordering::Relaxed);
+    let fd = if output == Output::Ndjson { libc::STDERR_FILENO } else { libc::STDOUT_FILENO };
+    let color = match color {
+        Color::Always => true,
+        Color::Never => false,
+        // SAFETY: isatty only inspects the descriptor
+        Color::Auto => std::env::var_os("NO_COLOR").is_none() && unsafe { libc::isatty(fd) } == 1,
+    };
+    COLOR.store(color, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+fn format(level: Level, color: bool, args: fmt::Arguments) -> String {
+    let (prefix, code) = match level {
+        Level::Error => ("", "31"),
+        Level::Warn => ("Warning: ", "33"),
+        Level::Info | Level::Verbose => ("", ""),
+        Level::Debug => ("debug: ", "2"),
+    };
+    if color && !code.is_empty() {
+        format!("\x1b[{}m{}{}\x1b[0m", code, prefix, args)
+    } else {
+        format!("{}{}", prefix, args)
+    }
+}
+
+/// Write one line at `level`; errors and warnings go to stderr, the rest to stdout unless
+/// stdout carries events
+pub fn write(level: Level, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let mut line = format(level, COLOR.load(Ordering::Relaxed), args);
+    if TIMESTAMPS.load(Ordering::Relaxed) {
+        line = format!("{} {}", LocalTime::now(), line);
+    }
+    let _ = match level {
+        Level::Error | Level::Warn => writeln!(std::io::stderr(), "{}", line),
+        _ if STDERR_ONLY.load(Ordering::Relaxed) => writeln!(std::io::stderr(), "{}", line),
+        _ => writeln!(std::io::stdout(), "{}", line),
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+
+macro_rules! verbose {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Verbose, format_args!($($arg)*)) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_from_flags() {
+        assert_eq!(level(0, 0), Level::Info);
+        assert_eq!(level(1, 0), Level::Verbose);
+        assert_eq!(level(5, 0), Level::Debug);
+        assert_eq!(level(0, 1), Level::Warn);
+        assert_eq!(level(0, 3), Level::Error);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(Level::Warn, false, format_args!("x")), "Warning: x");
+        assert_eq!(format(Level::Error, true, format_args!("x")), "\x1b[31mx\x1b[0m");
+        assert_eq!(format(Level::Info, true, format_args!("x")), "x");
+    }
+}
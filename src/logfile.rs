@@ -0,0 +1,147 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the log file is checked for rotation
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A log file that stdout and stderr are redirected to, rotated to `<path>.1`, `<path>.2`, ...
+/// once it grows past `max_size` bytes or gets older than `max_age`
+pub struct LogFile {
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub max_age: Option<Duration>,
+    /// Number of rotated files kept
+    pub keep: usize,
+    started: SystemTime,
+}
+
+impl LogFile {
+    /// Open `path` for appending and point stdout and stderr at it
+    pub fn open(path: PathBuf, max_size: u64, max_age: Option<Duration>, keep: usize) -> Result<Self, String> {
+        let mut log = LogFile { path, max_size, max_age, keep, started: SystemTime::now() };
+        log.redirect()?;
+        Ok(log)
+    }
+
+    fn redirect(&mut self) -> Result<(), String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("opening {} failed: {}", self.path.display(), e))?;
+        self.started = file
+            .metadata()
+            .and_then(|m| m.created())
+            .unwrap_or_else(|_| SystemTime::now());
+
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            // SAFETY: both descriptors are valid; dup2 replaces fd atomically
+            if unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+                return Err(format!("redirecting output failed: {}", std::io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    fn due(&self, size: u64, now: SystemTime) -> bool {
+        size >= self.max_size
+            || self
+                .max_age
+                .is_some_and(|age| now.duration_since(self.started).unwrap_or_default() >= age)
+    }
+
+    /// Rotate if the file is too large or too old. A file removed from under the daemon is
+    /// recreated.
+    pub fn rotate_if_due(&mut self) -> Result<bool, String> {
+        let size = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return self.redirect().map(|()| false),
+        };
+        if !self.due(size, SystemTime::now()) {
+            return Ok(false);
+        }
+        shift(&self.path, self.keep).map_err(|e| format!("rotating {} failed: {}", self.path.display(), e))?;
+        self.redirect()?;
+        Ok(true)
+    }
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), n))
+}
+
+fn ignore_missing(result: std::io::Result<()>) -> std::io::Result<()> {
+    match result {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+/// Move `path` to `path.1`, `path.1` to `path.2` and so on, deleting what falls beyond `keep`
+pub fn shift(path: &Path, keep: usize) -> std::io::Result<()> {
+    if keep == 0 {
+        return ignore_missing(fs::remove_file(path));
+    }
+    ignore_missing(fs::remove_file(numbered(path, keep)))?;
+    for n in (1..keep).rev() {
+        ignore_missing(fs::rename(numbered(path, n), numbered(path, n + 1)))?;
+    }
+    ignore_missing(fs::rename(path, numbered(path, 1)))
+}
+
+/// Check the log file for rotation in the background
+pub fn watch(mut log: LogFile) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            match log.rotate_if_due() {
+                Ok(true) => info!("Log file rotated"),
+                Ok(false) => {}
+                Err(e) => warn!("{}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shift_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("sol-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sol.log");
+        for generation in ["first", "second", "third"] {
+            fs::write(&path, generation).unwrap();
+            shift(&path, 2).unwrap();
+        }
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(numbered(&path, 1)).unwrap(), "third");
+        assert_eq!(fs::read_to_string(numbered(&path, 2)).unwrap(), "second");
+        assert!(!numbered(&path, 3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_due_by_size_and_age() {
+        let now = SystemTime::now();
+        let log = LogFile {
+            path: PathBuf::from("/var/log/sol.log"),
+            max_size: 1000,
+            max_age: Some(Duration::from_secs(3600)),
+            keep: 5,
+            started: now,
+        };
+        assert!(!log.due(999, now));
+        assert!(log.due(1000, now));
+        assert!(log.due(0, now + Duration::from_secs(3600)));
+        assert!(!LogFile { max_age: None, ..log }.due(0, now + Duration::from_secs(86400)));
+    }
+}
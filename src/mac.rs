@@ -0,0 +1,106 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// An Ethernet MAC address, written as `aa:bb:cc:dd:ee:ff`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const ZERO: MacAddr = MacAddr([0; 6]);
+}
+
+impl From<[u8; 6]> for MacAddr {
+    fn from(octets: [u8; 6]) -> Self {
+        MacAddr(octets)
+    }
+}
+
+#[cfg(feature = "pnet")]
+impl From<pnet::util::MacAddr> for MacAddr {
+    fn from(mac: pnet::util::MacAddr) -> Self {
+        MacAddr(mac.octets())
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+/// Accepts `aa:bb:cc:dd:ee:ff`, `aa-bb-cc-dd-ee-ff` and `aabbccddeeff`, in either case
+impl FromStr for MacAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid MAC address '{}'", s);
+        let hex: String = match s.len() {
+            12 => s.to_string(),
+            17 => {
+                let separator = s.as_bytes()[2];
+                if separator != b':' && separator != b'-' {
+                    return Err(invalid());
+                }
+                let groups: Vec<&str> = s.split(separator as char).collect();
+                if groups.len() != 6 || groups.iter().any(|group| group.len() != 2) {
+                    return Err(invalid());
+                }
+                groups.concat()
+            }
+            _ => return Err(invalid()),
+        };
+
+        let mut octets = [0u8; 6];
+        for (i, octet) in octets.iter_mut().enumerate() {
+            let pair = hex.get(i * 2..i * 2 + 2).ok_or_else(invalid)?;
+            if !pair.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            *octet = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+        }
+        Ok(MacAddr(octets))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for MacAddr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MacAddr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0xAA, 0xBB, 0xCC, 0x0D, 0xEE, 0xFF]);
+
+    #[test]
+    fn test_parse_forms() {
+        assert_eq!("aa:bb:cc:0d:ee:ff".parse(), Ok(MAC));
+        assert_eq!("AA-BB-CC-0D-EE-FF".parse(), Ok(MAC));
+        assert_eq!("aabbcc0deeff".parse(), Ok(MAC));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for s in ["", "aa:bb:cc:dd:ee", "aa:bb-cc:dd:ee:ff", "aa:bb:cc:dd:ee:fg", "aabbccddeef", "+a:bb:cc:dd:ee:ff"] {
+            assert!(s.parse::<MacAddr>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        assert_eq!(MAC.to_string(), "aa:bb:cc:0d:ee:ff");
+        assert_eq!(MAC.to_string().parse(), Ok(MAC));
+    }
+}
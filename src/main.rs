@@ -1,144 +1,2503 @@
-use clap::Parser;
-use pnet::datalink;
-use std::process::Command;
+#[macro_use]
+mod log;
+
+mod actions;
+mod audit;
+mod bans;
+mod bench;
+mod capture;
+mod caps;
+mod clock;
+mod coap;
+mod config;
+mod console;
+mod confirm;
+mod connections;
+mod containers;
+mod control;
+mod dbus;
+mod desktop;
+mod diagnose;
+mod events;
+mod firewall;
+mod flood;
+mod gotify;
+mod group;
+mod heartbeat;
+mod hostaccess;
+mod hosts;
+mod http;
+mod idle;
+mod inhibit;
+mod json;
+mod interfaces;
+mod jobs;
+mod landlock;
+mod listen;
+mod lockfiles;
+mod logfile;
+mod mac;
+mod media;
+mod migrate;
+mod monitor;
+mod maintenance;
+mod mounts;
+mod names;
+mod neighbors;
+mod netif;
+mod netns;
+mod nicwol;
+mod notify;
+mod ntfy;
+mod online;
+mod pause;
+mod peers;
+mod pipeline;
+mod policy;
+mod polkit;
+mod portmap;
+mod power;
+mod powerprofile;
+mod presuspend;
+mod protocol;
+mod pushover;
+#[cfg(feature = "quic")]
+mod quic;
+mod quorum;
+mod reach;
+mod relay;
+mod rejections;
+mod reload;
+mod replay;
+mod resume;
+mod resuspend;
+mod retry;
+mod rules;
+mod schedule;
+mod schema;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod seccomp;
+mod selftest;
+mod session;
+mod settings;
+mod snmp;
+mod supply;
+#[cfg(feature = "gui")]
+mod tray;
+mod verify;
+#[cfg(feature = "dbus")]
+mod wakelock;
+mod warn;
+mod webhook;
+mod zfs;
+
+use actions::NamedActions;
+use audit::Audit;
+use bans::{BanPolicy, Bans};
+use capture::Capture;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clock::LocalTime;
+use config::Config;
+use confirm::Confirmations;
+use containers::{ContainerMode, ContainerSpec, Containers, Runtime};
+use control::{Ask, Control, Refusal};
+use events::{Bus, Event};
+use flood::{Flood, FloodPolicy, Transition};
+use group::SleepGroup;
+use heartbeat::{Heartbeat, Monitor};
+use hostaccess::HostAccess;
+use hosts::Hosts;
+use inhibit::Inhibitors;
+use jobs::ScheduledJobs;
+use listen::{Listeners, Received};
+use lockfiles::LockFiles;
+use logfile::LogFile;
+use mac::MacAddr;
+use maintenance::Maintenance;
+use mounts::{MountMode, NetworkMounts};
+use names::Names;
+use neighbors::SenderMac;
+use pause::{Paused, Snooze};
+use peers::{Peer, Peers};
+use pipeline::{Pipeline, Pipelines};
+use policy::{Policy, Profiles};
+use power::{Backend, MemSleep, Power};
+use presuspend::PreSuspend;
+use protocol::{Request, Verifier};
+use quorum::Quorum;
+use reach::{Reachability, Target};
+use rejections::Rejections;
+use relay::{Relay, RelayEntry};
+use resume::{Clocks, PostResume};
+use retry::Backoff;
+use rules::{Action, Decision, Rule, Rules};
+use schedule::Schedule;
+use snmp::Snmp;
+use sol::packet::{self, Trailer};
+use sol::v2;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use supply::Supply;
 use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use verify::{Check, Checks};
+use zfs::{ScrubMode, Zfs};
 
 /// Sleep-on-LAN daemon - receives WoL-format UDP packets to trigger system suspend
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Port to listen on
-    #[arg(short, long, default_value = "10")]
-    port: u16,
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Log more: -v adds rejected packets, -vv adds raw packet dumps
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Log less: -q shows only warnings and errors, -qq only errors
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    quiet: u8,
+
+    /// Color console output
+    #[arg(long, value_enum, default_value = "auto")]
+    color: log::Color,
+
+    /// Leave timestamps out of log lines, e.g. when journald adds its own
+    #[arg(long)]
+    no_timestamps: bool,
+
+    /// What the daemon writes to stdout: log lines, or one JSON event per line with log lines on stderr
+    #[arg(long, value_enum, default_value = "text", conflicts_with = "log_file")]
+    output: log::Output,
+
+    /// Log sender addresses with their reverse-DNS names
+    #[arg(long)]
+    resolve_names: bool,
+
+    /// Port to listen on (repeatable)
+    #[arg(short, long = "port", value_name = "PORT", default_value = "10")]
+    ports: Vec<u16>,
+
+    /// Address to listen on (repeatable); every --port is bound on each
+    #[arg(long = "bind", value_name = "ADDRESS", default_value = "0.0.0.0")]
+    binds: Vec<IpAddr>,
+
+    /// Wait up to SECS at startup for an interface with an address and for every listener to bind, retrying with backoff; 0 waits forever
+    #[arg(long, value_name = "SECS")]
+    wait_for_network: Option<u64>,
+
+    /// Bind --bind addresses that no interface has yet, such as a DHCP or VPN address, and receive on them once they appear
+    #[arg(long)]
+    freebind: bool,
+
+    /// Receive buffer to ask for on each trigger socket, in bytes; above net.core.rmem_max this needs CAP_NET_ADMIN
+    #[arg(long, value_name = "BYTES")]
+    recv_buffer: Option<usize>,
+
+    /// Also listen inside this network namespace, by `ip netns` name or path (repeatable); every --port is bound on each --bind there
+    #[arg(long = "netns", value_name = "NAME|PATH")]
+    netns: Vec<String>,
+
+    /// Map packets to an action by sender, target MAC, port or power source, as ACTION[:from=SUBNET|HOST,mac=MAC,port=PORT,power=ac|battery,force=true,pipeline=NAME] (repeatable, first match wins)
+    #[arg(long = "rule", value_name = "RULE")]
+    rules: Vec<Rule>,
+
+    /// Seconds between lookups of the hostnames in `from=` rule matchers
+    #[arg(long, value_name = "SECS", default_value = "60")]
+    resolve_interval: u64,
+
+    /// Action for packets that match no rule
+    #[arg(long, value_enum, default_value = "suspend")]
+    default_action: Action,
+
+    /// Trigger an action at times given as a cron expression, as "MIN HOUR DAY MONTH WEEKDAY [ACTION]" (repeatable)
+    #[arg(long = "schedule", value_name = "CRON")]
+    schedules: Vec<schedule::Entry>,
+
+    /// Read the power source for power= rules from this NUT UPS instead of sysfs
+    #[arg(long, value_name = "UPS[@HOST[:PORT]]")]
+    nut_ups: Option<String>,
+
+    /// Re-broadcast packets for a non-local MAC to another subnet, as MAC@ADDRESS[:PORT] or MAC@INTERFACE[:PORT] (repeatable)
+    #[arg(long = "relay", value_name = "MAC@TARGET")]
+    relays: Vec<RelayEntry>,
+
+    /// Machine that can be woken with `sol wake NAME`, as NAME=MAC[@TARGET[:PORT]] (repeatable)
+    #[arg(long = "peer", value_name = "NAME=MAC")]
+    peers: Vec<Peer>,
+
+    /// Peer daemon to put to sleep before this machine, as MAC@ADDRESS[:PORT] (repeatable)
+    #[arg(long = "group-member", value_name = "MAC@ADDRESS", value_parser = group::parse_member)]
+    group_members: Vec<RelayEntry>,
+
+    /// Wait for group members to acknowledge the sleep packet before suspending
+    #[arg(long)]
+    group_wait_ack: bool,
+
+    /// Seconds to wait for group acknowledgements
+    #[arg(long, default_value = "5")]
+    group_timeout: u64,
+
+    /// Ask group members whether they are ready before putting anything to sleep, and stay awake if one is busy
+    #[arg(long)]
+    group_prepare: bool,
+
+    /// What to do when a group member does not answer the prepare request within --group-timeout
+    #[arg(long, value_enum, default_value = "abort", requires = "group_prepare")]
+    group_unanswered: group::Unanswered,
+
+    /// Host to probe after waking or putting to sleep the machine with this MAC, as MAC=HOST[:PORT]; without a port it is pinged (repeatable)
+    #[arg(long = "verify-wake", value_name = "MAC=TARGET")]
+    verify_wake: Vec<Check>,
+
+    /// Seconds a woken peer has to come up, or a group member to go down
+    #[arg(long, value_name = "SECS", default_value = "120")]
+    verify_timeout: u64,
+
+    /// Enable magic-packet Wake-on-LAN at startup on interfaces where it is supported but off
+    #[arg(long)]
+    fix: bool,
+
+    /// Times to retry a failed suspend/hibernate/poweroff command before giving up
+    #[arg(long, default_value = "2")]
+    action_retries: u32,
+
+    /// Seconds before the first retry; each further retry waits twice as long
+    #[arg(long, default_value = "5")]
+    retry_backoff: u64,
+
+    /// Seconds the suspend/hibernate/poweroff command may run before it is killed
+    #[arg(long, default_value = "60")]
+    action_timeout: u64,
+
+    /// Seconds to wait for the system to actually sleep after a successful suspend or
+    /// hibernate before reporting that it did not
+    #[arg(long, value_name = "SECS", default_value = "120")]
+    verify_sleep: u64,
+
+    /// Suspend again if nobody uses the machine within this many seconds of an unexplained
+    /// resume (no local input, new login or inhibitor)
+    #[arg(long, value_name = "SECS")]
+    resuspend_after: Option<u64>,
+
+    /// How the power action is carried out
+    #[arg(long, value_enum, default_value = "systemd")]
+    backend: Backend,
+
+    /// Suspend variant written to /sys/power/mem_sleep before suspending
+    #[arg(long, value_enum)]
+    mem_sleep: Option<MemSleep>,
+
+    /// When running in a container, suspend the host through its mounted D-Bus socket or nsenter
+    #[arg(long, value_enum, value_name = "MODE")]
+    host_access: Option<HostAccess>,
+
+    /// The host's system bus socket, as mounted into the container, for --host-access dbus
+    #[arg(long, value_name = "PATH", default_value = "/run/dbus/system_bus_socket")]
+    host_dbus: PathBuf,
+
+    /// Install a seccomp denylist refusing unneeded syscalls (ptrace, module loading, ...) once initialized
+    #[arg(long)]
+    seccomp: bool,
+
+    /// Restrict filesystem access with Landlock to system binaries and the daemon's own files
+    #[arg(long)]
+    landlock: bool,
+
+    /// Drop all capabilities once the trigger port is bound
+    #[arg(long)]
+    drop_capabilities: bool,
+
+    /// Extra path the daemon and its helpers may use under --landlock (repeatable)
+    #[arg(long = "landlock-allow", value_name = "PATH", requires = "landlock")]
+    landlock_allow: Vec<PathBuf>,
+
+    /// Reject packets from senders outside the receiving interface's own subnet
+    #[arg(long)]
+    local_only: bool,
+
+    /// Reject packets sent to a unicast address; only broadcast and multicast triggers are honored, like real Wake-on-LAN
+    #[arg(long)]
+    require_broadcast: bool,
+
+    /// Only accept triggers for the MAC of the interface the packet arrived on, instead of any local MAC
+    #[arg(long)]
+    match_interface_mac: bool,
+
+    /// Only accept packets from ADDRESS if the kernel's neighbor table maps it to MAC (repeatable)
+    #[arg(long = "sender-mac", value_name = "ADDRESS=MAC")]
+    sender_macs: Vec<SenderMac>,
+
+    /// Ban a sender after this many rejected packets within --ban-window seconds
+    #[arg(long, value_name = "N")]
+    ban_after: Option<usize>,
+
+    /// Window in seconds within which --ban-after rejected packets earn a ban
+    #[arg(long, value_name = "SECS", default_value = "60")]
+    ban_window: u64,
+
+    /// Seconds a ban lasts
+    #[arg(long, value_name = "SECS", default_value = "3600")]
+    ban_time: u64,
+
+    /// File keeping bans and their counters across restarts
+    #[arg(long, value_name = "PATH", default_value = "/var/lib/sol/bans")]
+    ban_state: PathBuf,
+
+    /// Rejected packets per second that switch on flood protection
+    #[arg(long, value_name = "N")]
+    flood_threshold: Option<u32>,
+
+    /// Seconds the rejection rate must stay under half the threshold before flood protection ends
+    #[arg(long, value_name = "SECS", default_value = "60")]
+    flood_recover: u64,
+
+    /// Log only the first of identical rejections (same sender and reason) within this many seconds, then a summary; 0 logs every one
+    #[arg(long, value_name = "SECS", default_value = "300")]
+    rejection_summary: u64,
+
+    /// Number of valid packets for the same MAC required before suspending
+    #[arg(long, default_value = "1")]
+    quorum: usize,
+
+    /// Window in seconds within which the quorum packets must arrive
+    #[arg(long, default_value = "10")]
+    quorum_window: u64,
+
+    /// Require a second packet echoing a confirmation token before suspending
+    #[arg(long)]
+    confirm: bool,
+
+    /// Seconds a confirmation token remains valid
+    #[arg(long, default_value = "30")]
+    confirm_timeout: u64,
+
+    /// File holding the shared key v2 packets must be signed with; without it v2 packets are
+    /// rejected
+    #[arg(long, value_name = "PATH")]
+    v2_key: Option<PathBuf>,
+
+    /// SecureOn password WoL triggers must end with, as six bytes written like a MAC or four
+    /// like an IPv4 address
+    #[arg(long, value_name = "PASSWORD")]
+    secureon: Option<SecureOn>,
+
+    /// Seconds a v2 packet's timestamp may differ from the local clock
+    #[arg(long, value_name = "SECS", default_value = "60")]
+    v2_max_skew: u64,
+
+    /// Seconds to wait before suspending, during which a cancel packet aborts the suspend
+    #[arg(long, default_value = "0")]
+    grace: u64,
+
+    /// Handle a single trigger, then exit with a status reflecting the outcome
+    #[arg(long)]
+    oneshot: bool,
+
+    /// Broadcast a countdown to logged-in users (via wall) during the grace period
+    #[arg(long)]
+    warn_users: bool,
+
+    /// Flush filesystem buffers with sync before suspending
+    #[arg(long)]
+    sync: bool,
+
+    /// Shell command to run before suspending, e.g. to drop write-back caches (repeatable)
+    #[arg(long = "flush-command", value_name = "COMMAND")]
+    flush_commands: Vec<String>,
+
+    /// Seconds each pre-suspend step may run before it is killed and the suspend aborted
+    #[arg(long, default_value = "30")]
+    pre_suspend_timeout: u64,
+
+    /// NFS/CIFS mount point to take offline before suspending and restore after resume (repeatable)
+    #[arg(long = "network-mount", value_name = "PATH")]
+    network_mounts: Vec<String>,
+
+    /// How network mounts are taken offline before suspending
+    #[arg(long, value_enum, default_value = "unmount")]
+    mount_mode: MountMode,
+
+    /// ZFS pool to wait for, sync and pin with --zfs-sync-always (repeatable); every imported pool by default
+    #[arg(long = "zfs-pool", value_name = "POOL")]
+    zfs_pools: Vec<String>,
+
+    /// What a running ZFS scrub or resilver does to a suspend
+    #[arg(long, value_enum)]
+    zfs_scrub: Option<ScrubMode>,
+
+    /// Seconds --zfs-scrub wait holds a suspend before giving up on it
+    #[arg(long, value_name = "SECS", default_value = "3600")]
+    zfs_scrub_timeout: u64,
+
+    /// Run zpool sync before suspending
+    #[arg(long)]
+    zfs_sync: bool,
+
+    /// Set sync=always on the pools before suspending and restore the previous setting after resume
+    #[arg(long)]
+    zfs_sync_always: bool,
+
+    /// Shell command to run after the system resumes (repeatable)
+    #[arg(long = "post-resume-command", value_name = "COMMAND")]
+    post_resume_commands: Vec<String>,
+
+    /// Container to pause or stop before suspending, as NAME[:TIMEOUT_SECS] (repeatable)
+    #[arg(long = "container", value_name = "NAME[:SECS]")]
+    containers: Vec<ContainerSpec>,
+
+    /// Container runtime CLI to use
+    #[arg(long, value_enum, default_value = "docker")]
+    container_runtime: Runtime,
+
+    /// Whether containers are paused or stopped before suspending
+    #[arg(long, value_enum, default_value = "pause")]
+    container_mode: ContainerMode,
+
+    /// Default per-container timeout in seconds
+    #[arg(long, default_value = "30")]
+    container_timeout: u64,
+
+    /// Refuse to suspend while NFS/SMB clients are connected
+    #[arg(long)]
+    inhibit_share_clients: bool,
+
+    /// Refuse to suspend while this local TCP port has established connections (repeatable)
+    #[arg(long = "inhibit-port", value_name = "PORT")]
+    inhibit_ports: Vec<u16>,
+
+    /// Refuse to suspend while HOST answers a ping, or HOST:PORT accepts a TCP connection (repeatable)
+    #[arg(long = "inhibit-reachable", value_name = "TARGET")]
+    inhibit_reachable: Vec<Target>,
+
+    /// Refuse to suspend unless HOST answers a ping, or HOST:PORT accepts a TCP connection (repeatable)
+    #[arg(long = "require-reachable", value_name = "TARGET")]
+    require_reachable: Vec<Target>,
+
+    /// Seconds between reachability probes
+    #[arg(long, value_name = "SECS", default_value = "30")]
+    reachability_interval: u64,
+
+    /// Refuse to suspend while PATH exists, or with a trailing slash while the directory has entries (repeatable)
+    #[arg(long = "inhibit-file", value_name = "PATH")]
+    inhibit_files: Vec<PathBuf>,
+
+    /// Refuse to suspend while another process holds a lock on PATH (repeatable)
+    #[arg(long = "inhibit-lock", value_name = "PATH")]
+    inhibit_locks: Vec<PathBuf>,
+
+    /// Refuse to suspend when a systemd timer is due within MINUTES
+    #[arg(long, value_name = "MINUTES")]
+    scheduled_window: Option<u64>,
+
+    /// Only watch this timer (NAME or NAME.timer), or cron jobs whose command has the word NAME (repeatable); all by default
+    #[arg(long = "scheduled-job", value_name = "NAME", requires = "scheduled_window")]
+    scheduled_jobs: Vec<String>,
+
+    /// Also watch cron jobs from /etc/crontab, /etc/cron.d and the users' crontabs
+    #[arg(long, requires = "scheduled_window")]
+    scheduled_cron: bool,
+
+    /// Before suspending, set the RTC alarm to wake --scheduled-window minutes before the next watched job
+    #[arg(long, requires = "scheduled_window")]
+    scheduled_wake: bool,
+
+    /// Suspend even while applications hold sleep/idle inhibits (logind or desktop session)
+    #[arg(long)]
+    ignore_desktop_inhibitors: bool,
+
+    /// Refuse to suspend while a desktop session is presenting, screen sharing or full-screen
+    #[arg(long)]
+    inhibit_session_activity: bool,
+
+    /// Minutes local users must have been idle before a trigger is honored
+    #[arg(long, value_name = "MINUTES")]
+    min_idle: Option<u64>,
+
+    /// Path of the control socket used by `sol status` and `sol maintenance`
+    #[arg(long, default_value = control::DEFAULT_SOCKET)]
+    control_socket: PathBuf,
+
+    /// Let members of GROUP use the control socket, e.g. for `sol tray`
+    #[arg(long, value_name = "GROUP")]
+    control_group: Option<String>,
+
+    /// Flag file whose presence enables maintenance mode
+    #[arg(long, default_value = "/var/lib/sol/maintenance")]
+    maintenance_file: PathBuf,
+
+    /// Configuration file with settings, `[profile.<name>]` and `[peer.<name>]` tables
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Write output to this file instead of stdout/stderr, rotating it by size and age
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Rotate the log file once it reaches this many megabytes
+    #[arg(long, value_name = "MB", default_value = "10")]
+    log_max_size: u64,
+
+    /// Also rotate the log file once it is this many hours old
+    #[arg(long, value_name = "HOURS")]
+    log_max_age: Option<u64>,
+
+    /// Append a hash-chained record of triggers and actions to this file
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<PathBuf>,
+
+    /// File holding the key audit entries are signed with
+    #[arg(long, value_name = "PATH")]
+    audit_key: Option<PathBuf>,
+
+    /// Number of rotated log files kept
+    #[arg(long, value_name = "N", default_value = "5")]
+    log_keep: usize,
+
+    /// Write every datagram received on the trigger ports to this pcap file
+    #[arg(long, value_name = "PATH")]
+    capture: Option<PathBuf>,
+
+    /// Bytes kept of each captured packet, including the IP and UDP headers
+    #[arg(long, value_name = "BYTES", default_value = "65535")]
+    capture_snaplen: u32,
+
+    /// Rotate the capture file once it reaches this many megabytes
+    #[arg(long, value_name = "MB", default_value = "10")]
+    capture_max_size: u64,
+
+    /// Number of rotated capture files kept
+    #[arg(long, value_name = "N", default_value = "3")]
+    capture_keep: usize,
+
+    /// Ask the router (NAT-PMP, then UPnP IGD) to forward the trigger ports from the internet
+    #[arg(long)]
+    port_mapping: bool,
+
+    /// Serve CoAP `POST /sleep` and `GET /status` on ADDR:PORT (CoAP's usual port is 5683)
+    #[arg(long, value_name = "ADDR:PORT")]
+    coap: Option<SocketAddr>,
+
+    /// Push a JSON heartbeat with the daemon's state and uptime to this monitor, as http://HOST[:PORT]/PATH or udp://HOST:PORT
+    #[arg(long, value_name = "URL")]
+    heartbeat: Option<Monitor>,
+
+    /// Seconds between heartbeats
+    #[arg(long, value_name = "SECS", default_value = "60")]
+    heartbeat_interval: u64,
+
+    /// Push suspends, resumes and failures to this ntfy topic, as http://HOST[:PORT]/TOPIC
+    #[arg(long, value_name = "URL")]
+    ntfy: Option<String>,
+
+    /// Priority of ntfy notifications; failures are sent at high or above
+    #[arg(long, value_enum, default_value = "default")]
+    ntfy_priority: sol::notifier::Priority,
+
+    /// URL opened when an ntfy notification is tapped
+    #[arg(long, value_name = "URL", requires = "ntfy")]
+    ntfy_click: Option<String>,
+
+    /// Serve HTTP `POST /sleep`, `POST /wol/MAC`, `POST /wake/NAME`, `GET /neighbors` and `GET /status` on ADDR:PORT
+    #[arg(long, value_name = "ADDR:PORT")]
+    http: Option<SocketAddr>,
+
+    /// Require HTTP Basic authentication with these credentials on the --http endpoint
+    #[arg(long, value_name = "USER:PASSWORD", requires = "http")]
+    http_auth: Option<String>,
+
+    /// Answer SNMPv2c GET, GETNEXT and GETBULK for sol's status objects on ADDR:PORT
+    #[arg(long, value_name = "ADDR:PORT")]
+    snmp: Option<SocketAddr>,
+
+    /// Community string the SNMP agent accepts and traps carry
+    #[arg(long, value_name = "NAME", default_value = "public")]
+    snmp_community: String,
+
+    /// Send SNMPv2c traps on suspend, resume and failure to ADDR:PORT (repeatable)
+    #[arg(long = "snmp-trap", value_name = "ADDR:PORT")]
+    snmp_traps: Vec<SocketAddr>,
+
+    /// Serve the org.sleeponlan.WakeLock1 interface on the system bus
+    #[cfg(feature = "dbus")]
+    #[arg(long)]
+    dbus: bool,
+
+    /// Serve sleep, status and wake requests over QUIC on ADDR:PORT, for clients holding a
+    /// certificate signed by --quic-client-ca
+    #[cfg(feature = "quic")]
+    #[arg(long, value_name = "ADDR:PORT", requires_all = ["quic_cert", "quic_key", "quic_client_ca"])]
+    quic: Option<SocketAddr>,
+
+    /// PEM certificate chain the QUIC listener presents
+    #[cfg(feature = "quic")]
+    #[arg(long, value_name = "PATH", requires = "quic")]
+    quic_cert: Option<PathBuf>,
+
+    /// PEM private key for --quic-cert
+    #[cfg(feature = "quic")]
+    #[arg(long, value_name = "PATH", requires = "quic")]
+    quic_key: Option<PathBuf>,
+
+    /// PEM CA certificates that QUIC client certificates must chain to
+    #[cfg(feature = "quic")]
+    #[arg(long, value_name = "PATH", requires = "quic")]
+    quic_client_ca: Option<PathBuf>,
 }
 
-const MAGIC_PACKET_HEADER: [u8; 6] = [0xFF; 6];
-const EXPECTED_PACKET_SIZE: usize = 102; // 6 (header) + 16*6 (MAC repeated 16 times)
+impl Args {
+    /// The first --port, which subcommands talk to
+    fn port(&self) -> u16 {
+        self.ports[0]
+    }
+
+    /// What may follow a packet's MAC repetition. Padding is tolerated unless a SecureOn
+    /// password or v2 key is set; then only WoL triggers carry the password and every other
+    /// packet must be exactly `PACKET_SIZE` bytes.
+    fn trailer(&self, trigger: bool) -> Trailer<'_> {
+        match &self.secureon {
+            Some(SecureOn(password)) if trigger => Trailer::Password(password),
+            Some(_) => Trailer::Exact,
+            None if self.v2_key.is_some() => Trailer::Exact,
+            None => Trailer::Padding,
+        }
+    }
+
+    /// The policy profiles start from
+    fn policy(&self) -> Policy {
+        Policy {
+            quorum: self.quorum,
+            quorum_window: self.quorum_window,
+            confirm: self.confirm,
+            grace: self.grace,
+            warn_users: self.warn_users,
+            inhibit_share_clients: self.inhibit_share_clients,
+            inhibit_ports: self.inhibit_ports.clone(),
+            ignore_desktop_inhibitors: self.ignore_desktop_inhibitors,
+            inhibit_session_activity: self.inhibit_session_activity,
+            min_idle: self.min_idle,
+        }
+    }
+}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Send a cancel packet to the local daemon to abort a pending suspend
+    Cancel,
+    /// Ask a daemon what a trigger for MAC would do, without it acting on it
+    Test {
+        /// Daemon address as HOST[:PORT]; the port defaults to --port
+        target: String,
+        /// Target MAC address
+        mac: String,
+    },
+    /// Send test and invalid packets to a daemon at a fixed rate and report how many it handled
+    Bench {
+        /// Daemon address as HOST[:PORT]; the port defaults to --port
+        target: String,
+        /// Target MAC address
+        mac: MacAddr,
+        /// Packets per second
+        #[arg(long, default_value = "1000")]
+        rate: u32,
+        /// How long to send for
+        #[arg(long, value_name = "SECS", default_value = "10")]
+        duration: u64,
+        /// Percentage of packets that are invalid
+        #[arg(long, value_name = "PERCENT", default_value = "50")]
+        invalid: u8,
+    },
+    /// Show the running daemon's status
+    Status,
+    /// Send harmless probe packets to the local daemon over loopback and broadcast and report what arrives
+    SelfTest,
+    /// Check the whole chain (config, binding, reachability, NIC Wake-on-LAN, suspend backend) and report problems first
+    Diagnose,
+    /// Show or change maintenance mode, in which triggers are logged but not executed
+    Maintenance {
+        #[arg(value_enum)]
+        state: Option<Toggle>,
+    },
+    /// Send a Wake-on-LAN packet to a known peer, or a host in the neighbor table, through the daemon
+    Wake {
+        peer: String,
+        /// Wait until the peer answers its --verify-wake probe
+        #[arg(long)]
+        wait: bool,
+    },
+    /// Hold off every sleep trigger for LENGTH (e.g. 45m, 2h, or plain minutes), or end a snooze with `off`
+    Snooze {
+        length: Option<String>,
+    },
+    /// List the peers the daemon can wake
+    Peers,
+    /// Show the active profile, select one by name, or return to the schedule with `auto`
+    Profile {
+        name: Option<String>,
+    },
+    /// List network interfaces with their MAC addresses and whether triggers for them are accepted
+    ListInterfaces {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the kernel's neighbor table, or the MAC it has for HOST, as used to wake hosts that are not peers
+    Neighbors {
+        host: Option<String>,
+    },
+    /// List senders banned for sending rejected packets, or lift bans
+    Bans {
+        /// Lift the ban on ADDRESS, or on everyone
+        #[arg(long)]
+        clear: bool,
+        #[arg(requires = "clear")]
+        address: Option<IpAddr>,
+    },
+    /// Show recent events and rejected packets from the daemon's history, newest last
+    Events {
+        /// How many of the matching events to show
+        #[arg(long, default_value = "50")]
+        last: usize,
+        /// Only events since Unix time SINCE, or within a duration such as 30m, 2h or 1d
+        #[arg(long)]
+        since: Option<String>,
+        /// Only this event, e.g. validated, inhibited or rejected
+        #[arg(long)]
+        outcome: Option<String>,
+        /// Only events from this sender, by address or host name
+        #[arg(long)]
+        sender: Option<String>,
+        /// Only events for this MAC
+        #[arg(long)]
+        mac: Option<MacAddr>,
+        /// Print a JSON array instead of lines
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check an audit log's hash chain and, with --audit-key, its signatures
+    VerifyAudit {
+        path: PathBuf,
+    },
+    /// Show the daemon's state in the system tray; clicking the icon toggles maintenance mode
+    #[cfg(feature = "gui")]
+    Tray,
+    /// Watch the daemon's events, senders and status live in the terminal
+    Monitor,
+    /// Run the datagrams in a pcap file through validation and show what each would have done
+    Replay {
+        path: PathBuf,
+        /// Treat MAC as local instead of this machine's interfaces (repeatable), for captures from another host
+        #[arg(long = "mac", value_name = "MAC")]
+        macs: Vec<MacAddr>,
+    },
+    /// Convert a Go sleep-on-lan sol.json into a config file for this daemon, printed to stdout
+    Migrate {
+        path: PathBuf,
+    },
+    /// Print configuration for other tools
+    Generate {
+        #[command(subcommand)]
+        what: Generate,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum Generate {
+    /// A polkit rule letting an unprivileged account suspend through logind
+    PolkitRule {
+        /// Account the daemon runs as
+        #[arg(long, default_value = "sol")]
+        user: String,
+    },
+    /// Firewall rules opening the trigger ports to the senders the rules accept
+    Firewall {
+        #[arg(long, value_enum, default_value = "nftables")]
+        format: firewall::Format,
+        /// Load the rules instead of printing them
+        #[arg(long)]
+        apply: bool,
+    },
+    /// A JSON Schema for the config file, for editor completion and validation
+    ConfigSchema,
+}
+
+/// A SecureOn password: six bytes written like a MAC, or four like an IPv4 address
+#[derive(Clone, Debug)]
+struct SecureOn(Vec<u8>);
 
+impl std::str::FromStr for SecureOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<std::net::Ipv4Addr>() {
+            return Ok(SecureOn(addr.octets().to_vec()));
+        }
+        let mac: MacAddr = s
+            .parse()
+            .map_err(|_| format!("Invalid SecureOn password '{}' (expected XX:XX:XX:XX:XX:XX or A.B.C.D)", s))?;
+        Ok(SecureOn(mac.0.to_vec()))
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Toggle {
+    On,
+    Off,
+}
+
+/// Everything that happens around the suspend itself
+struct SuspendPlan {
+    inhibitors: Arc<Inhibitors>,
+    backoff: Backoff,
+    power: Power,
+    group: SleepGroup,
+    containers: Containers,
+    pre_suspend: PreSuspend,
+    mounts: NetworkMounts,
+    zfs: Zfs,
+    post_resume: PostResume,
+    /// Jobs to set the RTC alarm for before suspending
+    wake_for: Option<ScheduledJobs>,
+    pipelines: Pipelines,
+    events: Arc<Bus>,
+    /// How long after a successful action the system must have slept
+    verify_sleep: Duration,
+    /// How long a resumed machine may sit unused before it is suspended again
+    resuspend: Option<Duration>,
+    status: Arc<Mutex<control::Status>>,
+    /// Sends a final beat before the power action and another after resume
+    heartbeat: Option<Heartbeat>,
+}
+
+impl SuspendPlan {
+    /// The first active inhibitor, checking the always-on ones before those the policy enables.
+    /// A forced decision only checks the hard ones, maintenance mode and pause.
+    async fn inhibited(&self, policy: &Policy, force: bool) -> Option<String> {
+        inhibit::first_active(self.inhibitors.clone(), policy.clone(), force).await
+    }
+
+    fn has_resume_steps(&self) -> bool {
+        !self.containers.specs.is_empty()
+            || !self.mounts.paths.is_empty()
+            || self.zfs.sync_always
+            || !self.post_resume.commands.is_empty()
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse_from(settings::layered(&Args::command())?);
+    let mut trigger = Vec::new();
+    let mut log = None;
+    // Capabilities and Landlock only apply to the calling thread and the threads it creates
+    // afterwards, so the daemon is locked down before the runtime starts its workers
+    if args.command.is_none() {
+        if let Some(path) = &args.log_file {
+            log = Some(LogFile::open(
+                path.clone(),
+                args.log_max_size * 1024 * 1024,
+                args.log_max_age.map(|hours| Duration::from_secs(hours * 3600)),
+                args.log_keep,
+            )?);
+        }
+        log::init(log::level(args.verbose, args.quiet), args.color, !args.no_timestamps, args.output);
+        trigger = listen::activated()?;
+        if trigger.is_empty() {
+            let bind = || {
+                let mut sockets = listen::bind(&args.binds, &args.ports, args.freebind)?;
+                for netns in &args.netns {
+                    sockets.extend(netns::bind(netns, &args.binds, &args.ports, args.freebind)?);
+                }
+                Ok(sockets)
+            };
+            trigger = match args.wait_for_network {
+                Some(secs) => {
+                    let timeout = (secs > 0).then(|| Duration::from_secs(secs));
+                    online::wait(timeout, Duration::from_secs(1), || online::ready(&netif::interfaces()).and_then(|()| bind()))?
+                }
+                None => bind()?,
+            };
+            for netns in &args.netns {
+                info!("Listening in network namespace {}", netns);
+            }
+        } else {
+            info!("Using {} socket(s) from socket activation", trigger.len());
+        }
+        if let Some(bytes) = args.recv_buffer {
+            let granted = listen::set_recv_buffer(&trigger, bytes)?;
+            if granted < bytes {
+                warn!(
+                    "Receive buffer is {} bytes, not the {} asked for; raise net.core.rmem_max or grant CAP_NET_ADMIN",
+                    granted, bytes
+                );
+            }
+        }
+        if args.drop_capabilities {
+            if args.fix || !args.network_mounts.is_empty() || args.host_access == Some(HostAccess::Nsenter) {
+                return Err("--drop-capabilities cannot be combined with --fix, --network-mount or --host-access nsenter".into());
+            }
+            caps::drop_all().map_err(|e| format!("Failed to drop capabilities: {}", e))?;
+            info!("Capabilities dropped");
+        }
+        if args.landlock {
+            sandbox_filesystem(&args)?;
+        }
+    }
+    tokio::runtime::Runtime::new()?.block_on(async {
+        if let Some(log) = log {
+            logfile::watch(log);
+        }
+        run(args, trigger).await
+    })
+}
+
+/// Fail when a rule or action names a pipeline the config file does not define
+fn check_pipelines(args: &Args, pipelines: &Pipelines, named_actions: &NamedActions) -> Result<(), String> {
+    if let Some(name) = args.rules.iter().filter_map(|rule| rule.pipeline.as_deref()).find(|name| !pipelines.contains(name)) {
+        return Err(format!("--rule names pipeline '{}', which is not defined in the config file", name));
+    }
+    if let Some(name) = named_actions.pipelines().find(|name| !pipelines.contains(name)) {
+        return Err(format!("An action names pipeline '{}', which is not defined in the config file", name));
+    }
+    Ok(())
+}
+
+/// Load the config file as the daemon does at startup, for `sol diagnose`
+fn check_config(args: &Args) -> Result<String, String> {
+    let Some(path) = &args.config else {
+        return Ok("no config file; command-line settings only".to_string());
+    };
+    let config = Config::load(path)?;
+    let profiles = Profiles::from_config(args.policy(), &config)?;
+    let peers = Peers::from_config(args.peers.clone(), &config)?;
+    media::servers(&config)?;
+    notify::Notifiers::from_config(Vec::new(), &config, &notify::backends())?;
+    let step_timeout = Duration::from_secs(args.pre_suspend_timeout);
+    let pipelines = Pipelines::from_config(&config, step_timeout)?;
+    check_pipelines(args, &pipelines, &NamedActions::from_config(&config, step_timeout)?)?;
+    Ok(format!("{} is valid ({} profile(s), {} peer(s))", path.display(), profiles.names().len(), peers.list().len()))
+}
+
+fn sandbox_filesystem(args: &Args) -> Result<(), String> {
+    if !args.network_mounts.is_empty() {
+        return Err("--landlock cannot be combined with --network-mount: sandboxed processes may not mount".into());
+    }
+    let mut state = Vec::new();
+    for file in [Some(&args.maintenance_file), Some(&args.control_socket), args.log_file.as_ref(), args.audit_log.as_ref(), Some(&args.ban_state), args.capture.as_ref()]
+        .into_iter()
+        .flatten()
+    {
+        if let Some(dir) = file.parent() {
+            state.push(dir.to_path_buf());
+        }
+    }
+    if args.scheduled_wake {
+        state.push(PathBuf::from(jobs::WAKEALARM).parent().unwrap().to_path_buf());
+    }
+    let mut watched: Vec<PathBuf> = args.inhibit_files.iter().chain(&args.inhibit_locks).cloned().collect();
+    if args.scheduled_cron {
+        watched.push(PathBuf::from(jobs::CRON_SPOOL));
+    }
+    let paths = landlock::Paths {
+        config: args.config.clone(),
+        state,
+        watched,
+        extra: args.landlock_allow.clone(),
+    };
+    match landlock::apply(&landlock::rules(&paths)) {
+        Ok(true) => info!("Landlock filesystem restrictions applied"),
+        Ok(false) => warn!("the kernel does not support Landlock; filesystem access is unrestricted"),
+        Err(e) => return Err(format!("Failed to apply Landlock restrictions: {}", e)),
+    }
+    Ok(())
+}
+
+async fn run(args: Args, trigger: Vec<std::net::UdpSocket>) -> Result<(), Box<dyn std::error::Error>> {
     // Get local MAC addresses
     let local_macs = get_local_mac_addresses();
+
+    let port = args.port();
+    match args.command {
+        Some(Commands::Cancel) => return send_cancel(port, &local_macs).await,
+        Some(Commands::ListInterfaces { json }) => {
+            let interfaces = interfaces::list();
+            println!("{}", if json { interfaces::json(&interfaces) } else { interfaces::table(&interfaces) });
+            return Ok(());
+        }
+        Some(Commands::Neighbors { ref host }) => {
+            match host {
+                Some(host) => {
+                    let (ip, mac) = neighbors::resolve(host).await?;
+                    println!("{} ({}) is at {}", host, ip, mac);
+                }
+                None => println!("{}", neighbors::format_table(&neighbors::table().await)),
+            }
+            return Ok(());
+        }
+        Some(Commands::VerifyAudit { path }) => {
+            let key = args.audit_key.as_deref().map(protocol::read_key).transpose()?;
+            let text = std::fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+            let (entries, _) = audit::verify(&text, key.as_deref()).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let signed = if key.is_some() { ", signatures valid" } else { "" };
+            println!("{}: {} entries, chain intact{}", path.display(), entries, signed);
+            return Ok(());
+        }
+        Some(Commands::SelfTest) => {
+            let mac = local_macs.first().ok_or("No network interfaces with MAC addresses found")?;
+            if !selftest::run(port, mac).await {
+                return Err("self-test failed".into());
+            }
+            return Ok(());
+        }
+        Some(Commands::Diagnose) => {
+            let mut actions = vec![args.default_action];
+            let chosen = args.rules.iter().map(|rule| rule.action).chain(args.schedules.iter().filter_map(|entry| entry.action));
+            for action in chosen {
+                if !actions.contains(&action) {
+                    actions.push(action);
+                }
+            }
+            let setup = diagnose::Setup {
+                ports: args.ports.clone(),
+                binds: args.binds.clone(),
+                control_socket: args.control_socket.clone(),
+                backend: args.backend,
+                actions,
+                mac: local_macs.first().copied(),
+                config: check_config(&args),
+            };
+            let findings = diagnose::run(&setup).await;
+            print!("{}", diagnose::render(&findings));
+            if findings.iter().any(|finding| finding.severity == diagnose::Severity::Fail) {
+                return Err("diagnose found problems".into());
+            }
+            return Ok(());
+        }
+        Some(Commands::Test { target, mac }) => {
+            let mac: MacAddr = mac.parse()?;
+            let destination = resolve_target(target, port).await?;
+            let reply = selftest::probe(destination, &selftest::probe_packet(&mac)).await?;
+            println!("{}", reply);
+            if reply.starts_with("error") {
+                return Err("trigger would be rejected".into());
+            }
+            return Ok(());
+        }
+        Some(Commands::Bench { target, mac, rate, duration, invalid }) => {
+            let destination = resolve_target(target, port).await?;
+            let local = destination.ip().is_loopback() || relay::local_addresses().contains(&destination.ip());
+            let load = bench::Load { rate, duration: Duration::from_secs(duration), invalid: invalid.min(100) };
+            let report = bench::run(destination, &mac, load, local.then_some(args.control_socket.as_path())).await?;
+            println!("{}", report.render());
+            return Ok(());
+        }
+        Some(Commands::Status) => {
+            println!("{}", control::request(&args.control_socket, "status").await?);
+            return Ok(());
+        }
+        #[cfg(feature = "gui")]
+        Some(Commands::Tray) => return Ok(tray::run(args.control_socket.clone()).await?),
+        Some(Commands::Monitor) => {
+            return Ok(monitor::run(args.control_socket.clone(), port, local_macs.first().copied()).await?);
+        }
+        Some(Commands::Replay { ref path, ref macs }) => {
+            let macs = if macs.is_empty() { &local_macs } else { macs };
+            return Ok(replay::run(&args, path, macs).await?);
+        }
+        Some(Commands::Migrate { ref path }) => {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            print!("{}", migrate::migrate(&text).map_err(|e| format!("{}: {}", path.display(), e))?);
+            return Ok(());
+        }
+        Some(Commands::Generate { ref what }) => {
+            match what {
+                Generate::PolkitRule { user } => print!("{}", polkit::rule(user)?),
+                Generate::ConfigSchema => print!("{}", schema::config_schema(&settings::settings(&Args::command()))),
+                Generate::Firewall { format, apply } => {
+                    let rules = Rules::new(args.rules.clone(), args.default_action, Supply::new(None), Hosts::default());
+                    rules.hosts.resolve(&rules.hostnames()).await;
+                    let interfaces = netif::interfaces();
+                    let local = args.local_only.then_some(interfaces.as_slice());
+                    let script = firewall::Allowed::from_rules(&args.ports, &rules, local).render(*format);
+                    if *apply {
+                        firewall::apply(&script, *format)?;
+                    } else {
+                        print!("{}", script);
+                    }
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::Bans { clear, address }) => {
+            let command = match (clear, address) {
+                (true, Some(address)) => format!("bans clear {}", address),
+                (true, None) => "bans clear".to_string(),
+                (false, _) => "bans".to_string(),
+            };
+            println!("{}", control::request(&args.control_socket, &command).await?);
+            return Ok(());
+        }
+        Some(Commands::Events { last, ref since, ref outcome, ref sender, ref mac, json }) => {
+            let mut command = format!("history last={}", last);
+            let filters =
+                [("since", since.clone()), ("outcome", outcome.clone()), ("sender", sender.clone()), ("mac", mac.map(|mac| mac.to_string()))];
+            for (key, value) in filters {
+                if let Some(value) = value {
+                    command += &format!(" {}={}", key, value);
+                }
+            }
+            if json {
+                command += " json";
+            }
+            println!("{}", control::request(&args.control_socket, &command).await?);
+            return Ok(());
+        }
+        Some(Commands::Maintenance { state }) => {
+            let command = match state {
+                Some(Toggle::On) => "maintenance on",
+                Some(Toggle::Off) => "maintenance off",
+                None => "maintenance",
+            };
+            println!("{}", control::request(&args.control_socket, command).await?);
+            return Ok(());
+        }
+        Some(Commands::Wake { peer, wait }) => {
+            let command = format!("wake {}{}", peer, if wait { " wait" } else { "" });
+            println!("{}", control::request(&args.control_socket, &command).await?);
+            return Ok(());
+        }
+        Some(Commands::Snooze { ref length }) => {
+            let command = match length {
+                Some(length) => format!("snooze {}", length),
+                None => "snooze".to_string(),
+            };
+            println!("{}", control::request(&args.control_socket, &command).await?);
+            return Ok(());
+        }
+        Some(Commands::Peers) => {
+            println!("{}", control::request(&args.control_socket, "peers").await?);
+            return Ok(());
+        }
+        Some(Commands::Profile { name }) => {
+            let command = match name {
+                Some(name) => format!("profile {}", name),
+                None => "profile".to_string(),
+            };
+            println!("{}", control::request(&args.control_socket, &command).await?);
+            return Ok(());
+        }
+        None => {}
+    }
+
     if local_macs.is_empty() {
-        eprintln!("Warning: No network interfaces with MAC addresses found");
+        warn!("No network interfaces with MAC addresses found");
     } else {
-        println!("Monitoring for WoL packets targeting:");
-        for mac in &local_macs {
-            println!("  {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-                     mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]);
+        let macs: Vec<String> = local_macs.iter().map(MacAddr::to_string).collect();
+        info!("Monitoring for WoL packets targeting {}", macs.join(", "));
+    }
+
+    nicwol::check_interfaces(args.fix);
+    if let Some(mode) = args.mem_sleep {
+        match power::mem_sleep_modes(Path::new("/sys/power")) {
+            Some((_, modes)) if !modes.iter().any(|available| available == mode.as_str()) => {
+                warn!("--mem-sleep {} is not supported here (available: {})", mode.as_str(), modes.join(", "));
+            }
+            None => warn!("--mem-sleep is set, but the kernel offers no suspend variants"),
+            _ => {}
         }
     }
+    let host = hostaccess::prepare(args.host_access, &args.host_dbus, args.backend).await?;
+    if let Some(mode) = args.host_access {
+        info!("Reaching the host through {}", match mode {
+            HostAccess::Dbus => args.host_dbus.display().to_string(),
+            HostAccess::Nsenter => "nsenter".to_string(),
+        });
+    }
+
+    let mut listeners = Listeners::new(trigger)?;
+    let addresses = listeners.addresses();
+    let listening: Vec<String> = addresses.iter().map(|addr| addr.to_string()).collect();
+    info!("Sleep-on-LAN daemon listening on {}", listening.join(", "));
+
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let profiles = Arc::new(Profiles::from_config(args.policy(), &config)?);
+    let peers = Arc::new(Peers::from_config(args.peers.clone(), &config)?);
+    if !profiles.names().is_empty() {
+        info!("Profiles: {}", profiles.names().join(", "));
+    }
+    if let Some(path) = &args.config
+        && let Err(e) = reload::watch(path.clone(), profiles.clone(), peers.clone())
+    {
+        warn!("Config reloading unavailable: {}", e);
+    }
+
+    let maintenance = Maintenance::new(args.maintenance_file.clone());
+    if maintenance.enabled() {
+        info!("Maintenance mode is enabled; triggers will not be executed");
+    }
+
+    let mut quorum = Quorum::new(args.quorum, Duration::from_secs(args.quorum_window));
+    let mut confirmations = Confirmations::new(Duration::from_secs(args.confirm_timeout));
+    let mut verifier = Verifier::load(args.v2_key.as_deref(), Duration::from_secs(args.v2_max_skew))?;
+    let paused = Paused::default();
+    if let Err(e) = pause::watch_signal(paused.clone()) {
+        warn!("Failed to install SIGUSR2 handler: {}", e);
+    }
+
+    let snooze = Snooze::default();
+
+    let mut inhibitors = Inhibitors::default();
+    inhibitors.push_hard(Box::new(maintenance.clone()));
+    inhibitors.push_hard(Box::new(paused.clone()));
+    inhibitors.push(Box::new(snooze.clone()));
+    let reachability = Reachability::new(args.inhibit_reachable.clone(), args.require_reachable.clone());
+    if !reachability.is_empty() {
+        reachability.probe_all().await;
+        reachability.refresh(Duration::from_secs(args.reachability_interval.max(1)));
+        inhibitors.push(Box::new(reachability));
+    }
+    if !args.inhibit_files.is_empty() || !args.inhibit_locks.is_empty() {
+        inhibitors.push(Box::new(LockFiles { flags: args.inhibit_files.clone(), locks: args.inhibit_locks.clone() }));
+    }
+    let scheduled = args.scheduled_window.map(|minutes| ScheduledJobs {
+        window: Duration::from_secs(minutes * 60),
+        cron: args.scheduled_cron,
+        names: args.scheduled_jobs.clone(),
+    });
+    if let Some(scheduled) = &scheduled {
+        inhibitors.push(Box::new(scheduled.clone()));
+    }
+    for server in media::servers(&config)? {
+        info!("Inhibiting while {} has active sessions", server.url);
+        inhibitors.push(Box::new(server));
+    }
+    if args.zfs_scrub == Some(ScrubMode::Inhibit) {
+        inhibitors.push(Box::new(zfs::ZfsScrub { pools: args.zfs_pools.clone() }));
+    }
+
+    #[cfg(feature = "dbus")]
+    let (_dbus, wake_locks) = if args.dbus {
+        let wake_locks = wakelock::WakeLocks::default();
+        let connection = wakelock::serve(wake_locks.clone()).await?;
+        info!("Wake-lock service available on the system bus");
+        inhibitors.push(Box::new(wake_locks.clone()));
+        (Some(connection), Some(wake_locks))
+    } else {
+        (None, None)
+    };
 
-    // Bind to UDP socket
-    let addr = format!("0.0.0.0:{}", args.port);
-    let socket = UdpSocket::bind(&addr).await?;
-    println!("Sleep-on-LAN daemon listening on {}", addr);
+    let inhibitors = Arc::new(inhibitors);
 
-    let mut buf = [0u8; 1024];
+    let ban_policy = args.ban_after.filter(|limit| *limit > 0).map(|limit| BanPolicy {
+        limit,
+        window: args.ban_window,
+        duration: args.ban_time,
+    });
+    let bans = Arc::new(Bans::new(ban_policy, Some(args.ban_state.clone())));
+    bans.flush_periodically();
+
+    let snmp = Arc::new(Snmp::new(args.snmp_community.clone(), args.snmp_traps.clone()));
+
+    let stream = events::Stream::default();
+    let history = events::History::default();
+    let mut events = Bus::default();
+    events.subscribe(Box::new(events::Log));
+    events.subscribe(Box::new(Arc::clone(&snmp)));
+    events.subscribe(Box::new(stream.clone()));
+    events.subscribe(Box::new(history.clone()));
+    if args.output == log::Output::Ndjson {
+        events.subscribe(Box::new(events::Ndjson));
+    }
+    if let Some(path) = &args.audit_log {
+        let key = args.audit_key.as_deref().map(protocol::read_key).transpose()?;
+        events.subscribe(Box::new(Audit::open(path, key)?));
+        // Open has verified the file, so its entries can seed the history
+        let text = std::fs::read_to_string(path).unwrap_or_default();
+        audit::records(&text).into_iter().for_each(|record| history.push(record));
+        info!("Audit log {}", path.display());
+    }
+    let mut routes = Vec::new();
+    if let Some(url) = &args.ntfy {
+        portmap::split_url(url).map_err(|e| format!("Invalid --ntfy: {}", e))?;
+        routes.push(notify::Route {
+            name: "ntfy".to_string(),
+            notifier: Box::new(ntfy::Ntfy { url: url.clone(), token: None }),
+            events: notify::NOTIFIED.iter().map(|name| name.to_string()).collect(),
+            priority: args.ntfy_priority,
+            click: args.ntfy_click.clone(),
+        });
+    }
+    let notifiers = notify::Notifiers::from_config(routes, &config, &notify::backends())?;
+    if !notifiers.is_empty() {
+        info!("Notifications: {}", notifiers.describe().join(", "));
+        events.subscribe(Box::new(notifiers));
+    }
+    let events = Arc::new(events);
+    let checks = (!args.verify_wake.is_empty())
+        .then(|| Checks::new(args.verify_wake.clone(), Duration::from_secs(args.verify_timeout), Arc::clone(&events)));
+
+    let mut control = Control::new(
+        maintenance,
+        paused,
+        Arc::clone(&inhibitors),
+        Arc::clone(&profiles),
+        peers,
+        Arc::clone(&bans),
+    );
+    {
+        let mut status = control.status.lock().unwrap();
+        status.listen = listening.join(", ");
+        status.recv_buffer = listeners.recv_buffer();
+    }
+    control.status.lock().unwrap().mem_sleep = args.mem_sleep;
+    control.events = stream;
+    control.history = history;
+    control.snooze = snooze;
+    control.checks = checks.clone();
+    #[cfg(feature = "dbus")]
+    {
+        control.wake_locks = wake_locks;
+    }
+    let (tx, mut triggers) = tokio::sync::mpsc::channel(16);
+    control.triggers = Some(tx);
+    let control_group = args.control_group.as_deref().map(control::group_id).transpose()?;
+    match control::listen(&args.control_socket, control_group, control.clone()) {
+        Ok(()) => info!("Control socket listening on {}", args.control_socket.display()),
+        Err(e) => warn!("Failed to open control socket {}: {}", args.control_socket.display(), e),
+    }
+    if args.log_file.is_none() && console::start(console::Console { control: control.clone(), mac: local_macs.first().copied() }) {
+        info!("Type commands on the terminal (help lists them)");
+    }
+
+    if args.port_mapping {
+        if !args.confirm {
+            warn!("--port-mapping exposes the trigger port to the internet; use --confirm so spoofed packets cannot suspend this machine");
+        }
+        portmap::maintain(args.ports.clone());
+    }
+
+    let rules = Rules::new(args.rules.clone(), args.default_action, Supply::new(args.nut_ups.clone()), Hosts::default());
+    let hostnames = rules.hostnames();
+    if !hostnames.is_empty() {
+        rules.hosts.resolve(&hostnames).await;
+        rules.hosts.refresh(hostnames, Duration::from_secs(args.resolve_interval.max(1)));
+    }
+    if rules.reads_power() {
+        rules.supply.poll().await;
+        rules.supply.refresh(supply::NUT_POLL);
+    }
+
+    if let Some(addr) = args.coap {
+        let coap = coap::Coap {
+            control: control.clone(),
+            mac: *local_macs.first().ok_or("--coap needs a network interface with a MAC address")?,
+        };
+        coap::listen(addr, coap).await.map_err(|e| format!("Cannot bind CoAP {}: {}", addr, e))?;
+        info!("CoAP endpoint listening on {}", addr);
+    }
+
+    if let Some(addr) = args.http {
+        let http = http::Http {
+            control: control.clone(),
+            mac: *local_macs.first().ok_or("--http needs a network interface with a MAC address")?,
+            auth: args.http_auth.clone(),
+        };
+        http::listen(addr, http).await.map_err(|e| format!("Cannot bind HTTP {}: {}", addr, e))?;
+        info!("HTTP endpoint listening on {}", addr);
+    }
+
+    #[cfg(feature = "quic")]
+    if let Some(addr) = args.quic {
+        let files = quic::TlsFiles {
+            cert: args.quic_cert.clone().unwrap_or_default(),
+            key: args.quic_key.clone().unwrap_or_default(),
+            client_ca: args.quic_client_ca.clone().unwrap_or_default(),
+        };
+        let mac = *local_macs.first().ok_or("--quic needs a network interface with a MAC address")?;
+        quic::listen(addr, &files, control.clone(), mac)?;
+        info!("QUIC control channel listening on {}", addr);
+    }
+
+    if let Some(addr) = args.snmp {
+        snmp::listen(addr, Arc::clone(&snmp), control.clone())
+            .await
+            .map_err(|e| format!("Cannot bind SNMP {}: {}", addr, e))?;
+        info!("SNMP agent listening on {}", addr);
+    }
+
+    let step_timeout = Duration::from_secs(args.pre_suspend_timeout);
+    let pipelines = Pipelines::from_config(&config, step_timeout)?;
+    let named_actions = NamedActions::from_config(&config, step_timeout)?;
+    check_pipelines(&args, &pipelines, &named_actions)?;
+    let plan = Arc::new(SuspendPlan {
+        inhibitors,
+        backoff: Backoff {
+            retries: args.action_retries,
+            initial: Duration::from_secs(args.retry_backoff),
+        },
+        power: Power {
+            backend: args.backend,
+            mem_sleep: args.mem_sleep,
+            timeout: Duration::from_secs(args.action_timeout),
+            sys_power: PathBuf::from("/sys/power"),
+            host,
+        },
+        group: SleepGroup {
+            members: args.group_members.clone(),
+            wait_ack: args.group_wait_ack,
+            timeout: Duration::from_secs(args.group_timeout),
+            prepare: args.group_prepare,
+            unanswered: args.group_unanswered,
+            checks: checks.clone(),
+        },
+        containers: Containers {
+            specs: args.containers.clone(),
+            program: args.container_runtime.program(),
+            mode: args.container_mode,
+            default_timeout: args.container_timeout,
+        },
+        pre_suspend: PreSuspend {
+            sync: args.sync,
+            flush_commands: args.flush_commands.clone(),
+            timeout: step_timeout,
+        },
+        mounts: NetworkMounts {
+            paths: args.network_mounts.clone(),
+            mode: args.mount_mode,
+            timeout: step_timeout,
+        },
+        zfs: Zfs {
+            pools: args.zfs_pools.clone(),
+            scrub: args.zfs_scrub,
+            scrub_timeout: Duration::from_secs(args.zfs_scrub_timeout),
+            sync: args.zfs_sync,
+            sync_always: args.zfs_sync_always,
+            timeout: step_timeout,
+            saved: Mutex::default(),
+        },
+        wake_for: scheduled.filter(|_| args.scheduled_wake),
+        post_resume: PostResume {
+            commands: args.post_resume_commands.clone(),
+            timeout: step_timeout,
+        },
+        pipelines,
+        events: Arc::clone(&events),
+        verify_sleep: Duration::from_secs(args.verify_sleep),
+        resuspend: args.resuspend_after.map(Duration::from_secs),
+        status: Arc::clone(&control.status),
+        heartbeat: args.heartbeat.clone().map(|monitor| Heartbeat {
+            monitor,
+            interval: Duration::from_secs(args.heartbeat_interval.max(1)),
+            control: control.clone(),
+            started: Instant::now(),
+        }),
+    });
+    if let Some(heartbeat) = &plan.heartbeat {
+        heartbeat.start();
+        info!("Sending heartbeats to {} every {}s", heartbeat.monitor, heartbeat.interval.as_secs());
+    }
+    let relay = Relay::new(args.relays.clone()).await?;
+    let local_addrs = relay::local_addresses();
+    if args.seccomp {
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        {
+            let needs = seccomp::Needs {
+                mounts: !args.network_mounts.is_empty(),
+                reboot: args.backend == Backend::Sysfs,
+            };
+            seccomp::apply(needs).map_err(|e| format!("Failed to install seccomp filter: {}", e))?;
+            info!("Seccomp filter installed");
+        }
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        warn!("--seccomp is not supported on this architecture");
+    }
+
+    let names = Names::new(args.resolve_names);
+    let flood_policy = args.flood_threshold.filter(|threshold| *threshold > 0).map(|threshold| FloodPolicy {
+        threshold,
+        recover: Duration::from_secs(args.flood_recover),
+    });
+    let mut flood = Flood::new(flood_policy, Instant::now());
+    let mut rejections = Rejections::new((args.rejection_summary > 0).then(|| Duration::from_secs(args.rejection_summary)));
+    let mut capture = match &args.capture {
+        Some(path) => {
+            let max_size = args.capture_max_size * 1024 * 1024;
+            let capture = Capture::open(path.clone(), args.capture_snaplen, max_size, args.capture_keep)?;
+            info!("Capturing received packets to {}", path.display());
+            Some(capture)
+        }
+        None => None,
+    };
+    let mut flood_tick = tokio::time::interval(Duration::from_secs(1));
+    let mut summary_tick = tokio::time::interval(Duration::from_secs(1));
+    let mut drops_tick = tokio::time::interval(Duration::from_secs(10));
+    let mut pending: Option<JoinHandle<Outcome>> = None;
+    let mut call_off = Cancel::default();
+    let mut command_action: Option<JoinHandle<bool>> = None;
+    let mut active_profile = String::new();
+    let mut schedule = Schedule::new(args.schedules.clone());
 
     loop {
-        let (len, peer) = socket.recv_from(&mut buf).await?;
-        let packet = &buf[..len];
+        let mut handed = None;
+        let received = tokio::select! {
+            received = listeners.recv() => received,
+            Some((trigger, verdict)) = triggers.recv() => {
+                let received = Received { listener: 0, packet: Vec::new(), peer: trigger.peer, arrival: None };
+                handed = Some((trigger, verdict));
+                Some(received)
+            }
+            outcome = finished(&mut pending), if args.oneshot => match outcome {
+                // A cancelled trigger doesn't count, so keep waiting for the next one
+                Outcome::Cancelled => continue,
+                outcome => exit_oneshot(outcome),
+            },
+            _ = flood_tick.tick(), if flood.active() => {
+                if let Some(transition) = flood.tick(Instant::now()) {
+                    flood_changed(transition, &listeners, &mut control.status.lock().unwrap());
+                }
+                continue;
+            }
+            _ = summary_tick.tick(), if rejections.pending() => {
+                for line in rejections.flush(Instant::now()) {
+                    verbose!("{}", line);
+                }
+                continue;
+            }
+            _ = drops_tick.tick() => {
+                let drops = listeners.drops();
+                let mut status = control.status.lock().unwrap();
+                if drops > status.kernel_drops {
+                    warn!(
+                        "The kernel dropped {} packet(s) before they were read ({} since startup); the {} byte receive buffer \
+                         is full or the daemon is too slow, see --recv-buffer",
+                        drops - status.kernel_drops,
+                        drops,
+                        status.recv_buffer
+                    );
+                }
+                status.kernel_drops = drops;
+                continue;
+            }
+            entry = schedule.due() => {
+                let action = entry.action.unwrap_or(args.default_action);
+                let (_, policy) = profiles.current(&LocalTime::now());
+                if action == Action::Ignore {
+                    info!("Schedule '{}' reached, but its action is ignore", entry.text);
+                } else if pending.as_ref().is_some_and(|handle| !handle.is_finished()) {
+                    info!("Schedule '{}' reached, but a suspend is already pending", entry.text);
+                } else if let Some(reason) = plan.inhibited(&policy, false).await {
+                    info!("Scheduled {} inhibited ({})", action.as_str(), reason);
+                    plan.events.publish(Event::Inhibited { reason }).await;
+                    if args.oneshot {
+                        exit_oneshot(Outcome::Inhibited);
+                    }
+                } else {
+                    info!("Schedule '{}' reached, running {} in {}s unless cancelled", entry.text, action.as_str(), policy.grace);
+                    let source = format!("schedule '{}'", entry.text);
+                    let plan = Arc::clone(&plan);
+                    let status = Arc::clone(&control.status);
+                    status.lock().unwrap().suspend_pending = true;
+                    call_off = Cancel::default();
+                    let cancel = call_off.clone();
+                    pending = Some(tokio::spawn(async move {
+                        tokio::select! {
+                            _ = warn::countdown(Duration::from_secs(policy.grace), action, source, policy.warn_users) => {}
+                            _ = cancel.cancelled() => return Outcome::Cancelled,
+                        }
+                        status.lock().unwrap().suspend_pending = false;
+                        run_action(&plan, &policy, Decision::plain(action), true, &cancel).await
+                    }));
+                }
+                continue;
+            }
+        };
+        let Some(Received { listener, packet, peer, arrival }) = received else {
+            return Err("all listening sockets failed".into());
+        };
+        let (packet, len) = (packet.as_slice(), packet.len());
+        let (handed, verdict): (Option<control::Trigger>, _) = handed.unzip();
+        let mut answer = Answer { socket: listeners.socket(listener), peer, handed: verdict };
+        let socket = answer.socket;
+        let local = handed.as_ref().map_or(addresses[listener], |trigger| trigger.local);
+        let port = local.port();
+        if handed.is_none()
+            && let Some(writer) = &mut capture
+            && let Err(e) = writer.write(peer, local, packet, SystemTime::now())
+        {
+            error!("Packet capture stopped: {}", e);
+            capture = None;
+        }
+        if handed.is_none() && log::enabled(log::Level::Debug) && !flood.active() {
+            let hex: Vec<String> = packet.iter().map(|b| format!("{:02x}", b)).collect();
+            let via = arrival.as_ref().map_or(String::new(), |arrival| format!(" on {}", arrival));
+            debug!("{} bytes from {}{}: {}", len, peer, via, hex.join(""));
+        }
+        if bans.is_banned(peer.ip(), protocol::unix_now()) {
+            reject(&mut flood, &mut rejections, &listeners, &control, &mut answer, "Dropped packet", "banned").await;
+            continue;
+        }
+        if handed.is_none() && !flood.active() {
+            plan.events.publish(Event::PacketReceived { peer, len }).await;
+        }
+
+        if args.local_only && !interfaces::on_link(&netif::interfaces(), local.ip(), peer.ip()) {
+            reject(&mut flood, &mut rejections, &listeners, &control, &mut answer, "Rejected packet", "not on a local subnet").await;
+            strike(&bans, peer);
+            continue;
+        }
+        if args.require_broadcast && !peer.ip().to_canonical().is_loopback() {
+            let unicast = match (&arrival, &handed) {
+                (Some(arrival), _) if interfaces::is_broadcast(&netif::interfaces(), arrival.destination) => None,
+                (Some(arrival), _) => Some(format!("sent to unicast address {}", arrival.destination)),
+                (None, Some(trigger)) => Some(format!("sent over {}, not broadcast", trigger.via)),
+                (None, None) => Some("destination address unknown".to_string()),
+            };
+            if let Some(reason) = unicast {
+                reject(&mut flood, &mut rejections, &listeners, &control, &mut answer, "Rejected packet", &reason).await;
+                strike(&bans, peer);
+                continue;
+            }
+        }
+        if let Some(expected) = neighbors::expected(&args.sender_macs, peer.ip())
+            && let Err(e) = neighbors::verify(peer.ip(), expected).await
+        {
+            reject(&mut flood, &mut rejections, &listeners, &control, &mut answer, "Rejected packet", &e.to_string()).await;
+            strike(&bans, peer);
+            continue;
+        }
+        if let Some(trigger) = handed.as_ref().filter(|trigger| trigger.ask == Ask::Admit) {
+            if rules.decide(peer.ip(), &trigger.mac, port).action == Action::Ignore {
+                answer.forbid("ignored by rule").await;
+            } else {
+                answer.tell("ok: admitted").await;
+            }
+            continue;
+        }
+
+        if let Some(mac) = relay_target(packet, &local_macs)
+            && relay.handles(&mac)
+        {
+            if local_addrs.contains(&peer.ip()) {
+                continue;
+            }
+            match relay.forward(&mac, packet).await {
+                Ok(sent) => {
+                    for destination in sent {
+                        info!("Relayed packet from {} for {} to {}", peer, mac, destination);
+                    }
+                    if let Some(checks) = &checks
+                        && packet.starts_with(&packet::MAGIC_HEADER)
+                    {
+                        checks.spawn(mac.to_string(), mac, true);
+                    }
+                }
+                Err(e) => error!("Failed to relay packet from {}: {}", peer, e),
+            }
+            continue;
+        }
+
+        let mut run = None;
+        let command = if packet.starts_with(&v2::PREFIX) {
+            let accepted = verifier.accept(packet, &local_macs, protocol::unix_now()).and_then(|mut command| {
+                run = named_actions.resolve(&mut command)?;
+                Ok(command)
+            });
+            match accepted {
+                Ok(command) => Some(command),
+                Err(e) => {
+                    reject(&mut flood, &mut rejections, &listeners, &control, &mut answer, "Received invalid v2 packet", &e.to_string()).await;
+                    strike(&bans, peer);
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+
+        if let Some(protocol::Command { request: Request::Snooze(length), mac, .. }) = &command {
+            let sender = describe(&names, &flood, peer).await;
+            if rules.decide(peer.ip(), mac, port).action == Action::Ignore {
+                info!("Ignoring snooze from {} by rule", sender);
+                continue;
+            }
+            control.snooze.set(*length);
+            match control.snooze.describe() {
+                Some(left) => info!("Snoozed by {}, {}", sender, left),
+                None => info!("Snooze ended by {}", sender),
+            }
+            continue;
+        }
+
+        let cancel = match &command {
+            Some(command) => (command.request == Request::Cancel).then_some(Ok(command.mac)),
+            None => packet.starts_with(&packet::CANCEL_HEADER).then(|| validate_cancel_packet(packet, &local_macs, args.trailer(false))),
+        };
+        if let Some(cancel) = cancel {
+            match cancel {
+                Ok(mac) => {
+                    let sender = describe(&names, &flood, peer).await;
+                    quorum.reset(&mac);
+                    confirmations.cancel(&mac);
+                    if pending.as_ref().is_some_and(|handle| !handle.is_finished()) {
+                        // Past the grace period the action stops at its next step and undoes
+                        // the ones already taken, rather than leaving them half done
+                        call_off.cancel();
+                        if std::mem::take(&mut control.status.lock().unwrap().suspend_pending) {
+                            info!("Pending suspend cancelled by {}", sender);
+                            answer.tell("ok: pending suspend cancelled").await;
+                        } else {
+                            info!("Running suspend cancelled by {}", sender);
+                            answer.tell("ok: cancelling, steps already taken are undone").await;
+                        }
+                    } else {
+                        info!("Cancel packet received from {} but no suspend is pending", sender);
+                        answer.tell("ok: no suspend is pending").await;
+                    }
+                }
+                Err(e) => {
+                    reject(&mut flood, &mut rejections, &listeners, &control, &mut answer, "Received invalid cancel packet", &e.to_string()).await;
+                    strike(&bans, peer);
+                }
+            }
+            continue;
+        }
+
+        if packet.starts_with(&group::PREPARE_PACKET_HEADER) {
+            match validate_magic_packet(packet, &group::PREPARE_PACKET_HEADER, &local_macs, args.trailer(false)) {
+                Ok(mac) => {
+                    let (_, policy) = profiles.current(&LocalTime::now());
+                    let decision = rules.decide(peer.ip(), &mac, port);
+                    let readiness = if decision.action == Action::Ignore {
+                        Err("ignored by rule".to_string())
+                    } else {
+                        match plan.inhibited(&policy, decision.force).await {
+                            Some(reason) => Err(format!("inhibited ({})", reason)),
+                            None => Ok(()),
+                        }
+                    };
+                    let sender = describe(&names, &flood, peer).await;
+                    match &readiness {
+                        Ok(()) => info!("Group prepare request from {}: ready", sender),
+                        Err(reason) => info!("Group prepare request from {}: busy, {}", sender, reason),
+                    }
+                    if let Err(e) = socket.send_to(&group::readiness_packet(&mac, &readiness), peer).await {
+                        error!("Failed to answer group prepare request from {}: {}", peer, e);
+                    }
+                }
+                Err(e) => reject(&mut flood, &mut rejections, &listeners, &control, &mut answer, "Received invalid group prepare request", &e).await,
+            }
+            continue;
+        }
 
-        match validate_wol_packet(packet, &local_macs) {
-            Ok(mac) => {
-                println!("Valid WoL packet received from {} for MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-                         peer, mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]);
+        if packet.starts_with(&selftest::PROBE_PACKET_HEADER) {
+            let reply = match validate_magic_packet(packet, &selftest::PROBE_PACKET_HEADER, &local_macs, args.trailer(false)) {
+                Ok(mac) => {
+                    let (_, policy) = profiles.current(&LocalTime::now());
+                    let decision = rules.decide(peer.ip(), &mac, port);
+                    probe_verdict(decision.action, &policy, plan.inhibited(&policy, decision.force).await)
+                }
+                Err(e) => format!("error: {}", e),
+            };
+            info!("Test packet from {}: {}", describe(&names, &flood, peer).await, reply);
+            if let Err(e) = socket.send_to(reply.as_bytes(), peer).await {
+                error!("Failed to answer test packet from {}: {}", peer, e);
+            }
+            continue;
+        }
+
+        let validated = match (&command, &handed) {
+            (Some(command), _) => Ok((command.mac, false)),
+            (None, Some(trigger)) => Ok((trigger.mac, false)),
+            (None, None) => validate_trigger(packet, &local_macs, args.trailer(true), &confirmations),
+        };
+        match validated {
+            Ok((mac, echo)) => {
+                let (profile, mut policy) = profiles.current(&LocalTime::now());
+                if profile != active_profile {
+                    info!("Using profile {}", profile);
+                    active_profile = profile;
+                }
+                quorum.configure(policy.quorum, Duration::from_secs(policy.quorum_window));
+
+                if args.match_interface_mac
+                    && let Some(arrival) = &arrival
+                    && let Err(e) = interfaces::owns(&netif::interfaces(), arrival.index, &mac)
+                {
+                    reject(&mut flood, &mut rejections, &listeners, &control, &mut answer, "Rejected packet", &e).await;
+                    strike(&bans, peer);
+                    continue;
+                }
+                let sender = describe(&names, &flood, peer).await;
+                match (&arrival, &handed) {
+                    (Some(arrival), _) => info!("Valid WoL packet received from {} for MAC {} on {}", sender, mac, arrival),
+                    (None, Some(trigger)) => info!("Valid {} trigger received from {} for MAC {}", trigger.via, sender, mac),
+                    (None, None) => info!("Valid WoL packet received from {} for MAC {}", sender, mac),
+                }
+                plan.events.publish(Event::Validated { sender: sender.clone(), mac, arrival: arrival.clone() }).await;
+                let mut decision = rules.decide(peer.ip(), &mac, port);
+                if let Some(command) = &command {
+                    if let Some(name) = command.pipeline.as_deref().filter(|name| !plan.pipelines.contains(name)) {
+                        verbose!("Ignoring v2 packet from {}: pipeline '{}' is not defined", sender, name);
+                        answer.forbid(&format!("pipeline '{}' is not defined", name)).await;
+                        continue;
+                    }
+                    decision = command.apply(decision);
+                    if let Some(grace) = command.grace {
+                        policy.grace = grace;
+                    }
+                }
+                let (action, force) = (decision.action, decision.force);
+                if action == Action::Ignore {
+                    info!("Ignoring packet from {} by rule", sender);
+                    answer.forbid("ignored by rule").await;
+                    continue;
+                }
+                if let Some(step) = run {
+                    if command_action.as_ref().is_some_and(|handle| !handle.is_finished()) {
+                        info!("Ignoring action {} from {}: a command action is already running", step.name, sender);
+                        answer.tell("error: a command action is already running").await;
+                        continue;
+                    }
+                    // Command actions skip the other inhibitors, but not maintenance mode or pause
+                    if let Some(reason) = plan.inhibited(&policy, true).await {
+                        info!("Action {} inhibited ({})", step.name, reason);
+                        answer.tell(&format!("error: inhibited ({})", reason)).await;
+                        plan.events.publish(Event::Inhibited { reason }).await;
+                        if args.oneshot {
+                            exit_oneshot(Outcome::Inhibited);
+                        }
+                        continue;
+                    }
+                    info!("Running action {} for {}", step.name, sender);
+                    answer.tell(&format!("ok: running action {}", step.name)).await;
+                    if args.oneshot {
+                        exit_oneshot(if step.run().await { Outcome::Done } else { Outcome::Failed });
+                    }
+                    command_action = Some(tokio::spawn(async move { step.run().await }));
+                    continue;
+                }
+                if force {
+                    info!("Forcing {} by rule, skipping quorum, confirmation and all but the hard inhibitors", action.as_str());
+                }
+                control.status.lock().unwrap().last_trigger = Some(sender);
 
-                match suspend_system() {
-                    Ok(_) => println!("System suspend initiated"),
-                    Err(e) => eprintln!("Failed to suspend system: {}", e),
+                let now = Instant::now();
+                let confirmed = policy.confirm
+                    && echo
+                    && confirm::packet_token(packet)
+                        .is_some_and(|token| confirmations.confirm(&mac, token, now));
+                if echo && !confirmed {
+                    // The token stood in for the trailer, so it must not pass as a plain trigger
+                    reject(&mut flood, &mut rejections, &listeners, &control, &mut answer, "Rejected confirmation", "wrong or expired token").await;
+                    strike(&bans, peer);
+                    continue;
+                }
+
+                if !confirmed && !force {
+                    let count = quorum.record(mac, now);
+                    if !quorum.is_met(count) {
+                        info!("Quorum not yet reached ({}/{} packets within {}s)",
+                                 count, quorum.required(), policy.quorum_window);
+                        let verdict = format!("ok: quorum not yet reached ({}/{})", count, quorum.required());
+                        answer.tell(&verdict).await;
+                        continue;
+                    }
+                    quorum.reset(&mac);
+
+                    if policy.confirm && handed.is_some() {
+                        answer.tell("error: --confirm is on, and only a packet sender can confirm").await;
+                        continue;
+                    }
+                    if policy.confirm {
+                        match confirmations.arm(mac, now) {
+                            Ok(token) => {
+                                let reply = confirm::confirmation_packet(&mac, &token);
+                                match socket.send_to(&reply, peer).await {
+                                    Ok(_) => info!("Suspend armed, confirmation token sent to {}", peer),
+                                    Err(e) => error!("Failed to send confirmation token to {}: {}", peer, e),
+                                }
+                                answer.tell("ok: suspend armed, waiting for confirmation").await;
+                            }
+                            Err(e) => {
+                                error!("Failed to generate confirmation token: {}", e);
+                                answer.tell(&format!("error: {}", e)).await;
+                            }
+                        }
+                        continue;
+                    }
+                }
+
+                let propagate = !plan.group.is_member(peer.ip());
+                if pending.as_ref().is_some_and(|handle| !handle.is_finished()) {
+                    info!("Suspend already pending");
+                    answer.tell("ok: suspend already pending").await;
+                } else if let Some(reason) = plan.inhibited(&policy, force).await {
+                    info!("Suspend inhibited ({})", reason);
+                    answer.tell(&format!("error: inhibited ({})", reason)).await;
+                    plan.events.publish(Event::Inhibited { reason }).await;
+                    if args.oneshot {
+                        exit_oneshot(Outcome::Inhibited);
+                    }
+                } else {
+                    answer.ack(&mac).await;
+                    // Even without a grace period the action runs in its own task, so the
+                    // loop keeps answering packets and a cancel can still call off the steps
+                    if policy.grace > 0 {
+                        info!("Running {} in {}s unless cancelled", action.as_str(), policy.grace);
+                    }
+                    let grace = Duration::from_secs(policy.grace);
+                    let warn_users = policy.warn_users;
+                    let plan = Arc::clone(&plan);
+                    let status = Arc::clone(&control.status);
+                    status.lock().unwrap().suspend_pending = true;
+                    call_off = Cancel::default();
+                    let cancel = call_off.clone();
+                    pending = Some(tokio::spawn(async move {
+                        if !grace.is_zero() {
+                            tokio::select! {
+                                _ = warn::countdown(grace, action, peer.ip().to_string(), warn_users) => {}
+                                _ = cancel.cancelled() => return Outcome::Cancelled,
+                            }
+                        }
+                        status.lock().unwrap().suspend_pending = false;
+                        run_action(&plan, &policy, decision, propagate, &cancel).await
+                    }));
                 }
             }
             Err(e) => {
-                eprintln!("Received invalid packet from {}: {}", peer, e);
+                reject(&mut flood, &mut rejections, &listeners, &control, &mut answer, "Received invalid packet", &e.to_string()).await;
+                strike(&bans, peer);
             }
         }
     }
 }
 
-fn validate_wol_packet(packet: &[u8], local_macs: &[[u8; 6]]) -> Result<[u8; 6], String> {
-    if packet.len() < EXPECTED_PACKET_SIZE {
-        return Err(format!("Invalid size: {} (expected {})", packet.len(), EXPECTED_PACKET_SIZE));
+/// Count a rejected packet towards flood protection and log it as `KIND from PEER: REASON`,
+/// sampled during a flood and summarized when the same sender repeats the same reason.
+/// Logged rejections also go into the event history, and the sender is told the reason if it
+/// can be answered.
+async fn reject(
+    flood: &mut Flood,
+    rejections: &mut Rejections,
+    listeners: &Listeners,
+    control: &Control,
+    answer: &mut Answer<'_>,
+    kind: &str,
+    reason: &str,
+) {
+    let peer = answer.peer;
+    let now = Instant::now();
+    let (log, transition) = flood.reject(now);
+    if rejections.record(peer.ip(), reason, now) && log {
+        verbose!("{} from {}: {}", kind, peer, reason);
+        control.history.record("rejected", &format!("sender={} reason={}: {}", peer, kind, reason));
+    }
+    {
+        let mut status = control.status.lock().unwrap();
+        status.rejected_packets = status.rejected_packets.wrapping_add(1);
+        if let Some(transition) = transition {
+            flood_changed(transition, listeners, &mut status);
+        }
     }
+    answer.forbid(reason).await;
+}
 
-    // Verify magic packet header (6 bytes of 0xFF)
-    if &packet[0..6] != MAGIC_PACKET_HEADER {
-        return Err("Invalid header".to_string());
+/// Who hears what became of a trigger: a packet sender on loopback, such as `sol cancel`, or
+/// whatever handed the trigger in through `Control`
+struct Answer<'a> {
+    socket: &'a UdpSocket,
+    peer: SocketAddr,
+    handed: Option<control::Verdict>,
+}
+
+impl Answer<'_> {
+    /// Pass on an `ok:` or `error:` line
+    async fn tell(&mut self, verdict: &str) {
+        if let Some(handed) = self.handed.take() {
+            let verdict = match verdict.strip_prefix("error: ") {
+                Some(error) => Err(Refusal::Conflict(error.to_string())),
+                None => Ok(verdict.strip_prefix("ok: ").unwrap_or(verdict).to_string()),
+            };
+            let _ = handed.send(verdict);
+        } else if self.peer.ip().to_canonical().is_loopback()
+            && let Err(e) = self.socket.send_to(verdict.as_bytes(), self.peer).await
+        {
+            error!("Failed to answer {}: {}", self.peer, e);
+        }
     }
 
-    // Extract MAC address (should be repeated 16 times after header)
-    let mac = &packet[6..12];
+    /// Tell the sender it may not do what it asked, and why
+    async fn forbid(&mut self, reason: &str) {
+        match self.handed.take() {
+            Some(handed) => {
+                let _ = handed.send(Err(Refusal::Forbidden(reason.to_string())));
+            }
+            None => self.tell(&format!("error: {}", reason)).await,
+        }
+    }
 
-    // Verify MAC is repeated 16 times
-    for i in 1..16 {
-        if &packet[6 + i*6..6 + (i+1)*6] != mac {
-            return Err("Invalid MAC repetition".to_string());
+    /// Tell the sender the trigger for `mac` was accepted, for daemons waiting on their sleep group
+    async fn ack(&mut self, mac: &MacAddr) {
+        if let Some(handed) = self.handed.take() {
+            let _ = handed.send(Ok("accepted".to_string()));
+        } else if let Err(e) = self.socket.send_to(&group::ack_packet(mac), self.peer).await {
+            error!("Failed to acknowledge trigger from {}: {}", self.peer, e);
         }
     }
+}
 
-    let mut mac_array = [0u8; 6];
-    mac_array.copy_from_slice(mac);
+fn flood_changed(transition: Transition, listeners: &Listeners, status: &mut control::Status) {
+    match transition {
+        Transition::Started(rate) => {
+            warn!("Flood protection on: {} rejected packets in one second, logging 1 in 100 and filtering in the kernel", rate);
+            listeners.set_strict(true);
+            status.flood = true;
+        }
+        Transition::Ended(unlogged) => {
+            info!("Flood protection off, {} rejected packets were not logged", unlogged);
+            listeners.set_strict(false);
+            status.flood = false;
+        }
+    }
+}
+
+/// The sender for log lines, skipping the reverse lookup during a flood
+async fn describe(names: &Names, flood: &Flood, peer: SocketAddr) -> String {
+    if flood.active() { peer.to_string() } else { names.describe(peer).await }
+}
+
+/// Count a rejected packet towards banning its sender
+fn strike(bans: &Bans, peer: SocketAddr) {
+    // Loopback senders are local tools such as `sol cancel` and the console
+    if peer.ip().to_canonical().is_loopback() {
+        return;
+    }
+    if bans.strike(peer.ip(), protocol::unix_now()) {
+        warn!("Banned {} after repeated rejected packets", peer.ip());
+    }
+}
+
+/// What a real trigger would do right now, reported in answer to a test packet
+fn probe_verdict(action: Action, policy: &Policy, inhibited: Option<String>) -> String {
+    if action == Action::Ignore {
+        return "blocked: ignored by rule".to_string();
+    }
+    if let Some(reason) = inhibited {
+        return format!("blocked: inhibited ({})", reason);
+    }
+
+    let mut verdict = format!("ok: would {}", action.as_str());
+    if policy.quorum > 1 {
+        verdict += &format!(" after {} packets within {}s", policy.quorum, policy.quorum_window);
+    }
+    if policy.confirm {
+        verdict += " after confirmation";
+    }
+    if policy.grace > 0 {
+        verdict += &format!(" after a {}s grace period", policy.grace);
+    }
+    verdict
+}
+
+/// Resolve HOST[:PORT], with `port` when none is given
+async fn resolve_target(target: String, port: u16) -> Result<SocketAddr, String> {
+    let target = match target.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => target,
+        _ => format!("{}:{}", target, port),
+    };
+    tokio::net::lookup_host(&target)
+        .await
+        .map_err(|e| format!("Cannot resolve {}: {}", target, e))?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve {}", target))
+}
+
+async fn send_cancel(port: u16, local_macs: &[MacAddr]) -> Result<(), Box<dyn std::error::Error>> {
+    let mac = local_macs.first().ok_or("No network interfaces with MAC addresses found")?;
+
+    let verdict = control::send_local(&packet::CANCEL_HEADER, port, mac).await?;
+    println!("{}", verdict);
+
+    Ok(())
+}
+
+/// Validate a WoL trigger, and say whether it is a confirmation echo: the packet followed by
+/// the token of a suspend armed for its MAC. The token takes the place of the trailer, so an
+/// echo needs neither the SecureOn password nor padding.
+fn validate_trigger(packet: &[u8], local_macs: &[MacAddr], trailer: Trailer, confirmations: &Confirmations) -> Result<(MacAddr, bool), String> {
+    let mac = packet_mac(packet, &packet::MAGIC_HEADER)?;
+    if packet.len() == packet::PACKET_SIZE + confirm::TOKEN_SIZE && confirmations.is_pending(&mac) && local_macs.contains(&mac) {
+        return Ok((mac, true));
+    }
+    validate_wol_packet(packet, local_macs, trailer).map(|mac| (mac, false))
+}
+
+fn validate_wol_packet(packet: &[u8], local_macs: &[MacAddr], trailer: Trailer) -> Result<MacAddr, String> {
+    validate_magic_packet(packet, &packet::MAGIC_HEADER, local_macs, trailer)
+}
+
+/// Cancel packets use the WoL layout with an inverted (all-zero) header
+fn validate_cancel_packet(packet: &[u8], local_macs: &[MacAddr], trailer: Trailer) -> Result<MacAddr, String> {
+    validate_magic_packet(packet, &packet::CANCEL_HEADER, local_macs, trailer)
+}
+
+fn validate_magic_packet(packet: &[u8], header: &[u8; 6], local_macs: &[MacAddr], trailer: Trailer) -> Result<MacAddr, String> {
+    let mac = packet_mac(packet, header)?;
+    packet::check_trailer(packet, trailer).map_err(|e| e.to_string())?;
 
     // Verify MAC matches one of the local interfaces
-    if !local_macs.contains(&mac_array) {
-        return Err(format!(
-            "MAC address {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x} does not match any local interface",
-            mac_array[0], mac_array[1], mac_array[2], mac_array[3], mac_array[4], mac_array[5]
-        ));
+    if !local_macs.contains(&mac) {
+        return Err(format!("MAC address {} does not match any local interface", mac));
     }
 
-    Ok(mac_array)
+    Ok(mac)
+}
+
+/// The MAC of a well-formed WoL or cancel packet addressed to another machine
+fn relay_target(packet: &[u8], local_macs: &[MacAddr]) -> Option<MacAddr> {
+    [packet::MAGIC_HEADER, packet::CANCEL_HEADER]
+        .iter()
+        .find_map(|header| packet_mac(packet, header).ok())
+        .filter(|mac| !local_macs.contains(mac))
+}
+
+/// Check the packet layout and return the MAC it carries
+fn packet_mac(packet: &[u8], header: &[u8; 6]) -> Result<MacAddr, String> {
+    packet::parse(packet, header).map(MacAddr).map_err(|e| e.to_string())
 }
 
-fn get_local_mac_addresses() -> Vec<[u8; 6]> {
+fn get_local_mac_addresses() -> Vec<MacAddr> {
     let mut macs = Vec::new();
 
-    for iface in datalink::interfaces() {
-        if let Some(mac) = iface.mac {
-            macs.push(mac.octets());
+    for iface in netif::interfaces() {
+        if let Some(mac) = iface.mac
+            && interfaces::monitored(&iface)
+        {
+            macs.push(mac);
         }
     }
 
     macs
 }
 
-fn suspend_system() -> Result<(), Box<dyn std::error::Error>> {
-    let output = Command::new("systemctl")
-        .arg("suspend")
-        .output()?;
+/// Log a step that stopped the action and publish it as a failure
+async fn fail(plan: &SuspendPlan, detail: String) {
+    error!("{}", detail);
+    plan.events.publish(Event::ActionFailed { detail }).await;
+}
+
+/// How a triggered action ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    /// Carried out; a suspend or hibernate also resumed
+    Done,
+    Inhibited,
+    /// A step or the power command failed
+    Failed,
+    /// The action reported success but the system never slept
+    Missed,
+    /// Called off before the power command; steps already taken were undone
+    Cancelled,
+}
+
+impl Outcome {
+    /// Exit status for `--oneshot`; 1 and 2 are left to errors and usage mistakes
+    fn code(self) -> i32 {
+        match self {
+            Outcome::Done => 0,
+            Outcome::Inhibited => 3,
+            Outcome::Failed => 4,
+            Outcome::Missed => 5,
+            Outcome::Cancelled => 6,
+        }
+    }
+}
+
+/// Calls off a triggered action: it ends the grace period, is checked between the steps
+/// before the power command and stops a re-suspend
+#[derive(Clone)]
+struct Cancel(Arc<tokio::sync::watch::Sender<bool>>);
+
+impl Default for Cancel {
+    fn default() -> Self {
+        Cancel(Arc::new(tokio::sync::watch::Sender::new(false)))
+    }
+}
 
-    if !output.status.success() {
-        return Err(format!(
-            "systemctl suspend failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ).into());
+impl Cancel {
+    fn cancel(&self) {
+        self.0.send_replace(true);
     }
 
-    Ok(())
+    fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Completes once `cancel` has been called
+    async fn cancelled(&self) {
+        let _ = self.0.subscribe().wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// Wait for the pending action to end. Never completes while nothing is pending.
+async fn finished(pending: &mut Option<JoinHandle<Outcome>>) -> Outcome {
+    let Some(handle) = pending.as_mut() else {
+        return std::future::pending().await;
+    };
+    let outcome = handle.await.unwrap_or(Outcome::Failed);
+    *pending = None;
+    outcome
+}
+
+fn exit_oneshot(outcome: Outcome) -> ! {
+    info!("Oneshot trigger handled ({:?}), exiting with status {}", outcome, outcome.code());
+    std::process::exit(outcome.code())
+}
+
+/// Run `action` with all its surrounding steps, then keep suspending again while
+/// `--resuspend-after` finds nobody using the machine after it resumes
+async fn run_action(plan: &SuspendPlan, policy: &Policy, decision: Decision, propagate: bool, cancel: &Cancel) -> Outcome {
+    // Reading the counts runs ethtool, so keep it off the runtime's threads
+    let wakeups = async || match plan.resuspend {
+        Some(_) => tokio::task::spawn_blocking(resuspend::Wakeups::read).await.unwrap_or_default(),
+        None => resuspend::Wakeups::default(),
+    };
+    let mut before = wakeups().await;
+    let mut outcome = run_once(plan, policy, &decision, propagate, cancel).await;
+    while outcome == Outcome::Done
+        && decision.action.resumes()
+        && let Some(delay) = plan.resuspend
+    {
+        if let Some(cause) = before.cause(&wakeups().await) {
+            info!("Staying awake after resume ({})", cause);
+            break;
+        }
+        let mut activity = Inhibitors::default();
+        activity.push(Box::new(resuspend::WakeActivity { window: delay, resumed: resuspend::monotonic_usec() }));
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = cancel.cancelled() => {
+                info!("Re-suspend cancelled, staying awake");
+                break;
+            }
+        }
+        let inhibited = match plan.inhibited(policy, false).await {
+            Some(reason) => Some(reason),
+            // The activity check runs loginctl, so it goes on the blocking pool too
+            None => tokio::task::spawn_blocking(move || activity.active()).await.unwrap_or_default(),
+        };
+        if let Some(reason) = inhibited {
+            info!("Staying awake after resume ({})", reason);
+            break;
+        }
+        info!("Nothing used the machine within {}s of resuming, running {} again", delay.as_secs(), decision.action.as_str());
+        let again = Decision { force: false, ..decision.clone() };
+        before = wakeups().await;
+        outcome = run_once(plan, policy, &again, false, cancel).await;
+    }
+    outcome
+}
+
+/// How far `run_once` got with the steps before the power command
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    Containers,
+    Zfs,
+    Mounts,
+    Pipeline,
+}
+
+/// Undo the steps taken up to `stage`, in reverse
+async fn undo(plan: &SuspendPlan, pipeline: Option<&Pipeline>, stage: Stage) {
+    if stage >= Stage::Pipeline
+        && let Some(pipeline) = pipeline
+    {
+        pipeline.after().await;
+    }
+    if stage >= Stage::Mounts {
+        plan.mounts.restore().await;
+    }
+    if stage >= Stage::Zfs {
+        plan.zfs.restore().await;
+    }
+    if stage >= Stage::Containers {
+        plan.containers.restore().await;
+    }
+}
+
+/// Whether the action was cancelled, in which case the steps up to `stage` are undone
+async fn called_off(plan: &SuspendPlan, cancel: &Cancel, pipeline: Option<&Pipeline>, stage: Stage) -> bool {
+    if !cancel.is_cancelled() {
+        return false;
+    }
+    info!("Suspend cancelled, undoing the steps already taken");
+    undo(plan, pipeline, stage).await;
+    plan.events.publish(Event::ActionFailed { detail: "cancelled".to_string() }).await;
+    true
+}
+
+/// Run the decided action once with all its surrounding steps. With `propagate`, the sleep
+/// group is put to sleep first; a forced decision skips all but the hard inhibitors. `cancel` is checked
+/// between the steps before the power command.
+async fn run_once(plan: &SuspendPlan, policy: &Policy, decision: &Decision, propagate: bool, cancel: &Cancel) -> Outcome {
+    let action = decision.action;
+    if let Some(reason) = plan.inhibited(policy, decision.force).await {
+        info!("Suspend inhibited ({})", reason);
+        plan.events.publish(Event::Inhibited { reason }).await;
+        return Outcome::Inhibited;
+    }
+    if propagate && let Err(reason) = plan.group.prepare().await {
+        info!("Suspend held back: {}", reason);
+        plan.events.publish(Event::Inhibited { reason }).await;
+        return Outcome::Inhibited;
+    }
+    let pipeline = plan.pipelines.get(decision.pipeline.as_deref());
+    if cancel.is_cancelled() {
+        info!("Suspend cancelled");
+        return Outcome::Cancelled;
+    }
+    plan.events.publish(Event::ActionStarted { action }).await;
+
+    if propagate {
+        plan.group.propagate().await;
+    }
+
+    if let Err(e) = plan.containers.prepare().await {
+        fail(plan, format!("Failed to pause containers, not suspending: {}", e)).await;
+        return Outcome::Failed;
+    }
+
+    if let Err(e) = plan.pre_suspend.run().await {
+        fail(plan, format!("Pre-suspend step failed, not suspending: {}", e)).await;
+        undo(plan, pipeline, Stage::Containers).await;
+        return Outcome::Failed;
+    }
+    if called_off(plan, cancel, pipeline, Stage::Containers).await {
+        return Outcome::Cancelled;
+    }
+
+    if plan.zfs.enabled()
+        && let Err(e) = plan.zfs.prepare().await
+    {
+        fail(plan, format!("ZFS step failed, not suspending: {}", e)).await;
+        undo(plan, pipeline, Stage::Containers).await;
+        return Outcome::Failed;
+    }
+    if called_off(plan, cancel, pipeline, Stage::Zfs).await {
+        return Outcome::Cancelled;
+    }
+
+    if let Err(e) = plan.mounts.prepare().await {
+        fail(plan, format!("Failed to take network mounts offline, not suspending: {}", e)).await;
+        undo(plan, pipeline, Stage::Zfs).await;
+        return Outcome::Failed;
+    }
+    if called_off(plan, cancel, pipeline, Stage::Mounts).await {
+        return Outcome::Cancelled;
+    }
+
+    if let Some(pipeline) = pipeline
+        && let Err(e) = pipeline.before().await
+    {
+        fail(plan, format!("Pipeline failed, not suspending: {}", e)).await;
+        undo(plan, Some(pipeline), Stage::Pipeline).await;
+        return Outcome::Failed;
+    }
+    if called_off(plan, cancel, pipeline, Stage::Pipeline).await {
+        return Outcome::Cancelled;
+    }
+
+    if let Some(jobs) = &plan.wake_for
+        && action.resumes()
+    {
+        match jobs.set_wake_alarm() {
+            Ok(Some((job, at))) => info!("Waking at {} for {}", LocalTime::from_unix(at as libc::time_t), job.name),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to set a wake alarm for the next scheduled job: {}", e),
+        }
+    }
+    if let Some(heartbeat) = &plan.heartbeat {
+        heartbeat.beat(Some(action)).await;
+    }
+    let before = Clocks::now();
+    match plan.backoff.run(action.as_str(), || plan.power.run(action)).await {
+        Ok(()) => {
+            info!("System {} initiated", action.as_str());
+            plan.events.publish(Event::ActionFinished { action }).await;
+        }
+        Err(e) => {
+            let retries = plan.backoff.retries;
+            fail(plan, format!("Failed to {} system after {} retries: {}", action.as_str(), retries, e)).await;
+            undo(plan, pipeline, Stage::Pipeline).await;
+            return Outcome::Failed;
+        }
+    }
+
+    let mut outcome = Outcome::Done;
+    if action.resumes() {
+        // A zero exit from systemctl does not mean the system slept: logind may still refuse,
+        // or a wakeup source may abort the suspend straight away
+        let slept = if plan.power.returns_after_resume(action) {
+            before.slept()
+        } else {
+            resume::wait_for_resume(&before, plan.verify_sleep).await
+        };
+        if slept {
+            info!("System resumed");
+            plan.events.publish(Event::Resumed { action }).await;
+            if let Some(heartbeat) = &plan.heartbeat {
+                heartbeat.beat(None).await;
+            }
+        } else {
+            let detail = format!(
+                "{} reported success but the system did not sleep within {}s",
+                action.as_str(),
+                plan.verify_sleep.as_secs()
+            );
+            error!("{}", detail);
+            plan.status.lock().unwrap().missed_sleeps += 1;
+            plan.events.publish(Event::MissedSleep { detail }).await;
+            outcome = Outcome::Missed;
+        }
+    }
+
+    if action.resumes()
+        && let Some(pipeline) = pipeline
+    {
+        pipeline.after().await;
+    }
+    if action.resumes() && plan.has_resume_steps() {
+        plan.mounts.restore().await;
+        plan.zfs.restore().await;
+        plan.containers.restore().await;
+        plan.post_resume.run().await;
+    }
+    outcome
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn create_valid_wol_packet(mac: &[u8; 6]) -> Vec<u8> {
+    fn create_valid_wol_packet(mac: &MacAddr) -> Vec<u8> {
         let mut packet = vec![0xFF; 6];
         for _ in 0..16 {
-            packet.extend_from_slice(mac);
+            packet.extend_from_slice(&mac.0);
         }
         packet
     }
 
     #[test]
     fn test_valid_wol_packet() {
-        let mac = [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF];
+        let mac = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
         let packet = create_valid_wol_packet(&mac);
         let local_macs = vec![mac];
 
-        let result = validate_wol_packet(&packet, &local_macs);
+        let result = validate_wol_packet(&packet, &local_macs, Trailer::Padding);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), mac);
     }
@@ -146,8 +2505,8 @@ mod tests {
     #[test]
     fn test_packet_too_short() {
         let packet = vec![0xFF; 50];
-        let local_macs = vec![[0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]];
-        let result = validate_wol_packet(&packet, &local_macs);
+        let local_macs = vec![MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF])];
+        let result = validate_wol_packet(&packet, &local_macs, Trailer::Padding);
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Invalid size"));
     }
@@ -155,13 +2514,13 @@ mod tests {
     #[test]
     fn test_invalid_header() {
         let mut packet = vec![0xAA; 6];
-        let mac = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+        let mac = MacAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
         for _ in 0..16 {
-            packet.extend_from_slice(&mac);
+            packet.extend_from_slice(&mac.0);
         }
 
         let local_macs = vec![mac];
-        let result = validate_wol_packet(&packet, &local_macs);
+        let result = validate_wol_packet(&packet, &local_macs, Trailer::Padding);
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Invalid header"));
     }
@@ -169,43 +2528,43 @@ mod tests {
     #[test]
     fn test_invalid_mac_repetition() {
         let mut packet = vec![0xFF; 6];
-        let mac1 = [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF];
-        let mac2 = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+        let mac1 = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let mac2 = MacAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
 
-        packet.extend_from_slice(&mac1);
+        packet.extend_from_slice(&mac1.0);
         for _ in 1..16 {
-            packet.extend_from_slice(&mac2);
+            packet.extend_from_slice(&mac2.0);
         }
 
         let local_macs = vec![mac1, mac2];
-        let result = validate_wol_packet(&packet, &local_macs);
+        let result = validate_wol_packet(&packet, &local_macs, Trailer::Padding);
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Invalid MAC repetition"));
     }
 
     #[test]
     fn test_exact_packet_size() {
-        let mac = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        let mac = MacAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
         let packet = create_valid_wol_packet(&mac);
-        assert_eq!(packet.len(), EXPECTED_PACKET_SIZE);
+        assert_eq!(packet.len(), packet::PACKET_SIZE);
 
         let local_macs = vec![mac];
-        let result = validate_wol_packet(&packet, &local_macs);
+        let result = validate_wol_packet(&packet, &local_macs, Trailer::Padding);
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_different_mac_addresses() {
         let test_macs = [
-            [0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-            [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
-            [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB],
+            MacAddr([0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            MacAddr([0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+            MacAddr([0x01, 0x23, 0x45, 0x67, 0x89, 0xAB]),
         ];
 
         for mac in &test_macs {
             let packet = create_valid_wol_packet(mac);
             let local_macs = vec![*mac];
-            let result = validate_wol_packet(&packet, &local_macs);
+            let result = validate_wol_packet(&packet, &local_macs, Trailer::Padding);
             assert!(result.is_ok());
             assert_eq!(result.unwrap(), *mac);
         }
@@ -213,13 +2572,245 @@ mod tests {
 
     #[test]
     fn test_mac_not_in_local_interfaces() {
-        let packet_mac = [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF];
-        let local_mac = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+        let packet_mac = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let local_mac = MacAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
         let packet = create_valid_wol_packet(&packet_mac);
         let local_macs = vec![local_mac];
 
-        let result = validate_wol_packet(&packet, &local_macs);
+        let result = validate_wol_packet(&packet, &local_macs, Trailer::Padding);
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("does not match any local interface"));
     }
+
+    #[test]
+    fn test_padded_packet() {
+        let mac = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let mut packet = create_valid_wol_packet(&mac);
+        packet.resize(128, 0);
+        let local_macs = vec![mac];
+
+        assert_eq!(validate_wol_packet(&packet, &local_macs, Trailer::Padding), Ok(mac));
+        assert!(validate_wol_packet(&packet, &local_macs, Trailer::Exact).unwrap_err().contains("Invalid size: 128"));
+
+        packet.truncate(packet::PACKET_SIZE);
+        packet.extend_from_slice(&[192, 168, 1, 1]);
+        assert!(validate_wol_packet(&packet, &local_macs, Trailer::Padding).unwrap_err().contains("Invalid data"));
+        let password: SecureOn = "192.168.1.1".parse().unwrap();
+        assert_eq!(validate_wol_packet(&packet, &local_macs, Trailer::Password(&password.0)), Ok(mac));
+        let password: SecureOn = "00:11:22:33:44:55".parse().unwrap();
+        assert_eq!(password.0.len(), 6);
+        assert!(validate_wol_packet(&packet, &local_macs, Trailer::Password(&password.0)).unwrap_err().contains("SecureOn"));
+        assert!("secret".parse::<SecureOn>().is_err());
+    }
+
+    #[test]
+    fn test_confirmation_echo() {
+        let mac = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let local_macs = vec![mac];
+        let password: SecureOn = "192.168.1.1".parse().unwrap();
+        let mut confirmations = Confirmations::new(Duration::from_secs(30));
+        let now = Instant::now();
+        let token = confirmations.arm(mac, now).unwrap();
+        let echo = confirm::confirmation_packet(&mac, &token);
+
+        for trailer in [Trailer::Padding, Trailer::Exact, Trailer::Password(&password.0)] {
+            assert_eq!(validate_trigger(&echo, &local_macs, trailer, &confirmations), Ok((mac, true)));
+        }
+        assert!(confirmations.confirm(&mac, confirm::packet_token(&echo).unwrap(), now));
+
+        // With nothing armed the token is just a trailer, and a random one is not padding
+        let mut echo = echo;
+        echo[packet::PACKET_SIZE..].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        assert!(validate_trigger(&echo, &local_macs, Trailer::Padding, &confirmations).unwrap_err().contains("Invalid data"));
+        assert!(validate_trigger(&echo, &local_macs, Trailer::Password(&password.0), &confirmations).unwrap_err().contains("SecureOn"));
+        assert_eq!(validate_trigger(&create_valid_wol_packet(&mac), &local_macs, Trailer::Padding, &confirmations), Ok((mac, false)));
+    }
+
+    #[test]
+    fn test_cancel_packet() {
+        let mac = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let mut packet = vec![0x00; 6];
+        for _ in 0..16 {
+            packet.extend_from_slice(&mac.0);
+        }
+        let local_macs = vec![mac];
+
+        assert_eq!(validate_cancel_packet(&packet, &local_macs, Trailer::Padding), Ok(mac));
+        assert!(validate_wol_packet(&packet, &local_macs, Trailer::Padding).unwrap_err().contains("Invalid header"));
+        assert!(validate_cancel_packet(&create_valid_wol_packet(&mac), &local_macs, Trailer::Padding)
+            .unwrap_err()
+            .contains("Invalid header"));
+    }
+
+    #[test]
+    fn test_relay_target() {
+        let remote_mac = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let local_mac = MacAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let local_macs = vec![local_mac];
+
+        assert_eq!(relay_target(&create_valid_wol_packet(&remote_mac), &local_macs), Some(remote_mac));
+        assert_eq!(relay_target(&create_valid_wol_packet(&local_mac), &local_macs), None);
+
+        let mut cancel = create_valid_wol_packet(&remote_mac);
+        cancel[..6].copy_from_slice(&packet::CANCEL_HEADER);
+        assert_eq!(relay_target(&cancel, &local_macs), Some(remote_mac));
+
+        assert_eq!(relay_target(&[0xFF; 50], &local_macs), None);
+    }
+
+    #[test]
+    fn test_probe_verdict() {
+        let mut policy = Policy {
+            quorum: 1,
+            quorum_window: 10,
+            confirm: false,
+            grace: 0,
+            warn_users: false,
+            inhibit_share_clients: false,
+            inhibit_ports: Vec::new(),
+            ignore_desktop_inhibitors: false,
+            inhibit_session_activity: false,
+            min_idle: None,
+        };
+        assert_eq!(probe_verdict(Action::Suspend, &policy, None), "ok: would suspend");
+        assert_eq!(probe_verdict(Action::Ignore, &policy, None), "blocked: ignored by rule");
+        assert_eq!(
+            probe_verdict(Action::Poweroff, &policy, Some("maintenance mode enabled".to_string())),
+            "blocked: inhibited (maintenance mode enabled)"
+        );
+
+        policy.quorum = 3;
+        policy.grace = 60;
+        assert_eq!(
+            probe_verdict(Action::Hibernate, &policy, None),
+            "ok: would hibernate after 3 packets within 10s after a 60s grace period"
+        );
+    }
+
+    /// A plan with nothing but `inhibitors` and `containers`. Should a test reach the power
+    /// command, the sysfs backend writes into `dir` instead of suspending.
+    fn test_plan(dir: &std::path::Path, inhibitors: Inhibitors, containers: Containers) -> SuspendPlan {
+        let timeout = Duration::from_secs(5);
+        SuspendPlan {
+            inhibitors: Arc::new(inhibitors),
+            backoff: Backoff { retries: 0, initial: Duration::ZERO },
+            power: Power { backend: Backend::Sysfs, mem_sleep: None, timeout, sys_power: dir.to_path_buf(), host: None },
+            group: SleepGroup {
+                members: Vec::new(),
+                wait_ack: false,
+                timeout,
+                prepare: false,
+                unanswered: group::Unanswered::Abort,
+                checks: None,
+            },
+            containers,
+            pre_suspend: PreSuspend { sync: false, flush_commands: Vec::new(), timeout },
+            mounts: NetworkMounts { paths: Vec::new(), mode: MountMode::Unmount, timeout },
+            zfs: Zfs {
+                pools: Vec::new(),
+                scrub: None,
+                scrub_timeout: timeout,
+                sync: false,
+                sync_always: false,
+                timeout,
+                saved: Mutex::default(),
+            },
+            post_resume: PostResume { commands: Vec::new(), timeout },
+            wake_for: None,
+            pipelines: Pipelines::default(),
+            events: Arc::default(),
+            verify_sleep: timeout,
+            resuspend: None,
+            status: Arc::default(),
+            heartbeat: None,
+        }
+    }
+
+    fn test_policy() -> Policy {
+        Policy {
+            quorum: 1,
+            quorum_window: 10,
+            confirm: false,
+            grace: 0,
+            warn_users: false,
+            inhibit_share_clients: false,
+            inhibit_ports: Vec::new(),
+            ignore_desktop_inhibitors: true,
+            inhibit_session_activity: false,
+            min_idle: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_force_respects_maintenance() {
+        let dir = std::env::temp_dir().join(format!("sol-force-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let maintenance = Maintenance::new(dir.join("maintenance"));
+        let paused = Paused::default();
+        let mut inhibitors = Inhibitors::default();
+        inhibitors.push_hard(Box::new(maintenance.clone()));
+        inhibitors.push_hard(Box::new(paused.clone()));
+        inhibitors.push(Box::new(LockFiles { flags: vec![dir.clone()], locks: Vec::new() }));
+        let containers = Containers { specs: Vec::new(), program: Runtime::Docker.program(), mode: ContainerMode::Pause, default_timeout: 5 };
+        let (plan, policy) = (test_plan(&dir, inhibitors, containers), test_policy());
+        let forced = Decision { force: true, ..Decision::plain(Action::Suspend) };
+
+        // A force rule overrides ordinary inhibitors such as a lock file
+        assert!(plan.inhibited(&policy, false).await.unwrap().starts_with("lock-files"));
+        assert_eq!(plan.inhibited(&policy, true).await, None);
+
+        maintenance.set(true).unwrap();
+        assert_eq!(run_once(&plan, &policy, &forced, false, &Cancel::default()).await, Outcome::Inhibited);
+        maintenance.set(false).unwrap();
+        paused.set(true);
+        assert_eq!(run_once(&plan, &policy, &forced, false, &Cancel::default()).await, Outcome::Inhibited);
+        assert!(!dir.join("state").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancel_restores_containers() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("sol-cancel-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (log, go) = (dir.join("log"), dir.join("go"));
+        // A docker stand-in that logs its arguments and holds `pause` until the test lets it go
+        let docker = dir.join("docker");
+        std::fs::write(
+            &docker,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> {}\n[ \"$1\" != pause ] || while [ ! -e {} ]; do sleep 0.05; done\n",
+                log.display(),
+                go.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&docker, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let containers = Containers {
+            specs: vec!["db".parse().unwrap()],
+            program: docker,
+            mode: ContainerMode::Pause,
+            default_timeout: 5,
+        };
+        let (plan, policy) = (Arc::new(test_plan(&dir, Inhibitors::default(), containers)), test_policy());
+        let cancel = Cancel::default();
+        let action = tokio::spawn({
+            let (plan, cancel) = (Arc::clone(&plan), cancel.clone());
+            async move { run_action(&plan, &policy, Decision::plain(Action::Suspend), false, &cancel).await }
+        });
+
+        while std::fs::read_to_string(&log).unwrap_or_default().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        cancel.cancel();
+        std::fs::write(&go, "").unwrap();
+
+        assert_eq!(action.await.unwrap(), Outcome::Cancelled);
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "pause db\nunpause db\n");
+        assert!(!dir.join("state").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
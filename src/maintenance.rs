@@ -0,0 +1,74 @@
+use crate::inhibit::Inhibitor;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Maintenance mode, persisted as the existence of a flag file so it survives restarts.
+/// Triggers are still validated and logged but never executed while it is enabled.
+#[derive(Clone, Debug)]
+pub struct Maintenance {
+    path: PathBuf,
+}
+
+impl Maintenance {
+    pub fn new(path: PathBuf) -> Self {
+        Maintenance { path }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn set(&self, enabled: bool) -> std::io::Result<()> {
+        if enabled {
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&self.path, b"")
+        } else {
+            match fs::remove_file(&self.path) {
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                result => result,
+            }
+        }
+    }
+}
+
+impl Inhibitor for Maintenance {
+    fn name(&self) -> &str {
+        "maintenance"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        Ok(self
+            .enabled()
+            .then(|| format!("maintenance mode enabled ({})", self.path.display())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle_maintenance() {
+        let path = std::env::temp_dir()
+            .join(format!("sol-test-{}", std::process::id()))
+            .join("maintenance");
+        let maintenance = Maintenance::new(path.clone());
+
+        assert!(!maintenance.enabled());
+        assert_eq!(maintenance.check(), Ok(None));
+
+        maintenance.set(true).unwrap();
+        assert!(maintenance.enabled());
+        assert!(maintenance.check().unwrap().is_some());
+
+        maintenance.set(false).unwrap();
+        assert!(!maintenance.enabled());
+        // Disabling twice is not an error
+        maintenance.set(false).unwrap();
+
+        fs::remove_dir(path.parent().unwrap()).unwrap();
+    }
+}
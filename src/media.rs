@@ -0,0 +1,272 @@
+use crate::config::{Config, Table, Value};
+use crate::inhibit::Inhibitor;
+use crate::json::Json;
+use crate::portmap::split_url;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long a media server may take to list its sessions
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Which API a media server speaks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Plex,
+    Jellyfin,
+}
+
+/// A session playing or transcoding on a media server
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub user: Option<String>,
+    pub title: String,
+    pub paused: bool,
+    pub transcoding: bool,
+}
+
+impl std::fmt::Display for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(user) = &self.user {
+            write!(f, "{} ", user)?;
+        }
+        write!(f, "{} {}", if self.paused { "paused" } else { "playing" }, self.title)?;
+        if self.transcoding {
+            f.write_str(" (transcoding)")?;
+        }
+        Ok(())
+    }
+}
+
+/// A Plex or Jellyfin server from a `[media.<name>]` table; blocks suspend while anything
+/// plays or transcodes on it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MediaServer {
+    pub name: String,
+    pub kind: Kind,
+    pub url: String,
+    pub token: String,
+    /// Let paused sessions through, so a client left paused does not keep the machine up
+    pub ignore_paused: bool,
+}
+
+impl MediaServer {
+    fn parse(name: &str, table: &Table) -> Result<MediaServer, String> {
+        let (mut kind, mut url, mut token, mut ignore_paused) = (None, None, None, false);
+        for (key, value) in table {
+            match key.as_str() {
+                "type" => {
+                    kind = Some(match string(key, value)? {
+                        "plex" => Kind::Plex,
+                        "jellyfin" => Kind::Jellyfin,
+                        other => return Err(format!("'type' must be plex or jellyfin, not '{}'", other)),
+                    })
+                }
+                "url" => {
+                    let value = string(key, value)?.trim_end_matches('/');
+                    split_url(value)?;
+                    url = Some(value.to_string());
+                }
+                "token" => token = Some(string(key, value)?.to_string()),
+                "ignore_paused" => ignore_paused = value.as_bool().ok_or("'ignore_paused' must be a boolean")?,
+                _ => return Err(format!("unknown key '{}'", key)),
+            }
+        }
+        Ok(MediaServer {
+            name: name.to_string(),
+            kind: kind.ok_or("'type' is required")?,
+            url: url.ok_or("'url' is required")?,
+            token: token.ok_or("'token' is required")?,
+            ignore_paused,
+        })
+    }
+
+    /// The sessions playing or transcoding right now
+    pub fn sessions(&self) -> Result<Vec<Session>, String> {
+        match self.kind {
+            Kind::Plex => {
+                let headers = [("X-Plex-Token", self.token.as_str()), ("Accept", "application/json")];
+                let mut sessions = parse_plex(&get(&format!("{}/status/sessions", self.url), &headers)?)?;
+                // Transcodes for playback are listed in both places; only downloads and
+                // optimized versions need the second list
+                let transcodes = parse_plex(&get(&format!("{}/transcode/sessions", self.url), &headers)?)?;
+                if sessions.is_empty() {
+                    sessions.extend(transcodes);
+                }
+                Ok(sessions)
+            }
+            Kind::Jellyfin => {
+                let authorization = format!("MediaBrowser Token=\"{}\"", self.token);
+                let headers = [("Authorization", authorization.as_str()), ("Accept", "application/json")];
+                parse_jellyfin(&get(&format!("{}/Sessions", self.url), &headers)?)
+            }
+        }
+    }
+}
+
+impl Inhibitor for MediaServer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        let sessions: Vec<String> = self
+            .sessions()?
+            .iter()
+            .filter(|session| !(self.ignore_paused && session.paused))
+            .map(Session::to_string)
+            .collect();
+        Ok((!sessions.is_empty()).then(|| sessions.join(", ")))
+    }
+}
+
+fn string<'a>(key: &str, value: &'a Value) -> Result<&'a str, String> {
+    value.as_str().ok_or_else(|| format!("'{}' must be a string", key))
+}
+
+/// Media servers from the `[media.<name>]` tables of the config file
+pub fn servers(config: &Config) -> Result<Vec<MediaServer>, String> {
+    config
+        .subtables("media")
+        .map(|(name, table)| MediaServer::parse(name, table).map_err(|e| format!("media.{}: {}", name, e)))
+        .collect()
+}
+
+/// GET `url` with extra `headers`, expecting a 2xx status, and return the body. Speaks
+/// HTTP/1.0 so the body never comes chunked.
+fn get(url: &str, headers: &[(&str, &str)]) -> Result<String, String> {
+    let (host, path) = split_url(url)?;
+    let exchange = || {
+        let addr = host.to_socket_addrs()?.next().ok_or(std::io::ErrorKind::NotFound)?;
+        let mut stream = TcpStream::connect_timeout(&addr, QUERY_TIMEOUT)?;
+        stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+        stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+        let headers: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+        write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n{}\r\n", path, host, headers)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        Ok::<_, std::io::Error>(String::from_utf8_lossy(&response).into_owned())
+    };
+    let response = exchange().map_err(|e| format!("{}: {}", url, e))?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    match status {
+        "401" => Err(format!("{}: the token was refused", url)),
+        _ if status.starts_with('2') => Ok(body.to_string()),
+        _ => Err(format!("{}: HTTP status {}", url, if status.is_empty() { "missing" } else { status })),
+    }
+}
+
+fn text<'a>(value: &'a Json, path: &[&str]) -> Option<&'a str> {
+    path.iter().try_fold(value, |value, key| value.get(key))?.as_str()
+}
+
+fn items(value: Option<&Json>) -> &[Json] {
+    match value {
+        Some(Json::Array(items)) => items,
+        _ => &[],
+    }
+}
+
+/// Sessions in the JSON answer to Plex's `/status/sessions` or `/transcode/sessions`
+fn parse_plex(body: &str) -> Result<Vec<Session>, String> {
+    let json = Json::parse(body).map_err(|e| format!("Unexpected answer from Plex: {}", e))?;
+    let container = json.get("MediaContainer").ok_or("Unexpected answer from Plex: no MediaContainer")?;
+    let playing = items(container.get("Metadata")).iter().map(|item| Session {
+        user: text(item, &["User", "title"]).map(str::to_string),
+        title: text(item, &["grandparentTitle"]).or(text(item, &["title"])).unwrap_or("something").to_string(),
+        paused: text(item, &["Player", "state"]) == Some("paused"),
+        transcoding: item.get("TranscodeSession").is_some(),
+    });
+    let transcodes = items(container.get("TranscodeSession")).iter().map(|_| Session {
+        user: None,
+        title: "a background transcode".to_string(),
+        paused: false,
+        transcoding: true,
+    });
+    Ok(playing.chain(transcodes).collect())
+}
+
+/// Sessions with something playing in the JSON answer to Jellyfin's `/Sessions`
+fn parse_jellyfin(body: &str) -> Result<Vec<Session>, String> {
+    let json = Json::parse(body).map_err(|e| format!("Unexpected answer from Jellyfin: {}", e))?;
+    let Json::Array(sessions) = json else {
+        return Err("Unexpected answer from Jellyfin: not a list of sessions".to_string());
+    };
+    Ok(sessions
+        .iter()
+        .filter_map(|session| {
+            let item = session.get("NowPlayingItem")?;
+            Some(Session {
+                user: text(session, &["UserName"]).map(str::to_string),
+                title: text(item, &["SeriesName"]).or(text(item, &["Name"])).unwrap_or("something").to_string(),
+                paused: session.get("PlayState").and_then(|state| state.get("IsPaused")) == Some(&Json::Bool(true)),
+                transcoding: session.get("TranscodingInfo").is_some(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plex() {
+        let body = r#"{"MediaContainer":{"size":2,"Metadata":[
+            {"title":"Dune","User":{"title":"alice"},"Player":{"state":"playing"},"TranscodeSession":{"key":"x"}},
+            {"title":"Pilot","grandparentTitle":"Severance","User":{"title":"bob"},"Player":{"state":"paused"}}]}}"#;
+        let sessions: Vec<String> = parse_plex(body).unwrap().iter().map(Session::to_string).collect();
+        assert_eq!(sessions, ["alice playing Dune (transcoding)", "bob paused Severance"]);
+        assert!(parse_plex(r#"{"MediaContainer":{"size":0}}"#).unwrap().is_empty());
+        assert_eq!(parse_plex(r#"{"MediaContainer":{"size":1,"TranscodeSession":[{"key":"y"}]}}"#).unwrap().len(), 1);
+        assert!(parse_plex("{}").is_err());
+    }
+
+    #[test]
+    fn test_parse_jellyfin() {
+        let body = r#"[{"UserName":"alice","NowPlayingItem":{"Name":"Dune"},"PlayState":{"IsPaused":true}},
+            {"UserName":"bob","DeviceName":"TV"}]"#;
+        let sessions = parse_jellyfin(body).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].to_string(), "alice paused Dune");
+        assert!(parse_jellyfin("{}").is_err());
+    }
+
+    #[test]
+    fn test_servers() {
+        let config = Config::parse(
+            "[media.plex]\ntype = \"plex\"\nurl = \"http://nas:32400/\"\ntoken = \"abc\"\nignore_paused = true\n",
+        )
+        .unwrap();
+        let plex = &servers(&config).unwrap()[0];
+        assert_eq!((plex.kind, plex.url.as_str(), plex.ignore_paused), (Kind::Plex, "http://nas:32400", true));
+        let e = servers(&Config::parse("[media.x]\ntype = \"emby\"").unwrap()).unwrap_err();
+        assert_eq!(e, "media.x: 'type' must be plex or jellyfin, not 'emby'");
+        assert!(servers(&Config::parse("[media.x]\ntype = \"plex\"\nurl = \"https://nas\"").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = MediaServer {
+            name: "jellyfin".to_string(),
+            kind: Kind::Jellyfin,
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            token: "abc".to_string(),
+            ignore_paused: false,
+        };
+        let answer = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let len = stream.read(&mut request).unwrap();
+            let body = r#"[{"UserName":"alice","NowPlayingItem":{"Name":"Dune"},"TranscodingInfo":{}}]"#;
+            write!(stream, "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n{}", body).unwrap();
+            String::from_utf8_lossy(&request[..len]).into_owned()
+        });
+        assert_eq!(server.check(), Ok(Some("alice playing Dune (transcoding)".to_string())));
+        let request = answer.join().unwrap();
+        assert!(request.starts_with("GET /Sessions HTTP/1.0\r\n"), "{}", request);
+        assert!(request.contains("Authorization: MediaBrowser Token=\"abc\"\r\n"), "{}", request);
+    }
+}
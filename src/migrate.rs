@@ -0,0 +1,188 @@
+use crate::json::Json;
+use crate::rules::Action;
+
+/// The power action a Go sleep-on-lan command carries out, when it is one this daemon can
+/// perform itself
+fn power_action(command: &str) -> Option<Action> {
+    let words: Vec<&str> = command.split_whitespace().filter(|word| *word != "sudo").collect();
+    match words.as_slice() {
+        ["systemctl", "suspend"] | ["pm-suspend"] | ["loginctl", "suspend"] | ["suspend" | "sleep"] => Some(Action::Suspend),
+        ["systemctl", "hibernate"] | ["pm-hibernate"] | ["loginctl", "hibernate"] | ["hibernate"] => Some(Action::Hibernate),
+        ["systemctl", "poweroff"] | ["poweroff"] | ["halt" | "shutdown"] | ["shutdown", "-h" | "-P", "now"] => Some(Action::Poweroff),
+        _ => None,
+    }
+}
+
+/// `s` as a config file string
+fn string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t"))
+}
+
+/// A Go sleep-on-lan boolean, which may be written as a JSON boolean or a string
+fn truthy(value: Option<&Json>) -> bool {
+    match value {
+        Some(Json::Bool(b)) => *b,
+        Some(Json::String(s)) => s.eq_ignore_ascii_case("true"),
+        _ => false,
+    }
+}
+
+fn array<'a>(config: &'a Json, key: &str) -> Result<&'a [Json], String> {
+    match config.get(key) {
+        None => Ok(&[]),
+        Some(Json::Array(values)) => Ok(values),
+        Some(_) => Err(format!("'{}' is not an array", key)),
+    }
+}
+
+/// Translate the Go sleep-on-lan's `sol.json` into this daemon's config file. Settings with
+/// no equivalent are kept as comments, so nothing is dropped silently.
+pub fn migrate(text: &str) -> Result<String, String> {
+    let config = Json::parse(text)?;
+    let Json::Object(members) = &config else {
+        return Err("expected a JSON object".to_string());
+    };
+    let mut settings = Vec::new();
+    let mut notes = Vec::new();
+    let mut actions = Vec::new();
+
+    let (mut ports, mut http) = (Vec::new(), None);
+    for listener in array(&config, "Listeners")? {
+        let listener = listener.as_str().unwrap_or_default();
+        let (kind, port) = listener
+            .split_once(':')
+            .and_then(|(kind, port)| Some((kind.to_ascii_uppercase(), port.parse::<u16>().ok()?)))
+            .ok_or_else(|| format!("Invalid listener '{}' (expected UDP:PORT or HTTP:PORT)", listener))?;
+        match kind.as_str() {
+            "UDP" => ports.push(port.to_string()),
+            "HTTP" if http.is_none() => http = Some(port),
+            "HTTP" => notes.push(format!("Listener {}: only one HTTP endpoint is supported", listener)),
+            _ => return Err(format!("Invalid listener '{}' (expected UDP:PORT or HTTP:PORT)", listener)),
+        }
+    }
+    if !ports.is_empty() {
+        settings.push(format!("port = [{}]", ports.join(", ")));
+    }
+    if let Some(port) = http {
+        settings.push(format!("http = \"0.0.0.0:{}\"", port));
+    }
+
+    for command in array(&config, "Commands")? {
+        let field = |key: &str| command.get(key).and_then(Json::as_str).unwrap_or_default();
+        let (operation, run) = (field("Operation"), field("Command"));
+        if operation.is_empty() || !operation.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("Invalid command operation '{}'", operation));
+        }
+        let power = power_action(run);
+        if truthy(command.get("Default")) {
+            match power {
+                Some(action) => settings.push(format!("default_action = {}", string(action.as_str()))),
+                None => notes.push(format!(
+                    "Default command '{}' is not a power action; plain triggers suspend, and v2 packets can select action '{}'",
+                    run, operation
+                )),
+            }
+        }
+        actions.push(match power {
+            Some(action) => format!("[action.{}]\npower = {}", operation, string(action.as_str())),
+            None => format!("[action.{}]\nrun = {}", operation, string(run)),
+        });
+    }
+
+    if let Some(auth) = config.get("Auth") {
+        let field = |key: &str| auth.get(key).and_then(Json::as_str).unwrap_or_default();
+        let (login, password) = (field("Login"), field("Password"));
+        if !login.is_empty() || !password.is_empty() {
+            if http.is_some() {
+                settings.push(format!("http_auth = {}", string(&format!("{}:{}", login, password))));
+            } else {
+                notes.push("Auth: there is no HTTP listener to protect".to_string());
+            }
+        }
+    }
+
+    for (key, value) in members {
+        match key.as_str() {
+            "Listeners" | "Commands" | "Auth" => {}
+            "AvoidDualUDPSending" | "AvoidDualUDPSendingActive" | "AvoidDualUDPSendingDelay" => notes.push(format!(
+                "{}: not needed; triggers that arrive while a suspend is pending are already ignored",
+                key
+            )),
+            "LogLevel" => notes.push(format!("LogLevel {}: use -v or -q on the command line", value.as_str().unwrap_or_default())),
+            "BroadcastIP" => notes.push(format!(
+                "BroadcastIP {}: pass ?broadcast= to POST /wol/MAC instead",
+                value.as_str().unwrap_or_default()
+            )),
+            "HTTPOutput" => notes.push("HTTPOutput: HTTP responses are always plain text".to_string()),
+            _ => notes.push(format!("{}: no equivalent setting", key)),
+        }
+    }
+
+    let mut out = String::from("# Migrated from a Go sleep-on-lan sol.json\n");
+    for note in &notes {
+        out += &format!("# {}\n", note);
+    }
+    if !notes.is_empty() {
+        out.push('\n');
+    }
+    for setting in &settings {
+        out += &format!("{}\n", setting);
+    }
+    for action in &actions {
+        out += &format!("\n{}\n", action);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::NamedActions;
+    use crate::config::{Config, Value};
+    use std::time::Duration;
+
+    const SOL_JSON: &str = r#"{
+        "Listeners": ["UDP:9", "UDP:7", "HTTP:8009"],
+        "LogLevel": "INFO",
+        "BroadcastIP": "192.168.1.255",
+        "Commands": [
+            { "Operation": "halt", "Command": "sudo systemctl suspend", "Default": "true", "Type": "external" },
+            { "Operation": "backup", "Command": "/usr/local/bin/backup \"nightly\"", "Default": false, "Type": "external" }
+        ],
+        "Auth": { "Login": "admin", "Password": "s3cret" },
+        "AvoidDualUDPSending": { "AvoidDualUDPSendingActive": true, "AvoidDualUDPSendingDelay": "100ms" },
+        "HTTPOutput": "XML"
+    }"#;
+
+    #[test]
+    fn test_migrate() {
+        let text = migrate(SOL_JSON).unwrap();
+        assert!(text.contains("# AvoidDualUDPSending: not needed"));
+        assert!(text.contains("# HTTPOutput: "));
+
+        let config = Config::parse(&text).unwrap();
+        let root = &config.tables[""];
+        assert_eq!(root["port"], Value::Array(vec![Value::Integer(9), Value::Integer(7)]));
+        assert_eq!(root["http"].as_str(), Some("0.0.0.0:8009"));
+        assert_eq!(root["http_auth"].as_str(), Some("admin:s3cret"));
+        assert_eq!(root["default_action"].as_str(), Some("suspend"));
+        assert_eq!(config.tables["action.halt"]["power"].as_str(), Some("suspend"));
+        assert_eq!(config.tables["action.backup"]["run"].as_str(), Some("/usr/local/bin/backup \"nightly\""));
+        NamedActions::from_config(&config, Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_errors() {
+        assert!(migrate(r#"{ "Listeners": ["TCP:9"] }"#).is_err());
+        assert!(migrate(r#"{ "Listeners": ["UDP:port"] }"#).is_err());
+        assert!(migrate(r#"{ "Commands": [{ "Operation": "a b", "Command": "true" }] }"#).is_err());
+        assert!(migrate("[]").is_err());
+    }
+
+    #[test]
+    fn test_power_action() {
+        assert_eq!(power_action("pm-hibernate"), Some(Action::Hibernate));
+        assert_eq!(power_action("sudo shutdown -h now"), Some(Action::Poweroff));
+        assert_eq!(power_action("systemctl suspend && beep"), None);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::clock::LocalTime;
+use crate::control;
+use crate::mac::MacAddr;
+use std::collections::{BTreeMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+/// Events kept for the scrolling list
+const RECENT_EVENTS: usize = 200;
+
+/// How often the status header is refreshed
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long `s` snoozes for
+const SNOOZE: &str = "1h";
+
+const KEYS: &str = "[m] maintenance  [s] snooze 1h  [c] cancel pending suspend  [q] quit";
+
+/// What Ctrl-C and Ctrl-D send once the terminal no longer turns them into a signal or EOF
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct SenderStats {
+    packets: u64,
+    valid: u64,
+    last_seen: u64,
+}
+
+/// What the monitor shows: the status report, per-sender counts and recent events
+#[derive(Default)]
+struct View {
+    status: String,
+    senders: BTreeMap<IpAddr, SenderStats>,
+    recent: VecDeque<(u64, String, String)>,
+}
+
+impl View {
+    /// Take in one `UNIX_TIME<TAB>EVENT<TAB>DETAIL` line from the event stream
+    fn record(&mut self, line: &str) {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(time), Some(name), detail) = (fields.next(), fields.next(), fields.next().unwrap_or_default()) else {
+            return;
+        };
+        let time = time.parse().unwrap_or_default();
+        match name {
+            "packet-received" => {
+                if let Some(ip) = field(detail, "peer").and_then(sender_ip) {
+                    let stats = self.senders.entry(ip).or_default();
+                    stats.packets += 1;
+                    stats.last_seen = time;
+                }
+            }
+            "validated" => {
+                let sender = detail.strip_prefix("sender=").and_then(|rest| rest.rsplit_once(" mac=")).map(|(sender, _)| sender);
+                if let Some(ip) = sender.and_then(sender_ip) {
+                    let stats = self.senders.entry(ip).or_default();
+                    stats.valid += 1;
+                    stats.last_seen = time;
+                }
+            }
+            _ => {}
+        }
+        // Every packet shows up in the sender table; the list is for what happened to them
+        if name != "packet-received" {
+            if self.recent.len() == RECENT_EVENTS {
+                self.recent.pop_front();
+            }
+            self.recent.push_back((time, name.to_string(), detail.to_string()));
+        }
+    }
+
+    /// Draw the screen for a terminal of `width` by `height`
+    fn render(&self, width: usize, height: usize, now: u64) -> Vec<String> {
+        let mut lines = vec![format!("Sleep-on-LAN monitor  {}", LocalTime::from_unix(now as libc::time_t)), String::new()];
+        lines.extend(self.status.lines().map(str::to_string));
+        lines.push(String::new());
+
+        lines.push(format!("{:<40} {:>8} {:>6} {:>10}", "SENDER", "PACKETS", "VALID", "LAST SEEN"));
+        let mut senders: Vec<(&IpAddr, &SenderStats)> = self.senders.iter().collect();
+        senders.sort_by(|a, b| b.1.last_seen.cmp(&a.1.last_seen).then(a.0.cmp(b.0)));
+        // Leave room for the event list below
+        let rows = height.saturating_sub(lines.len() + 6) / 2;
+        for (ip, stats) in senders.iter().take(rows.max(1)) {
+            let ago = format!("{}s ago", now.saturating_sub(stats.last_seen));
+            lines.push(format!("{:<40} {:>8} {:>6} {:>10}", ip, stats.packets, stats.valid, ago));
+        }
+        if senders.is_empty() {
+            lines.push("(no packets yet)".to_string());
+        }
+        lines.push(String::new());
+
+        lines.push("EVENTS".to_string());
+        let rows = height.saturating_sub(lines.len() + 2);
+        let skip = self.recent.len().saturating_sub(rows);
+        for (time, name, detail) in self.recent.iter().skip(skip) {
+            let time = LocalTime::from_unix(*time as libc::time_t);
+            lines.push(format!("{:02}:{:02}:{:02} {:<16} {}", time.hour, time.minute, time.second, name, detail));
+        }
+        while lines.len() + 1 < height {
+            lines.push(String::new());
+        }
+        lines.push(KEYS.to_string());
+        lines.iter().map(|line| line.chars().take(width).collect()).collect()
+    }
+}
+
+/// The value of `key=` in a space-separated detail
+fn field<'a>(detail: &'a str, key: &str) -> Option<&'a str> {
+    detail.split(' ').find_map(|word| word.strip_prefix(key)?.strip_prefix('='))
+}
+
+/// The address in `ADDRESS:PORT` or `NAME (ADDRESS:PORT)`
+fn sender_ip(sender: &str) -> Option<IpAddr> {
+    let address = match sender.rsplit_once(" (") {
+        Some((_, rest)) => rest.strip_suffix(')')?,
+        None => sender,
+    };
+    address.parse::<SocketAddr>().ok().map(|addr| addr.ip().to_canonical())
+}
+
+/// Puts the terminal into raw mode with the alternate screen, and restores it when dropped.
+/// Signal keys are turned off so Ctrl-C arrives as a key and the terminal is always restored.
+struct Terminal {
+    saved: libc::termios,
+}
+
+impl Terminal {
+    fn enter() -> Result<Self, String> {
+        // SAFETY: an all-zero termios is valid, and tcgetattr only writes to it
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err("sol monitor needs a terminal".to_string());
+        }
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: raw is a valid termios
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) };
+        print!("\x1b[?1049h\x1b[?25l");
+        Ok(Terminal { saved })
+    }
+
+    fn size() -> (usize, usize) {
+        // SAFETY: an all-zero winsize is valid, and the ioctl only writes to it
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+        if ok && size.ws_col > 0 && size.ws_row > 0 { (size.ws_col as usize, size.ws_row as usize) } else { (80, 24) }
+    }
+
+    fn draw(lines: &[String]) {
+        use std::io::Write;
+        let mut screen = String::from("\x1b[H");
+        for line in lines {
+            screen += line;
+            screen += "\x1b[K\r\n";
+        }
+        screen.truncate(screen.len() - 2);
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(screen.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        // SAFETY: saved is the termios read at entry
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+/// Subscribe to the daemon's events and show them live until `q`, Ctrl-C or Ctrl-D is pressed. `s` snoozes
+/// for an hour or ends a running snooze, and `c` sends a cancel packet for `cancel_mac` to
+/// the daemon's `port`.
+pub async fn run(socket: PathBuf, port: u16, cancel_mac: Option<MacAddr>) -> Result<(), String> {
+    let mut stream = UnixStream::connect(&socket)
+        .await
+        .map_err(|e| format!("Cannot connect to daemon at {}: {}", socket.display(), e))?;
+    stream.write_all(b"events\n").await.map_err(|e| e.to_string())?;
+    let (reader, _writer) = stream.into_split();
+    let mut events = BufReader::new(reader).lines();
+
+    let _terminal = Terminal::enter()?;
+    let mut view = View::default();
+    let mut notice = String::new();
+    let mut keys = tokio::io::stdin();
+    let mut key = [0u8; 1];
+    let mut tick = tokio::time::interval(STATUS_INTERVAL);
+    loop {
+        tokio::select! {
+            line = events.next_line() => match line {
+                Ok(Some(line)) => view.record(&line),
+                _ => return Err("The daemon closed the event stream".to_string()),
+            },
+            read = keys.read(&mut key) => {
+                match (read, key[0]) {
+                    (Ok(0) | Err(_), _) | (_, b'q' | CTRL_C | CTRL_D) => return Ok(()),
+                    (_, b'm') => notice = toggle_maintenance(&socket, &view.status).await,
+                    (_, b's') => notice = toggle_snooze(&socket, &view.status).await,
+                    (_, b'c') => {
+                        notice = match cancel_mac {
+                            Some(mac) => match control::send_local(&sol::packet::CANCEL_HEADER, port, &mac).await {
+                                Ok(verdict) => verdict,
+                                Err(e) => format!("cancel failed: {}", e),
+                            },
+                            None => "no local MAC to cancel with".to_string(),
+                        };
+                    }
+                    _ => {}
+                }
+            }
+            _ = tick.tick() => {
+                view.status = match control::request(&socket, "status").await {
+                    Ok(report) => report,
+                    Err(e) => format!("status unavailable: {}", e),
+                };
+            }
+        }
+        let (width, height) = Terminal::size();
+        let mut lines = view.render(width, height, crate::protocol::unix_now());
+        if !notice.is_empty()
+            && let Some(line) = lines.get_mut(1)
+        {
+            *line = notice.chars().take(width).collect();
+        }
+        Terminal::draw(&lines);
+    }
+}
+
+async fn toggle_maintenance(socket: &Path, status: &str) -> String {
+    let command = if status.lines().any(|line| line == "maintenance: on") { "maintenance off" } else { "maintenance on" };
+    match control::request(socket, command).await {
+        Ok(reply) => reply,
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+/// Snooze for `SNOOZE`, or end the snooze when one is running
+async fn toggle_snooze(socket: &Path, status: &str) -> String {
+    let command = if status.lines().any(|line| line.starts_with("snoozed: ") && line != "snoozed: no") {
+        "snooze off".to_string()
+    } else {
+        format!("snooze {}", SNOOZE)
+    };
+    match control::request(socket, &command).await {
+        Ok(reply) => reply,
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sender_stats() {
+        let mut view = View::default();
+        view.record("100\tpacket-received\tpeer=192.168.1.5:40000 len=102");
+        view.record("101\tpacket-received\tpeer=[::ffff:192.168.1.5]:40001 len=102");
+        view.record("101\tvalidated\tsender=desk.lan (192.168.1.5:40001) mac=aa:bb:cc:dd:ee:ff");
+        view.record("105\tpacket-received\tpeer=10.0.0.9:5000 len=4");
+        view.record("106\tinhibited\twake-lock: held by backup");
+        view.record("garbage");
+
+        let desk = &view.senders[&"192.168.1.5".parse::<IpAddr>().unwrap()];
+        assert_eq!(*desk, SenderStats { packets: 2, valid: 1, last_seen: 101 });
+        assert_eq!(view.recent.len(), 2);
+
+        let screen = view.render(80, 20, 110);
+        assert_eq!(screen.len(), 20);
+        assert!(screen.iter().all(|line| line.chars().count() <= 80));
+        let table = screen.iter().position(|line| line.starts_with("SENDER")).unwrap();
+        // Most recently seen first
+        assert!(screen[table + 1].starts_with("10.0.0.9"));
+        assert!(screen[table + 2].contains("9s ago"));
+        assert!(screen.iter().any(|line| line.contains("inhibited        wake-lock: held by backup")));
+        assert!(screen[19].starts_with("[m] maintenance"));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::presuspend::run_step;
+use clap::ValueEnum;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// How network filesystems are taken offline before suspending
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MountMode {
+    /// Unmount before suspend and mount again (from fstab) after resume
+    Unmount,
+    /// Remount read-only before suspend and read-write after resume
+    ReadOnly,
+}
+
+/// Network filesystems (NFS/CIFS) taken offline around a suspend to avoid stale handles
+pub struct NetworkMounts {
+    pub paths: Vec<String>,
+    pub mode: MountMode,
+    pub timeout: Duration,
+}
+
+impl NetworkMounts {
+    /// Take every mount offline. On failure, mounts already handled are restored.
+    pub async fn prepare(&self) -> Result<(), String> {
+        for (i, path) in self.paths.iter().enumerate() {
+            if let Err(e) = run_step(path, self.prepare_command(path), self.timeout).await {
+                self.restore_paths(&self.paths[..i]).await;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Bring every mount back online, logging failures without stopping
+    pub async fn restore(&self) {
+        self.restore_paths(&self.paths).await;
+    }
+
+    async fn restore_paths(&self, paths: &[String]) {
+        for path in paths {
+            if let Err(e) = run_step(path, self.restore_command(path), self.timeout).await {
+                error!("Failed to restore network mount: {}", e);
+            }
+        }
+    }
+
+    fn prepare_command(&self, path: &str) -> Command {
+        match self.mode {
+            MountMode::Unmount => {
+                let mut cmd = Command::new("umount");
+                cmd.arg(path);
+                cmd
+            }
+            MountMode::ReadOnly => {
+                let mut cmd = Command::new("mount");
+                cmd.args(["-o", "remount,ro", path]);
+                cmd
+            }
+        }
+    }
+
+    fn restore_command(&self, path: &str) -> Command {
+        let mut cmd = Command::new("mount");
+        match self.mode {
+            MountMode::Unmount => cmd.arg(path),
+            MountMode::ReadOnly => cmd.args(["-o", "remount,rw", path]),
+        };
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<String> {
+        let std = cmd.as_std();
+        std::iter::once(std.get_program())
+            .chain(std.get_args())
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_unmount_commands() {
+        let mounts = NetworkMounts {
+            paths: vec!["/mnt/nas".to_string()],
+            mode: MountMode::Unmount,
+            timeout: Duration::from_secs(5),
+        };
+        assert_eq!(args(&mounts.prepare_command("/mnt/nas")), ["umount", "/mnt/nas"]);
+        assert_eq!(args(&mounts.restore_command("/mnt/nas")), ["mount", "/mnt/nas"]);
+    }
+
+    #[test]
+    fn test_read_only_commands() {
+        let mounts = NetworkMounts {
+            paths: vec!["/mnt/nas".to_string()],
+            mode: MountMode::ReadOnly,
+            timeout: Duration::from_secs(5),
+        };
+        assert_eq!(
+            args(&mounts.prepare_command("/mnt/nas")),
+            ["mount", "-o", "remount,ro", "/mnt/nas"]
+        );
+        assert_eq!(
+            args(&mounts.restore_command("/mnt/nas")),
+            ["mount", "-o", "remount,rw", "/mnt/nas"]
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// How long an event line waits for a lookup before falling back to the bare address
+const LOOKUP_WAIT: Duration = Duration::from_millis(500);
+/// How long a lookup may take in the background to fill the cache
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long names, and the absence of one, are cached
+const CACHE_TTL: Duration = Duration::from_secs(600);
+
+type Cache = HashMap<IpAddr, (Option<String>, Instant)>;
+
+/// Reverse-DNS names for sender addresses in log lines
+#[derive(Clone, Default)]
+pub struct Names {
+    enabled: bool,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl Names {
+    pub fn new(enabled: bool) -> Self {
+        Names { enabled, cache: Arc::default() }
+    }
+
+    /// `name (address)` when the sender has a name, else just the address. Never holds the
+    /// caller up for more than a moment; slow lookups finish in the background for next time.
+    pub async fn describe(&self, peer: SocketAddr) -> String {
+        if !self.enabled {
+            return peer.to_string();
+        }
+        let ip = peer.ip();
+        if let Some((name, at)) = self.cache.lock().unwrap().get(&ip)
+            && at.elapsed() < CACHE_TTL
+        {
+            return format(peer, name.as_deref());
+        }
+
+        let cache = self.cache.clone();
+        let mut lookup = tokio::spawn(async move {
+            let name = tokio::time::timeout(LOOKUP_TIMEOUT, lookup(ip)).await.ok().flatten();
+            cache.lock().unwrap().insert(ip, (name.clone(), Instant::now()));
+            name
+        });
+        match tokio::time::timeout(LOOKUP_WAIT, &mut lookup).await {
+            Ok(Ok(name)) => format(peer, name.as_deref()),
+            _ => peer.to_string(),
+        }
+    }
+}
+
+fn format(peer: SocketAddr, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("{} ({})", name, peer),
+        None => peer.to_string(),
+    }
+}
+
+/// Reverse lookup through NSS, so /etc/hosts, DNS and mDNS all apply
+async fn lookup(ip: IpAddr) -> Option<String> {
+    let output = Command::new("getent")
+        .args(["hosts", &ip.to_string()])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_getent(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_getent(output: &str) -> Option<String> {
+    output.split_whitespace().nth(1).map(|name| name.trim_end_matches('.').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_getent() {
+        assert_eq!(
+            parse_getent("192.168.1.5     nas-controller.lan nas\n"),
+            Some("nas-controller.lan".to_string())
+        );
+        assert_eq!(parse_getent(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_describe_uses_cache() {
+        let names = Names::new(true);
+        let peer: SocketAddr = "192.0.2.7:4000".parse().unwrap();
+        names
+            .cache
+            .lock()
+            .unwrap()
+            .insert(peer.ip(), (Some("ups.lan".to_string()), Instant::now()));
+        assert_eq!(names.describe(peer).await, "ups.lan (192.0.2.7:4000)");
+        assert_eq!(Names::new(false).describe(peer).await, "192.0.2.7:4000");
+    }
+}
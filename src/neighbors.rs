@@ -0,0 +1,195 @@
+use crate::mac::MacAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::process::Command;
+
+/// How long to wait for address resolution after prompting it
+const RESOLVE_WAIT: Duration = Duration::from_millis(300);
+
+/// The MAC a sender address must belong to, written as `ADDRESS=MAC`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SenderMac {
+    pub ip: IpAddr,
+    pub mac: MacAddr,
+}
+
+impl std::str::FromStr for SenderMac {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ip, mac) = s.split_once('=').ok_or_else(|| format!("Invalid sender MAC '{}' (expected ADDRESS=MAC)", s))?;
+        Ok(SenderMac {
+            ip: ip.trim().parse().map_err(|_| format!("Invalid address '{}' in '{}'", ip, s))?,
+            mac: mac.trim().parse()?,
+        })
+    }
+}
+
+/// The MAC configured for `ip`, if any
+pub fn expected(senders: &[SenderMac], ip: IpAddr) -> Option<MacAddr> {
+    let ip = ip.to_canonical();
+    senders.iter().find(|sender| sender.ip == ip).map(|sender| sender.mac)
+}
+
+/// Check that the kernel's neighbor entry for `ip` holds `expected`. Senders that broadcast
+/// may never have resolved us, so a missing entry is prompted with an empty datagram to the
+/// discard port and looked up once more.
+pub async fn verify(ip: IpAddr, expected: MacAddr) -> Result<(), String> {
+    let ip = ip.to_canonical();
+    match lookup_prompted(ip).await {
+        Some(mac) if mac == expected => Ok(()),
+        Some(mac) => Err(format!("neighbor table has {} for {}, expected {}", mac, ip, expected)),
+        None => Err(format!("no neighbor table entry for {}", ip)),
+    }
+}
+
+/// The kernel's entry for `ip`, prompting resolution only when there is none, so that a
+/// stale entry for a sleeping machine is not replaced by a failed one
+async fn lookup_prompted(ip: IpAddr) -> Option<MacAddr> {
+    if let Some(mac) = lookup(ip).await {
+        return Some(mac);
+    }
+    prompt(ip).await;
+    tokio::time::sleep(RESOLVE_WAIT).await;
+    lookup(ip).await
+}
+
+/// The MAC of `host`, a hostname or address, from the neighbor table
+pub async fn resolve(host: &str) -> Result<(IpAddr, MacAddr), String> {
+    let ip = match host.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => tokio::net::lookup_host((host, 0))
+            .await
+            .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+            .map(|addr| addr.ip())
+            .min_by_key(|ip| ip.is_ipv6())
+            .ok_or_else(|| format!("no address for {}", host))?,
+    };
+    let mac = lookup_prompted(ip).await.ok_or_else(|| format!("no neighbor table entry for {} ({})", host, ip))?;
+    Ok((ip, mac))
+}
+
+/// Every resolved entry in the kernel's IPv4 and IPv6 neighbor tables
+pub async fn table() -> Vec<(IpAddr, MacAddr)> {
+    let arp = tokio::fs::read_to_string("/proc/net/arp").await.unwrap_or_default();
+    let mut entries: Vec<(IpAddr, MacAddr)> = parse_arp_table(&arp).map(|(ip, mac)| (IpAddr::V4(ip), mac)).collect();
+    if let Ok(output) = Command::new("ip").args(["-6", "neigh", "show"]).kill_on_drop(true).output().await {
+        entries.extend(parse_neigh_table(&String::from_utf8_lossy(&output.stdout)));
+    }
+    entries.sort();
+    entries.dedup();
+    entries
+}
+
+/// The neighbor table as `ADDRESS MAC` lines
+pub fn format_table(entries: &[(IpAddr, MacAddr)]) -> String {
+    if entries.is_empty() {
+        return "no neighbor table entries".to_string();
+    }
+    let lines: Vec<String> = entries.iter().map(|(ip, mac)| format!("{:<39} {}", ip, mac)).collect();
+    lines.join("\n")
+}
+
+async fn prompt(ip: IpAddr) {
+    let bind: SocketAddr = if ip.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+    if let Ok(socket) = UdpSocket::bind(bind).await {
+        let _ = socket.send_to(&[], SocketAddr::new(ip, 9)).await;
+    }
+}
+
+/// The link-layer address the kernel has for `ip`, from `/proc/net/arp` or `ip -6 neigh`
+pub async fn lookup(ip: IpAddr) -> Option<MacAddr> {
+    match ip {
+        IpAddr::V4(ip) => parse_arp(&tokio::fs::read_to_string("/proc/net/arp").await.ok()?, ip),
+        IpAddr::V6(ip) => {
+            let output = Command::new("ip")
+                .args(["-6", "neigh", "show", "to", &ip.to_string()])
+                .kill_on_drop(true)
+                .output()
+                .await
+                .ok()?;
+            parse_neigh(&String::from_utf8_lossy(&output.stdout))
+        }
+    }
+}
+
+/// Lines are `IP HWTYPE FLAGS MAC MASK DEVICE`; flags 0x0 mark an unresolved entry
+fn parse_arp_table(text: &str) -> impl Iterator<Item = (Ipv4Addr, MacAddr)> + '_ {
+    text.lines().skip(1).filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 || fields[2] == "0x0" {
+            return None;
+        }
+        let mac = fields[3].parse().ok().filter(|mac| *mac != MacAddr::ZERO)?;
+        Some((fields[0].parse().ok()?, mac))
+    })
+}
+
+fn parse_arp(text: &str, ip: Ipv4Addr) -> Option<MacAddr> {
+    parse_arp_table(text).find(|(entry, _)| *entry == ip).map(|(_, mac)| mac)
+}
+
+/// Lines look like `fe80::1 dev eth0 lladdr aa:bb:cc:dd:ee:ff REACHABLE`
+fn parse_neigh_table(text: &str) -> impl Iterator<Item = (IpAddr, MacAddr)> + '_ {
+    text.lines().filter_map(|line| {
+        let mut words = line.split_whitespace();
+        let ip = words.next()?.parse().ok()?;
+        words.find(|word| *word == "lladdr")?;
+        if line.ends_with("FAILED") || line.ends_with("INCOMPLETE") {
+            return None;
+        }
+        Some((ip, words.next()?.parse().ok()?))
+    })
+}
+
+fn parse_neigh(text: &str) -> Option<MacAddr> {
+    parse_neigh_table(text).next().map(|(_, mac)| mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+    #[test]
+    fn test_parse_tables() {
+        let arp = "IP address       HW type     Flags       HW address            Mask     Device\n\
+                   192.168.1.42     0x1         0x2         aa:bb:cc:dd:ee:ff     *        eth0\n\
+                   192.168.1.43     0x1         0x0         00:00:00:00:00:00     *        eth0\n";
+        assert_eq!(parse_arp(arp, Ipv4Addr::new(192, 168, 1, 42)), Some(MAC));
+        assert_eq!(parse_arp(arp, Ipv4Addr::new(192, 168, 1, 43)), None);
+        assert_eq!(parse_arp(arp, Ipv4Addr::new(192, 168, 1, 44)), None);
+
+        assert_eq!(parse_neigh("fe80::1 dev eth0 lladdr aa:bb:cc:dd:ee:ff router STALE\n"), Some(MAC));
+        assert_eq!(parse_neigh("fe80::1 dev eth0  FAILED\n"), None);
+
+        let neigh = "fe80::1 dev eth0 lladdr aa:bb:cc:dd:ee:ff router STALE\n\
+                     fe80::2 dev eth0 INCOMPLETE\n\
+                     fd00::7 dev eth0 lladdr 00:11:32:aa:bb:cc REACHABLE\n";
+        let entries: Vec<(IpAddr, MacAddr)> = parse_neigh_table(neigh).collect();
+        assert_eq!(entries, [("fe80::1".parse().unwrap(), MAC), ("fd00::7".parse().unwrap(), "00:11:32:aa:bb:cc".parse().unwrap())]);
+        assert_eq!(parse_arp_table(arp).count(), 1);
+        assert_eq!(
+            format_table(&[("192.168.1.42".parse().unwrap(), MAC)]),
+            "192.168.1.42                            aa:bb:cc:dd:ee:ff"
+        );
+        assert_eq!(format_table(&[]), "no neighbor table entries");
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        assert!(resolve("127.0.0.1").await.unwrap_err().starts_with("no neighbor table entry for 127.0.0.1"));
+    }
+
+    #[test]
+    fn test_expected_sender() {
+        let sender: SenderMac = "192.168.1.42=aa-bb-cc-dd-ee-ff".parse().unwrap();
+        let senders = [sender];
+        assert_eq!(expected(&senders, "::ffff:192.168.1.42".parse().unwrap()), Some(MAC));
+        assert_eq!(expected(&senders, "192.168.1.43".parse().unwrap()), None);
+        assert!("192.168.1.42".parse::<SenderMac>().is_err());
+        assert!("host=aa:bb:cc:dd:ee:ff".parse::<SenderMac>().is_err());
+    }
+}
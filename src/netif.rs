@@ -0,0 +1,318 @@
+use crate::mac::MacAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// An IPv4 address with the prefix length of its subnet
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv4Network {
+    addr: Ipv4Addr,
+    prefix: u8,
+}
+
+impl Ipv4Network {
+    fn mask(&self) -> u32 {
+        u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) & self.mask())
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) | !self.mask())
+    }
+}
+
+/// An IPv6 address with the prefix length of its subnet
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv6Network {
+    addr: Ipv6Addr,
+    prefix: u8,
+}
+
+impl Ipv6Network {
+    fn mask(&self) -> u128 {
+        u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0)
+    }
+
+    pub fn network(&self) -> Ipv6Addr {
+        Ipv6Addr::from(u128::from(self.addr) & self.mask())
+    }
+}
+
+/// An address and prefix length, written `ADDRESS/PREFIX`; a bare address is a single host
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IpNetwork {
+    V4(Ipv4Network),
+    V6(Ipv6Network),
+}
+
+impl IpNetwork {
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, String> {
+        match addr {
+            IpAddr::V4(addr) if prefix <= 32 => Ok(IpNetwork::V4(Ipv4Network { addr, prefix })),
+            IpAddr::V6(addr) if prefix <= 128 => Ok(IpNetwork::V6(Ipv6Network { addr, prefix })),
+            _ => Err(format!("invalid prefix length {} for {}", prefix, addr)),
+        }
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            IpNetwork::V4(net) => IpAddr::V4(net.addr),
+            IpNetwork::V6(net) => IpAddr::V6(net.addr),
+        }
+    }
+
+    pub fn prefix(&self) -> u8 {
+        match self {
+            IpNetwork::V4(net) => net.prefix,
+            IpNetwork::V6(net) => net.prefix,
+        }
+    }
+
+    pub fn network(&self) -> IpAddr {
+        match self {
+            IpNetwork::V4(net) => IpAddr::V4(net.network()),
+            IpNetwork::V6(net) => IpAddr::V6(net.network()),
+        }
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        matches!(self, IpNetwork::V6(_))
+    }
+
+    /// Whether `ip` is on this subnet; addresses of the other family never are
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self, ip) {
+            (IpNetwork::V4(net), IpAddr::V4(ip)) => u32::from(ip) & net.mask() == u32::from(net.network()),
+            (IpNetwork::V6(net), IpAddr::V6(ip)) => u128::from(ip) & net.mask() == u128::from(net.network()),
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for IpNetwork {
+    fn from(addr: IpAddr) -> Self {
+        let prefix = if addr.is_ipv4() { 32 } else { 128 };
+        IpNetwork::new(addr, prefix).expect("full-length prefix")
+    }
+}
+
+impl std::str::FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid network '{}' (expected ADDRESS[/PREFIX])", s);
+        match s.split_once('/') {
+            Some((addr, prefix)) => {
+                let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+                let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+                IpNetwork::new(addr, prefix).map_err(|_| invalid())
+            }
+            None => s.parse::<IpAddr>().map(IpNetwork::from).map_err(|_| invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.ip(), self.prefix())
+    }
+}
+
+/// A network interface with its link-layer address and IP addresses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub index: u32,
+    pub mac: Option<MacAddr>,
+    pub ips: Vec<IpNetwork>,
+    /// `IFF_*` flags
+    pub flags: u32,
+}
+
+impl NetworkInterface {
+    pub fn is_up(&self) -> bool {
+        self.flags & libc::IFF_UP as u32 != 0
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.flags & libc::IFF_LOOPBACK as u32 != 0
+    }
+}
+
+/// The system's network interfaces, through pnet
+#[cfg(feature = "pnet")]
+pub fn interfaces() -> Vec<NetworkInterface> {
+    use pnet::ipnetwork::IpNetwork as PnetNetwork;
+    pnet::datalink::interfaces()
+        .into_iter()
+        .map(|iface| NetworkInterface {
+            mac: iface.mac.map(|mac| MacAddr(mac.octets())),
+            ips: iface
+                .ips
+                .iter()
+                .filter_map(|net| match net {
+                    PnetNetwork::V4(net) => IpNetwork::new(IpAddr::V4(net.ip()), net.prefix()).ok(),
+                    PnetNetwork::V6(net) => IpNetwork::new(IpAddr::V6(net.ip()), net.prefix()).ok(),
+                })
+                .collect(),
+            name: iface.name,
+            index: iface.index,
+            flags: iface.flags,
+        })
+        .collect()
+}
+
+/// The system's network interfaces, from getifaddrs, with MACs read from sysfs on Linux
+#[cfg(not(feature = "pnet"))]
+pub fn interfaces() -> Vec<NetworkInterface> {
+    let mut interfaces: Vec<NetworkInterface> = Vec::new();
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs fills `list` with a linked list we free below
+    if unsafe { libc::getifaddrs(&mut list) } != 0 {
+        return interfaces;
+    }
+    let mut cursor = list;
+    while !cursor.is_null() {
+        // SAFETY: `cursor` is a node of the list getifaddrs returned, which is still alive
+        let entry = unsafe { &*cursor };
+        cursor = entry.ifa_next;
+        // SAFETY: ifa_name is a NUL-terminated string owned by the list
+        let name = unsafe { std::ffi::CStr::from_ptr(entry.ifa_name) }.to_string_lossy().into_owned();
+        let position = match interfaces.iter().position(|iface| iface.name == name) {
+            Some(position) => position,
+            None => {
+                // SAFETY: if_nametoindex only reads the NUL-terminated name
+                let index = unsafe { libc::if_nametoindex(entry.ifa_name) };
+                interfaces.push(NetworkInterface { name, index, mac: None, ips: Vec::new(), flags: entry.ifa_flags });
+                interfaces.len() - 1
+            }
+        };
+        let iface = &mut interfaces[position];
+        // SAFETY: the addresses are null or point to sockaddrs of the family they announce
+        match unsafe { (sockaddr_ip(entry.ifa_addr), sockaddr_ip(entry.ifa_netmask)) } {
+            (Some(addr), netmask) => {
+                let prefix = match netmask {
+                    Some(IpAddr::V4(mask)) => u32::from(mask).count_ones() as u8,
+                    Some(IpAddr::V6(mask)) => u128::from(mask).count_ones() as u8,
+                    None if addr.is_ipv4() => 32,
+                    None => 128,
+                };
+                iface.ips.extend(IpNetwork::new(addr, prefix).ok());
+            }
+            // SAFETY: as above
+            (None, _) => iface.mac = iface.mac.or(unsafe { sockaddr_mac(entry.ifa_addr) }),
+        }
+    }
+    // SAFETY: `list` came from getifaddrs and is not used afterwards
+    unsafe { libc::freeifaddrs(list) };
+
+    if cfg!(target_os = "linux") {
+        for iface in &mut interfaces {
+            if let Ok(address) = std::fs::read_to_string(format!("/sys/class/net/{}/address", iface.name)) {
+                iface.mac = address.trim().parse().ok();
+            }
+        }
+    }
+    interfaces
+}
+
+/// The IP address in `addr`, if it is an IPv4 or IPv6 sockaddr
+///
+/// # Safety
+///
+/// `addr` must be null or point to a sockaddr of the family in its `sa_family`
+#[cfg(not(feature = "pnet"))]
+unsafe fn sockaddr_ip(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    if addr.is_null() {
+        return None;
+    }
+    // SAFETY: the caller guarantees the pointer and the family-specific layout
+    unsafe {
+        match (*addr).sa_family as i32 {
+            libc::AF_INET => {
+                let addr = &*(addr as *const libc::sockaddr_in);
+                Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))))
+            }
+            libc::AF_INET6 => {
+                let addr = &*(addr as *const libc::sockaddr_in6);
+                Some(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The Ethernet address in a link-layer sockaddr
+///
+/// # Safety
+///
+/// `addr` must be null or point to a sockaddr of the family in its `sa_family`
+#[cfg(all(not(feature = "pnet"), any(target_os = "linux", target_os = "android")))]
+unsafe fn sockaddr_mac(addr: *const libc::sockaddr) -> Option<MacAddr> {
+    // SAFETY: the caller guarantees the pointer and the family-specific layout
+    unsafe {
+        if addr.is_null() || (*addr).sa_family as i32 != libc::AF_PACKET {
+            return None;
+        }
+        let link = &*(addr as *const libc::sockaddr_ll);
+        (link.sll_halen == 6).then(|| MacAddr(link.sll_addr[..6].try_into().unwrap()))
+    }
+}
+
+/// The Ethernet address in a link-layer sockaddr
+///
+/// # Safety
+///
+/// `addr` must be null or point to a sockaddr of the family in its `sa_family`
+#[cfg(all(not(feature = "pnet"), any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd")))]
+unsafe fn sockaddr_mac(addr: *const libc::sockaddr) -> Option<MacAddr> {
+    // SAFETY: the caller guarantees the pointer and the family-specific layout
+    unsafe {
+        if addr.is_null() || (*addr).sa_family as i32 != libc::AF_LINK {
+            return None;
+        }
+        let link = &*(addr as *const libc::sockaddr_dl);
+        if link.sdl_alen != 6 {
+            return None;
+        }
+        // The link-layer address follows the interface name in sdl_data
+        let data = std::slice::from_raw_parts(link.sdl_data.as_ptr() as *const u8, link.sdl_nlen as usize + 6);
+        Some(MacAddr(data[link.sdl_nlen as usize..].try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network() {
+        let net: IpNetwork = "192.168.1.10/24".parse().unwrap();
+        assert_eq!(net.to_string(), "192.168.1.10/24");
+        assert_eq!(net.network(), IpAddr::from([192, 168, 1, 0]));
+        assert!(net.contains("192.168.1.77".parse().unwrap()));
+        assert!(!net.contains("192.168.2.1".parse().unwrap()));
+        assert!(!net.contains("::1".parse().unwrap()));
+        let IpNetwork::V4(v4) = net else { panic!("not IPv4") };
+        assert_eq!(v4.broadcast(), Ipv4Addr::new(192, 168, 1, 255));
+
+        let host: IpNetwork = "10.0.0.5".parse().unwrap();
+        assert_eq!(host.to_string(), "10.0.0.5/32");
+        assert!(host.contains("10.0.0.5".parse().unwrap()));
+        let all: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("8.8.8.8".parse().unwrap()));
+
+        let v6: IpNetwork = "fd00::1/8".parse().unwrap();
+        assert_eq!(v6.network().to_string(), "fd00::");
+        assert!(v6.contains("fdab::2".parse().unwrap()));
+        assert!("192.168.1.0/33".parse::<IpNetwork>().is_err());
+        assert!("192.168.1.300".parse::<IpNetwork>().is_err());
+    }
+
+    #[test]
+    fn test_loopback_is_listed() {
+        let lo = interfaces().into_iter().find(|iface| iface.is_loopback()).expect("no loopback interface");
+        assert!(lo.ips.iter().any(|net| net.ip() == IpAddr::from([127, 0, 0, 1])));
+    }
+}
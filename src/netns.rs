@@ -0,0 +1,47 @@
+use crate::listen;
+use std::fs::File;
+use std::net::IpAddr;
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+
+/// Where `ip netns` keeps the named namespaces
+const NETNS_RUN_DIR: &str = "/run/netns";
+
+/// The namespace file for `netns`: a name from `ip netns`, or a path such as `/proc/PID/ns/net`
+pub fn path(netns: &str) -> PathBuf {
+    if netns.contains('/') { PathBuf::from(netns) } else { PathBuf::from(NETNS_RUN_DIR).join(netns) }
+}
+
+/// Bind every port on every address inside the network namespace `netns`. The sockets are
+/// created on a short-lived thread that enters the namespace, and stay in it afterwards.
+pub fn bind(netns: &str, addrs: &[IpAddr], ports: &[u16], freebind: bool) -> Result<Vec<std::net::UdpSocket>, String> {
+    let path = path(netns);
+    let file = File::open(&path).map_err(|e| format!("Cannot open network namespace {}: {}", path.display(), e))?;
+    let (addrs, ports, netns) = (addrs.to_vec(), ports.to_vec(), netns.to_string());
+    std::thread::spawn(move || {
+        // SAFETY: setns only reads the descriptor, and only changes this thread's namespace
+        if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(match e.raw_os_error() {
+                Some(libc::EPERM) => format!("Cannot enter network namespace {}: {} (needs CAP_SYS_ADMIN)", netns, e),
+                _ => format!("Cannot enter network namespace {}: {}", netns, e),
+            });
+        }
+        listen::bind(&addrs, &ports, freebind).map_err(|e| format!("In network namespace {}: {}", netns, e))
+    })
+    .join()
+    .map_err(|_| format!("Binding in network namespace {} panicked", path.display()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path() {
+        assert_eq!(path("mgmt"), PathBuf::from("/run/netns/mgmt"));
+        assert_eq!(path("/proc/1/ns/net"), PathBuf::from("/proc/1/ns/net"));
+        let e = bind("sol-test-missing", &[IpAddr::from([127, 0, 0, 1])], &[0], false).unwrap_err();
+        assert!(e.starts_with("Cannot open network namespace /run/netns/sol-test-missing"), "{}", e);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::inhibit;
+use crate::netif;
+use std::io::ErrorKind;
+use std::process::Command;
+
+/// Wake-on-LAN flags reported by ethtool, e.g. `pumbg` supported and `d` (disabled) enabled
+#[derive(Debug, PartialEq, Eq)]
+pub struct WolSettings {
+    pub supported: String,
+    pub enabled: String,
+}
+
+impl WolSettings {
+    pub fn supports_magic(&self) -> bool {
+        self.supported.contains('g')
+    }
+
+    pub fn magic_enabled(&self) -> bool {
+        self.enabled.contains('g')
+    }
+}
+
+/// Parse `ethtool IFACE` output; `None` when the driver reports no Wake-on-LAN support at all
+pub fn parse_ethtool(output: &str) -> Option<WolSettings> {
+    let mut supported = None;
+    let mut enabled = None;
+    for line in output.lines() {
+        if let Some((key, value)) = line.trim().split_once(':') {
+            match key {
+                "Supports Wake-on" => supported = Some(value.trim().to_string()),
+                "Wake-on" => enabled = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+    Some(WolSettings { supported: supported?, enabled: enabled? })
+}
+
+fn ethtool(args: &[&str]) -> std::io::Result<std::process::Output> {
+    inhibit::output(Command::new("ethtool").args(args), inhibit::COMMAND_TIMEOUT)
+}
+
+/// The Wake-on-LAN settings of `iface`; None when its driver reports none
+pub fn settings(iface: &str) -> std::io::Result<Option<WolSettings>> {
+    Ok(parse_ethtool(&String::from_utf8_lossy(&ethtool(&[iface])?.stdout)))
+}
+
+/// Warn about interfaces that could not be woken again after a suspend, and with `fix`
+/// turn on magic-packet wake where the hardware supports it
+pub fn check_interfaces(fix: bool) {
+    for iface in netif::interfaces() {
+        if iface.is_loopback() || !iface.is_up() || iface.mac.is_none() {
+            continue;
+        }
+
+        let settings = match settings(&iface.name) {
+            Ok(Some(settings)) => settings,
+            Ok(None) => continue,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                warn!("ethtool not found; cannot check Wake-on-LAN settings");
+                return;
+            }
+            Err(e) => {
+                warn!("Failed to run ethtool on {}: {}", iface.name, e);
+                continue;
+            }
+        };
+
+        if settings.magic_enabled() {
+            info!("Wake-on-LAN (magic packet) enabled on {}", iface.name);
+        } else if !settings.supports_magic() {
+            warn!(
+                "{} does not support magic-packet Wake-on-LAN (supports: {})",
+                iface.name, settings.supported
+            );
+        } else if fix {
+            match ethtool(&["-s", &iface.name, "wol", "g"]) {
+                Ok(output) if output.status.success() => {
+                    info!("Enabled Wake-on-LAN (magic packet) on {}", iface.name)
+                }
+                Ok(output) => warn!(
+                    "Failed to enable Wake-on-LAN on {}: {}",
+                    iface.name,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Err(e) => warn!("Failed to enable Wake-on-LAN on {}: {}", iface.name, e),
+            }
+        } else {
+            warn!(
+                "Wake-on-LAN is disabled on {} (wake-on: {}); this machine may not wake after suspending. Run with --fix or `ethtool -s {} wol g`",
+                iface.name, settings.enabled, iface.name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETHTOOL: &str = "Settings for eth0:
+\tSupported ports: [ TP ]
+\tSpeed: 1000Mb/s
+\tSupports Wake-on: pumbg
+\tWake-on: d
+\tCurrent message level: 0x00000007 (7)
+\tLink detected: yes
+";
+
+    #[test]
+    fn test_parse_ethtool() {
+        let settings = parse_ethtool(ETHTOOL).unwrap();
+        assert_eq!(settings, WolSettings { supported: "pumbg".to_string(), enabled: "d".to_string() });
+        assert!(settings.supports_magic());
+        assert!(!settings.magic_enabled());
+
+        let enabled = parse_ethtool(&ETHTOOL.replace("Wake-on: d", "Wake-on: g")).unwrap();
+        assert!(enabled.magic_enabled());
+
+        // Virtual interfaces report no Wake-on-LAN lines
+        assert_eq!(parse_ethtool("Settings for veth0:\n\tLink detected: yes\n"), None);
+    }
+}
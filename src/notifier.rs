@@ -0,0 +1,131 @@
+use std::future::Future;
+use std::pin::Pin;
+
+/// Notification priority, mapped onto each service's own scale; failures are sent at `high`
+/// or above
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Min,
+    Low,
+    Default,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Min => "min",
+            Priority::Low => "low",
+            Priority::Default => "default",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+}
+
+/// What a backend is asked to deliver
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notification {
+    /// Name of the event, e.g. `resumed`
+    pub event: &'static str,
+    pub host: String,
+    pub title: String,
+    pub message: String,
+    pub priority: Priority,
+    /// Emoji shortcode for services with tags, e.g. `zzz`
+    pub tag: &'static str,
+    /// URL opened when the notification is tapped
+    pub click: Option<String>,
+}
+
+pub type Sent<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+/// A push service notifications can be delivered to
+pub trait Notifier: Send + Sync {
+    /// Where notifications go, for log lines
+    fn describe(&self) -> String;
+    fn send<'a>(&'a self, notification: &'a Notification) -> Sent<'a>;
+}
+
+/// Where a `[notify.<name>]` table says to deliver
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Target {
+    pub url: String,
+    pub token: Option<String>,
+    pub user: Option<String>,
+}
+
+/// Builds a backend for a target, or says what the table is missing
+pub type Build = fn(Target) -> Result<Box<dyn Notifier>, String>;
+
+/// The backends a table's `type` can name. A new service registers its `Build` here, and the
+/// code that routes events to backends stays as it is.
+#[derive(Default)]
+pub struct Registry {
+    backends: Vec<(&'static str, Build)>,
+}
+
+impl Registry {
+    pub fn register(&mut self, kind: &'static str, build: Build) {
+        self.backends.push((kind, build));
+    }
+
+    /// Build the backend registered as `kind`
+    pub fn build(&self, kind: &str, target: Target) -> Result<Box<dyn Notifier>, String> {
+        match self.backends.iter().find(|(name, _)| *name == kind) {
+            Some((_, build)) => build(target),
+            None => Err(format!("unknown type '{}' (expected {})", kind, self.expected())),
+        }
+    }
+
+    /// The registered types, as `a, b or c`
+    fn expected(&self) -> String {
+        let names: Vec<&str> = self.backends.iter().map(|(name, _)| *name).collect();
+        match names.split_last() {
+            Some((last, [])) => String::from(*last),
+            Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+            None => String::from("nothing, as no backend is registered"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Null(String);
+
+    impl Notifier for Null {
+        fn describe(&self) -> String {
+            format!("null {}", self.0)
+        }
+
+        fn send<'a>(&'a self, _: &'a Notification) -> Sent<'a> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn null(target: Target) -> Result<Box<dyn Notifier>, String> {
+        match target.token {
+            None => Ok(Box::new(Null(target.url))),
+            Some(_) => Err("'token' does not apply to null".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::default();
+        let target = Target { url: "http://x".to_string(), ..Target::default() };
+        assert_eq!(registry.build("null", target.clone()).err().as_deref(), Some("unknown type 'null' (expected nothing, as no backend is registered)"));
+
+        registry.register("null", null);
+        assert_eq!(registry.build("null", target.clone()).unwrap().describe(), "null http://x");
+        let with_token = Target { token: Some("t".to_string()), ..target.clone() };
+        assert_eq!(registry.build("null", with_token).err().as_deref(), Some("'token' does not apply to null"));
+
+        registry.register("void", null);
+        registry.register("nowhere", null);
+        assert_eq!(registry.build("slack", target).err().as_deref(), Some("unknown type 'slack' (expected null, void or nowhere)"));
+    }
+}
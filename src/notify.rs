@@ -0,0 +1,240 @@
+use crate::config::{Config, Table, Value};
+use crate::events::{Event, Handled, Listener};
+use crate::{gotify, ntfy, pushover, webhook};
+use clap::ValueEnum;
+use sol::notifier::{Notification, Notifier, Priority, Registry, Target};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// How long one notification may take to deliver
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The events that produce notifications, and the default filter of every backend
+pub const NOTIFIED: &[&str] = &["action-finished", "resumed", "action-failed", "missed-sleep", "peer-unverified"];
+
+/// The backends `[notify.<name>]` tables can name, in the order their types are listed
+pub fn backends() -> Registry {
+    let mut registry = Registry::default();
+    registry.register("ntfy", ntfy::build);
+    registry.register("gotify", gotify::build);
+    registry.register("pushover", pushover::build);
+    registry.register("webhook", webhook::build);
+    registry
+}
+
+/// A backend with the events it wants and how they are presented
+pub struct Route {
+    pub name: String,
+    pub notifier: Box<dyn Notifier>,
+    pub events: Vec<String>,
+    pub priority: Priority,
+    pub click: Option<String>,
+}
+
+impl Route {
+    /// The notification for `event`, or None if this route does not want it
+    fn notification(&self, event: &Event, host: &str) -> Option<Notification> {
+        if !self.events.iter().any(|name| name == event.name()) {
+            return None;
+        }
+        let failure = self.priority.max(Priority::High);
+        let (title, message, priority, tag) = match event {
+            Event::ActionFinished { action } => {
+                (format!("{}: {}", host, action.as_str()), format!("{} is going to {}", host, action.as_str()), self.priority, "zzz")
+            }
+            Event::Resumed { action } => {
+                (format!("{}: resumed", host), format!("{} resumed from {}", host, action.as_str()), self.priority, "sunny")
+            }
+            Event::ActionFailed { detail } | Event::MissedSleep { detail } | Event::Verified { ok: false, detail, .. } => {
+                (format!("{}: {}", host, event.name()), detail.clone(), failure, "warning")
+            }
+            _ => return None,
+        };
+        Some(Notification {
+            event: event.name(),
+            host: host.to_string(),
+            title: title.replace(|c: char| c.is_control(), " "),
+            message,
+            priority,
+            tag,
+            click: self.click.clone(),
+        })
+    }
+}
+
+fn string(key: &str, value: &Value) -> Result<String, String> {
+    value.as_str().map(str::to_string).ok_or_else(|| format!("'{}' must be a string", key))
+}
+
+fn route(name: &str, table: &Table, registry: &Registry) -> Result<Route, String> {
+    let (mut kind, mut url, mut token, mut user) = (None, None, None, None);
+    let (mut events, mut priority, mut click) = (NOTIFIED.iter().map(|name| name.to_string()).collect(), Priority::Default, None);
+    for (key, value) in table {
+        match key.as_str() {
+            "type" => kind = Some(string(key, value)?),
+            "url" => {
+                let value = string(key, value)?;
+                crate::portmap::split_url(&value)?;
+                url = Some(value);
+            }
+            "token" => token = Some(string(key, value)?),
+            "user" => user = Some(string(key, value)?),
+            "priority" => priority = Priority::from_str(&string(key, value)?, true).map_err(|_| "'priority' must be min, low, default, high or urgent")?,
+            "click" => click = Some(string(key, value)?),
+            "events" => {
+                let Value::Array(values) = value else {
+                    return Err("'events' must be an array of event names".to_string());
+                };
+                events = values.iter().map(|value| string(key, value)).collect::<Result<Vec<_>, _>>()?;
+                if let Some(unknown) = events.iter().find(|name| !NOTIFIED.contains(&name.as_str())) {
+                    return Err(format!("'events' names '{}', which is not one of {}", unknown, NOTIFIED.join(", ")));
+                }
+            }
+            _ => return Err(format!("unknown key '{}'", key)),
+        }
+    }
+    let url = url.ok_or("'url' is required")?;
+    let kind = kind.ok_or("'type' is required")?;
+    let notifier = registry.build(&kind, Target { url, token, user })?;
+    Ok(Route { name: name.to_string(), notifier, events, priority, click })
+}
+
+/// Hands events to every notification backend whose filter wants them
+#[derive(Default)]
+pub struct Notifiers {
+    routes: Vec<Arc<Route>>,
+}
+
+impl Notifiers {
+    /// Build backends from `[notify.<name>]` tables, after the ones given on the command line,
+    /// looking each table's type up in `registry`
+    pub fn from_config(routes: Vec<Route>, config: &Config, registry: &Registry) -> Result<Self, String> {
+        let mut routes: Vec<Arc<Route>> = routes.into_iter().map(Arc::new).collect();
+        for (name, table) in config.subtables("notify") {
+            routes.push(Arc::new(route(name, table, registry).map_err(|e| format!("notify.{}: {}", name, e))?));
+        }
+        Ok(Notifiers { routes })
+    }
+
+    pub fn describe(&self) -> Vec<String> {
+        self.routes.iter().map(|route| format!("{} ({})", route.name, route.notifier.describe())).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+impl Listener for Notifiers {
+    fn handle<'a>(&'a self, event: &'a Event) -> Handled<'a> {
+        Box::pin(async move {
+            let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+            // Backends are sent to at once, so a slow one delays the action by one timeout at most
+            let mut sends = JoinSet::new();
+            for route in &self.routes {
+                let Some(notification) = route.notification(event, hostname.trim()) else {
+                    continue;
+                };
+                let route = Arc::clone(route);
+                sends.spawn(async move {
+                    let sent = tokio::time::timeout(SEND_TIMEOUT, route.notifier.send(&notification)).await;
+                    if let Err(e) = sent.map_err(|_| "timed out".to_string()).and_then(|sent| sent) {
+                        warn!("Notification to {} failed: {}", route.notifier.describe(), e);
+                    }
+                });
+            }
+            sends.join_all().await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Action;
+    use sol::notifier::Sent;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Arc<Mutex<Vec<Notification>>>);
+
+    impl Notifier for Recorder {
+        fn describe(&self) -> String {
+            "recorder".to_string()
+        }
+
+        fn send<'a>(&'a self, notification: &'a Notification) -> Sent<'a> {
+            Box::pin(async move {
+                self.0.lock().unwrap().push(notification.clone());
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_with_filters() {
+        let (all, failures) = (Recorder::default(), Recorder::default());
+        let (all_sent, failures_sent) = (Arc::clone(&all.0), Arc::clone(&failures.0));
+        let route = |notifier: Recorder, events: &[&str], priority| Route {
+            name: "test".to_string(),
+            notifier: Box::new(notifier),
+            events: events.iter().map(|name| name.to_string()).collect(),
+            priority,
+            click: None,
+        };
+        let notifiers = Notifiers::from_config(
+            vec![route(all, NOTIFIED, Priority::Low), route(failures, &["action-failed"], Priority::Urgent)],
+            &Config::default(),
+            &backends(),
+        )
+        .unwrap();
+
+        notifiers.handle(&Event::ActionFinished { action: Action::Suspend }).await;
+        notifiers.handle(&Event::Inhibited { reason: "ssh".to_string() }).await;
+        notifiers.handle(&Event::ActionFailed { detail: "pre-suspend failed".to_string() }).await;
+
+        let all_sent = all_sent.lock().unwrap();
+        assert_eq!(all_sent.iter().map(|n| (n.event, n.priority)).collect::<Vec<_>>(), [
+            ("action-finished", Priority::Low),
+            ("action-failed", Priority::High)
+        ]);
+        assert!(all_sent[0].message.ends_with(" is going to suspend"));
+        let failures_sent = failures_sent.lock().unwrap();
+        assert_eq!(failures_sent.iter().map(|n| (n.event, n.priority)).collect::<Vec<_>>(), [("action-failed", Priority::Urgent)]);
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = Config::parse(
+            r#"
+[notify.phone]
+type = "gotify"
+url = "http://gotify.lan"
+token = "AbC"
+priority = "high"
+events = ["resumed"]
+
+[notify.hook]
+type = "webhook"
+url = "http://automation.lan:8123/api/webhook/sol"
+"#,
+        )
+        .unwrap();
+        let notifiers = Notifiers::from_config(Vec::new(), &config, &backends()).unwrap();
+        assert_eq!(notifiers.describe(), ["hook (webhook http://automation.lan:8123/api/webhook/sol)", "phone (gotify http://gotify.lan)"]);
+        assert_eq!(notifiers.routes[1].events, ["resumed"]);
+        assert_eq!(notifiers.routes[1].priority, Priority::High);
+
+        for (table, error) in [
+            ("type = \"gotify\"\nurl = \"http://g\"", "notify.x: 'token' is required for gotify"),
+            ("type = \"pushover\"\nurl = \"http://p\"\ntoken = \"t\"", "notify.x: 'token' and 'user' are required for pushover"),
+            ("type = \"ntfy\"\nurl = \"https://ntfy.sh/x\"", "notify.x: unsupported URL https://ntfy.sh/x"),
+            ("type = \"ntfy\"\nurl = \"http://ntfy.sh/x\"\nevents = [\"inhibited\"]", "notify.x: 'events' names 'inhibited', which is not one of action-finished, resumed, action-failed, missed-sleep, peer-unverified"),
+            ("type = \"slack\"\nurl = \"http://s\"", "notify.x: unknown type 'slack' (expected ntfy, gotify, pushover or webhook)"),
+        ] {
+            let config = Config::parse(&format!("[notify.x]\n{}\n", table)).unwrap();
+            assert_eq!(Notifiers::from_config(Vec::new(), &config, &backends()).err().as_deref(), Some(error));
+        }
+    }
+}
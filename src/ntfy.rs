@@ -0,0 +1,86 @@
+use crate::heartbeat::post;
+use sol::notifier::{Notification, Notifier, Priority, Sent, Target};
+
+/// Publishes to an ntfy topic, given as `http://HOST[:PORT]/TOPIC`
+pub struct Ntfy {
+    pub url: String,
+    /// Access token for topics on a server with access control
+    pub token: Option<String>,
+}
+
+fn priority(priority: Priority) -> &'static str {
+    match priority {
+        Priority::Min => "1",
+        Priority::Low => "2",
+        Priority::Default => "3",
+        Priority::High => "4",
+        Priority::Urgent => "5",
+    }
+}
+
+/// The backend for a `type = "ntfy"` table, where the token is optional
+pub fn build(target: Target) -> Result<Box<dyn Notifier>, String> {
+    match target {
+        Target { url, token, user: None } => Ok(Box::new(Ntfy { url, token })),
+        Target { user: Some(_), .. } => Err("'user' only applies to pushover".to_string()),
+    }
+}
+
+impl Notifier for Ntfy {
+    fn describe(&self) -> String {
+        format!("ntfy {}", self.url)
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> Sent<'a> {
+        Box::pin(async move {
+            let authorization = self.token.as_ref().map(|token| format!("Bearer {}", token));
+            let mut headers = vec![
+                ("Title", notification.title.as_str()),
+                ("Priority", priority(notification.priority)),
+                ("Tags", notification.tag),
+            ];
+            if let Some(click) = &notification.click {
+                headers.push(("Click", click));
+            }
+            if let Some(authorization) = &authorization {
+                headers.push(("Authorization", authorization));
+            }
+            post(&self.url, &headers, &notification.message).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/nas-alerts", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 1024];
+            let len = stream.read(&mut request).await.unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+            String::from_utf8_lossy(&request[..len]).into_owned()
+        });
+        let notification = Notification {
+            event: "missed-sleep",
+            host: "nas".to_string(),
+            title: "nas: missed-sleep".to_string(),
+            message: "did not sleep".to_string(),
+            priority: Priority::Urgent,
+            tag: "warning",
+            click: Some("http://nas.lan:8080/".to_string()),
+        };
+        let ntfy = Ntfy { url, token: None };
+        assert_eq!(ntfy.send(&notification).await, Ok(()));
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /nas-alerts HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nTitle: nas: missed-sleep\r\nPriority: 5\r\nTags: warning\r\nClick: http://nas.lan:8080/\r\n"));
+        assert!(request.ends_with("\r\n\r\ndid not sleep"));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::netif::NetworkInterface;
+use crate::retry::Backoff;
+use std::time::{Duration, Instant};
+
+/// Longest pause between two attempts
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Whether an interface other than loopback is up with a MAC and an address, which is what
+/// the daemon needs to have anything to monitor
+pub fn ready(interfaces: &[NetworkInterface]) -> Result<(), String> {
+    let up = interfaces.iter().any(|iface| iface.is_up() && !iface.is_loopback() && iface.mac.is_some() && !iface.ips.is_empty());
+    if up { Ok(()) } else { Err("no network interface with an address is up yet".to_string()) }
+}
+
+/// Run `attempt` until it succeeds, pausing `initial`, then twice that and so on up to 30s.
+/// Gives up with the last error once `timeout` has passed; without one it waits forever.
+pub fn wait<T>(timeout: Option<Duration>, initial: Duration, mut attempt: impl FnMut() -> Result<T, String>) -> Result<T, String> {
+    let started = Instant::now();
+    let backoff = Backoff { retries: u32::MAX, initial };
+    let mut tries = 0;
+    loop {
+        tries += 1;
+        let e = match attempt() {
+            Ok(value) => {
+                if tries > 1 {
+                    info!("Network ready after {}s", started.elapsed().as_secs());
+                }
+                return Ok(value);
+            }
+            Err(e) => e,
+        };
+        let delay = backoff.delay(tries).min(MAX_DELAY);
+        if timeout.is_some_and(|timeout| started.elapsed() + delay > timeout) {
+            return Err(format!("network not ready after {}s: {}", started.elapsed().as_secs(), e));
+        }
+        if tries == 1 {
+            info!("Waiting for the network: {}", e);
+        } else {
+            verbose!("Still waiting for the network: {}", e);
+        }
+        std::thread::sleep(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ready() {
+        let iface = |flags: i32, ips: &[&str]| NetworkInterface {
+            name: "eth0".to_string(),
+            index: 2,
+            mac: Some(crate::mac::MacAddr([0x02, 0, 0, 0, 0, 1])),
+            ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            flags: flags as u32,
+        };
+        assert!(ready(&[iface(libc::IFF_UP, &["192.168.1.10/24"])]).is_ok());
+        assert!(ready(&[iface(libc::IFF_UP, &[])]).is_err());
+        assert!(ready(&[iface(0, &["192.168.1.10/24"])]).is_err());
+        assert!(ready(&[iface(libc::IFF_UP | libc::IFF_LOOPBACK, &["127.0.0.1/8"])]).is_err());
+    }
+
+    #[test]
+    fn test_wait() {
+        let mut tries = 0;
+        let result = wait(Some(Duration::from_secs(5)), Duration::from_millis(5), || {
+            tries += 1;
+            if tries < 3 { Err("not yet".to_string()) } else { Ok(tries) }
+        });
+        assert_eq!(result, Ok(3));
+
+        let e = wait::<()>(Some(Duration::from_millis(50)), Duration::from_millis(20), || Err("no carrier".to_string())).unwrap_err();
+        assert!(e.starts_with("network not ready after 0s: no carrier"), "{}", e);
+    }
+}
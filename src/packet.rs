@@ -0,0 +1,131 @@
+use core::fmt;
+
+/// Header of a Wake-on-LAN trigger
+pub const MAGIC_HEADER: [u8; 6] = [0xFF; 6];
+
+/// Header of a cancel packet: the WoL layout with the header inverted
+pub const CANCEL_HEADER: [u8; 6] = [0x00; 6];
+
+/// 6 (header) + 16*6 (MAC repeated 16 times)
+pub const PACKET_SIZE: usize = 102;
+
+/// Why a datagram is not a well-formed packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Shorter than `PACKET_SIZE`; holds the actual length
+    Size(usize),
+    Header,
+    Repetition,
+    /// Bytes after the MAC repetition that are not padding
+    Trailer,
+    /// Missing or wrong SecureOn password
+    Password,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Size(len) => write!(f, "Invalid size: {} (expected {})", len, PACKET_SIZE),
+            Error::Header => write!(f, "Invalid header"),
+            Error::Repetition => write!(f, "Invalid MAC repetition"),
+            Error::Trailer => write!(f, "Invalid data after the MAC repetition"),
+            Error::Password => write!(f, "Wrong SecureOn password"),
+        }
+    }
+}
+
+/// Check the packet layout and return the MAC it carries. Bytes after the first
+/// `PACKET_SIZE` are left to `check_trailer`.
+pub fn parse(packet: &[u8], header: &[u8; 6]) -> Result<[u8; 6], Error> {
+    if packet.len() < PACKET_SIZE {
+        return Err(Error::Size(packet.len()));
+    }
+    if &packet[..6] != header {
+        return Err(Error::Header);
+    }
+
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&packet[6..12]);
+    if packet[6..PACKET_SIZE].chunks_exact(6).any(|chunk| chunk != mac) {
+        return Err(Error::Repetition);
+    }
+    Ok(mac)
+}
+
+/// What may follow the first `PACKET_SIZE` bytes of a packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trailer<'a> {
+    /// Nothing, or padding: a run of `0x00` or `0xFF` bytes, as some routers and apps send
+    Padding,
+    /// Nothing at all
+    Exact,
+    /// Exactly this SecureOn password
+    Password(&'a [u8]),
+}
+
+/// Check what follows the MAC repetition of a packet that `parse` accepted
+pub fn check_trailer(packet: &[u8], trailer: Trailer) -> Result<(), Error> {
+    let rest = packet.get(PACKET_SIZE..).unwrap_or_default();
+    match trailer {
+        Trailer::Padding if rest.iter().all(|b| *b == 0x00) || rest.iter().all(|b| *b == 0xFF) => Ok(()),
+        Trailer::Padding => Err(Error::Trailer),
+        Trailer::Exact if rest.is_empty() => Ok(()),
+        Trailer::Exact => Err(Error::Size(packet.len())),
+        Trailer::Password(password) => {
+            // Compare every byte so the time taken does not reveal how much matched
+            let diff = rest.iter().zip(password).fold(0, |diff, (a, b)| diff | (a ^ b));
+            if rest.len() == password.len() && diff == 0 { Ok(()) } else { Err(Error::Password) }
+        }
+    }
+}
+
+/// Build a packet with `header` followed by `mac` repeated 16 times
+pub fn encode(header: &[u8; 6], mac: &[u8; 6]) -> [u8; PACKET_SIZE] {
+    let mut packet = [0u8; PACKET_SIZE];
+    packet[..6].copy_from_slice(header);
+    for chunk in packet[6..].chunks_exact_mut(6) {
+        chunk.copy_from_slice(mac);
+    }
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF];
+
+    #[test]
+    fn test_round_trip() {
+        let packet = encode(&MAGIC_HEADER, &MAC);
+        assert_eq!(parse(&packet, &MAGIC_HEADER), Ok(MAC));
+        assert_eq!(parse(&packet, &CANCEL_HEADER), Err(Error::Header));
+        assert_eq!(parse(&encode(&CANCEL_HEADER, &MAC), &CANCEL_HEADER), Ok(MAC));
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut packet = encode(&MAGIC_HEADER, &MAC);
+        assert_eq!(parse(&packet[..50], &MAGIC_HEADER), Err(Error::Size(50)));
+        packet[PACKET_SIZE - 1] = 0;
+        assert_eq!(parse(&packet, &MAGIC_HEADER), Err(Error::Repetition));
+    }
+
+    #[test]
+    fn test_trailer() {
+        let mut packet = [0u8; 128];
+        packet[..PACKET_SIZE].copy_from_slice(&encode(&MAGIC_HEADER, &MAC));
+        assert_eq!(parse(&packet, &MAGIC_HEADER), Ok(MAC));
+        assert_eq!(check_trailer(&packet, Trailer::Padding), Ok(()));
+        assert_eq!(check_trailer(&packet[..PACKET_SIZE], Trailer::Padding), Ok(()));
+        assert_eq!(check_trailer(&packet, Trailer::Exact), Err(Error::Size(128)));
+        assert_eq!(check_trailer(&packet[..PACKET_SIZE], Trailer::Exact), Ok(()));
+
+        packet[PACKET_SIZE..PACKET_SIZE + 6].copy_from_slice(&MAC);
+        assert_eq!(check_trailer(&packet, Trailer::Padding), Err(Error::Trailer));
+        let secureon = &packet[..PACKET_SIZE + 6];
+        assert_eq!(check_trailer(secureon, Trailer::Password(&MAC)), Ok(()));
+        assert_eq!(check_trailer(secureon, Trailer::Password(&[0xAA, 0xBB, 0xCC, 0xDD])), Err(Error::Password));
+        assert_eq!(check_trailer(&packet[..PACKET_SIZE], Trailer::Password(&MAC)), Err(Error::Password));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::clock;
+use crate::inhibit::Inhibitor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{SignalKind, signal};
+
+/// Runtime switch that disarms trigger processing without stopping the daemon
+#[derive(Clone, Default)]
+pub struct Paused(Arc<AtomicBool>);
+
+impl Paused {
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Set the state and return whether it changed
+    pub fn set(&self, paused: bool) -> bool {
+        self.0.swap(paused, Ordering::SeqCst) != paused
+    }
+
+    /// Flip the state and return the new value
+    pub fn toggle(&self) -> bool {
+        !self.0.fetch_xor(true, Ordering::SeqCst)
+    }
+}
+
+impl Inhibitor for Paused {
+    fn name(&self) -> &str {
+        "paused"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        Ok(self
+            .is_paused()
+            .then(|| "trigger processing paused (send SIGUSR2 to resume)".to_string()))
+    }
+}
+
+/// The longest snooze accepted from any source
+pub const MAX_SNOOZE: Duration = Duration::from_secs(7 * 86400);
+
+/// Blocks sleep until a deadline, for "give me 45 more minutes"
+#[derive(Clone, Default)]
+pub struct Snooze(Arc<Mutex<Option<Instant>>>);
+
+impl Snooze {
+    /// Block sleep for `duration` from now, at most `MAX_SNOOZE`, replacing any earlier
+    /// snooze; zero ends it
+    pub fn set(&self, duration: Duration) {
+        *self.0.lock().unwrap() = (!duration.is_zero()).then(|| Instant::now() + duration.min(MAX_SNOOZE));
+    }
+
+    /// How long the snooze has left, if one is running
+    pub fn remaining(&self) -> Option<Duration> {
+        let until = (*self.0.lock().unwrap())?;
+        Some(until.saturating_duration_since(Instant::now())).filter(|left| !left.is_zero())
+    }
+
+    /// The remaining time as shown in status output, e.g. `45 minute(s) left`
+    pub fn describe(&self) -> Option<String> {
+        self.remaining().map(|left| format!("{} minute(s) left", left.as_secs().div_ceil(60)))
+    }
+}
+
+impl Inhibitor for Snooze {
+    fn name(&self) -> &str {
+        "snooze"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        Ok(self.describe().map(|left| format!("snoozed, {}", left)))
+    }
+}
+
+/// A snooze length: a duration such as `45m` or `2h`, a plain number of minutes, or `off`
+pub fn parse_snooze(s: &str) -> Result<Duration, String> {
+    let length = match s {
+        "off" => return Ok(Duration::ZERO),
+        _ => match s.parse::<u64>() {
+            Ok(minutes) => Duration::from_secs(minutes.checked_mul(60).ok_or_else(|| too_long(s))?),
+            Err(_) => clock::parse_duration(s)?,
+        },
+    };
+    check_snooze(length).map_err(|_| too_long(s))
+}
+
+/// Reject a snooze longer than `MAX_SNOOZE`
+pub fn check_snooze(length: Duration) -> Result<Duration, String> {
+    if length <= MAX_SNOOZE {
+        Ok(length)
+    } else {
+        Err(format!("A snooze can last at most {} days", MAX_SNOOZE.as_secs() / 86400))
+    }
+}
+
+fn too_long(s: &str) -> String {
+    format!("Snooze '{}' is too long: a snooze can last at most {} days", s, MAX_SNOOZE.as_secs() / 86400)
+}
+
+/// Toggle `paused` every time the daemon receives SIGUSR2
+pub fn watch_signal(paused: Paused) -> std::io::Result<()> {
+    let mut signals = signal(SignalKind::user_defined2())?;
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            if paused.toggle() {
+                info!("SIGUSR2 received: trigger processing paused");
+            } else {
+                info!("SIGUSR2 received: trigger processing resumed");
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle() {
+        let paused = Paused::default();
+        assert!(!paused.is_paused());
+        assert_eq!(paused.check(), Ok(None));
+
+        assert!(paused.toggle());
+        assert!(paused.is_paused());
+        assert!(paused.check().unwrap().is_some());
+
+        assert!(!paused.toggle());
+        assert!(!paused.is_paused());
+
+        assert!(paused.set(true));
+        assert!(!paused.set(true));
+        assert!(paused.is_paused());
+    }
+
+    #[test]
+    fn test_snooze() {
+        let snooze = Snooze::default();
+        assert_eq!(snooze.check(), Ok(None));
+        snooze.set(parse_snooze("45").unwrap());
+        assert_eq!(snooze.check(), Ok(Some("snoozed, 45 minute(s) left".to_string())));
+        snooze.set(parse_snooze("off").unwrap());
+        assert_eq!(snooze.remaining(), None);
+        snooze.set(Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(snooze.check(), Ok(None));
+        assert_eq!(parse_snooze("1h30m"), Ok(Duration::from_secs(5400)));
+        assert!(parse_snooze("later").is_err());
+
+        assert_eq!(parse_snooze("7d"), Ok(MAX_SNOOZE));
+        assert!(parse_snooze("8d").unwrap_err().contains("too long"));
+        assert!(parse_snooze("307445734561825861").unwrap_err().contains("too long"));
+        assert!(parse_snooze("999999999999999d").unwrap_err().contains("too long"));
+        snooze.set(Duration::MAX);
+        assert_eq!(snooze.describe(), Some("10080 minute(s) left".to_string()));
+    }
+}
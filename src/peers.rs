@@ -0,0 +1,200 @@
+use crate::config::Config;
+use crate::mac::MacAddr;
+use crate::relay::{RelayEntry, RelayTarget, DEFAULT_RELAY_PORT};
+use sol::packet;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Mutex;
+
+/// A machine this daemon can wake on request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Peer {
+    pub name: String,
+    pub target: RelayEntry,
+}
+
+/// Written as `NAME=MAC[@TARGET[:PORT]]`; without a target the packet goes to the
+/// limited broadcast address on port 9
+impl std::str::FromStr for Peer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, target) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid peer '{}' (expected NAME=MAC[@TARGET[:PORT]])", s))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("Missing name in peer '{}'", s));
+        }
+
+        let target = if target.contains('@') {
+            target.parse()?
+        } else {
+            format!("{}@{}", target, Ipv4Addr::BROADCAST).parse()?
+        };
+        Ok(Peer { name: name.to_string(), target })
+    }
+}
+
+/// Build a Wake-on-LAN magic packet for `mac`
+pub fn wol_packet(mac: &MacAddr) -> Vec<u8> {
+    packet::encode(&packet::MAGIC_HEADER, &mac.0).to_vec()
+}
+
+/// Where a machine without a configured target is woken: the limited broadcast address on port 9
+pub fn broadcast_target(mac: MacAddr) -> RelayEntry {
+    RelayEntry { mac, target: RelayTarget::Address(Ipv4Addr::BROADCAST), port: DEFAULT_RELAY_PORT }
+}
+
+/// Send a magic packet for the target's MAC to its destination and return where it went
+pub fn send_wol(target: &RelayEntry) -> Result<SocketAddr, String> {
+    let destination = target.destination()?;
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+    socket.set_broadcast(true).map_err(|e| e.to_string())?;
+    socket
+        .send_to(&wol_packet(&target.mac), destination)
+        .map_err(|e| format!("failed to send to {}: {}", destination, e))?;
+    Ok(destination)
+}
+
+/// Known peers from `--peer` and `[peer.<name>]` config tables
+#[derive(Debug, Default)]
+pub struct Peers {
+    /// Peers given with --peer, which config reloads keep
+    cli: Vec<Peer>,
+    peers: Mutex<Vec<Peer>>,
+}
+
+impl Peers {
+    pub fn from_config(cli: Vec<Peer>, config: &Config) -> Result<Self, String> {
+        let peers = Peers { cli, peers: Mutex::default() };
+        *peers.peers.lock().unwrap() = peers.parse(config)?;
+        Ok(peers)
+    }
+
+    /// The --peer peers plus `[peer.<name>]` tables with a `mac` and optional `target` and `port`
+    pub fn parse(&self, config: &Config) -> Result<Vec<Peer>, String> {
+        let mut peers = self.cli.clone();
+        for (name, table) in config.subtables("peer") {
+            let mac = table
+                .get("mac")
+                .ok_or_else(|| format!("peer.{}: missing 'mac'", name))?
+                .as_str()
+                .ok_or_else(|| format!("peer.{}: 'mac' must be a string", name))?;
+            let target = match table.get("target") {
+                Some(value) => value
+                    .as_str()
+                    .ok_or_else(|| format!("peer.{}: 'target' must be a string", name))?
+                    .to_string(),
+                None => Ipv4Addr::BROADCAST.to_string(),
+            };
+            let port = match table.get("port") {
+                Some(value) => value
+                    .as_integer()
+                    .and_then(|port| u16::try_from(port).ok())
+                    .ok_or_else(|| format!("peer.{}: 'port' must be a port number", name))?,
+                None => DEFAULT_RELAY_PORT,
+            };
+            if let Some(key) = table.keys().find(|key| !["mac", "target", "port"].contains(&key.as_str())) {
+                return Err(format!("peer.{}: unknown key '{}'", name, key));
+            }
+
+            let target = format!("{}@{}:{}", mac, target, port)
+                .parse()
+                .map_err(|e| format!("peer.{}: {}", name, e))?;
+            peers.push(Peer { name: name.to_string(), target });
+        }
+        Ok(peers)
+    }
+
+    /// Swap in peers from a reloaded config and describe what changed
+    pub fn replace(&self, peers: Vec<Peer>) -> Vec<String> {
+        let mut current = self.peers.lock().unwrap();
+        let changes = crate::reload::changes("peer", &current, &peers, |peer| &peer.name);
+        *current = peers;
+        changes
+    }
+
+    pub fn get(&self, name: &str) -> Option<Peer> {
+        self.peers.lock().unwrap().iter().find(|peer| peer.name == name).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Peer> {
+        self.peers.lock().unwrap().clone()
+    }
+
+    /// Send a magic packet to the named peer and return where it was sent
+    pub fn wake(&self, name: &str) -> Result<SocketAddr, String> {
+        let peer = self.get(name).ok_or_else(|| format!("unknown peer '{}'", name))?;
+        send_wol(&peer.target)
+    }
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} ", self.name, self.target.mac)?;
+        match &self.target.target {
+            RelayTarget::Address(addr) => write!(f, "{}:{}", addr, self.target.port),
+            RelayTarget::Interface(name) => write!(f, "{}:{}", name, self.target.port),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+    #[test]
+    fn test_parse_peer() {
+        let peer: Peer = "nas=aa:bb:cc:dd:ee:ff".parse().unwrap();
+        assert_eq!(peer.name, "nas");
+        assert_eq!(peer.target.mac, MAC);
+        assert_eq!(peer.target.target, RelayTarget::Address(Ipv4Addr::BROADCAST));
+        assert_eq!(peer.to_string(), "nas aa:bb:cc:dd:ee:ff 255.255.255.255:9");
+
+        let peer: Peer = "media=aa:bb:cc:dd:ee:ff@eth1:7".parse().unwrap();
+        assert_eq!(peer.target.target, RelayTarget::Interface("eth1".to_string()));
+        assert_eq!(peer.target.port, 7);
+
+        assert!("aa:bb:cc:dd:ee:ff".parse::<Peer>().is_err());
+        assert!("=aa:bb:cc:dd:ee:ff".parse::<Peer>().is_err());
+    }
+
+    #[test]
+    fn test_peers_from_config() {
+        let config = Config::parse(
+            "[peer.nas]\nmac = \"aa:bb:cc:dd:ee:ff\"\ntarget = \"192.168.20.255\"\n\n[peer.desk]\nmac = \"11:22:33:44:55:66\"",
+        )
+        .unwrap();
+        let peers = Peers::from_config(vec!["tv=aa:bb:cc:00:00:01".parse().unwrap()], &config).unwrap();
+
+        let names: Vec<String> = peers.list().into_iter().map(|peer| peer.name).collect();
+        assert_eq!(names, ["tv", "desk", "nas"]);
+        assert_eq!(
+            peers.get("nas").unwrap().target.target,
+            RelayTarget::Address(Ipv4Addr::new(192, 168, 20, 255))
+        );
+        assert_eq!(peers.get("desk").unwrap().target.port, DEFAULT_RELAY_PORT);
+
+        let unknown = Config::parse("[peer.nas]\nmac = \"aa:bb:cc:dd:ee:ff\"\nip = \"1.2.3.4\"").unwrap();
+        assert!(Peers::from_config(Vec::new(), &unknown).is_err());
+        let missing = Config::parse("[peer.nas]\nport = 9").unwrap();
+        assert!(Peers::from_config(Vec::new(), &missing).is_err());
+    }
+
+    #[test]
+    fn test_wake_sends_magic_packet() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let peer = format!("nas=aa:bb:cc:dd:ee:ff@127.0.0.1:{}", port).parse().unwrap();
+        let peers = Peers::from_config(vec![peer], &Config::default()).unwrap();
+
+        assert_eq!(peers.wake("nas").unwrap(), SocketAddr::from(([127, 0, 0, 1], port)));
+        assert!(peers.wake("printer").is_err());
+
+        let mut buf = [0u8; 128];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], wol_packet(&MAC).as_slice());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::config::{Config, Table, Value};
+use crate::powerprofile;
+use crate::presuspend::run_step;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// Name of the pipeline used when a rule does not pick one
+pub const DEFAULT_PIPELINE: &str = "default";
+
+/// What a failed step does to the rest of its pipeline
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnFailure {
+    /// Stop; before suspend this also cancels the suspend
+    Abort,
+    /// Log the failure and carry on with the next step
+    Continue,
+}
+
+/// A shell command from a `[step.<name>]` table, or a switch of the platform power profile
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub name: String,
+    pub command: String,
+    /// power-profiles-daemon profile to switch to instead of running a command
+    pub power_profile: Option<String>,
+    pub timeout: Duration,
+    pub on_failure: OnFailure,
+}
+
+impl Step {
+    fn parse(name: &str, table: &Table, default_timeout: Duration) -> Result<Step, String> {
+        let mut step = Step {
+            name: name.to_string(),
+            command: String::new(),
+            power_profile: None,
+            timeout: default_timeout,
+            on_failure: OnFailure::Abort,
+        };
+        for (key, value) in table {
+            match key.as_str() {
+                "run" => step.command = string(key, value)?.to_string(),
+                "power_profile" => step.power_profile = Some(string(key, value)?.to_string()),
+                "timeout" => {
+                    let secs = value.as_integer().and_then(|i| u64::try_from(i).ok()).filter(|secs| *secs > 0);
+                    step.timeout = Duration::from_secs(secs.ok_or("'timeout' must be a positive integer")?);
+                }
+                "on_failure" => {
+                    step.on_failure = match string(key, value)? {
+                        "abort" => OnFailure::Abort,
+                        "continue" => OnFailure::Continue,
+                        other => return Err(format!("'on_failure' must be abort or continue, not '{}'", other)),
+                    }
+                }
+                _ => return Err(format!("unknown key '{}'", key)),
+            }
+        }
+        if step.command.is_empty() == step.power_profile.is_none() {
+            return Err("exactly one of 'run' and 'power_profile' is required".to_string());
+        }
+        Ok(step)
+    }
+
+    /// Run the step, logging how it went. Returns false if the pipeline should stop.
+    pub async fn run(&self) -> bool {
+        let started = Instant::now();
+        let result = match &self.power_profile {
+            Some(profile) => self.switch_profile(profile.clone()).await,
+            None => {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c").arg(&self.command);
+                run_step(&self.name, cmd, self.timeout).await
+            }
+        };
+        match result {
+            Ok(()) => {
+                verbose!("Step {} finished in {:.1}s", self.name, started.elapsed().as_secs_f32());
+                true
+            }
+            Err(e) if self.on_failure == OnFailure::Continue => {
+                warn!("Step {} failed, continuing: {}", self.name, e);
+                true
+            }
+            Err(e) => {
+                error!("Step {} failed: {}", self.name, e);
+                false
+            }
+        }
+    }
+
+    async fn switch_profile(&self, profile: String) -> Result<(), String> {
+        let switch = tokio::task::spawn_blocking(move || powerprofile::switch(&profile));
+        match tokio::time::timeout(self.timeout, switch).await {
+            Ok(result) => result.map_err(|e| e.to_string())?.map_err(|e| format!("{}: {}", self.name, e)),
+            Err(_) => Err(format!("{}: power-profiles-daemon did not answer within {}s", self.name, self.timeout.as_secs())),
+        }
+    }
+}
+
+fn string<'a>(key: &str, value: &'a Value) -> Result<&'a str, String> {
+    value.as_str().ok_or_else(|| format!("'{}' must be a string", key))
+}
+
+/// Ordered steps from a `[pipeline.<name>]` table, run around the power action
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pipeline {
+    pub name: String,
+    pub before: Vec<Step>,
+    pub after: Vec<Step>,
+}
+
+impl Pipeline {
+    /// Run the steps before the power action, failing on the first step set to abort
+    pub async fn before(&self) -> Result<(), String> {
+        for step in &self.before {
+            if !step.run().await {
+                return Err(format!("step {} of pipeline {} failed", step.name, self.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the steps after resume, stopping early only at a step set to abort. A power profile
+    /// switched by the `before` steps is restored first.
+    pub async fn after(&self) {
+        if self.before.iter().any(|step| step.power_profile.is_some()) {
+            let restored = tokio::task::spawn_blocking(powerprofile::restore).await;
+            if let Err(e) = restored.map_err(|e| e.to_string()).and_then(|result| result) {
+                warn!("Failed to restore the power profile: {}", e);
+            }
+        }
+        for step in &self.after {
+            if !step.run().await {
+                return;
+            }
+        }
+    }
+}
+
+/// Pipelines defined in the config file, by name
+#[derive(Debug, Default)]
+pub struct Pipelines {
+    pipelines: BTreeMap<String, Pipeline>,
+}
+
+impl Pipelines {
+    /// Build pipelines from `[pipeline.<name>]` tables, whose `before` and `after` arrays name
+    /// `[step.<name>]` tables. Steps without a `timeout` get `default_timeout`.
+    pub fn from_config(config: &Config, default_timeout: Duration) -> Result<Self, String> {
+        let mut steps = BTreeMap::new();
+        for (name, table) in config.subtables("step") {
+            let step = Step::parse(name, table, default_timeout).map_err(|e| format!("step.{}: {}", name, e))?;
+            steps.insert(name, step);
+        }
+
+        let mut pipelines = BTreeMap::new();
+        for (name, table) in config.subtables("pipeline") {
+            let mut pipeline = Pipeline { name: name.to_string(), ..Pipeline::default() };
+            for (key, value) in table {
+                let list = match key.as_str() {
+                    "before" => &mut pipeline.before,
+                    "after" => &mut pipeline.after,
+                    _ => return Err(format!("pipeline.{}: unknown key '{}'", name, key)),
+                };
+                let Value::Array(names) = value else {
+                    return Err(format!("pipeline.{}: '{}' must be an array of step names", name, key));
+                };
+                for step in names {
+                    let step = step.as_str().and_then(|step| steps.get(step)).ok_or_else(|| {
+                        format!("pipeline.{}: unknown step {:?} in '{}'", name, step, key)
+                    })?;
+                    if key == "after" && step.power_profile.is_some() {
+                        return Err(format!(
+                            "pipeline.{}: step {} switches the power profile, which belongs in 'before'; it is restored after resume",
+                            name, step.name
+                        ));
+                    }
+                    list.push(step.clone());
+                }
+            }
+            pipelines.insert(name.to_string(), pipeline);
+        }
+        Ok(Pipelines { pipelines })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.pipelines.contains_key(name)
+    }
+
+    /// The named pipeline, or the default one when `name` is None
+    pub fn get(&self, name: Option<&str>) -> Option<&Pipeline> {
+        self.pipelines.get(name.unwrap_or(DEFAULT_PIPELINE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[step.notify]
+run = "exit 1"
+on_failure = "continue"
+
+[step.stop-backup]
+run = "true"
+timeout = 5
+
+[step.fail]
+run = "exit 2"
+
+[step.quiet]
+power_profile = "power-saver"
+on_failure = "continue"
+
+[pipeline.default]
+before = ["notify", "stop-backup"]
+after = ["notify"]
+
+[pipeline.strict]
+before = ["fail", "stop-backup"]
+
+[pipeline.saver]
+before = ["quiet"]
+"#;
+
+    fn pipelines(text: &str) -> Result<Pipelines, String> {
+        Pipelines::from_config(&Config::parse(text).unwrap(), Duration::from_secs(30))
+    }
+
+    #[test]
+    fn test_parse_pipelines() {
+        let pipelines = pipelines(CONFIG).unwrap();
+        let default = pipelines.get(None).unwrap();
+        let names: Vec<&str> = default.before.iter().map(|step| step.name.as_str()).collect();
+        assert_eq!(names, ["notify", "stop-backup"]);
+        assert_eq!(default.before[0].timeout, Duration::from_secs(30));
+        assert_eq!(default.before[1].timeout, Duration::from_secs(5));
+        assert_eq!(default.before[0].on_failure, OnFailure::Continue);
+        assert!(pipelines.contains("strict"));
+        let saver = pipelines.get(Some("saver")).unwrap();
+        assert_eq!((saver.before[0].command.as_str(), saver.before[0].power_profile.as_deref()), ("", Some("power-saver")));
+        assert!(pipelines.get(Some("missing")).is_none());
+    }
+
+    #[test]
+    fn test_invalid_pipelines() {
+        assert!(pipelines("[pipeline.x]\nbefore = [\"nope\"]").unwrap_err().contains("unknown step"));
+        assert!(pipelines("[step.x]\ntimeout = 5").unwrap_err().contains("exactly one of 'run' and 'power_profile'"));
+        assert!(pipelines("[step.x]\nrun = \"true\"\npower_profile = \"balanced\"").is_err());
+        let e = pipelines("[step.x]\npower_profile = \"balanced\"\n[pipeline.p]\nafter = [\"x\"]").unwrap_err();
+        assert!(e.contains("belongs in 'before'"), "{}", e);
+        assert!(pipelines("[step.x]\nrun = \"true\"\non_failure = \"retry\"").is_err());
+        assert!(pipelines("[pipeline.x]\nbefore = \"x\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failure_policy() {
+        let pipelines = pipelines(CONFIG).unwrap();
+        assert!(pipelines.get(None).unwrap().before().await.is_ok());
+        assert_eq!(
+            pipelines.get(Some("strict")).unwrap().before().await,
+            Err("step fail of pipeline strict failed".to_string())
+        );
+    }
+}
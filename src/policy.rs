@@ -0,0 +1,313 @@
+use crate::clock::{HourRange, LocalTime};
+use crate::config::{Config, Table, Value};
+use crate::inhibit::Inhibitors;
+use crate::{connections, desktop, idle, session};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Name reported when no profile applies and the command-line settings are in effect
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Settings that may differ between profiles
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Policy {
+    pub quorum: usize,
+    pub quorum_window: u64,
+    pub confirm: bool,
+    pub grace: u64,
+    pub warn_users: bool,
+    pub inhibit_share_clients: bool,
+    pub inhibit_ports: Vec<u16>,
+    pub ignore_desktop_inhibitors: bool,
+    pub inhibit_session_activity: bool,
+    pub min_idle: Option<u64>,
+}
+
+impl Policy {
+    /// Return a copy with a profile table's keys applied on top
+    pub fn apply(&self, table: &Table) -> Result<Policy, String> {
+        let mut policy = self.clone();
+        for (key, value) in table {
+            match key.as_str() {
+                "hours" => {}
+                "quorum" => policy.quorum = uint(key, value)? as usize,
+                "quorum_window" => policy.quorum_window = uint(key, value)?,
+                "confirm" => policy.confirm = boolean(key, value)?,
+                "grace" => policy.grace = uint(key, value)?,
+                "warn_users" => policy.warn_users = boolean(key, value)?,
+                "inhibit_share_clients" => policy.inhibit_share_clients = boolean(key, value)?,
+                "inhibit_ports" => policy.inhibit_ports = ports(key, value)?,
+                "ignore_desktop_inhibitors" => policy.ignore_desktop_inhibitors = boolean(key, value)?,
+                "inhibit_session_activity" => policy.inhibit_session_activity = boolean(key, value)?,
+                "min_idle" => policy.min_idle = Some(uint(key, value)?).filter(|minutes| *minutes > 0),
+                _ => return Err(format!("unknown key '{}'", key)),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Inhibitors enabled by this policy
+    pub fn inhibitors(&self) -> Inhibitors {
+        let mut inhibitors = Inhibitors::default();
+        if self.inhibit_share_clients {
+            inhibitors.push(Box::new(connections::ShareClients));
+        }
+        if !self.inhibit_ports.is_empty() {
+            inhibitors.push(Box::new(connections::PortClients { ports: self.inhibit_ports.clone() }));
+        }
+        if !self.ignore_desktop_inhibitors {
+            inhibitors.push(Box::new(desktop::DesktopInhibitors));
+        }
+        if self.inhibit_session_activity {
+            inhibitors.push(Box::new(session::SessionActivity));
+        }
+        if let Some(minutes) = self.min_idle {
+            inhibitors.push(Box::new(idle::UserIdle {
+                min_idle: Duration::from_secs(minutes * 60),
+            }));
+        }
+        inhibitors
+    }
+}
+
+fn uint(key: &str, value: &Value) -> Result<u64, String> {
+    value
+        .as_integer()
+        .and_then(|i| u64::try_from(i).ok())
+        .ok_or_else(|| format!("'{}' must be a non-negative integer", key))
+}
+
+fn ports(key: &str, value: &Value) -> Result<Vec<u16>, String> {
+    let Value::Array(values) = value else {
+        return Err(format!("'{}' must be an array of ports", key));
+    };
+    values
+        .iter()
+        .map(|port| port.as_integer().and_then(|i| u16::try_from(i).ok()).filter(|port| *port > 0))
+        .collect::<Option<_>>()
+        .ok_or_else(|| format!("'{}' must be an array of ports", key))
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool, String> {
+    value
+        .as_bool()
+        .ok_or_else(|| format!("'{}' must be true or false", key))
+}
+
+/// A named set of overrides, optionally active during a daily time window
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub hours: Option<HourRange>,
+    pub policy: Policy,
+}
+
+/// The command-line policy plus named profiles from `[profile.<name>]` config tables
+pub struct Profiles {
+    base: Policy,
+    profiles: Mutex<Vec<Profile>>,
+    selected: Mutex<Option<String>>,
+}
+
+impl Profiles {
+    pub fn new(base: Policy) -> Self {
+        Profiles {
+            base,
+            profiles: Mutex::new(Vec::new()),
+            selected: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(base: Policy, config: &Config) -> Result<Self, String> {
+        let profiles = Profiles::new(base);
+        *profiles.profiles.lock().unwrap() = profiles.parse(config)?;
+        Ok(profiles)
+    }
+
+    /// Build the profiles defined by `config` on top of the command-line settings
+    pub fn parse(&self, config: &Config) -> Result<Vec<Profile>, String> {
+        let mut profiles = Vec::new();
+        for (name, table) in config.subtables("profile") {
+            let hours = match table.get("hours") {
+                Some(value) => Some(
+                    value
+                        .as_str()
+                        .ok_or_else(|| format!("profile.{}: 'hours' must be a string", name))?
+                        .parse()
+                        .map_err(|e| format!("profile.{}: {}", name, e))?,
+                ),
+                None => None,
+            };
+            let policy = self
+                .base
+                .apply(table)
+                .map_err(|e| format!("profile.{}: {}", name, e))?;
+            profiles.push(Profile {
+                name: name.to_string(),
+                hours,
+                policy,
+            });
+        }
+        Ok(profiles)
+    }
+
+    /// Swap in profiles from a reloaded config and describe what changed. A selected profile
+    /// that no longer exists falls back to the schedule.
+    pub fn replace(&self, profiles: Vec<Profile>) -> Vec<String> {
+        let mut current = self.profiles.lock().unwrap();
+        let changes = crate::reload::changes("profile", &current, &profiles, |profile| &profile.name);
+        let mut selected = self.selected.lock().unwrap();
+        if let Some(name) = selected.as_deref()
+            && name != DEFAULT_PROFILE
+            && !profiles.iter().any(|profile| profile.name == name)
+        {
+            *selected = None;
+        }
+        *current = profiles;
+        changes
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.profiles.lock().unwrap().iter().map(|profile| profile.name.clone()).collect()
+    }
+
+    /// Select a profile by name, overriding the schedule, or return to scheduling with `None`
+    pub fn select(&self, name: Option<&str>) -> Result<(), String> {
+        if let Some(name) = name
+            && name != DEFAULT_PROFILE
+            && !self.profiles.lock().unwrap().iter().any(|profile| profile.name == name)
+        {
+            return Err(format!("unknown profile '{}'", name));
+        }
+        *self.selected.lock().unwrap() = name.map(str::to_string);
+        Ok(())
+    }
+
+    /// Whether a profile was selected explicitly rather than by schedule
+    pub fn is_selected(&self) -> bool {
+        self.selected.lock().unwrap().is_some()
+    }
+
+    /// The profile in effect: the selected one, else the first whose hours contain `now`,
+    /// else the command-line settings
+    pub fn current(&self, now: &LocalTime) -> (String, Policy) {
+        let selected = self.selected.lock().unwrap().clone();
+        let profiles = self.profiles.lock().unwrap();
+        let profile = match selected {
+            Some(name) => profiles.iter().find(|profile| profile.name == name),
+            None => profiles
+                .iter()
+                .find(|profile| profile.hours.is_some_and(|hours| hours.contains(now))),
+        };
+
+        match profile {
+            Some(profile) => (profile.name.clone(), profile.policy.clone()),
+            None => (DEFAULT_PROFILE.to_string(), self.base.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Policy {
+        Policy {
+            quorum: 1,
+            quorum_window: 10,
+            confirm: false,
+            grace: 0,
+            warn_users: false,
+            inhibit_share_clients: false,
+            inhibit_ports: Vec::new(),
+            ignore_desktop_inhibitors: false,
+            inhibit_session_activity: false,
+            min_idle: None,
+        }
+    }
+
+    fn at(hour: u32) -> LocalTime {
+        LocalTime { second: 0, minute: 0, hour, day: 1, month: 1, year: 2024, weekday: 1 }
+    }
+
+    const CONFIG: &str = r#"
+        [profile.day]
+        hours = "08:00-18:00"
+        quorum = 3
+        min_idle = 30
+        inhibit_ports = [5900, 8096]
+
+        [profile.night]
+        hours = "18:00-08:00"
+        ignore_desktop_inhibitors = true
+    "#;
+
+    #[test]
+    fn test_scheduled_profiles() {
+        let profiles = Profiles::from_config(base(), &Config::parse(CONFIG).unwrap()).unwrap();
+
+        let (name, policy) = profiles.current(&at(10));
+        assert_eq!(name, "day");
+        assert_eq!(policy.quorum, 3);
+        assert_eq!(policy.min_idle, Some(30));
+        assert_eq!(policy.inhibit_ports, [5900, 8096]);
+
+        let (name, policy) = profiles.current(&at(23));
+        assert_eq!(name, "night");
+        assert_eq!(policy.quorum, 1);
+        assert!(policy.ignore_desktop_inhibitors);
+    }
+
+    #[test]
+    fn test_selected_profile_overrides_schedule() {
+        let profiles = Profiles::from_config(base(), &Config::parse(CONFIG).unwrap()).unwrap();
+
+        profiles.select(Some("night")).unwrap();
+        assert!(profiles.is_selected());
+        assert_eq!(profiles.current(&at(10)).0, "night");
+
+        profiles.select(Some(DEFAULT_PROFILE)).unwrap();
+        assert_eq!(profiles.current(&at(10)), (DEFAULT_PROFILE.to_string(), base()));
+
+        profiles.select(None).unwrap();
+        assert!(!profiles.is_selected());
+        assert_eq!(profiles.current(&at(10)).0, "day");
+
+        assert!(profiles.select(Some("weekend")).is_err());
+    }
+
+    #[test]
+    fn test_no_matching_profile_uses_base() {
+        let config = Config::parse("[profile.lunch]\nhours = \"12:00-13:00\"\ngrace = 60").unwrap();
+        let profiles = Profiles::from_config(base(), &config).unwrap();
+        assert_eq!(profiles.current(&at(9)), (DEFAULT_PROFILE.to_string(), base()));
+        assert_eq!(profiles.current(&at(12)).1.grace, 60);
+    }
+
+    #[test]
+    fn test_replace_profiles() {
+        let profiles = Profiles::from_config(base(), &Config::parse(CONFIG).unwrap()).unwrap();
+        profiles.select(Some("night")).unwrap();
+
+        let config = Config::parse("[profile.day]\nhours = \"08:00-18:00\"\nquorum = 2").unwrap();
+        let changes = profiles.replace(profiles.parse(&config).unwrap());
+        assert_eq!(changes, ["profile day changed", "profile night removed"]);
+        assert!(!profiles.is_selected());
+        assert_eq!(profiles.current(&at(10)).1.quorum, 2);
+    }
+
+    #[test]
+    fn test_invalid_profiles() {
+        let unknown = Config::parse("[profile.day]\nquorom = 3").unwrap();
+        assert_eq!(
+            Profiles::from_config(base(), &unknown).err().unwrap(),
+            "profile.day: unknown key 'quorom'"
+        );
+
+        let negative = Config::parse("[profile.day]\ngrace = -1").unwrap();
+        assert!(Profiles::from_config(base(), &negative).is_err());
+
+        let hours = Config::parse("[profile.day]\nhours = \"morning\"").unwrap();
+        assert!(Profiles::from_config(base(), &hours).is_err());
+    }
+}
@@ -0,0 +1,47 @@
+/// logind actions the daemon needs: each power action, also while other users are logged in
+const ACTIONS: [&str; 6] = [
+    "org.freedesktop.login1.suspend",
+    "org.freedesktop.login1.suspend-multiple-sessions",
+    "org.freedesktop.login1.hibernate",
+    "org.freedesktop.login1.hibernate-multiple-sessions",
+    "org.freedesktop.login1.power-off",
+    "org.freedesktop.login1.power-off-multiple-sessions",
+];
+
+/// A polkit rule letting `user` suspend, hibernate and power off through logind without
+/// authentication, for /etc/polkit-1/rules.d
+pub fn rule(user: &str) -> Result<String, String> {
+    let valid = !user.is_empty()
+        && user.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        && !user.starts_with('-');
+    if !valid {
+        return Err(format!("Invalid user name '{}'", user));
+    }
+    let actions: Vec<String> = ACTIONS.iter().map(|action| format!("        \"{}\",", action)).collect();
+    Ok(format!(
+        "// Allow the sol daemon's account to suspend, hibernate and power off through logind\n\
+         polkit.addRule(function(action, subject) {{\n    \
+             var actions = [\n{}\n    ];\n    \
+             if (subject.user == \"{}\" && actions.indexOf(action.id) >= 0) {{\n        \
+                 return polkit.Result.YES;\n    \
+             }}\n\
+         }});\n",
+        actions.join("\n"),
+        user
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule() {
+        let rule = rule("sol").unwrap();
+        assert!(rule.contains("subject.user == \"sol\""));
+        assert!(rule.contains("        \"org.freedesktop.login1.suspend\",\n"));
+        assert!(rule.ends_with("    }\n});\n"));
+        assert!(super::rule("sol\"; evil").is_err());
+        assert!(super::rule("").is_err());
+    }
+}
@@ -0,0 +1,330 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+/// Lease requested from the router; mappings are renewed at half of it
+pub const LEASE: Duration = Duration::from_secs(3600);
+
+/// Wait before trying again after the router refused or did not answer
+const RETRY: Duration = Duration::from_secs(300);
+
+const NATPMP_PORT: u16 = 5351;
+const SSDP: &str = "239.255.255.250:1900";
+const IGD: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+const WAN_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The IPv4 default gateway from the contents of `/proc/net/route`
+fn parse_route(table: &str) -> Option<Ipv4Addr> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [_, "00000000", gateway, ..] => {
+                let gateway = u32::from_str_radix(gateway, 16).ok()?;
+                // The kernel prints the address in host byte order
+                Some(Ipv4Addr::from(gateway.to_le_bytes())).filter(|gw| !gw.is_unspecified())
+            }
+            _ => None,
+        }
+    })
+}
+
+fn default_gateway() -> Result<Ipv4Addr, String> {
+    let table = std::fs::read_to_string("/proc/net/route").map_err(|e| format!("/proc/net/route: {}", e))?;
+    parse_route(&table).ok_or_else(|| "no IPv4 default gateway".to_string())
+}
+
+/// NAT-PMP request to map UDP `port` to the same external port
+fn natpmp_request(port: u16, lease: u32) -> [u8; 12] {
+    let mut request = [0u8; 12];
+    request[1] = 1;
+    request[4..6].copy_from_slice(&port.to_be_bytes());
+    request[6..8].copy_from_slice(&port.to_be_bytes());
+    request[8..12].copy_from_slice(&lease.to_be_bytes());
+    request
+}
+
+/// The external port and lease granted by a NAT-PMP response
+fn parse_natpmp_response(response: &[u8]) -> Result<(u16, u32), String> {
+    if response.len() < 16 || response[0] != 0 || response[1] != 129 {
+        return Err("malformed NAT-PMP response".to_string());
+    }
+    match u16::from_be_bytes([response[2], response[3]]) {
+        0 => Ok((
+            u16::from_be_bytes([response[10], response[11]]),
+            u32::from_be_bytes([response[12], response[13], response[14], response[15]]),
+        )),
+        1 => Err("NAT-PMP version not supported by the router".to_string()),
+        2 => Err("NAT-PMP mapping refused by the router".to_string()),
+        3 => Err("router has no external network".to_string()),
+        4 => Err("router is out of mappings".to_string()),
+        code => Err(format!("NAT-PMP error {}", code)),
+    }
+}
+
+async fn natpmp(gateway: Ipv4Addr, port: u16) -> Result<(u16, u32), String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+    socket.connect((gateway, NATPMP_PORT)).await.map_err(|e| e.to_string())?;
+    let request = natpmp_request(port, LEASE.as_secs() as u32);
+    let mut wait = Duration::from_millis(250);
+    let mut buf = [0u8; 16];
+    // RFC 6886 retries with doubling intervals; give up sooner than it allows
+    for _ in 0..4 {
+        socket.send(&request).await.map_err(|e| e.to_string())?;
+        if let Ok(received) = timeout(wait, socket.recv(&mut buf)).await {
+            let len = received.map_err(|e| e.to_string())?;
+            return parse_natpmp_response(&buf[..len]);
+        }
+        wait *= 2;
+    }
+    Err(format!("no NAT-PMP answer from {}", gateway))
+}
+
+/// Value of `name` in an HTTP-style header block
+fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    response.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Find the router's description URL with SSDP
+async fn discover() -> Result<String, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+        SSDP, IGD
+    );
+    socket.send_to(search.as_bytes(), SSDP).await.map_err(|e| e.to_string())?;
+    let mut buf = [0u8; 2048];
+    let answer = async {
+        loop {
+            let (len, _) = socket.recv_from(&mut buf).await?;
+            if let Some(location) = header(&String::from_utf8_lossy(&buf[..len]), "location") {
+                return Ok::<_, std::io::Error>(location.to_string());
+            }
+        }
+    };
+    match timeout(Duration::from_secs(3), answer).await {
+        Ok(location) => location.map_err(|e| e.to_string()),
+        Err(_) => Err("no UPnP gateway answered".to_string()),
+    }
+}
+
+/// Split `http://host[:port]/path` into the address and path
+pub fn split_url(url: &str) -> Result<(String, String), String> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| format!("unsupported URL {}", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let host = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    Ok((host, path.to_string()))
+}
+
+/// Undo `Transfer-Encoding: chunked`
+fn dechunk(mut body: &str) -> String {
+    let mut out = String::new();
+    while let Some((size, rest)) = body.split_once("\r\n") {
+        let Ok(size) = usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16) else { break };
+        if size == 0 || rest.len() < size {
+            break;
+        }
+        out.push_str(&rest[..size]);
+        body = rest[size..].trim_start_matches("\r\n");
+    }
+    out
+}
+
+/// A minimal HTTP/1.1 exchange, enough for an IGD; returns the status code and body
+async fn http(url: &str, soap_action: Option<&str>, body: &str) -> Result<(u16, String), String> {
+    let (host, path) = split_url(url)?;
+    let exchange = async {
+        let mut stream = TcpStream::connect(&host).await?;
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+            if soap_action.is_some() { "POST" } else { "GET" },
+            path,
+            host
+        );
+        if let Some(action) = soap_action {
+            request += &format!(
+                "Content-Type: text/xml; charset=\"utf-8\"\r\nSOAPAction: \"{}\"\r\nContent-Length: {}\r\n",
+                action,
+                body.len()
+            );
+        }
+        request += "\r\n";
+        request += body;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(String::from_utf8_lossy(&response).into_owned())
+    };
+    let response = timeout(HTTP_TIMEOUT, exchange)
+        .await
+        .map_err(|_| format!("{} timed out", url))?
+        .map_err(|e| format!("{}: {}", url, e))?;
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("{}: malformed HTTP response", url))?;
+    let chunked = header(head, "transfer-encoding").is_some_and(|te| te.eq_ignore_ascii_case("chunked"));
+    Ok((status, if chunked { dechunk(body) } else { body.to_string() }))
+}
+
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = xml[start..].find(&format!("</{}>", name))?;
+    Some(xml[start..start + end].trim())
+}
+
+/// The WAN connection service type and its absolute control URL from a device description
+fn find_service(description: &str, location: &str) -> Option<(String, String)> {
+    let blocks: Vec<&str> = description.split("<service>").skip(1).collect();
+    let (service, block) = WAN_SERVICES
+        .iter()
+        .find_map(|wanted| blocks.iter().find(|block| element(block, "serviceType") == Some(*wanted)).map(|b| (*wanted, *b)))?;
+    let control = element(block, "controlURL")?;
+    let url = if control.starts_with("http://") {
+        control.to_string()
+    } else {
+        let origin_end = location.strip_prefix("http://").and_then(|rest| rest.find('/')).map_or(location.len(), |i| i + 7);
+        let slash = if control.starts_with('/') { "" } else { "/" };
+        format!("{}{}{}", &location[..origin_end], slash, control)
+    };
+    Some((service.to_string(), url))
+}
+
+fn add_port_mapping(service: &str, port: u16, client: Ipv4Addr, lease: u64) -> String {
+    format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:AddPortMapping xmlns:u=\"{}\">\
+         <NewRemoteHost></NewRemoteHost><NewExternalPort>{}</NewExternalPort><NewProtocol>UDP</NewProtocol>\
+         <NewInternalPort>{}</NewInternalPort><NewInternalClient>{}</NewInternalClient><NewEnabled>1</NewEnabled>\
+         <NewPortMappingDescription>sol</NewPortMappingDescription><NewLeaseDuration>{}</NewLeaseDuration>\
+         </u:AddPortMapping></s:Body></s:Envelope>",
+        service, port, port, client, lease
+    )
+}
+
+/// The local address used to reach `gateway`
+async fn local_address(gateway: Ipv4Addr) -> Result<Ipv4Addr, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+    socket.connect((gateway, 1900)).await.map_err(|e| e.to_string())?;
+    match socket.local_addr().map_err(|e| e.to_string())? {
+        SocketAddr::V4(addr) => Ok(*addr.ip()),
+        SocketAddr::V6(_) => Err("no IPv4 route to the gateway".to_string()),
+    }
+}
+
+async fn upnp(gateway: Ipv4Addr, ports: &[u16]) -> Result<(), String> {
+    let location = discover().await?;
+    let (status, description) = http(&location, None, "").await?;
+    if status != 200 {
+        return Err(format!("{} returned HTTP {}", location, status));
+    }
+    let (service, control) =
+        find_service(&description, &location).ok_or_else(|| "router has no WAN connection service".to_string())?;
+    let client = local_address(gateway).await?;
+    for &port in ports {
+        let body = add_port_mapping(&service, port, client, LEASE.as_secs());
+        let (status, response) = http(&control, Some(&format!("{}#AddPortMapping", service)), &body).await?;
+        if status != 200 {
+            let reason = element(&response, "errorDescription").unwrap_or("no description");
+            return Err(format!("AddPortMapping for UDP {} failed: HTTP {} ({})", port, status, reason));
+        }
+        info!("Router forwards UDP {} to {}:{} (UPnP, lease {}s)", port, client, port, LEASE.as_secs());
+    }
+    Ok(())
+}
+
+/// Map every port, preferring NAT-PMP and falling back to UPnP IGD. Returns how long
+/// until the mappings need renewing.
+async fn map(ports: &[u16]) -> Result<Duration, String> {
+    let gateway = default_gateway()?;
+    let mut renew = LEASE / 2;
+    let mut natpmp_error = None;
+    for &port in ports {
+        match natpmp(gateway, port).await {
+            Ok((external, lease)) => {
+                info!("Router {} forwards UDP {} to port {} (NAT-PMP, lease {}s)", gateway, external, port, lease);
+                renew = renew.min(Duration::from_secs(lease as u64 / 2));
+            }
+            Err(e) => {
+                natpmp_error = Some(e);
+                break;
+            }
+        }
+    }
+    let Some(natpmp_error) = natpmp_error else { return Ok(renew) };
+    verbose!("NAT-PMP failed ({}); trying UPnP", natpmp_error);
+    upnp(gateway, ports)
+        .await
+        .map(|()| LEASE / 2)
+        .map_err(|e| format!("NAT-PMP: {}; UPnP: {}", natpmp_error, e))
+}
+
+/// Keep router port mappings for the trigger ports in place in the background
+pub fn maintain(ports: Vec<u16>) {
+    tokio::spawn(async move {
+        loop {
+            let wait = match map(&ports).await {
+                Ok(renew) => renew.max(Duration::from_secs(60)),
+                Err(e) => {
+                    warn!("Port mapping failed, retrying in {}s: {}", RETRY.as_secs(), e);
+                    RETRY
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_route() {
+        let table = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                     eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\n\
+                     eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\n";
+        assert_eq!(parse_route(table), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(parse_route("Iface\tDestination\tGateway\n"), None);
+    }
+
+    #[test]
+    fn test_natpmp() {
+        assert_eq!(natpmp_request(10, 3600), [0, 1, 0, 0, 0, 10, 0, 10, 0, 0, 0x0E, 0x10]);
+        let ok = [0, 129, 0, 0, 0, 0, 0, 1, 0, 10, 0, 10, 0, 0, 0x0E, 0x10];
+        assert_eq!(parse_natpmp_response(&ok), Ok((10, 3600)));
+        let refused = [0, 129, 0, 2, 0, 0, 0, 1, 0, 10, 0, 0, 0, 0, 0, 0];
+        assert!(parse_natpmp_response(&refused).unwrap_err().contains("refused"));
+    }
+
+    #[test]
+    fn test_upnp_description() {
+        let description = "<root><device><serviceList>\
+            <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType><controlURL>/l3f</controlURL></service>\
+            <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+            <controlURL>/ctl/IPConn</controlURL></service></serviceList></device></root>";
+        assert_eq!(
+            find_service(description, "http://192.168.1.1:5000/rootDesc.xml"),
+            Some((WAN_SERVICES[1].to_string(), "http://192.168.1.1:5000/ctl/IPConn".to_string()))
+        );
+        assert_eq!(split_url("http://192.168.1.1/desc.xml"), Ok(("192.168.1.1:80".to_string(), "/desc.xml".to_string())));
+        assert_eq!(dechunk("5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"), "hello world");
+        assert_eq!(header("HTTP/1.1 200 OK\r\nLOCATION: http://x/\r\n", "location"), Some("http://x/"));
+    }
+}
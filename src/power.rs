@@ -0,0 +1,176 @@
+use crate::hostaccess::{self, Host};
+use crate::presuspend::run_step;
+use crate::rules::Action;
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+
+/// How the power action is carried out
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// `systemctl suspend|hibernate|poweroff`
+    Systemd,
+    /// Write to /sys/power/state directly, for systems without systemd
+    Sysfs,
+    /// Call logind over D-Bus without interactive authentication, so polkit decides
+    Logind,
+}
+
+pub const LOGIND: &str = "org.freedesktop.login1";
+pub const LOGIND_PATH: &str = "/org/freedesktop/login1";
+pub const LOGIND_MANAGER: &str = "org.freedesktop.login1.Manager";
+
+/// Kernel suspend variant written to /sys/power/mem_sleep
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemSleep {
+    S2idle,
+    Shallow,
+    Deep,
+}
+
+impl MemSleep {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemSleep::S2idle => "s2idle",
+            MemSleep::Shallow => "shallow",
+            MemSleep::Deep => "deep",
+        }
+    }
+}
+
+/// The suspend variants the kernel offers, with the one in use first
+pub fn mem_sleep_modes(sys_power: &Path) -> Option<(String, Vec<String>)> {
+    let text = std::fs::read_to_string(sys_power.join("mem_sleep")).ok()?;
+    let mut current = None;
+    let mut modes = Vec::new();
+    for word in text.split_whitespace() {
+        match word.strip_prefix('[').and_then(|word| word.strip_suffix(']')) {
+            Some(mode) => {
+                current = Some(mode.to_string());
+                modes.push(mode.to_string());
+            }
+            None => modes.push(word.to_string()),
+        }
+    }
+    Some((current?, modes))
+}
+
+pub struct Power {
+    pub backend: Backend,
+    pub mem_sleep: Option<MemSleep>,
+    pub timeout: Duration,
+    /// Normally /sys/power
+    pub sys_power: PathBuf,
+    /// Set when running in a container, to reach the host
+    pub host: Option<Host>,
+}
+
+impl Power {
+    /// Whether `run` only returns once the machine is awake again. Writing to
+    /// /sys/power/state blocks for the whole sleep, while systemctl returns straight away.
+    pub fn returns_after_resume(&self, action: Action) -> bool {
+        self.backend == Backend::Sysfs && action.resumes()
+    }
+
+    pub async fn run(&self, action: Action) -> Result<(), String> {
+        if action == Action::Suspend
+            && let Some(mode) = self.mem_sleep
+        {
+            self.write("mem_sleep", mode.as_str()).await?;
+        }
+        match (self.backend, action) {
+            (_, Action::Ignore) => Ok(()),
+            (Backend::Systemd, _) => {
+                let mut cmd = hostaccess::command(self.host.as_ref(), "systemctl");
+                cmd.arg(action.as_str());
+                run_step(&format!("systemctl {}", action.as_str()), cmd, self.timeout).await
+            }
+            (Backend::Logind, _) => {
+                let method = match action {
+                    Action::Hibernate => "Hibernate",
+                    Action::Poweroff => "PowerOff",
+                    _ => "Suspend",
+                };
+                let mut cmd = hostaccess::command(self.host.as_ref(), "busctl");
+                cmd.arg(hostaccess::bus_option(self.host.as_ref()));
+                cmd.args(["call", LOGIND, LOGIND_PATH, LOGIND_MANAGER, method, "b", "false"]);
+                run_step(&format!("logind {}", method), cmd, self.timeout).await
+            }
+            (Backend::Sysfs, Action::Suspend) => self.write("state", "mem").await,
+            (Backend::Sysfs, Action::Hibernate) => self.write("state", "disk").await,
+            (Backend::Sysfs, Action::Poweroff) => {
+                run_step("poweroff", Command::new("poweroff"), self.timeout).await
+            }
+        }
+    }
+
+    async fn write(&self, file: &str, value: &'static str) -> Result<(), String> {
+        let path = self.sys_power.join(file);
+        let display = path.display().to_string();
+        // Writing "mem" or "disk" to the state file blocks until the system is back up
+        tokio::task::spawn_blocking(move || std::fs::write(&path, value))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("writing {} to {} failed: {}", value, display, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn sysfs(dir: &std::path::Path) -> Power {
+        Power {
+            backend: Backend::Sysfs,
+            mem_sleep: Some(MemSleep::Deep),
+            timeout: Duration::from_secs(5),
+            sys_power: dir.to_path_buf(),
+            host: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sysfs_suspend_writes_state() {
+        let dir = std::env::temp_dir().join(format!("sol-power-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let power = sysfs(&dir);
+
+        power.run(Action::Suspend).await.unwrap();
+        assert_eq!(fs::read_to_string(dir.join("mem_sleep")).unwrap(), "deep");
+        assert_eq!(fs::read_to_string(dir.join("state")).unwrap(), "mem");
+
+        power.run(Action::Hibernate).await.unwrap();
+        assert_eq!(fs::read_to_string(dir.join("state")).unwrap(), "disk");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sysfs_write_error() {
+        let power = sysfs(std::path::Path::new("/nonexistent/sol"));
+        let error = power.run(Action::Hibernate).await.unwrap_err();
+        assert!(error.starts_with("writing disk to /nonexistent/sol/state failed"));
+    }
+
+    #[test]
+    fn test_mem_sleep_modes() {
+        let dir = std::env::temp_dir().join(format!("sol-mem-sleep-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(mem_sleep_modes(&dir), None);
+        fs::write(dir.join("mem_sleep"), "[s2idle] deep\n").unwrap();
+        let (current, modes) = mem_sleep_modes(&dir).unwrap();
+        assert_eq!((current.as_str(), modes), ("s2idle", vec!["s2idle".to_string(), "deep".to_string()]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_returns_after_resume() {
+        let mut power = sysfs(std::path::Path::new("/sys/power"));
+        assert!(power.returns_after_resume(Action::Suspend));
+        assert!(!power.returns_after_resume(Action::Poweroff));
+        power.backend = Backend::Systemd;
+        assert!(!power.returns_after_resume(Action::Suspend));
+    }
+}
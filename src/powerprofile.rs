@@ -0,0 +1,54 @@
+use crate::dbus::{self, Bus};
+use std::sync::Mutex;
+
+/// power-profiles-daemon's bus name, object and interface; newer releases also offer
+/// `org.freedesktop.UPower.PowerProfiles`, but keep this one for compatibility
+const DEST: &str = "net.hadess.PowerProfiles";
+const PATH: &str = "/net/hadess/PowerProfiles";
+
+/// The profile that was active before the first switch, put back by `restore`
+static PREVIOUS: Mutex<Option<String>> = Mutex::new(None);
+
+/// The active platform profile, e.g. `balanced`
+pub fn active() -> Result<String, String> {
+    dbus::get_string(&Bus::System, DEST, PATH, DEST, "ActiveProfile")
+}
+
+fn set(profile: &str) -> Result<(), String> {
+    dbus::set_string(&Bus::System, DEST, PATH, DEST, "ActiveProfile", profile)
+}
+
+/// Switch to `profile`, remembering the profile it replaces. Further switches before a
+/// `restore` keep the first one, so the profile from before the action comes back.
+pub fn switch(profile: &str) -> Result<(), String> {
+    let current = active()?;
+    if current != profile {
+        set(profile)?;
+        info!("Power profile switched from {} to {}", current, profile);
+    }
+    PREVIOUS.lock().unwrap().get_or_insert(current);
+    Ok(())
+}
+
+/// Put back the profile that was active before `switch`, returning it, or None when
+/// nothing was switched
+pub fn restore() -> Result<Option<String>, String> {
+    let Some(previous) = PREVIOUS.lock().unwrap().take() else {
+        return Ok(None);
+    };
+    if active()? != previous {
+        set(&previous)?;
+        info!("Power profile restored to {}", previous);
+    }
+    Ok(Some(previous))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_without_switch() {
+        assert_eq!(restore(), Ok(None));
+    }
+}
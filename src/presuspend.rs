@@ -0,0 +1,123 @@
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Steps run before suspending to get data safely onto disk
+pub struct PreSuspend {
+    pub sync: bool,
+    pub flush_commands: Vec<String>,
+    pub timeout: Duration,
+}
+
+impl PreSuspend {
+    /// Run all configured steps, stopping at the first failure
+    pub async fn run(&self) -> Result<(), String> {
+        if self.sync {
+            run_step("sync", Command::new("sync"), self.timeout).await?;
+        }
+
+        for command in &self.flush_commands {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(command);
+            run_step(command, cmd, self.timeout).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Run a single step, killing it and everything it started if it does not finish within `timeout`
+pub async fn run_step(name: &str, mut cmd: Command, timeout: Duration) -> Result<(), String> {
+    cmd.kill_on_drop(true)
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let child = cmd.spawn().map_err(|e| format!("{}: {}", name, e))?;
+    let pid = child.id();
+
+    let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return Err(format!("{}: {}", name, e)),
+        Err(_) => {
+            if let Some(pid) = pid {
+                // SAFETY: plain syscall; the step runs in its own process group
+                unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+            }
+            return Err(format!(
+                "{}: timed out after {}s and was killed; check what it is blocked on or raise its timeout",
+                name,
+                timeout.as_secs()
+            ));
+        }
+    };
+
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell(script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script);
+        cmd
+    }
+
+    #[tokio::test]
+    async fn test_step_success() {
+        assert!(run_step("true", shell("true"), Duration::from_secs(5)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_step_failure_reports_stderr() {
+        let result = run_step("fail", shell("echo broken >&2; exit 1"), Duration::from_secs(5)).await;
+        assert_eq!(result, Err("fail failed: broken".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_step_timeout() {
+        let result = run_step("hang", shell("sleep 5"), Duration::from_millis(100)).await;
+        assert!(result.unwrap_err().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_step_timeout_kills_process_group() {
+        let pid_file = std::env::temp_dir().join(format!("sol-step-{}", std::process::id()));
+        let script = format!("sleep 30 & echo $! > {}; wait", pid_file.display());
+        assert!(run_step("hang", shell(&script), Duration::from_millis(300)).await.is_err());
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap().trim().to_string();
+        std::fs::remove_file(&pid_file).unwrap();
+        let stat = format!("/proc/{}/stat", pid);
+        let mut alive = true;
+        for _ in 0..50 {
+            // A killed orphan may linger as a zombie until it is reaped
+            alive = std::fs::read_to_string(&stat).is_ok_and(|stat| !stat.contains(") Z "));
+            if !alive {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!alive, "background child of a timed-out step survived");
+    }
+
+    #[tokio::test]
+    async fn test_flush_commands_stop_at_failure() {
+        let pre = PreSuspend {
+            sync: false,
+            flush_commands: vec!["exit 3".to_string(), "true".to_string()],
+            timeout: Duration::from_secs(5),
+        };
+        assert!(pre.run().await.unwrap_err().starts_with("exit 3 failed"));
+    }
+}
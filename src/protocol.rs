@@ -0,0 +1,233 @@
+use crate::json::Json;
+use crate::mac::MacAddr;
+use crate::pause;
+use crate::rules::{Action, Decision};
+use sol::v2;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What a v2 packet asks for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    Action(Action),
+    Cancel,
+    /// Block sleep for this long; zero ends a snooze
+    Snooze(Duration),
+    /// An `[action.<name>]` from the config file
+    Named(String),
+}
+
+/// The JSON body of a v2 packet, e.g.
+/// `{"action":"hibernate","mac":"aa:bb:cc:dd:ee:ff","ts":1760000000,"args":{"grace":30}}`.
+/// A snooze gives its length in seconds as `"args":{"duration":2700}`.
+/// Unknown keys are ignored so newer senders can add fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Command {
+    pub request: Request,
+    pub mac: MacAddr,
+    /// Unix time the sender built the packet
+    pub timestamp: u64,
+    /// Grace period to use instead of the policy's
+    pub grace: Option<u64>,
+    /// `[pipeline.<name>]` to run instead of the one the rules pick
+    pub pipeline: Option<String>,
+}
+
+impl Command {
+    pub fn parse(body: &[u8]) -> Result<Command, String> {
+        let text = std::str::from_utf8(body).map_err(|_| "body is not UTF-8".to_string())?;
+        let json = Json::parse(text)?;
+        if !matches!(json, Json::Object(_)) {
+            return Err("body is not a JSON object".to_string());
+        }
+
+        let action = json.get("action").and_then(Json::as_str).ok_or("missing 'action'")?;
+        let request = match action {
+            "suspend" => Request::Action(Action::Suspend),
+            "hibernate" => Request::Action(Action::Hibernate),
+            "poweroff" => Request::Action(Action::Poweroff),
+            "cancel" => Request::Cancel,
+            // The length comes from `args`
+            "snooze" => Request::Snooze(Duration::ZERO),
+            "ignore" => return Err("unknown action 'ignore'".to_string()),
+            other => Request::Named(other.to_string()),
+        };
+        let mac = json.get("mac").and_then(Json::as_str).ok_or("missing 'mac'")?;
+        let timestamp = json.get("ts").ok_or("missing 'ts'")?;
+
+        let mut command = Command {
+            request,
+            mac: mac.parse()?,
+            timestamp: timestamp.as_u64().ok_or("'ts' must be a unix time in seconds")?,
+            grace: None,
+            pipeline: None,
+        };
+        if let Some(args) = json.get("args") {
+            if !matches!(args, Json::Object(_)) {
+                return Err("'args' must be an object".to_string());
+            }
+            if let Some(grace) = args.get("grace") {
+                command.grace = Some(grace.as_u64().ok_or("'grace' must be a number of seconds")?);
+            }
+            if let Some(pipeline) = args.get("pipeline") {
+                command.pipeline = Some(pipeline.as_str().ok_or("'pipeline' must be a string")?.to_string());
+            }
+        }
+        if let Request::Snooze(length) = &mut command.request {
+            let duration = json.get("args").and_then(|args| args.get("duration")).ok_or("'snooze' needs a 'duration' argument")?;
+            let duration = Duration::from_secs(duration.as_u64().ok_or("'duration' must be a number of seconds")?);
+            *length = pause::check_snooze(duration)?;
+        }
+        Ok(command)
+    }
+
+    /// Apply the request on top of what the rules decided; an ignore rule still wins
+    pub fn apply(&self, mut decision: Decision) -> Decision {
+        if decision.action == Action::Ignore {
+            return decision;
+        }
+        if let Request::Action(action) = &self.request {
+            decision.action = *action;
+        }
+        if self.pipeline.is_some() {
+            decision.pipeline = self.pipeline.clone();
+        }
+        decision
+    }
+}
+
+/// Checks v2 packets against the shared key, the clock and the signatures already seen
+pub struct Verifier {
+    key: Option<Vec<u8>>,
+    max_skew: Duration,
+    /// Signatures accepted within the last `max_skew`, with their timestamps
+    seen: HashMap<[u8; 32], u64>,
+}
+
+impl Verifier {
+    /// With a key, only packets signed with it are accepted; without one, none are, since an
+    /// unsigned packet could pick any action, grace period or pipeline
+    pub fn new(key: Option<Vec<u8>>, max_skew: Duration) -> Self {
+        Verifier { key, max_skew, seen: HashMap::new() }
+    }
+
+    /// Read the key from `path`, if given
+    pub fn load(path: Option<&Path>, max_skew: Duration) -> Result<Self, String> {
+        Ok(Verifier::new(path.map(read_key).transpose()?, max_skew))
+    }
+
+    /// Validate a v2 packet received at unix time `now` and return its command
+    pub fn accept(&mut self, packet: &[u8], local_macs: &[MacAddr], now: u64) -> Result<Command, String> {
+        let packet = v2::parse(packet).map_err(|e| e.to_string())?;
+        let Some(key) = &self.key else {
+            return Err("v2 packets are only accepted with --v2-key".to_string());
+        };
+        if !packet.is_signed() {
+            return Err("unsigned packet".to_string());
+        }
+        if !packet.verify(key) {
+            return Err("bad signature".to_string());
+        }
+
+        let command = Command::parse(packet.body)?;
+        let skew = self.max_skew.as_secs();
+        if command.timestamp.abs_diff(now) > skew {
+            return Err(format!("timestamp {} is more than {}s from local time {}", command.timestamp, skew, now));
+        }
+        if !local_macs.contains(&command.mac) {
+            return Err(format!("MAC address {} does not match any local interface", command.mac));
+        }
+
+        self.seen.retain(|_, timestamp| timestamp.abs_diff(now) <= skew);
+        if self.seen.insert(packet.signature, command.timestamp).is_some() {
+            return Err("replayed packet".to_string());
+        }
+        Ok(command)
+    }
+}
+
+/// Read a shared key from `path`, ignoring a trailing newline
+pub fn read_key(path: &Path) -> Result<Vec<u8>, String> {
+    let mut key = std::fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    while key.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+        key.pop();
+    }
+    if key.is_empty() {
+        return Err(format!("{} is empty", path.display()));
+    }
+    Ok(key)
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+    const NOW: u64 = 1_760_000_000;
+
+    fn packet(key: Option<&[u8]>, body: &str) -> Vec<u8> {
+        let mut buf = vec![0u8; v2::HEADER_SIZE + body.len()];
+        let len = v2::encode(key, body.as_bytes(), &mut buf).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let body = br#"{"action":"hibernate","mac":"aa:bb:cc:dd:ee:ff","ts":1760000000,"args":{"grace":30,"pipeline":"nas"},"future":1}"#;
+        let command = Command::parse(body).unwrap();
+        assert_eq!(command.request, Request::Action(Action::Hibernate));
+        assert_eq!(command.mac, MAC);
+        assert_eq!(command.grace, Some(30));
+
+        let decision = command.apply(Decision::plain(Action::Suspend));
+        assert_eq!((decision.action, decision.pipeline.as_deref()), (Action::Hibernate, Some("nas")));
+        assert_eq!(command.apply(Decision::plain(Action::Ignore)).action, Action::Ignore);
+
+        assert!(Command::parse(br#"{"action":"ignore","mac":"aa:bb:cc:dd:ee:ff","ts":1}"#).is_err());
+        let named = Command::parse(br#"{"action":"lock","mac":"aa:bb:cc:dd:ee:ff","ts":1}"#).unwrap();
+        assert_eq!(named.request, Request::Named("lock".to_string()));
+        assert!(Command::parse(br#"{"action":"suspend","mac":"aa:bb:cc:dd:ee:ff"}"#).is_err());
+        assert!(Command::parse(br#"["suspend"]"#).is_err());
+    }
+
+    #[test]
+    fn test_signed_packets() {
+        let body = r#"{"action":"suspend","mac":"aa:bb:cc:dd:ee:ff","ts":1760000000}"#;
+        let mut verifier = Verifier::new(Some(b"secret".to_vec()), Duration::from_secs(60));
+        let signed = packet(Some(b"secret"), body);
+
+        assert!(verifier.accept(&signed, &[MAC], NOW + 30).is_ok());
+        assert_eq!(verifier.accept(&signed, &[MAC], NOW + 30), Err("replayed packet".to_string()));
+        assert!(verifier.accept(&packet(Some(b"wrong"), body), &[MAC], NOW).unwrap_err().contains("signature"));
+        assert!(verifier.accept(&packet(None, body), &[MAC], NOW).unwrap_err().contains("unsigned"));
+        assert!(verifier.accept(&signed, &[MAC], NOW + 61).unwrap_err().contains("timestamp"));
+
+        let other = r#"{"action":"suspend","mac":"11:22:33:44:55:66","ts":1760000000}"#;
+        assert!(verifier.accept(&packet(Some(b"secret"), other), &[MAC], NOW).unwrap_err().contains("local"));
+    }
+
+    #[test]
+    fn test_snooze_request() {
+        let body = br#"{"action":"snooze","mac":"aa:bb:cc:dd:ee:ff","ts":1760000000,"args":{"duration":2700}}"#;
+        assert_eq!(Command::parse(body).unwrap().request, Request::Snooze(Duration::from_secs(2700)));
+        let e = Command::parse(br#"{"action":"snooze","mac":"aa:bb:cc:dd:ee:ff","ts":1760000000}"#).unwrap_err();
+        assert_eq!(e, "'snooze' needs a 'duration' argument");
+        let long = br#"{"action":"snooze","mac":"aa:bb:cc:dd:ee:ff","ts":1760000000,"args":{"duration":999999999}}"#;
+        assert!(Command::parse(long).unwrap_err().contains("at most 7 days"));
+    }
+
+    #[test]
+    fn test_no_key_rejects_everything() {
+        let body = r#"{"action":"poweroff","mac":"aa:bb:cc:dd:ee:ff","ts":1760000000,"args":{"grace":0}}"#;
+        let mut verifier = Verifier::new(None, Duration::from_secs(60));
+        assert!(verifier.accept(&packet(None, body), &[MAC], NOW).unwrap_err().contains("--v2-key"));
+        assert!(verifier.accept(&packet(Some(b"secret"), body), &[MAC], NOW).is_err());
+    }
+}
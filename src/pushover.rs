@@ -0,0 +1,100 @@
+use crate::heartbeat::post;
+use sol::notifier::{Notification, Notifier, Priority, Sent, Target};
+
+/// How often, and for how long, Pushover repeats an urgent (emergency) notification until
+/// it is acknowledged
+const EMERGENCY_RETRY: &str = "60";
+const EMERGENCY_EXPIRE: &str = "3600";
+
+/// Sends messages through the Pushover API at `url` with an application token and user key.
+/// Pushover only accepts HTTPS, so `url` is usually a local TLS proxy.
+pub struct Pushover {
+    pub url: String,
+    pub token: String,
+    pub user: String,
+}
+
+fn priority(priority: Priority) -> &'static str {
+    match priority {
+        Priority::Min => "-2",
+        Priority::Low => "-1",
+        Priority::Default => "0",
+        Priority::High => "1",
+        Priority::Urgent => "2",
+    }
+}
+
+/// `s` percent-encoded for an `application/x-www-form-urlencoded` body
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            b' ' => "+".to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The form body of `POST /1/messages.json`
+fn body(pushover: &Pushover, notification: &Notification) -> String {
+    let mut fields = vec![
+        ("token", pushover.token.as_str()),
+        ("user", pushover.user.as_str()),
+        ("title", notification.title.as_str()),
+        ("message", notification.message.as_str()),
+        ("priority", priority(notification.priority)),
+    ];
+    if notification.priority == Priority::Urgent {
+        fields.extend([("retry", EMERGENCY_RETRY), ("expire", EMERGENCY_EXPIRE)]);
+    }
+    if let Some(click) = &notification.click {
+        fields.push(("url", click));
+    }
+    fields.iter().map(|(key, value)| format!("{}={}", key, encode(value))).collect::<Vec<_>>().join("&")
+}
+
+/// The backend for a `type = "pushover"` table
+pub fn build(target: Target) -> Result<Box<dyn Notifier>, String> {
+    match target {
+        Target { url, token: Some(token), user: Some(user) } => Ok(Box::new(Pushover { url, token, user })),
+        _ => Err("'token' and 'user' are required for pushover".to_string()),
+    }
+}
+
+impl Notifier for Pushover {
+    fn describe(&self) -> String {
+        format!("pushover {}", self.url)
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> Sent<'a> {
+        Box::pin(async move {
+            let headers = [("Content-Type", "application/x-www-form-urlencoded")];
+            post(&self.url, &headers, &body(self, notification)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body() {
+        let pushover = Pushover { url: "http://localhost:8443/1/messages.json".to_string(), token: "app".to_string(), user: "me".to_string() };
+        let mut notification = Notification {
+            event: "action-failed",
+            host: "nas".to_string(),
+            title: "nas: action-failed".to_string(),
+            message: "step 'backup' failed: exit 1 & more".to_string(),
+            priority: Priority::High,
+            tag: "warning",
+            click: None,
+        };
+        assert_eq!(
+            body(&pushover, &notification),
+            "token=app&user=me&title=nas%3A+action-failed&message=step+%27backup%27+failed%3A+exit+1+%26+more&priority=1"
+        );
+        notification.priority = Priority::Urgent;
+        assert!(body(&pushover, &notification).ends_with("&priority=2&retry=60&expire=3600"));
+    }
+}
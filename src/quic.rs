@@ -0,0 +1,164 @@
+use crate::control::{Ask, Control, Trigger};
+use crate::mac::MacAddr;
+use quinn::crypto::rustls::QuicServerConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// ALPN protocol id clients must offer
+pub const ALPN: &[u8] = b"sol-control";
+
+/// Longest command accepted on a stream
+const MAX_REQUEST: usize = 256;
+
+/// Certificates for the QUIC listener. Clients must present a certificate signed by `client_ca`.
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: PathBuf,
+}
+
+/// A control message carried on one bidirectional stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Request {
+    Sleep,
+    Status,
+    Wake(String),
+}
+
+fn parse_request(request: &[u8]) -> Result<Request, String> {
+    let request = std::str::from_utf8(request).map_err(|_| "request is not UTF-8".to_string())?;
+    let words: Vec<&str> = request.split_whitespace().collect();
+    match words.as_slice() {
+        ["sleep"] => Ok(Request::Sleep),
+        ["status"] => Ok(Request::Status),
+        ["wake", name] => Ok(Request::Wake(name.to_string())),
+        _ => Err(format!("unknown command: {}", request.trim())),
+    }
+}
+
+fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", path.display()));
+    }
+    Ok(certs)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path.display(), e))?
+        .ok_or_else(|| format!("{}: no private key found", path.display()))
+}
+
+fn server_config(files: &TlsFiles) -> Result<quinn::ServerConfig, String> {
+    let mut roots = rustls::RootCertStore::empty();
+    for ca in certificates(&files.client_ca)? {
+        roots.add(ca).map_err(|e| format!("{}: {}", files.client_ca.display(), e))?;
+    }
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+        .build()
+        .map_err(|e| format!("client CA: {}", e))?;
+    let mut tls = rustls::ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certificates(&files.cert)?, private_key(&files.key)?)
+        .map_err(|e| format!("{}: {}", files.cert.display(), e))?;
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicServerConfig::try_from(tls).map_err(|e| e.to_string())?;
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+/// Hand the daemon a trigger from `peer` the way a WoL packet from it would be checked, so bans,
+/// rules, inhibitors and the grace period all apply
+async fn sleep(control: &Control, peer: SocketAddr, local: SocketAddr, mac: MacAddr) -> String {
+    match control.trigger(Trigger { ask: Ask::Sleep, via: "QUIC", peer, local, mac }).await {
+        Ok(verdict) => format!("sleep: {}", verdict),
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+async fn respond(request: &[u8], control: &Control, peer: SocketAddr, local: SocketAddr, mac: MacAddr) -> String {
+    match parse_request(request) {
+        Ok(Request::Sleep) => sleep(control, peer, local, mac).await,
+        Ok(Request::Status) => control.respond("status").await,
+        Ok(Request::Wake(name)) => {
+            // Rules see a known peer's MAC; a host from the neighbor table is checked as a trigger
+            let target = control.peers.get(&name).map_or(mac, |peer| peer.target.mac);
+            match control.trigger(Trigger { ask: Ask::Admit, via: "QUIC", peer, local, mac: target }).await {
+                Ok(_) => control.respond(&format!("wake {}", name)).await,
+                Err(e) => format!("error: {}", e),
+            }
+        }
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+async fn serve(connection: quinn::Connection, control: Control, local: SocketAddr, mac: MacAddr) {
+    let peer = connection.remote_address();
+    loop {
+        let (mut send, mut recv) = match connection.accept_bi().await {
+            Ok(streams) => streams,
+            Err(quinn::ConnectionError::ApplicationClosed(_)) => return,
+            Err(e) => {
+                verbose!("QUIC connection from {} closed: {}", peer, e);
+                return;
+            }
+        };
+        let response = match recv.read_to_end(MAX_REQUEST).await {
+            Ok(request) => {
+                info!("QUIC request from {}: {}", peer, String::from_utf8_lossy(&request).trim());
+                respond(&request, &control, peer, local, mac).await
+            }
+            Err(e) => format!("error: {}", e),
+        };
+        if let Err(e) = send.write_all(response.as_bytes()).await {
+            warn!("Failed to answer QUIC request from {}: {}", peer, e);
+            continue;
+        }
+        let _ = send.finish();
+    }
+}
+
+/// Serve the control channel on `addr` in the background. `sleep` triggers for `mac`.
+pub fn listen(addr: SocketAddr, files: &TlsFiles, control: Control, mac: MacAddr) -> Result<(), String> {
+    let endpoint = quinn::Endpoint::server(server_config(files)?, addr)
+        .map_err(|e| format!("Cannot bind QUIC {}: {}", addr, e))?;
+    let local = endpoint.local_addr().map_err(|e| format!("Cannot bind QUIC {}: {}", addr, e))?;
+
+    tokio::spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            let control = control.clone();
+            tokio::spawn(async move {
+                let remote = incoming.remote_address();
+                match incoming.await {
+                    Ok(connection) => serve(connection, control, local, mac).await,
+                    // Includes clients without a certificate signed by the client CA
+                    Err(e) => warn!("QUIC handshake with {} failed: {}", remote, e),
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(parse_request(b"sleep\n"), Ok(Request::Sleep));
+        assert_eq!(parse_request(b"status"), Ok(Request::Status));
+        assert_eq!(parse_request(b"wake nas"), Ok(Request::Wake("nas".to_string())));
+        assert!(parse_request(b"maintenance on").is_err());
+        assert!(parse_request(&[0xFF, 0xFE]).is_err());
+    }
+}
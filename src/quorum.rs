@@ -0,0 +1,101 @@
+use crate::mac::MacAddr;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Tracks valid packets per MAC and reports when enough have arrived within the window
+pub struct Quorum {
+    required: usize,
+    window: Duration,
+    seen: HashMap<MacAddr, Vec<Instant>>,
+}
+
+impl Quorum {
+    pub fn new(required: usize, window: Duration) -> Self {
+        Quorum {
+            required: required.max(1),
+            window,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Change the required count and window, e.g. when the active profile changes
+    pub fn configure(&mut self, required: usize, window: Duration) {
+        self.required = required.max(1);
+        self.window = window;
+    }
+
+    /// Record a valid packet for `mac` and return how many packets are currently within the window
+    pub fn record(&mut self, mac: MacAddr, now: Instant) -> usize {
+        let window = self.window;
+        let times = self.seen.entry(mac).or_default();
+        times.retain(|t| now.duration_since(*t) <= window);
+        times.push(now);
+        times.len()
+    }
+
+    /// Whether a count returned by `record` satisfies the quorum
+    pub fn is_met(&self, count: usize) -> bool {
+        count >= self.required
+    }
+
+    pub fn required(&self) -> usize {
+        self.required
+    }
+
+    /// Forget all packets seen for `mac`, e.g. once the action has been triggered
+    pub fn reset(&mut self, mac: &MacAddr) {
+        self.seen.remove(mac);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+    #[test]
+    fn test_single_packet_quorum() {
+        let mut quorum = Quorum::new(1, Duration::from_secs(10));
+        let count = quorum.record(MAC, Instant::now());
+        assert!(quorum.is_met(count));
+    }
+
+    #[test]
+    fn test_quorum_within_window() {
+        let mut quorum = Quorum::new(3, Duration::from_secs(10));
+        let start = Instant::now();
+
+        let count = quorum.record(MAC, start);
+        assert!(!quorum.is_met(count));
+        let count = quorum.record(MAC, start + Duration::from_secs(2));
+        assert!(!quorum.is_met(count));
+        let count = quorum.record(MAC, start + Duration::from_secs(4));
+        assert!(quorum.is_met(count));
+    }
+
+    #[test]
+    fn test_quorum_expires_old_packets() {
+        let mut quorum = Quorum::new(2, Duration::from_secs(5));
+        let start = Instant::now();
+
+        quorum.record(MAC, start);
+        let count = quorum.record(MAC, start + Duration::from_secs(6));
+        assert_eq!(count, 1);
+        assert!(!quorum.is_met(count));
+    }
+
+    #[test]
+    fn test_quorum_is_per_mac() {
+        let mut quorum = Quorum::new(2, Duration::from_secs(10));
+        let other = MacAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let now = Instant::now();
+
+        quorum.record(MAC, now);
+        assert_eq!(quorum.record(other, now), 1);
+        assert_eq!(quorum.record(MAC, now), 2);
+
+        quorum.reset(&MAC);
+        assert_eq!(quorum.record(MAC, now), 1);
+    }
+}
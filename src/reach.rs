@@ -0,0 +1,173 @@
+use crate::inhibit::Inhibitor;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+/// How long one probe may take
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A host to probe, with a TCP port to connect to or none for an ICMP ping. Written as
+/// `HOST`, `HOST:PORT`, or `[ADDRESS]:PORT` for IPv6.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Target {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl std::str::FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid target '{}' (expected HOST or HOST:PORT)", s);
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            let (host, port) = rest.split_once("]:").ok_or_else(invalid)?;
+            (host, Some(port))
+        } else if s.matches(':').count() == 1 {
+            let (host, port) = s.split_once(':').unwrap();
+            (host, Some(port))
+        } else {
+            (s, None)
+        };
+        if host.is_empty() || host.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        let port = match port {
+            Some(port) => Some(port.parse().ok().filter(|port| *port > 0).ok_or_else(invalid)?),
+            None => None,
+        };
+        Ok(Target { host: host.to_string(), port })
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.port {
+            Some(port) if self.host.contains(':') => write!(f, "[{}]:{}", self.host, port),
+            Some(port) => write!(f, "{}:{}", self.host, port),
+            None => f.write_str(&self.host),
+        }
+    }
+}
+
+/// Blocks suspend while a `busy` target answers or a `required` target does not. Probes run
+/// in the background, so checking only reads the last results.
+#[derive(Clone, Default)]
+pub struct Reachability {
+    busy: Vec<Target>,
+    required: Vec<Target>,
+    reachable: Arc<RwLock<HashMap<Target, bool>>>,
+}
+
+impl Reachability {
+    pub fn new(busy: Vec<Target>, required: Vec<Target>) -> Self {
+        Reachability { busy, required, reachable: Arc::default() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.busy.is_empty() && self.required.is_empty()
+    }
+
+    /// Probe every target once, concurrently
+    pub async fn probe_all(&self) {
+        let targets: Vec<&Target> = self.busy.iter().chain(&self.required).collect();
+        let probes: Vec<_> = targets.iter().map(|target| tokio::spawn(probe((*target).clone()))).collect();
+        let mut results = Vec::with_capacity(probes.len());
+        for probe in probes {
+            results.push(probe.await.unwrap_or(false));
+        }
+        let mut reachable = self.reachable.write().unwrap();
+        for (target, up) in targets.into_iter().zip(results) {
+            if reachable.insert(target.clone(), up) != Some(up) {
+                verbose!("{} is {}", target, if up { "reachable" } else { "unreachable" });
+            }
+        }
+    }
+
+    /// Probe again every `interval`
+    pub fn refresh(&self, interval: Duration) {
+        let reachability = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                reachability.probe_all().await;
+            }
+        });
+    }
+}
+
+impl Inhibitor for Reachability {
+    fn name(&self) -> &str {
+        "reachability"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        let reachable = self.reachable.read().unwrap();
+        if let Some(target) = self.busy.iter().find(|target| reachable.get(*target) == Some(&true)) {
+            return Ok(Some(format!("{} is reachable", target)));
+        }
+        // A target that was never probed counts as unreachable
+        if let Some(target) = self.required.iter().find(|target| reachable.get(*target) != Some(&true)) {
+            return Ok(Some(format!("{} is unreachable", target)));
+        }
+        Ok(None)
+    }
+}
+
+/// Whether `target` accepts a TCP connection or, without a port, answers a ping
+pub async fn probe(target: Target) -> bool {
+    match target.port {
+        Some(port) => tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect((target.host.as_str(), port)))
+            .await
+            .is_ok_and(|connected| connected.is_ok()),
+        None => Command::new("ping")
+            .args(["-c", "1", "-W", &PROBE_TIMEOUT.as_secs().to_string(), &target.host])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .status()
+            .await
+            .is_ok_and(|status| status.success()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(s: &str) -> Target {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(target("backup-client.lan:873"), Target { host: "backup-client.lan".to_string(), port: Some(873) });
+        assert_eq!(target("192.168.1.5").port, None);
+        assert_eq!(target("fe80::1").host, "fe80::1");
+        assert_eq!(target("[fe80::1]:22"), Target { host: "fe80::1".to_string(), port: Some(22) });
+        assert_eq!(target("[fe80::1]:22").to_string(), "[fe80::1]:22");
+        assert!("host:0".parse::<Target>().is_err());
+        assert!("host:ssh".parse::<Target>().is_err());
+        assert!(":22".parse::<Target>().is_err());
+        assert!("[fe80::1]".parse::<Target>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probe() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = target(&listener.local_addr().unwrap().to_string());
+        drop(listener);
+        let reachability = Reachability::new(vec![open.clone()], Vec::new());
+        reachability.probe_all().await;
+        assert_eq!(reachability.check(), Ok(None));
+
+        let listener = std::net::TcpListener::bind(open.to_string()).unwrap();
+        reachability.probe_all().await;
+        assert_eq!(reachability.check(), Ok(Some(format!("{} is reachable", open))));
+        drop(listener);
+
+        let required = Reachability::new(Vec::new(), vec![open.clone()]);
+        assert_eq!(required.check(), Ok(Some(format!("{} is unreachable", open))));
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Most sender and reason pairs tracked at once; rejections beyond this are logged one by one
+const MAX_TRACKED: usize = 1024;
+
+#[derive(Debug)]
+struct Seen {
+    first: Instant,
+    count: u64,
+}
+
+/// Collapses identical rejections (same sender, same reason) into one line per window: the
+/// first is logged as it happens, the rest are counted and summarized when the window ends
+#[derive(Debug)]
+pub struct Rejections {
+    window: Option<Duration>,
+    seen: HashMap<(IpAddr, String), Seen>,
+}
+
+impl Rejections {
+    /// Without a window every rejection is logged
+    pub fn new(window: Option<Duration>) -> Self {
+        Rejections { window, seen: HashMap::new() }
+    }
+
+    /// Whether any window is open, so `flush` has work to do
+    pub fn pending(&self) -> bool {
+        !self.seen.is_empty()
+    }
+
+    /// Count a rejection. Returns whether to log it now.
+    pub fn record(&mut self, sender: IpAddr, reason: &str, now: Instant) -> bool {
+        if self.window.is_none() {
+            return true;
+        }
+        if let Some(seen) = self.seen.get_mut(&(sender, reason.to_string())) {
+            seen.count += 1;
+            return false;
+        }
+        if self.seen.len() < MAX_TRACKED {
+            self.seen.insert((sender, reason.to_string()), Seen { first: now, count: 1 });
+        }
+        true
+    }
+
+    /// Close the windows that have ended, returning a summary line for each that held more
+    /// than the one rejection already logged
+    pub fn flush(&mut self, now: Instant) -> Vec<String> {
+        let Some(window) = self.window else {
+            return Vec::new();
+        };
+        let mut lines = Vec::new();
+        self.seen.retain(|(sender, reason), seen| {
+            if now.duration_since(seen.first) < window {
+                return true;
+            }
+            if seen.count > 1 {
+                lines.push(format!("{}: {} rejected packets in the last {}, reason: {}", sender, seen.count, span(window), reason));
+            }
+            false
+        });
+        lines.sort();
+        lines
+    }
+}
+
+/// `window` as `5m` or `90s`
+fn span(window: Duration) -> String {
+    match window.as_secs() {
+        secs if secs >= 60 && secs % 60 == 0 => format!("{}m", secs / 60),
+        secs => format!("{}s", secs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let start = Instant::now();
+        let (chatty, other): (IpAddr, IpAddr) = ("192.168.1.77".parse().unwrap(), "192.168.1.8".parse().unwrap());
+        let mut rejections = Rejections::new(Some(Duration::from_secs(300)));
+        assert!(rejections.record(chatty, "Invalid header", start));
+        for _ in 0..411 {
+            assert!(!rejections.record(chatty, "Invalid header", start + Duration::from_secs(10)));
+        }
+        assert!(rejections.record(chatty, "Invalid size: 12", start));
+        assert!(rejections.record(other, "Invalid header", start));
+        assert!(rejections.flush(start + Duration::from_secs(299)).is_empty());
+
+        let lines = rejections.flush(start + Duration::from_secs(300));
+        assert_eq!(lines, ["192.168.1.77: 412 rejected packets in the last 5m, reason: Invalid header"]);
+        assert!(!rejections.pending());
+        assert!(rejections.record(chatty, "Invalid header", start + Duration::from_secs(301)));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut rejections = Rejections::new(None);
+        let sender: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(rejections.record(sender, "banned", Instant::now()));
+        assert!(rejections.record(sender, "banned", Instant::now()));
+        assert!(!rejections.pending());
+        assert_eq!(span(Duration::from_secs(90)), "90s");
+    }
+}
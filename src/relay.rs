@@ -0,0 +1,173 @@
+use crate::mac::MacAddr;
+use crate::netif::{self, IpNetwork};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+
+/// Port relayed packets are sent to unless the entry names one
+pub const DEFAULT_RELAY_PORT: u16 = 9;
+
+/// A non-local MAC whose packets are re-broadcast elsewhere, written as
+/// `MAC@TARGET[:PORT]` where TARGET is a (broadcast) IPv4 address or an interface name,
+/// e.g. `aa:bb:cc:dd:ee:ff@192.168.20.255` or `aa:bb:cc:dd:ee:ff@eth1:10`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelayEntry {
+    pub mac: MacAddr,
+    pub target: RelayTarget,
+    pub port: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RelayTarget {
+    Address(Ipv4Addr),
+    /// Resolved to the interface's IPv4 broadcast address when packets are relayed
+    Interface(String),
+}
+
+impl std::str::FromStr for RelayEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mac, target) = s
+            .split_once('@')
+            .ok_or_else(|| format!("Invalid relay '{}' (expected MAC@TARGET[:PORT])", s))?;
+        let mac: MacAddr = mac
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid MAC address '{}' in relay '{}'", mac, s))?;
+
+        let (target, port) = match target.rsplit_once(':') {
+            Some((target, port)) => (
+                target,
+                port.parse()
+                    .map_err(|_| format!("Invalid port '{}' in relay '{}'", port, s))?,
+            ),
+            None => (target, DEFAULT_RELAY_PORT),
+        };
+        if target.is_empty() {
+            return Err(format!("Missing target in relay '{}'", s));
+        }
+
+        let target = match target.parse() {
+            Ok(addr) => RelayTarget::Address(addr),
+            Err(_) => RelayTarget::Interface(target.to_string()),
+        };
+
+        Ok(RelayEntry { mac, target, port })
+    }
+}
+
+impl RelayEntry {
+    pub fn destination(&self) -> Result<SocketAddr, String> {
+        let addr = match &self.target {
+            RelayTarget::Address(addr) => *addr,
+            RelayTarget::Interface(name) => interface_broadcast(name)?,
+        };
+        Ok(SocketAddr::new(IpAddr::V4(addr), self.port))
+    }
+}
+
+fn interface_broadcast(name: &str) -> Result<Ipv4Addr, String> {
+    let iface = netif::interfaces()
+        .into_iter()
+        .find(|iface| iface.name == name)
+        .ok_or_else(|| format!("no interface named {}", name))?;
+    iface
+        .ips
+        .iter()
+        .find_map(|ip| match ip {
+            IpNetwork::V4(net) => Some(net.broadcast()),
+            IpNetwork::V6(_) => None,
+        })
+        .ok_or_else(|| format!("interface {} has no IPv4 address", name))
+}
+
+/// Forwards packets for the MACs in the relay table to other subnets
+pub struct Relay {
+    entries: Vec<RelayEntry>,
+    socket: Option<UdpSocket>,
+}
+
+impl Relay {
+    pub async fn new(entries: Vec<RelayEntry>) -> std::io::Result<Self> {
+        let socket = if entries.is_empty() {
+            None
+        } else {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.set_broadcast(true)?;
+            Some(socket)
+        };
+        Ok(Relay { entries, socket })
+    }
+
+    pub fn handles(&self, mac: &MacAddr) -> bool {
+        self.entries.iter().any(|entry| entry.mac == *mac)
+    }
+
+    /// Send `packet` unchanged to every target configured for `mac`
+    pub async fn forward(&self, mac: &MacAddr, packet: &[u8]) -> Result<Vec<SocketAddr>, String> {
+        let Some(socket) = &self.socket else {
+            return Ok(Vec::new());
+        };
+
+        let mut sent = Vec::new();
+        for entry in self.entries.iter().filter(|entry| entry.mac == *mac) {
+            let destination = entry.destination()?;
+            socket
+                .send_to(packet, destination)
+                .await
+                .map_err(|e| format!("failed to send to {}: {}", destination, e))?;
+            sent.push(destination);
+        }
+        Ok(sent)
+    }
+}
+
+/// Addresses assigned to local interfaces, used to avoid relaying our own broadcasts back out
+pub fn local_addresses() -> Vec<IpAddr> {
+    netif::interfaces()
+        .into_iter()
+        .flat_map(|iface| iface.ips.into_iter().map(|ip| ip.ip()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+    #[test]
+    fn test_parse_relay_entry() {
+        let entry: RelayEntry = "aa:bb:cc:dd:ee:ff@192.168.20.255".parse().unwrap();
+        assert_eq!(entry.mac, MAC);
+        assert_eq!(entry.target, RelayTarget::Address(Ipv4Addr::new(192, 168, 20, 255)));
+        assert_eq!(entry.port, DEFAULT_RELAY_PORT);
+
+        let entry: RelayEntry = "aa:bb:cc:dd:ee:ff@eth1:10".parse().unwrap();
+        assert_eq!(entry.target, RelayTarget::Interface("eth1".to_string()));
+        assert_eq!(entry.port, 10);
+
+        assert!("aa:bb:cc:dd:ee:ff".parse::<RelayEntry>().is_err());
+        assert!("nope@eth1".parse::<RelayEntry>().is_err());
+        assert!("aa:bb:cc:dd:ee:ff@eth1:port".parse::<RelayEntry>().is_err());
+        assert!("aa:bb:cc:dd:ee:ff@".parse::<RelayEntry>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_forward_to_address() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let entry = format!("aa:bb:cc:dd:ee:ff@127.0.0.1:{}", port).parse().unwrap();
+        let relay = Relay::new(vec![entry]).await.unwrap();
+
+        assert!(relay.handles(&MAC));
+        assert!(!relay.handles(&MacAddr::ZERO));
+
+        let sent = relay.forward(&MAC, b"packet").await.unwrap();
+        assert_eq!(sent, vec![SocketAddr::from(([127, 0, 0, 1], port))]);
+
+        let mut buf = [0u8; 16];
+        let len = receiver.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"packet");
+    }
+}
@@ -0,0 +1,204 @@
+use crate::config::Config;
+use crate::peers::Peers;
+use crate::policy::Profiles;
+use std::ffi::{CString, OsStr, OsString};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::signal::unix::{SignalKind, signal};
+
+/// Time for a tool rewriting the file to finish before it is read
+const SETTLE: Duration = Duration::from_millis(200);
+
+/// Size of `struct inotify_event` without the name
+const EVENT_HEADER: usize = 16;
+
+/// Describe how a reloaded list of named items differs from the current one
+pub fn changes<T: PartialEq>(kind: &str, old: &[T], new: &[T], name: impl Fn(&T) -> &str) -> Vec<String> {
+    let mut changes = Vec::new();
+    for item in new {
+        match old.iter().find(|o| name(o) == name(item)) {
+            Some(previous) if previous != item => changes.push(format!("{} {} changed", kind, name(item))),
+            Some(_) => {}
+            None => changes.push(format!("{} {} added", kind, name(item))),
+        }
+    }
+    for item in old {
+        if !new.iter().any(|n| name(n) == name(item)) {
+            changes.push(format!("{} {} removed", kind, name(item)));
+        }
+    }
+    changes
+}
+
+/// Load `path` and swap in its profiles and peers only if all of it is valid
+fn apply(path: &Path, profiles: &Profiles, peers: &Peers) {
+    let loaded = Config::load(path).and_then(|config| Ok((profiles.parse(&config)?, peers.parse(&config)?)));
+    match loaded {
+        Ok((new_profiles, new_peers)) => {
+            let mut changes = profiles.replace(new_profiles);
+            changes.extend(peers.replace(new_peers));
+            if changes.is_empty() {
+                verbose!("Config {} reloaded; nothing changed", path.display());
+            }
+            for change in changes {
+                info!("Config reloaded: {}", change);
+            }
+        }
+        Err(e) => warn!("Config {} not applied, keeping the previous settings: {}", path.display(), e),
+    }
+}
+
+/// Watches the directory holding the config file, since editors and deployment tools
+/// usually replace the file rather than write to it
+struct Inotify {
+    fd: AsyncFd<OwnedFd>,
+    file: OsString,
+}
+
+impl Inotify {
+    fn new(path: &Path) -> std::io::Result<Self> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let file = path.file_name().unwrap_or_default().to_os_string();
+        let c_dir = CString::new(dir.as_os_str().as_bytes())?;
+
+        // SAFETY: inotify_init1 returns a new descriptor that we take ownership of
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE;
+        // SAFETY: c_dir is a valid NUL-terminated path
+        if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), c_dir.as_ptr(), mask) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Inotify { fd: AsyncFd::new(fd)?, file })
+    }
+
+    /// Wait until the config file was written or replaced
+    async fn changed(&self) -> std::io::Result<()> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            match guard.try_io(|fd| read_events(fd.as_raw_fd())) {
+                Ok(Ok(names)) if names.contains(&self.file) => return Ok(()),
+                Ok(Ok(_)) | Err(_) => {}
+                Ok(Err(e)) => return Err(e),
+            }
+        }
+    }
+
+    /// Discard events queued while the file settled
+    fn drain(&self) {
+        while read_events(self.fd.as_raw_fd()).is_ok_and(|names| !names.is_empty()) {}
+    }
+}
+
+fn read_events(fd: libc::c_int) -> std::io::Result<Vec<OsString>> {
+    let mut buf = [0u8; 4096];
+    // SAFETY: buf is valid for writes of its length
+    let len = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+    if len < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(event_names(&buf[..len as usize]))
+}
+
+/// File names of the inotify events in `buf`
+fn event_names(mut buf: &[u8]) -> Vec<OsString> {
+    let mut names = Vec::new();
+    while buf.len() >= EVENT_HEADER {
+        let len = u32::from_ne_bytes(buf[12..16].try_into().unwrap()) as usize;
+        let Some(name) = buf.get(EVENT_HEADER..EVENT_HEADER + len) else { break };
+        let name = name.split(|&b| b == 0).next().unwrap_or_default();
+        names.push(OsStr::from_bytes(name).to_os_string());
+        buf = &buf[EVENT_HEADER + len..];
+    }
+    names
+}
+
+/// Reload the config file when it changes on disk or the daemon receives SIGHUP
+pub fn watch(path: PathBuf, profiles: Arc<Profiles>, peers: Arc<Peers>) -> std::io::Result<()> {
+    let mut hangups = signal(SignalKind::hangup())?;
+    let mut inotify = match Inotify::new(&path) {
+        Ok(inotify) => Some(inotify),
+        Err(e) => {
+            warn!("Cannot watch {} for changes, reload with SIGHUP instead: {}", path.display(), e);
+            None
+        }
+    };
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = hangups.recv() => info!("SIGHUP received: reloading {}", path.display()),
+                result = async { inotify.as_ref().unwrap().changed().await }, if inotify.is_some() => {
+                    if let Err(e) = result {
+                        // A failing watch tends to keep failing, e.g. once its directory is gone
+                        warn!("Watching {} failed, reload with SIGHUP instead: {}", path.display(), e);
+                        inotify = None;
+                        continue;
+                    }
+                    tokio::time::sleep(SETTLE).await;
+                    inotify.as_ref().unwrap().drain();
+                }
+            }
+            apply(&path, &profiles, &peers);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_names() {
+        let mut buf = Vec::new();
+        for name in ["sol.toml", ".sol.toml.swp"] {
+            let padded = (name.len() / 16 + 1) * 16;
+            buf.extend_from_slice(&[0; 12]);
+            buf.extend_from_slice(&(padded as u32).to_ne_bytes());
+            buf.extend_from_slice(name.as_bytes());
+            buf.resize(buf.len() + padded - name.len(), 0);
+        }
+        assert_eq!(event_names(&buf), [OsString::from("sol.toml"), OsString::from(".sol.toml.swp")]);
+    }
+
+    #[test]
+    fn test_changes() {
+        let old = [("a", 1), ("b", 2)];
+        let new = [("a", 1), ("b", 3), ("c", 4)];
+        assert_eq!(
+            changes("peer", &old, &new, |item| item.0),
+            ["peer b changed", "peer c added"]
+        );
+        assert_eq!(changes("peer", &new, &old, |item| item.0), ["peer b changed", "peer c removed"]);
+    }
+
+    #[tokio::test]
+    async fn test_reload_on_file_change() {
+        let dir = std::env::temp_dir().join(format!("sol-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sol.toml");
+        std::fs::write(&path, "").unwrap();
+
+        let inotify = Inotify::new(&path).unwrap();
+        std::fs::write(dir.join("other"), "").unwrap();
+        std::fs::write(dir.join("sol.toml.new"), "[profile.night]\ngrace = 5").unwrap();
+        std::fs::rename(dir.join("sol.toml.new"), &path).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), inotify.changed())
+            .await
+            .unwrap()
+            .unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
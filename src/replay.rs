@@ -0,0 +1,200 @@
+use crate::actions::NamedActions;
+use crate::capture::{self, Captured};
+use crate::clock::LocalTime;
+use crate::config::Config;
+use crate::hosts::Hosts;
+use crate::interfaces;
+use crate::mac::MacAddr;
+use crate::netif::{self, NetworkInterface};
+use crate::pipeline::Pipelines;
+use crate::policy::Profiles;
+use crate::protocol::{Command, Request, Verifier};
+use crate::quorum::Quorum;
+use crate::rules::{Action, Rules};
+use crate::supply::Supply;
+use crate::{Args, selftest};
+use sol::{packet, v2};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The daemon's validation state, fed with captured datagrams instead of live ones
+struct Replay<'a> {
+    args: &'a Args,
+    local_macs: &'a [MacAddr],
+    interfaces: Vec<NetworkInterface>,
+    profiles: Profiles,
+    pipelines: Pipelines,
+    named_actions: NamedActions,
+    verifier: Verifier,
+    rules: Rules,
+    quorum: Quorum,
+    /// Maps capture time onto the monotonic clock the quorum runs on
+    start: (Duration, Instant),
+}
+
+impl Replay<'_> {
+    /// What the daemon would have done with `datagram` when it arrived. The first word is the
+    /// outcome: accepted, waiting, rejected, ignored, cancel, test or relayed.
+    fn verdict(&mut self, datagram: &Captured) -> String {
+        let Captured { peer, local, payload: packet, .. } = datagram;
+        let now = datagram.time.as_secs();
+        if !self.args.ports.contains(&local.port()) {
+            return format!("ignored: port {} is not a trigger port", local.port());
+        }
+        if self.args.local_only && !interfaces::on_link(&self.interfaces, local.ip().to_canonical(), peer.ip()) {
+            return "rejected: not on a local subnet".to_string();
+        }
+        if let Some(mac) = crate::relay_target(packet, self.local_macs)
+            && self.args.relays.iter().any(|entry| entry.mac == mac)
+        {
+            return format!("relayed: for {}", mac);
+        }
+
+        let mut run = None;
+        let command = if packet.starts_with(&v2::PREFIX) {
+            let accepted = self.verifier.accept(packet, self.local_macs, now).and_then(|mut command| {
+                run = self.named_actions.resolve(&mut command)?;
+                Ok(command)
+            });
+            match accepted {
+                Ok(command) => Some(command),
+                Err(e) => return format!("rejected: invalid v2 packet: {}", e),
+            }
+        } else {
+            None
+        };
+
+        if let Some(Command { request: Request::Snooze(length), mac, .. }) = &command {
+            return format!("snooze: for {}, would hold off sleep for {}s", mac, length.as_secs());
+        }
+
+        let cancel = match &command {
+            Some(command) => (command.request == Request::Cancel).then_some(Ok(command.mac)),
+            None => packet.starts_with(&packet::CANCEL_HEADER).then(|| crate::validate_cancel_packet(packet, self.local_macs, self.args.trailer(false))),
+        };
+        match cancel {
+            Some(Ok(mac)) => {
+                self.quorum.reset(&mac);
+                return format!("cancel: for {}, would abort a pending suspend", mac);
+            }
+            Some(Err(e)) => return format!("rejected: invalid cancel packet: {}", e),
+            None => {}
+        }
+
+        let time = LocalTime::from_unix(now as libc::time_t);
+        if packet.starts_with(&selftest::PROBE_PACKET_HEADER) {
+            return match crate::validate_magic_packet(packet, &selftest::PROBE_PACKET_HEADER, self.local_macs, self.args.trailer(false)) {
+                Ok(mac) => {
+                    let (_, policy) = self.profiles.current(&time);
+                    let decision = self.rules.decide(peer.ip(), &mac, local.port());
+                    format!("test: {}", crate::probe_verdict(decision.action, &policy, None))
+                }
+                Err(e) => format!("rejected: invalid test packet: {}", e),
+            };
+        }
+
+        let mac = match &command {
+            Some(command) => command.mac,
+            None => match crate::validate_wol_packet(packet, self.local_macs, self.args.trailer(true)) {
+                Ok(mac) => mac,
+                Err(e) => return format!("rejected: {}", e),
+            },
+        };
+        let (profile, mut policy) = self.profiles.current(&time);
+        self.quorum.configure(policy.quorum, Duration::from_secs(policy.quorum_window));
+        let mut decision = self.rules.decide(peer.ip(), &mac, local.port());
+        if let Some(command) = &command {
+            if let Some(name) = command.pipeline.as_deref().filter(|name| !self.pipelines.contains(name)) {
+                return format!("ignored: pipeline '{}' is not defined", name);
+            }
+            decision = command.apply(decision);
+            if let Some(grace) = command.grace {
+                policy.grace = grace;
+            }
+        }
+        if decision.action == Action::Ignore {
+            return "ignored: by rule".to_string();
+        }
+        if let Some(step) = run {
+            return format!("accepted: would run action {}", step.name);
+        }
+
+        let mut verdict = format!("accepted: would {}", decision.action.as_str());
+        if decision.force {
+            verdict += ", forced by rule";
+        } else {
+            let (since, start) = self.start;
+            let count = self.quorum.record(mac, start + datagram.time.saturating_sub(since));
+            if !self.quorum.is_met(count) {
+                return format!("waiting: quorum {}/{} within {}s", count, self.quorum.required(), policy.quorum_window);
+            }
+            self.quorum.reset(&mac);
+            if policy.confirm && command.is_none() {
+                // Whether a confirmation was pending depends on replies the capture cannot show
+                verdict = format!("accepted: would {} after confirmation", decision.action.as_str());
+            }
+        }
+        if policy.grace > 0 {
+            verdict += &format!(" after a {}s grace period", policy.grace);
+        }
+        if let Some(pipeline) = &decision.pipeline {
+            verdict += &format!(" with pipeline {}", pipeline);
+        }
+        format!("{} (profile {}, MAC {})", verdict, profile, mac)
+    }
+}
+
+/// Feed the datagrams in the pcap file at `path` through the daemon's validation in order,
+/// printing the verdict for each without acting on any of them
+pub async fn run(args: &Args, path: &Path, local_macs: &[MacAddr]) -> Result<(), String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let datagrams = capture::read(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let step_timeout = Duration::from_secs(args.pre_suspend_timeout);
+    let rules = Rules::new(args.rules.clone(), args.default_action, Supply::new(args.nut_ups.clone()), Hosts::default());
+    let hostnames = rules.hostnames();
+    if !hostnames.is_empty() {
+        rules.hosts.resolve(&hostnames).await;
+    }
+    if rules.reads_power() {
+        rules.supply.poll().await;
+    }
+    let mut replay = Replay {
+        args,
+        local_macs,
+        interfaces: netif::interfaces(),
+        profiles: Profiles::from_config(args.policy(), &config)?,
+        pipelines: Pipelines::from_config(&config, step_timeout)?,
+        named_actions: NamedActions::from_config(&config, step_timeout)?,
+        verifier: Verifier::load(args.v2_key.as_deref(), Duration::from_secs(args.v2_max_skew))?,
+        rules,
+        quorum: Quorum::new(args.quorum, Duration::from_secs(args.quorum_window)),
+        start: (datagrams.first().map(|datagram| datagram.time).unwrap_or_default(), Instant::now()),
+    };
+
+    let mut outcomes: BTreeMap<String, usize> = BTreeMap::new();
+    for (n, datagram) in datagrams.iter().enumerate() {
+        let verdict = replay.verdict(datagram);
+        let outcome = verdict.split(':').next().unwrap_or_default();
+        *outcomes.entry(outcome.to_string()).or_default() += 1;
+        let time = LocalTime::from_unix(datagram.time.as_secs() as libc::time_t);
+        println!(
+            "{:>5} {:02}:{:02}:{:02} {} -> {} {} bytes: {}",
+            n + 1,
+            time.hour,
+            time.minute,
+            time.second,
+            datagram.peer,
+            datagram.local.port(),
+            datagram.payload.len(),
+            verdict
+        );
+    }
+    let counts: Vec<String> = outcomes.iter().map(|(outcome, count)| format!("{} {}", count, outcome)).collect();
+    println!("{} datagrams: {}", datagrams.len(), if counts.is_empty() { "none".to_string() } else { counts.join(", ") });
+    Ok(())
+}
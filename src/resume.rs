@@ -0,0 +1,91 @@
+use crate::presuspend::run_step;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// How far the boot-time clock must run ahead of the monotonic clock to count as a sleep
+const SLEEP_THRESHOLD: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Monotonic and boot-time clock readings taken together, to tell later whether the system slept.
+///
+/// CLOCK_MONOTONIC stops while the system sleeps but CLOCK_BOOTTIME does not, so a resume
+/// shows up as the boot-time clock jumping ahead of the monotonic one. Unlike wall-clock time,
+/// neither is moved by NTP or someone setting the date.
+pub struct Clocks {
+    mono: Duration,
+    boot: Duration,
+}
+
+impl Clocks {
+    pub fn now() -> Self {
+        Clocks { mono: clock(libc::CLOCK_MONOTONIC), boot: clock(libc::CLOCK_BOOTTIME) }
+    }
+
+    /// Whether the system has slept since the readings were taken
+    pub fn slept(&self) -> bool {
+        let now = Clocks::now();
+        slept(now.mono.saturating_sub(self.mono), now.boot.saturating_sub(self.boot))
+    }
+}
+
+/// The current reading of `id`
+fn clock(id: libc::clockid_t) -> Duration {
+    // SAFETY: clock_gettime only writes to the timespec we pass in
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(id, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Wait until the system has been through a suspend/resume cycle since `start`, or `limit`
+/// passes while awake. Returns whether a sleep was observed.
+pub async fn wait_for_resume(start: &Clocks, limit: Duration) -> bool {
+    while !start.slept() {
+        if clock(libc::CLOCK_MONOTONIC).saturating_sub(start.mono) >= limit {
+            return false;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    true
+}
+
+fn slept(mono_elapsed: Duration, boot_elapsed: Duration) -> bool {
+    boot_elapsed.saturating_sub(mono_elapsed) > SLEEP_THRESHOLD
+}
+
+/// Commands run after the system resumes
+pub struct PostResume {
+    pub commands: Vec<String>,
+    pub timeout: Duration,
+}
+
+impl PostResume {
+    /// Run every command, logging failures without stopping
+    pub async fn run(&self) {
+        for command in &self.commands {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(command);
+            if let Err(e) = run_step(command, cmd, self.timeout).await {
+                error!("Post-resume step failed: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slept_detection() {
+        assert!(!slept(Duration::from_secs(10), Duration::from_secs(10)));
+        assert!(!slept(Duration::from_secs(10), Duration::from_secs(12)));
+        assert!(slept(Duration::from_secs(10), Duration::from_secs(600)));
+        assert!(!slept(Duration::from_secs(10), Duration::from_secs(0)));
+        assert!(!Clocks::now().slept());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_resume_times_out_without_sleep() {
+        assert!(!wait_for_resume(&Clocks::now(), Duration::from_millis(10)).await);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::desktop::{self, property};
+use crate::idle::UserIdle;
+use crate::inhibit::Inhibitor;
+use crate::{interfaces, netif, nicwol};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const WAKEUP_SOURCES: &str = "/sys/class/wakeup";
+
+/// Microseconds on CLOCK_MONOTONIC, the clock logind stamps sessions with
+pub fn monotonic_usec() -> u64 {
+    // SAFETY: clock_gettime only writes to the timespec we pass in
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1_000
+}
+
+/// Signs that a resumed machine is in use: local input within `window`, or a login
+/// session, local or remote, opened since `resumed` (see `monotonic_usec`)
+pub struct WakeActivity {
+    pub window: Duration,
+    pub resumed: u64,
+}
+
+impl Inhibitor for WakeActivity {
+    fn name(&self) -> &str {
+        "wake-activity"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        if let Some(reason) = (UserIdle { min_idle: self.window }).check()? {
+            return Ok(Some(reason));
+        }
+
+        for session in desktop::session_ids()? {
+            let properties = desktop::loginctl(&["show-session", &session, "-p", "Name", "-p", "TimestampMonotonic"])?;
+            if opened_since(&properties, self.resumed) {
+                let user = property(&properties, "Name").unwrap_or("unknown");
+                return Ok(Some(format!("{} logged in after resume", user)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// What a wakeup source belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// A monitored NIC that only wakes on magic packets
+    MagicPacket,
+    /// A button, keyboard, lid or other input device
+    Input,
+    Other,
+}
+
+/// Wakeup counts of the devices that can wake the machine, taken before sleeping so the
+/// device that woke it can be told apart afterwards
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Wakeups(Vec<(String, Source, u64)>);
+
+impl Wakeups {
+    /// Read the counts from /sys/class/wakeup; empty where the kernel does not provide them
+    pub fn read() -> Wakeups {
+        let magic_nics: Vec<PathBuf> = netif::interfaces()
+            .iter()
+            .filter(|iface| interfaces::monitored(iface))
+            .filter(|iface| {
+                nicwol::settings(&iface.name).ok().flatten().is_some_and(|settings| settings.enabled == "g")
+            })
+            .filter_map(|iface| fs::canonicalize(format!("/sys/class/net/{}/device", iface.name)).ok())
+            .collect();
+        let Ok(entries) = fs::read_dir(WAKEUP_SOURCES) else {
+            return Wakeups::default();
+        };
+        let mut sources = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let read = |name: &str| fs::read_to_string(path.join(name)).map(|text| text.trim().to_string());
+            let (Ok(name), Some(count)) = (read("name"), read("wakeup_count").ok().and_then(|count| count.parse().ok())) else {
+                continue;
+            };
+            let device = fs::canonicalize(path.join("device")).ok();
+            let source = match device {
+                Some(device) if magic_nics.contains(&device) => Source::MagicPacket,
+                Some(device) if has_input(&device, 3) => Source::Input,
+                _ => Source::Other,
+            };
+            sources.push((name, source, count));
+        }
+        Wakeups(sources)
+    }
+
+    /// Why the machine woke, when a magic packet or a user woke it since `self` was taken
+    pub fn cause(&self, after: &Wakeups) -> Option<String> {
+        after.0.iter().find_map(|(name, source, count)| {
+            let before = self.0.iter().find(|(other, ..)| other == name).map_or(0, |(_, _, before)| *before);
+            match source {
+                _ if *count <= before => None,
+                Source::MagicPacket => Some(format!("woken by a magic packet on {}", name)),
+                Source::Input => Some(format!("woken by {}", name)),
+                Source::Other => None,
+            }
+        })
+    }
+}
+
+/// Whether `device` or one of its children up to `depth` levels down is an input device
+fn has_input(device: &Path, depth: u32) -> bool {
+    if device.join("input").is_dir() {
+        return true;
+    }
+    depth > 0
+        && fs::read_dir(device).into_iter().flatten().flatten().any(|entry| {
+            // Child devices are real directories; the symlinks point back up the tree
+            entry.file_type().is_ok_and(|kind| kind.is_dir()) && has_input(&entry.path(), depth - 1)
+        })
+}
+
+fn opened_since(properties: &str, resumed: u64) -> bool {
+    property(properties, "TimestampMonotonic")
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .is_some_and(|usec| usec >= resumed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opened_since() {
+        let properties = "Name=alice\nTimestampMonotonic=5000000\n";
+        assert!(opened_since(properties, 4_000_000));
+        assert!(!opened_since(properties, 6_000_000));
+        assert!(!opened_since("Name=alice\n", 0));
+    }
+
+    #[test]
+    fn test_wake_cause() {
+        let before = Wakeups(vec![
+            ("0000:00:1f.6".to_string(), Source::MagicPacket, 2),
+            ("LNXPWRBN:00".to_string(), Source::Input, 0),
+            ("rtc0".to_string(), Source::Other, 5),
+        ]);
+        let woken = |name: &str| {
+            let sources = before.0.iter().map(|(other, source, count)| (other.clone(), *source, count + u64::from(other == name)));
+            Wakeups(sources.collect())
+        };
+        assert_eq!(before.cause(&woken("0000:00:1f.6")), Some("woken by a magic packet on 0000:00:1f.6".to_string()));
+        assert_eq!(before.cause(&woken("LNXPWRBN:00")), Some("woken by LNXPWRBN:00".to_string()));
+        assert_eq!(before.cause(&woken("rtc0")), None);
+        assert_eq!(before.cause(&woken("none")), None);
+        assert_eq!(Wakeups::default().cause(&Wakeups::default()), None);
+    }
+}
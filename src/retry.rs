@@ -0,0 +1,88 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+/// How often a failed step is retried, waiting `initial`, then twice that, and so on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub retries: u32,
+    pub initial: Duration,
+}
+
+impl Backoff {
+    /// Delay before retry number `attempt` (starting at 1)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial.saturating_mul(1 << (attempt - 1).min(16))
+    }
+
+    /// Run `step` until it succeeds or the retries are used up, returning the last error
+    pub async fn run<T, E, F, Fut>(&self, name: &str, mut step: F) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match step().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.retries => {
+                    attempt += 1;
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "{} failed: {}; retrying in {}s ({}/{})",
+                        name,
+                        e,
+                        delay.as_secs(),
+                        attempt,
+                        self.retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_delay_doubles() {
+        let backoff = Backoff { retries: 3, initial: Duration::from_secs(5) };
+        assert_eq!(backoff.delay(1), Duration::from_secs(5));
+        assert_eq!(backoff.delay(2), Duration::from_secs(10));
+        assert_eq!(backoff.delay(3), Duration::from_secs(20));
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success() {
+        let backoff = Backoff { retries: 3, initial: Duration::from_millis(1) };
+        let calls = Cell::new(0);
+        let result = backoff
+            .run("step", || {
+                calls.set(calls.get() + 1);
+                let n = calls.get();
+                async move { if n < 3 { Err("busy") } else { Ok(n) } }
+            })
+            .await;
+        assert_eq!(result, Ok(3));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_retries() {
+        let backoff = Backoff { retries: 2, initial: Duration::from_millis(1) };
+        let calls = Cell::new(0);
+        let result: Result<(), &str> = backoff
+            .run("step", || {
+                calls.set(calls.get() + 1);
+                async { Err("busy") }
+            })
+            .await;
+        assert_eq!(result, Err("busy"));
+        assert_eq!(calls.get(), 3);
+    }
+}
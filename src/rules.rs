@@ -0,0 +1,289 @@
+use crate::hosts::Hosts;
+use crate::mac::MacAddr;
+use crate::netif::IpNetwork;
+use crate::supply::{PowerSource, Supply};
+use clap::ValueEnum;
+use std::net::IpAddr;
+
+/// What a validated trigger does
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Suspend,
+    Hibernate,
+    Poweroff,
+    /// Log the packet and do nothing
+    Ignore,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Suspend => "suspend",
+            Action::Hibernate => "hibernate",
+            Action::Poweroff => "poweroff",
+            Action::Ignore => "ignore",
+        }
+    }
+
+    /// Whether the machine comes back afterwards, so resume steps should run
+    pub fn resumes(&self) -> bool {
+        matches!(self, Action::Suspend | Action::Hibernate)
+    }
+}
+
+/// Who a `from=` matcher accepts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Sender {
+    Subnet(IpNetwork),
+    /// A hostname, matched against the addresses it last resolved to
+    Host(String),
+}
+
+impl Sender {
+    fn contains(&self, ip: IpAddr, hosts: &Hosts) -> bool {
+        match self {
+            Sender::Subnet(net) => net.contains(ip.to_canonical()),
+            Sender::Host(name) => hosts.contains(name, ip),
+        }
+    }
+}
+
+impl std::str::FromStr for Sender {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(net) = s.parse() {
+            return Ok(Sender::Subnet(net));
+        }
+        let label = |label: &str| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        };
+        let name = s.trim_end_matches('.');
+        // A name made only of digits and dots is a mistyped address, not a host
+        if name.split('.').all(label) && !name.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            Ok(Sender::Host(name.to_ascii_lowercase()))
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// Maps packets matching a sender subnet, target MAC, port and/or power source to an action.
+/// Written as `ACTION[:KEY=VALUE,...]` with keys `from`, `mac`, `port`, `power`, `force` and `pipeline`,
+/// e.g. `poweroff:from=192.168.1.20`, `ignore:from=10.8.0.0/16,port=9` or `hibernate:from=my-phone.lan`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    pub from: Option<Sender>,
+    pub mac: Option<MacAddr>,
+    pub port: Option<u16>,
+    pub power: Option<PowerSource>,
+    /// Skip quorum, confirmation and inhibitors for matching packets
+    pub force: bool,
+    /// `[pipeline.<name>]` to run around the action instead of the default one
+    pub pipeline: Option<String>,
+}
+
+impl Rule {
+    /// `power` is only called when the rule has a `power` matcher
+    pub fn matches(
+        &self,
+        sender: IpAddr,
+        hosts: &Hosts,
+        mac: &MacAddr,
+        port: u16,
+        power: impl FnOnce() -> PowerSource,
+    ) -> bool {
+        self.from.as_ref().is_none_or(|from| from.contains(sender, hosts))
+            && self.mac.is_none_or(|m| m == *mac)
+            && self.port.is_none_or(|p| p == port)
+            && self.power.is_none_or(|source| source == power())
+    }
+}
+
+impl std::str::FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (action, matchers) = s.split_once(':').unwrap_or((s, ""));
+        let mut rule = Rule {
+            action: Action::from_str(action.trim(), true)
+                .map_err(|_| format!("Invalid action '{}' in rule '{}'", action, s))?,
+            from: None,
+            mac: None,
+            port: None,
+            power: None,
+            force: false,
+            pipeline: None,
+        };
+
+        for matcher in matchers.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+            let (key, value) = matcher
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid matcher '{}' in rule '{}' (expected KEY=VALUE)", matcher, s))?;
+            let invalid = |what: &str| format!("Invalid {} '{}' in rule '{}'", what, value, s);
+            match key.trim() {
+                "from" => rule.from = Some(value.parse().map_err(|_| invalid("subnet or hostname"))?),
+                "mac" => rule.mac = Some(value.parse().map_err(|_| invalid("MAC address"))?),
+                "port" => rule.port = Some(value.parse().map_err(|_| invalid("port"))?),
+                "power" => rule.power = Some(value.parse().map_err(|_| invalid("power source"))?),
+                "force" => rule.force = value.trim().parse().map_err(|_| invalid("force flag"))?,
+                "pipeline" => rule.pipeline = Some(value.trim().to_string()).filter(|name| !name.is_empty()),
+                other => return Err(format!("Unknown matcher '{}' in rule '{}'", other, s)),
+            }
+        }
+
+        Ok(rule)
+    }
+}
+
+/// What the rules decided for a packet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub action: Action,
+    pub force: bool,
+    pub pipeline: Option<String>,
+}
+
+impl Decision {
+    /// The action with no rule options, as for packets that match no rule
+    pub fn plain(action: Action) -> Self {
+        Decision { action, force: false, pipeline: None }
+    }
+}
+
+/// Ordered rules; the first match decides the action, otherwise `default` applies
+#[derive(Clone, Debug)]
+pub struct Rules {
+    pub rules: Vec<Rule>,
+    pub default: Action,
+    pub supply: Supply,
+    pub hosts: Hosts,
+}
+
+impl Rules {
+    pub fn new(rules: Vec<Rule>, default: Action, supply: Supply, hosts: Hosts) -> Self {
+        Rules { rules, default, supply, hosts }
+    }
+
+    /// Whether any rule has a `power=` matcher, so the power source needs reading
+    pub fn reads_power(&self) -> bool {
+        self.rules.iter().any(|rule| rule.power.is_some())
+    }
+
+    /// Hostnames used in `from=` matchers, which need resolving
+    pub fn hostnames(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .rules
+            .iter()
+            .filter_map(|rule| match &rule.from {
+                Some(Sender::Host(name)) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn decide(&self, sender: IpAddr, mac: &MacAddr, port: u16) -> Decision {
+        // Read the power source at most once per packet, and only if a rule asks for it
+        let source = std::cell::OnceCell::new();
+        let power = || *source.get_or_init(|| self.supply.source());
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(sender, &self.hosts, mac, port, power))
+            .map_or(Decision::plain(self.default), |rule| Decision {
+                action: rule.action,
+                force: rule.force,
+                pipeline: rule.pipeline.clone(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn on_ac() -> PowerSource {
+        PowerSource::Ac
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let rule: Rule = "poweroff:from=192.168.1.0/24, mac=aa:bb:cc:dd:ee:ff,port=9".parse().unwrap();
+        assert_eq!(rule.action, Action::Poweroff);
+        assert_eq!(rule.mac, Some(MAC));
+        assert_eq!(rule.port, Some(9));
+        assert!(rule.matches(ip("192.168.1.7"), &Hosts::default(), &MAC, 9, on_ac));
+        assert!(!rule.matches(ip("192.168.2.7"), &Hosts::default(), &MAC, 9, on_ac));
+        assert!(!rule.matches(ip("192.168.1.7"), &Hosts::default(), &MAC, 10, on_ac));
+
+        let any: Rule = "hibernate".parse().unwrap();
+        assert!(any.matches(ip("::1"), &Hosts::default(), &MacAddr::ZERO, 1, || unreachable!()));
+        assert!(!any.force);
+
+        assert!("explode".parse::<Rule>().is_err());
+        assert!("suspend:from=no_where".parse::<Rule>().is_err());
+        assert!("suspend:from=192.168.1.300".parse::<Rule>().is_err());
+        assert!("suspend:via=eth0".parse::<Rule>().is_err());
+        assert!("suspend:port".parse::<Rule>().is_err());
+        assert!("suspend:power=solar".parse::<Rule>().is_err());
+        assert!("suspend:force=maybe".parse::<Rule>().is_err());
+        assert_eq!("suspend:pipeline=nightly".parse::<Rule>().unwrap().pipeline.as_deref(), Some("nightly"));
+    }
+
+    #[test]
+    fn test_power_rules() {
+        let forced: Rule = "poweroff:from=10.0.0.5,power=battery,force=true".parse().unwrap();
+        assert_eq!(forced.power, Some(PowerSource::Battery));
+        assert!(forced.force);
+        assert!(forced.matches(ip("10.0.0.5"), &Hosts::default(), &MAC, 9, || PowerSource::Battery));
+        assert!(!forced.matches(ip("10.0.0.5"), &Hosts::default(), &MAC, 9, on_ac));
+
+        let mut supply = Supply::default();
+        supply.sysfs = "/nonexistent".into();
+        let rules = Rules::new(vec![forced, "ignore:power=ac".parse().unwrap()], Action::Suspend, supply, Hosts::default());
+        assert_eq!(
+            rules.decide(ip("10.0.0.5"), &MAC, 9),
+            Decision::plain(Action::Ignore)
+        );
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let rules = Rules::new(
+            vec![
+                "poweroff:from=10.0.0.5".parse().unwrap(),
+                "ignore:from=10.0.0.0/8".parse().unwrap(),
+            ],
+            Action::Suspend,
+            Supply::default(),
+            Hosts::default(),
+        );
+
+        assert_eq!(rules.decide(ip("10.0.0.5"), &MAC, 10).action, Action::Poweroff);
+        assert_eq!(rules.decide(ip("10.1.2.3"), &MAC, 10).action, Action::Ignore);
+        assert_eq!(rules.decide(ip("192.168.1.2"), &MAC, 10).action, Action::Suspend);
+    }
+
+    #[test]
+    fn test_hostname_rules() {
+        let rule: Rule = "hibernate:from=My-Phone.lan.".parse().unwrap();
+        assert_eq!(rule.from, Some(Sender::Host("my-phone.lan".to_string())));
+
+        let rules = Rules::new(vec![rule, "ignore:from=other".parse().unwrap()], Action::Suspend, Supply::default(), Hosts::default());
+        assert_eq!(rules.hostnames(), ["my-phone.lan", "other"]);
+        // Unresolved names match nobody
+        assert_eq!(rules.decide(ip("192.168.1.42"), &MAC, 10).action, Action::Suspend);
+    }
+}
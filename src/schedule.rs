@@ -0,0 +1,203 @@
+use crate::clock::LocalTime;
+use crate::rules::Action;
+use clap::ValueEnum;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A set of allowed values for one cron field, as a bitmask
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Field {
+    bits: u64,
+    /// Written as `*`, which matters for the day-of-month/day-of-week rule
+    any: bool,
+}
+
+impl Field {
+    fn parse(s: &str, min: u32, max: u32) -> Result<Field, String> {
+        let invalid = || format!("Invalid cron field '{}' (expected values {}-{})", s, min, max);
+        let mut bits = 0u64;
+        for part in s.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+                None => (part, 1),
+            };
+            let (start, end) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((start, end)) => (
+                        start.parse().map_err(|_| invalid())?,
+                        end.parse().map_err(|_| invalid())?,
+                    ),
+                    // `N/step` runs from N to the end of the range, as in Vixie cron
+                    None => {
+                        let start = range.parse().map_err(|_| invalid())?;
+                        (start, if part.contains('/') { max } else { start })
+                    }
+                },
+            };
+            if step == 0 || start < min || end > max || start > end {
+                return Err(invalid());
+            }
+            for value in (start..=end).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(Field { bits, any: s == "*" })
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.bits & (1 << value) != 0
+    }
+}
+
+/// A five-field cron expression: minute, hour, day of month, month and day of week
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cron {
+    minute: Field,
+    hour: Field,
+    day: Field,
+    month: Field,
+    weekday: Field,
+}
+
+impl Cron {
+    pub fn matches(&self, time: &LocalTime) -> bool {
+        // When both day fields are restricted, either may match (as in cron)
+        let day = match (self.day.any, self.weekday.any) {
+            (false, false) => self.day.contains(time.day) || self.weekday.contains(time.weekday),
+            _ => self.day.contains(time.day) && self.weekday.contains(time.weekday),
+        };
+        self.minute.contains(time.minute) && self.hour.contains(time.hour) && self.month.contains(time.month) && day
+    }
+}
+
+impl std::str::FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("Invalid cron expression '{}' (expected 5 fields)", s));
+        };
+        let mut weekday = Field::parse(weekday, 0, 7)?;
+        // 7 is another name for Sunday
+        if weekday.contains(7) {
+            weekday.bits = (weekday.bits & !(1 << 7)) | 1;
+        }
+        Ok(Cron {
+            minute: Field::parse(minute, 0, 59)?,
+            hour: Field::parse(hour, 0, 23)?,
+            day: Field::parse(day, 1, 31)?,
+            month: Field::parse(month, 1, 12)?,
+            weekday,
+        })
+    }
+}
+
+/// A scheduled trigger, written as a cron expression optionally followed by an action,
+/// e.g. `30 1 * * *` or `0 23 * * 1-5 hibernate`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub text: String,
+    pub cron: Cron,
+    /// None uses `--default-action`
+    pub action: Option<Action>,
+}
+
+impl std::str::FromStr for Entry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let (cron, action) = match fields.len() {
+            5 => (fields.join(" "), None),
+            6 => (
+                fields[..5].join(" "),
+                Some(
+                    Action::from_str(fields[5], true)
+                        .map_err(|_| format!("Invalid action '{}' in schedule '{}'", fields[5], s))?,
+                ),
+            ),
+            _ => return Err(format!("Invalid schedule '{}' (expected MIN HOUR DAY MONTH WEEKDAY [ACTION])", s)),
+        };
+        Ok(Entry { text: s.trim().to_string(), cron: cron.parse()?, action })
+    }
+}
+
+/// Scheduled triggers, checked once per local minute
+pub struct Schedule {
+    entries: Vec<Entry>,
+    /// Unix minute last checked, so no minute fires twice or is skipped when `due` is restarted
+    checked: Option<u64>,
+}
+
+impl Schedule {
+    pub fn new(entries: Vec<Entry>) -> Self {
+        Schedule { entries, checked: None }
+    }
+
+    /// Wait for the next minute an entry matches. Never returns without entries. Safe to
+    /// drop and call again, e.g. in `select!`.
+    pub async fn due(&mut self) -> Entry {
+        if self.entries.is_empty() {
+            std::future::pending::<()>().await;
+        }
+        loop {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            let minute = now.as_secs() / 60;
+            if self.checked != Some(minute) {
+                self.checked = Some(minute);
+                let time = LocalTime::from_unix((minute * 60) as libc::time_t);
+                if let Some(entry) = self.entries.iter().find(|entry| entry.cron.matches(&time)) {
+                    return entry.clone();
+                }
+            }
+            let next = Duration::from_secs((minute + 1) * 60);
+            tokio::time::sleep(next.saturating_sub(now)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01 was a Monday
+    fn at(day: u32, hour: u32, minute: u32) -> LocalTime {
+        LocalTime { second: 0, minute, hour, day, month: 1, year: 2024, weekday: day % 7 }
+    }
+
+    #[test]
+    fn test_cron_fields() {
+        let cron: Cron = "*/15 1-3 * * *".parse().unwrap();
+        assert!(cron.matches(&at(1, 1, 0)));
+        assert!(cron.matches(&at(1, 3, 45)));
+        assert!(!cron.matches(&at(1, 3, 50)));
+        assert!(!cron.matches(&at(1, 4, 0)));
+
+        let weekdays: Cron = "30 23 * * 1-5".parse().unwrap();
+        assert!(weekdays.matches(&at(5, 23, 30)));
+        assert!(!weekdays.matches(&at(6, 23, 30)));
+
+        let sunday: Cron = "0 0 * * 7".parse().unwrap();
+        assert!(sunday.matches(&at(7, 0, 0)));
+    }
+
+    #[test]
+    fn test_restricted_days_match_either() {
+        let cron: Cron = "0 2 15 * 0".parse().unwrap();
+        assert!(cron.matches(&at(15, 2, 0)));
+        assert!(cron.matches(&at(14, 2, 0)));
+        assert!(!cron.matches(&at(13, 2, 0)));
+    }
+
+    #[test]
+    fn test_parse_entry() {
+        let entry: Entry = "0 23 * * 1-5 hibernate".parse().unwrap();
+        assert_eq!(entry.action, Some(Action::Hibernate));
+        assert_eq!("30 1 * * *".parse::<Entry>().unwrap().action, None);
+
+        for s in ["30 1 * *", "60 1 * * *", "0 1 * * * nap", "*/0 * * * *", "5-1 * * * *", "0 0 0 * *"] {
+            assert!(s.parse::<Entry>().is_err(), "{}", s);
+        }
+    }
+}
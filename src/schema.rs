@@ -0,0 +1,281 @@
+use crate::json::quote;
+use crate::settings::{Kind, Setting};
+
+/// The value a key in a `[<table>.<name>]` config table takes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Type {
+    String,
+    Integer,
+    Boolean,
+    Strings,
+    Integers,
+}
+
+/// A key of a config table, with its type and description
+type Key = (&'static str, Type, &'static str);
+
+/// The named config tables, with their description and keys
+const TABLES: &[(&str, &str, &[Key])] = &[
+    (
+        "action",
+        "A named action v2 packets can select; exactly one of `power` and `run`",
+        &[
+            ("power", Type::String, "Power action: suspend, hibernate or poweroff"),
+            ("pipeline", Type::String, "Pipeline to run around the power action"),
+            ("run", Type::String, "Shell command run instead of a power action"),
+            ("timeout", Type::Integer, "Seconds the command may run"),
+        ],
+    ),
+    (
+        "peer",
+        "A machine that can be woken with `sol wake NAME`",
+        &[
+            ("mac", Type::String, "MAC address of the peer"),
+            ("target", Type::String, "IPv4 (broadcast) address or interface the packet goes to"),
+            ("port", Type::Integer, "Port the packet goes to"),
+        ],
+    ),
+    (
+        "step",
+        "A shell command or power profile switch pipelines can run",
+        &[
+            ("run", Type::String, "Shell command"),
+            ("power_profile", Type::String, "power-profiles-daemon profile to switch to instead, restored after resume"),
+            ("timeout", Type::Integer, "Seconds the command may run"),
+            ("on_failure", Type::String, "abort or continue"),
+        ],
+    ),
+    (
+        "pipeline",
+        "Steps run around the power action",
+        &[
+            ("before", Type::Strings, "Steps run before the power action"),
+            ("after", Type::Strings, "Steps run after resume"),
+        ],
+    ),
+    (
+        "media",
+        "A Plex or Jellyfin server whose active playback or transcode sessions block suspend",
+        &[
+            ("type", Type::String, "plex or jellyfin"),
+            ("url", Type::String, "http:// URL of the server, e.g. http://localhost:32400"),
+            ("token", Type::String, "X-Plex-Token (plex) or API key (jellyfin)"),
+            ("ignore_paused", Type::Boolean, "Let paused sessions through"),
+        ],
+    ),
+    (
+        "notify",
+        "A notification backend that receives suspends, resumes and failures",
+        &[
+            ("type", Type::String, "ntfy, gotify, pushover or webhook"),
+            ("url", Type::String, "http:// URL of the topic, server or endpoint"),
+            ("token", Type::String, "Access token (ntfy), application token (gotify, pushover)"),
+            ("user", Type::String, "User key (pushover)"),
+            ("priority", Type::String, "min, low, default, high or urgent; failures are sent at high or above"),
+            ("click", Type::String, "URL opened when the notification is tapped"),
+            ("events", Type::Strings, "Events to notify about"),
+        ],
+    ),
+    (
+        "profile",
+        "Policy overrides, optionally active during a daily time window",
+        &[
+            ("hours", Type::String, "Daily window as HH:MM-HH:MM"),
+            ("quorum", Type::Integer, "Packets needed within the quorum window"),
+            ("quorum_window", Type::Integer, "Seconds the quorum packets must arrive within"),
+            ("confirm", Type::Boolean, "Require the confirmation handshake"),
+            ("grace", Type::Integer, "Seconds to wait before suspending"),
+            ("warn_users", Type::Boolean, "Warn logged-in users during the grace period"),
+            ("inhibit_share_clients", Type::Boolean, "Block suspend while SMB or NFS clients are connected"),
+            ("inhibit_ports", Type::Integers, "Block suspend while these TCP ports have clients"),
+            ("ignore_desktop_inhibitors", Type::Boolean, "Ignore logind inhibitors taken by desktop applications"),
+            ("inhibit_session_activity", Type::Boolean, "Block suspend while a user session is active"),
+            ("min_idle", Type::Integer, "Minutes users must have been idle"),
+        ],
+    ),
+];
+
+fn type_schema(kind: Type) -> &'static str {
+    match kind {
+        Type::String => r#""type": "string""#,
+        Type::Integer => r#""type": "integer", "minimum": 0"#,
+        Type::Boolean => r#""type": "boolean""#,
+        Type::Strings => r#""type": "array", "items": { "type": "string" }"#,
+        Type::Integers => r#""type": "array", "items": { "type": "integer", "minimum": 0 }"#,
+    }
+}
+
+/// The schema of one value of a setting. Values with a numeric default are integers; the
+/// config file also accepts integers where the command line takes any other text.
+fn value_schema(setting: &Setting) -> String {
+    if !setting.values.is_empty() {
+        let values: Vec<String> = setting.values.iter().map(|value| quote(value)).collect();
+        return format!(r#""type": "string", "enum": [{}]"#, values.join(", "));
+    }
+    match &setting.default {
+        Some(default) if default.parse::<u64>().is_ok() => r#""type": "integer", "minimum": 0"#.to_string(),
+        _ => r#""type": ["string", "integer"]"#.to_string(),
+    }
+}
+
+fn setting_schema(setting: &Setting) -> String {
+    let mut fields = Vec::new();
+    if !setting.help.is_empty() {
+        fields.push(format!(r#""description": {}"#, quote(&setting.help)));
+    }
+    match setting.kind {
+        Kind::Flag => fields.push(r#""type": "boolean""#.to_string()),
+        Kind::Value => {
+            fields.push(value_schema(setting));
+            if let Some(default) = &setting.default {
+                let default = if default.parse::<u64>().is_ok() && setting.values.is_empty() { default.clone() } else { quote(default) };
+                fields.push(format!(r#""default": {}"#, default));
+            }
+        }
+        Kind::Repeated => fields.push(format!(r#""type": "array", "items": {{ {} }}"#, value_schema(setting))),
+    }
+    format!("{{ {} }}", fields.join(", "))
+}
+
+/// A JSON Schema (draft 2020-12) for the config file: the top-level keys mirror `settings`,
+/// and `[<table>.<name>]` tables are described by `TABLES`
+pub fn config_schema(settings: &[Setting]) -> String {
+    let mut properties = Vec::new();
+    for setting in settings.iter().filter(|setting| setting.long != "config") {
+        let key = setting.key();
+        let schema = setting_schema(setting);
+        // `peer = [...]` and `[peer.<name>]` tables can share a key
+        let schema = match TABLES.iter().find(|(table, _, _)| *table == key) {
+            Some(_) => format!(r##"{{ "anyOf": [{}, {{ "type": "object", "additionalProperties": {{ "$ref": "#/$defs/{}" }} }}] }}"##, schema, key),
+            None => schema,
+        };
+        properties.push(format!("    {}: {}", quote(&key), schema));
+    }
+    for (table, _, _) in TABLES.iter().filter(|(table, _, _)| !settings.iter().any(|setting| setting.key() == *table)) {
+        properties.push(format!(
+            r##"    {}: {{ "type": "object", "additionalProperties": {{ "$ref": "#/$defs/{}" }} }}"##,
+            quote(table),
+            table
+        ));
+    }
+
+    let mut defs = Vec::new();
+    for (table, description, keys) in TABLES {
+        let keys: Vec<String> = keys
+            .iter()
+            .map(|(key, kind, description)| format!(r#"        {}: {{ "description": {}, {} }}"#, quote(key), quote(description), type_schema(*kind)))
+            .collect();
+        defs.push(format!(
+            "    {}: {{\n      \"description\": {},\n      \"type\": \"object\",\n      \"additionalProperties\": false,\n      \"properties\": {{\n{}\n      }}\n    }}",
+            quote(table),
+            quote(description),
+            keys.join(",\n")
+        ));
+    }
+
+    format!(
+        "{{\n  \"$schema\": \"https://json-schema.org/draft/2020-12/schema\",\n  \"title\": \"sol config file\",\n  \"type\": \"object\",\n  \"additionalProperties\": false,\n  \"properties\": {{\n{}\n  }},\n  \"$defs\": {{\n{}\n  }}\n}}\n",
+        properties.join(",\n"),
+        defs.join(",\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::NamedActions;
+    use crate::config::Config;
+    use crate::json::Json;
+    use crate::media;
+    use crate::notify::{self, Notifiers};
+    use crate::peers::Peers;
+    use crate::pipeline::Pipelines;
+    use crate::policy::{Policy, Profiles};
+    use std::time::Duration;
+
+    fn setting(long: &str, kind: Kind, default: Option<&str>, values: &[&str]) -> Setting {
+        Setting {
+            long: long.to_string(),
+            short: None,
+            kind,
+            help: format!("Help for \"{}\"", long),
+            default: default.map(str::to_string),
+            values: values.iter().map(|value| value.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_schema() {
+        let settings = [
+            setting("grace", Kind::Value, Some("0"), &[]),
+            setting("backend", Kind::Value, Some("logind"), &["logind", "sysfs"]),
+            setting("seccomp", Kind::Flag, None, &[]),
+            setting("peer", Kind::Repeated, None, &[]),
+            setting("config", Kind::Value, None, &[]),
+        ];
+        let schema = Json::parse(&config_schema(&settings)).unwrap();
+        let properties = schema.get("properties").unwrap();
+        let grace = properties.get("grace").unwrap();
+        assert_eq!(grace.get("type").and_then(Json::as_str), Some("integer"));
+        assert_eq!(grace.get("description").and_then(Json::as_str), Some("Help for \"grace\""));
+        assert_eq!(properties.get("backend").unwrap().get("default").and_then(Json::as_str), Some("logind"));
+        assert_eq!(properties.get("seccomp").unwrap().get("type").and_then(Json::as_str), Some("boolean"));
+        assert!(properties.get("peer").unwrap().get("anyOf").is_some());
+        assert!(properties.get("config").is_none());
+        assert!(properties.get("profile").is_some());
+        assert!(schema.get("$defs").unwrap().get("step").unwrap().get("properties").unwrap().get("on_failure").is_some());
+    }
+
+    /// Every key the schema lists must be one the config parsers accept
+    #[test]
+    fn test_table_keys_are_accepted() {
+        let value = |kind: Type, key: &str| match (kind, key) {
+            (_, "power") => "\"suspend\"".to_string(),
+            (_, "mac") => "\"aa:bb:cc:dd:ee:ff\"".to_string(),
+            (_, "on_failure") => "\"continue\"".to_string(),
+            (_, "hours") => "\"22:00-06:00\"".to_string(),
+            (_, "target") => "\"192.168.1.255\"".to_string(),
+            (_, "type") => "\"pushover\"".to_string(),
+            (_, "url") => "\"http://localhost:8443/1/messages.json\"".to_string(),
+            (_, "priority") => "\"high\"".to_string(),
+            (_, "events") => "[\"resumed\"]".to_string(),
+            (Type::String, _) => "\"x\"".to_string(),
+            (Type::Integer, _) => "5".to_string(),
+            (Type::Boolean, _) => "true".to_string(),
+            (Type::Strings, _) => "[\"x\"]".to_string(),
+            (Type::Integers, _) => "[445]".to_string(),
+        };
+        let mut text = "[step.x]\nrun = \"true\"\n\n[pipeline.p]\n".to_string();
+        for (table, _, keys) in TABLES {
+            text += &format!("[{}.t]\n", table);
+            for (key, kind, _) in keys.iter().filter(|(key, _, _)| !matches!((*table, *key), ("action", "run") | ("step", "power_profile"))) {
+                let value = match (*table, *key) {
+                    ("action", "pipeline") => "\"p\"".to_string(),
+                    ("media", "type") => "\"plex\"".to_string(),
+                    _ => value(*kind, key),
+                };
+                text += &format!("{} = {}\n", key, value);
+            }
+        }
+        let config = Config::parse(&text).unwrap();
+        let timeout = Duration::from_secs(1);
+        NamedActions::from_config(&config, timeout).unwrap();
+        Peers::from_config(Vec::new(), &config).unwrap();
+        Pipelines::from_config(&config, timeout).unwrap();
+        Notifiers::from_config(Vec::new(), &config, &notify::backends()).unwrap();
+        media::servers(&config).unwrap();
+        let policy = Policy {
+            quorum: 1,
+            quorum_window: 10,
+            confirm: false,
+            grace: 0,
+            warn_users: false,
+            inhibit_share_clients: false,
+            inhibit_ports: Vec::new(),
+            ignore_desktop_inhibitors: true,
+            inhibit_session_activity: false,
+            min_idle: None,
+        };
+        Profiles::from_config(policy, &config).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Set by `seccomp(2)` to apply the filter to every thread, not just the caller
+const SECCOMP_FILTER_FLAG_TSYNC: libc::c_ulong = 1;
+
+/// Offsets of `nr` and `arch` in `struct seccomp_data`
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+
+/// Which otherwise denied syscalls the configured features need
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Needs {
+    /// Taking network filesystems offline runs mount/umount, which may also use the keyring
+    pub mounts: bool,
+    /// busybox `poweroff` calls reboot(2) itself
+    pub reboot: bool,
+}
+
+/// Syscalls refused once the filter is installed. Helper programs the daemon runs
+/// (systemctl, mount, container CLIs, ...) inherit the filter, so rather than an allowlist
+/// this lists what nothing here has a reason to do: debug other processes, load kernel
+/// code, manage swap and the like. Everything else stays allowed, parsers included.
+fn denied(needs: Needs) -> Vec<libc::c_long> {
+    let mut syscalls = vec![
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_kexec_load,
+        libc::SYS_kexec_file_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_pivot_root,
+        libc::SYS_acct,
+        libc::SYS_quotactl,
+    ];
+    #[cfg(target_arch = "x86_64")]
+    syscalls.extend([libc::SYS_iopl, libc::SYS_ioperm]);
+    if !needs.mounts {
+        syscalls.extend([
+            libc::SYS_mount,
+            libc::SYS_umount2,
+            libc::SYS_add_key,
+            libc::SYS_request_key,
+            libc::SYS_keyctl,
+        ]);
+    }
+    if !needs.reboot {
+        syscalls.push(libc::SYS_reboot);
+    }
+    syscalls
+}
+
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter { code: code as u16, jt: 0, jf: 0, k }
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code: code as u16, jt, jf, k }
+}
+
+/// Build the BPF program: refuse foreign architectures and the denied syscalls with EPERM
+fn program(syscalls: &[libc::c_long]) -> Vec<libc::sock_filter> {
+    let deny = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    let mut filter = vec![
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, ARCH_OFFSET),
+        jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, AUDIT_ARCH, 1, 0),
+        stmt(libc::BPF_RET | libc::BPF_K, deny),
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, NR_OFFSET),
+    ];
+    // x32 syscalls share the x86_64 arch value but set this bit; refuse them all
+    #[cfg(target_arch = "x86_64")]
+    filter.push(jump(
+        libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+        X32_SYSCALL_BIT,
+        syscalls.len() as u8 + 1,
+        0,
+    ));
+    for (i, nr) in syscalls.iter().enumerate() {
+        // On a match, skip the remaining checks and the allow to land on the deny
+        let skip = (syscalls.len() - i) as u8;
+        filter.push(jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, *nr as u32, skip, 0));
+    }
+    filter.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    filter.push(stmt(libc::BPF_RET | libc::BPF_K, deny));
+    filter
+}
+
+/// Install the filter on all threads of the process. Cannot be undone.
+pub fn apply(needs: Needs) -> std::io::Result<()> {
+    let filter = program(&denied(needs));
+    let prog = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut libc::sock_filter,
+    };
+
+    // SAFETY: prctl and seccomp only read the arguments passed; `filter` outlives the calls
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            SECCOMP_FILTER_FLAG_TSYNC,
+            &prog as *const libc::sock_fprog,
+        ) != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program_jumps_to_deny() {
+        let syscalls = [libc::SYS_ptrace, libc::SYS_bpf];
+        let filter = program(&syscalls);
+        let deny = filter.len() - 1;
+        let first = filter.len() - 2 - syscalls.len();
+
+        for (i, nr) in syscalls.iter().enumerate() {
+            let check = &filter[first + i];
+            assert_eq!(check.k, *nr as u32);
+            assert_eq!(first + i + 1 + check.jt as usize, deny);
+        }
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(first + filter[first - 1].jt as usize, deny);
+        assert_eq!(filter[deny - 1].k, libc::SECCOMP_RET_ALLOW);
+        assert_eq!(filter[deny].k, libc::SECCOMP_RET_ERRNO | libc::EPERM as u32);
+    }
+
+    #[test]
+    fn test_needs_allow_syscalls() {
+        let strict = denied(Needs::default());
+        assert!(strict.contains(&libc::SYS_mount));
+        assert!(strict.contains(&libc::SYS_reboot));
+
+        let relaxed = denied(Needs { mounts: true, reboot: true });
+        assert!(!relaxed.contains(&libc::SYS_mount));
+        assert!(!relaxed.contains(&libc::SYS_keyctl));
+        assert!(!relaxed.contains(&libc::SYS_reboot));
+        assert!(relaxed.contains(&libc::SYS_ptrace));
+    }
+}
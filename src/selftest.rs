@@ -0,0 +1,131 @@
+use crate::mac::MacAddr;
+use crate::netif::{self, IpNetwork};
+use sol::packet;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Header of test packets, which the daemon checks like a trigger and answers with the
+/// verdict but never acts on
+pub const PROBE_PACKET_HEADER: [u8; 6] = [0x5A; 6];
+
+/// How long to wait for the daemon's reply to each probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn probe_packet(mac: &MacAddr) -> Vec<u8> {
+    packet::encode(&PROBE_PACKET_HEADER, &mac.0).to_vec()
+}
+
+/// Destinations a real trigger might arrive on: loopback, the limited broadcast address
+/// and each interface's subnet broadcast address
+fn destinations(port: u16) -> Vec<(String, SocketAddr)> {
+    let mut destinations = vec![
+        ("loopback".to_string(), SocketAddr::from((Ipv4Addr::LOCALHOST, port))),
+        ("broadcast".to_string(), SocketAddr::from((Ipv4Addr::BROADCAST, port))),
+    ];
+    for iface in netif::interfaces() {
+        if iface.is_loopback() || !iface.is_up() {
+            continue;
+        }
+        for ip in &iface.ips {
+            if let IpNetwork::V4(net) = ip {
+                destinations.push((
+                    format!("broadcast on {}", iface.name),
+                    SocketAddr::from((net.broadcast(), port)),
+                ));
+            }
+        }
+    }
+    destinations
+}
+
+/// Send one test packet and return the daemon's verdict
+pub async fn probe(destination: SocketAddr, packet: &[u8]) -> Result<String, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+    socket.set_broadcast(true).map_err(|e| e.to_string())?;
+    socket
+        .send_to(packet, destination)
+        .await
+        .map_err(|e| format!("send failed: {}", e))?;
+
+    let mut buf = [0u8; 512];
+    match tokio::time::timeout(PROBE_TIMEOUT, socket.recv(&mut buf)).await {
+        Ok(Ok(len)) => Ok(String::from_utf8_lossy(&buf[..len]).into_owned()),
+        Ok(Err(e)) => Err(format!("receive failed: {}", e)),
+        Err(_) => Err(format!("no reply within {}s", PROBE_TIMEOUT.as_secs())),
+    }
+}
+
+/// Send a probe for `mac` to the daemon on `port` by every path, returning each path's
+/// name and destination with the daemon's verdict
+pub async fn probe_all(port: u16, mac: &MacAddr) -> Vec<(String, SocketAddr, Result<String, String>)> {
+    let packet = probe_packet(mac);
+    let mut results = Vec::new();
+    for (name, destination) in destinations(port) {
+        results.push((name, destination, probe(destination, &packet).await));
+    }
+    results
+}
+
+/// Send probes for `mac` to the daemon on `port` by every path and report what arrived.
+/// Returns whether every probe was received and validated.
+pub async fn run(port: u16, mac: &MacAddr) -> bool {
+    match std::net::UdpSocket::bind(("0.0.0.0", port)) {
+        Ok(_) => println!("Warning: nothing is listening on UDP port {}; is the daemon running?", port),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => println!("UDP port {} is bound", port),
+        Err(_) => {}
+    }
+
+    let mut passed = true;
+    for (name, destination, result) in probe_all(port, mac).await {
+        match result {
+            Ok(reply) if !reply.starts_with("error") => println!("PASS {} ({}): {}", name, destination, reply),
+            Ok(reply) => {
+                passed = false;
+                println!("FAIL {} ({}): received but rejected: {}", name, destination, reply);
+            }
+            Err(e) => {
+                passed = false;
+                println!("FAIL {} ({}): {}", name, destination, e);
+            }
+        }
+    }
+
+    if !passed {
+        println!();
+        println!("A probe with no reply never reached the daemon or its reply was dropped. Check that the");
+        println!("daemon listens on port {} on all addresses and that the firewall accepts UDP to it.", port);
+    }
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_destinations_include_loopback_and_broadcast() {
+        let destinations = destinations(10);
+        assert_eq!(destinations[0].1, SocketAddr::from(([127, 0, 0, 1], 10)));
+        assert_eq!(destinations[1].1, SocketAddr::from(([255, 255, 255, 255], 10)));
+    }
+
+    #[tokio::test]
+    async fn test_probe_reply() {
+        let daemon = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let destination = daemon.local_addr().unwrap();
+        let packet = probe_packet(&MacAddr([0xAA; 6]));
+
+        let responder = tokio::spawn(async move {
+            let mut buf = [0u8; 128];
+            let (len, from) = daemon.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..6], &PROBE_PACKET_HEADER);
+            assert_eq!(len, 102);
+            daemon.send_to(b"ok", from).await.unwrap();
+        });
+
+        assert_eq!(probe(destination, &packet).await.unwrap(), "ok");
+        responder.await.unwrap();
+    }
+}
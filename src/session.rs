@@ -0,0 +1,97 @@
+use crate::dbus::{self, Bus};
+use crate::desktop::{self, GraphicalSession};
+use crate::inhibit::{self, COMMAND_TIMEOUT, Inhibitor};
+use std::process::Command;
+
+/// PowerDevil PolicyAgent flags: InterruptSession (1) | ChangeScreenSettings (4).
+/// Presentation mode, screen sharing and video playback hold these in Plasma.
+const KDE_SESSION_POLICIES: u32 = 5;
+
+/// Blocks suspend while a desktop session is presenting, screen sharing or showing a
+/// full-screen application
+pub struct SessionActivity;
+
+impl Inhibitor for SessionActivity {
+    fn name(&self) -> &str {
+        "session"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        for session in desktop::graphical_sessions()? {
+            if kde_policy_inhibited(&session.user) {
+                return Ok(Some(format!(
+                    "presentation or screen sharing active in {}'s Plasma session",
+                    session.user
+                )));
+            }
+
+            if has_fullscreen_window(&session) {
+                return Ok(Some(format!("full-screen application in {}'s session", session.user)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn kde_policy_inhibited(user: &str) -> bool {
+    dbus::call_bool(
+        &Bus::User(user.to_string()),
+        "org.kde.Solid.PowerManagement.PolicyAgent",
+        "/org/kde/Solid/PowerManagement/PolicyAgent",
+        "org.kde.Solid.PowerManagement.PolicyAgent",
+        "HasInhibition",
+        Some(KDE_SESSION_POLICIES),
+    )
+    .unwrap_or(false)
+}
+
+/// Check the focused window of an X11 session for _NET_WM_STATE_FULLSCREEN.
+/// Wayland compositors do not expose other clients' windows, so those sessions
+/// rely on the inhibits checked by the desktop inhibitor instead.
+fn has_fullscreen_window(session: &GraphicalSession) -> bool {
+    let Some(display) = &session.display else {
+        return false;
+    };
+
+    let Some(window) = xprop(&session.user, display, &["-root", "_NET_ACTIVE_WINDOW"])
+        .and_then(|output| active_window(&output))
+    else {
+        return false;
+    };
+
+    xprop(&session.user, display, &["-id", &window, "_NET_WM_STATE"])
+        .is_some_and(|output| output.contains("_NET_WM_STATE_FULLSCREEN"))
+}
+
+fn xprop(user: &str, display: &str, args: &[&str]) -> Option<String> {
+    let mut cmd = Command::new("runuser");
+    cmd.args(["-u", user, "--", "env"]).arg(format!("DISPLAY={}", display)).arg("xprop").args(args);
+    let output = inhibit::output(&mut cmd, COMMAND_TIMEOUT).ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse `_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007`
+fn active_window(output: &str) -> Option<String> {
+    let id = output.split_whitespace().last()?;
+    (id.starts_with("0x") && id != "0x0").then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active_window() {
+        assert_eq!(
+            active_window("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007\n"),
+            Some("0x3a00007".to_string())
+        );
+        assert_eq!(active_window("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x0\n"), None);
+        assert_eq!(active_window("_NET_ACTIVE_WINDOW:  not found.\n"), None);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::config::{Config, Table, Value};
+use clap::ArgAction;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// How an option takes its value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Flag,
+    Value,
+    Repeated,
+}
+
+/// A long option that can also come from the environment or the config file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Setting {
+    pub long: String,
+    pub short: Option<char>,
+    pub kind: Kind,
+    pub help: String,
+    pub default: Option<String>,
+    /// The accepted values, for options that take one of a fixed set
+    pub values: Vec<String>,
+}
+
+impl Setting {
+    /// `SOL_` followed by the option name, e.g. `SOL_PRE_SUSPEND` for `--pre-suspend`
+    fn env_name(&self) -> String {
+        format!("SOL_{}", self.long.replace('-', "_").to_uppercase())
+    }
+
+    /// Config file key, e.g. `pre_suspend` for `--pre-suspend`
+    pub fn key(&self) -> String {
+        self.long.replace('-', "_")
+    }
+
+    fn flag(&self) -> OsString {
+        format!("--{}", self.long).into()
+    }
+
+    fn given(&self, argv: &[OsString]) -> bool {
+        let flag = format!("--{}", self.long);
+        let prefix = format!("{}=", flag);
+        let short = self.short.map(|c| format!("-{}", c));
+        argv.iter().any(|arg| {
+            let arg = arg.to_string_lossy();
+            arg == flag
+                || arg.starts_with(&prefix)
+                || short.as_ref().is_some_and(|short| arg.starts_with(short.as_str()))
+        })
+    }
+}
+
+/// The long options of `command` that layering applies to
+pub fn settings(command: &clap::Command) -> Vec<Setting> {
+    command
+        .get_arguments()
+        .filter_map(|arg| {
+            let kind = match arg.get_action() {
+                ArgAction::SetTrue => Kind::Flag,
+                ArgAction::Set => Kind::Value,
+                ArgAction::Append => Kind::Repeated,
+                _ => return None,
+            };
+            let values = match kind {
+                Kind::Flag => Vec::new(),
+                _ => arg.get_possible_values().iter().map(|value| value.get_name().to_string()).collect(),
+            };
+            Some(Setting {
+                long: arg.get_long()?.to_string(),
+                short: arg.get_short(),
+                kind,
+                help: arg.get_help().map(|help| help.to_string()).unwrap_or_default(),
+                default: arg.get_default_values().first().map(|value| value.to_string_lossy().into_owned()),
+                values,
+            })
+        })
+        .collect()
+}
+
+fn truthy(name: &str, value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "" | "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("{}: expected true or false, got '{}'", name, value)),
+    }
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        Value::Array(_) => Err(format!("'{}' takes a single value", key)),
+    }
+}
+
+fn from_file(setting: &Setting, value: &Value) -> Result<Vec<OsString>, String> {
+    let key = setting.key();
+    Ok(match (setting.kind, value) {
+        (Kind::Flag, Value::Boolean(true)) => vec![setting.flag()],
+        (Kind::Flag, Value::Boolean(false)) => Vec::new(),
+        (Kind::Flag, _) => return Err(format!("'{}' must be true or false", key)),
+        (Kind::Repeated, Value::Array(values)) => {
+            let mut args = Vec::new();
+            for value in values {
+                args.extend([setting.flag(), scalar(&key, value)?.into()]);
+            }
+            args
+        }
+        _ => vec![setting.flag(), scalar(&key, value)?.into()],
+    })
+}
+
+/// Fill in options not on the command line from `SOL_*` variables, then from top-level keys
+/// of the config file. The command line wins over the environment, which wins over the file;
+/// a repeatable option takes its values from one source only.
+pub fn layer(
+    argv: Vec<OsString>,
+    settings: &[Setting],
+    env: impl Fn(&str) -> Option<String>,
+    file: Option<&Table>,
+) -> Result<Vec<OsString>, String> {
+    if let Some(table) = file
+        && let Some(key) = table.keys().find(|key| !settings.iter().any(|s| &s.key() == *key))
+    {
+        return Err(format!("unknown setting '{}'", key));
+    }
+
+    let mut extra = Vec::new();
+    for setting in settings {
+        if setting.given(&argv) {
+            continue;
+        }
+        if let Some(value) = env(&setting.env_name()) {
+            match setting.kind {
+                Kind::Flag if truthy(&setting.env_name(), &value)? => extra.push(setting.flag()),
+                Kind::Flag => {}
+                _ => extra.extend([setting.flag(), value.into()]),
+            }
+        } else if let Some(value) = file.and_then(|table| table.get(&setting.key())) {
+            extra.extend(from_file(setting, value)?);
+        }
+    }
+
+    let mut args = argv;
+    let at = args.len().min(1);
+    args.splice(at..at, extra);
+    Ok(args)
+}
+
+fn config_path(argv: &[OsString], env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let mut args = argv.iter().map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(|path| PathBuf::from(path.as_ref()));
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    env("SOL_CONFIG").map(PathBuf::from)
+}
+
+/// The process arguments with the environment and config file layered in
+pub fn layered(command: &clap::Command) -> Result<Vec<OsString>, String> {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let env = |name: &str| std::env::var(name).ok();
+    let config = match config_path(&argv, env) {
+        Some(path) => Some(Config::load(&path).map_err(|e| format!("{}: {}", path.display(), e))?),
+        None => None,
+    };
+    layer(argv, &settings(command), env, config.as_ref().and_then(|config| config.tables.get("")))
+        .map_err(|e| format!("Invalid configuration: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn settings() -> Vec<Setting> {
+        [("port", Kind::Value), ("rule", Kind::Repeated), ("seccomp", Kind::Flag), ("grace", Kind::Value)]
+            .iter()
+            .map(|(long, kind)| Setting {
+                long: long.to_string(),
+                short: (*long == "port").then_some('p'),
+                kind: *kind,
+                help: String::new(),
+                default: None,
+                values: Vec::new(),
+            })
+            .collect()
+    }
+
+    fn argv(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_precedence() {
+        let env: HashMap<&str, &str> = [("SOL_PORT", "9"), ("SOL_GRACE", "30"), ("SOL_SECCOMP", "yes")].into();
+        let config = Config::parse("port = 7\ngrace = 60\nrule = [\"ignore:from=10.0.0.0/8\", \"suspend\"]").unwrap();
+
+        let args = layer(
+            argv(&["sol", "--grace=5", "status"]),
+            &settings(),
+            |name| env.get(name).map(|v| v.to_string()),
+            config.tables.get(""),
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            argv(&[
+                "sol",
+                "--port",
+                "9",
+                "--rule",
+                "ignore:from=10.0.0.0/8",
+                "--rule",
+                "suspend",
+                "--seccomp",
+                "--grace=5",
+                "status"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_short_option_counts_as_given() {
+        let env = |name: &str| (name == "SOL_PORT").then(|| "7".to_string());
+        let args = layer(argv(&["sol", "-p9"]), &settings(), env, None).unwrap();
+        assert!(!args.contains(&OsString::from("--port")));
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let none = |_: &str| None;
+        let unknown = Config::parse("prot = 7").unwrap();
+        assert_eq!(
+            layer(argv(&["sol"]), &settings(), none, unknown.tables.get("")).unwrap_err(),
+            "unknown setting 'prot'"
+        );
+        let flag = Config::parse("seccomp = \"on\"").unwrap();
+        assert!(layer(argv(&["sol"]), &settings(), none, flag.tables.get("")).is_err());
+        assert!(layer(argv(&["sol"]), &settings(), |_| Some("maybe".to_string()), None).is_err());
+    }
+
+    #[test]
+    fn test_config_path() {
+        assert_eq!(config_path(&argv(&["sol", "--config", "/etc/sol.toml"]), |_| None), Some("/etc/sol.toml".into()));
+        assert_eq!(config_path(&argv(&["sol", "--config=a.toml"]), |_| None), Some("a.toml".into()));
+        assert_eq!(config_path(&argv(&["sol"]), |_| Some("b.toml".to_string())), Some("b.toml".into()));
+    }
+}
@@ -0,0 +1,506 @@
+use crate::clock::LocalTime;
+use crate::control::Control;
+use crate::events::{Event, Handled, Listener};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+use tokio::net::UdpSocket;
+
+/// Root of sol's objects: NET-SNMP's netSnmpPlaypen arc, set aside for local use
+pub const BASE: &[u32] = &[1, 3, 6, 1, 4, 1, 8072, 9999, 9999, 1];
+
+const SYS_UP_TIME: &[u32] = &[1, 3, 6, 1, 2, 1, 1, 3, 0];
+const SNMP_TRAP_OID: &[u32] = &[1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0];
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const COUNTER32: u8 = 0x41;
+const TIME_TICKS: u8 = 0x43;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const NO_SUCH_INSTANCE: u8 = 0x81;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+const GET: u8 = 0xA0;
+const GET_NEXT: u8 = 0xA1;
+const RESPONSE: u8 = 0xA2;
+const SET: u8 = 0xA3;
+const GET_BULK: u8 = 0xA5;
+const TRAP: u8 = 0xA7;
+
+const VERSION_2C: i64 = 1;
+const NOT_WRITABLE: i64 = 17;
+
+/// Most repetitions answered for one GETBULK varbind
+const MAX_REPETITIONS: i64 = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Integer(i64),
+    String(String),
+    Counter(u32),
+    Ticks(u32),
+    Oid(Vec<u32>),
+    Null,
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+/// What a trap reports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Trap {
+    Suspend,
+    Resume,
+    Failure,
+    /// The action succeeded but the system never slept
+    Missed,
+}
+
+impl Trap {
+    fn arc(self) -> u32 {
+        match self {
+            Trap::Suspend => 1,
+            Trap::Resume => 2,
+            Trap::Failure => 3,
+            Trap::Missed => 4,
+        }
+    }
+}
+
+fn push_length(buf: &mut Vec<u8>, len: usize) {
+    match len {
+        0..=0x7F => buf.push(len as u8),
+        0x80..=0xFF => buf.extend_from_slice(&[0x81, len as u8]),
+        _ => buf.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]),
+    }
+}
+
+fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut buf = vec![tag];
+    push_length(&mut buf, value.len());
+    buf.extend_from_slice(value);
+    buf
+}
+
+fn integer(tag: u8, value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // Drop leading bytes that only repeat the sign
+    let mut start = 0;
+    while start < 7 && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0) || (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0)) {
+        start += 1;
+    }
+    tlv(tag, &bytes[start..])
+}
+
+fn unsigned(tag: u8, value: u32) -> Vec<u8> {
+    integer(tag, value as i64)
+}
+
+fn oid(arcs: &[u32]) -> Vec<u8> {
+    let mut value = Vec::new();
+    if arcs.len() >= 2 {
+        value.push((arcs[0] * 40 + arcs[1]) as u8);
+    }
+    for &arc in arcs.iter().skip(2) {
+        let mut chunk = vec![(arc & 0x7F) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            chunk.push(0x80 | (rest & 0x7F) as u8);
+            rest >>= 7;
+        }
+        value.extend(chunk.iter().rev());
+    }
+    tlv(OID, &value)
+}
+
+fn encode_value(value: &Value) -> Vec<u8> {
+    match value {
+        Value::Integer(i) => integer(INTEGER, *i),
+        Value::String(s) => tlv(OCTET_STRING, s.as_bytes()),
+        Value::Counter(c) => unsigned(COUNTER32, *c),
+        Value::Ticks(t) => unsigned(TIME_TICKS, *t),
+        Value::Oid(arcs) => oid(arcs),
+        Value::Null => tlv(NULL, &[]),
+        Value::NoSuchObject => tlv(NO_SUCH_OBJECT, &[]),
+        Value::NoSuchInstance => tlv(NO_SUCH_INSTANCE, &[]),
+        Value::EndOfMibView => tlv(END_OF_MIB_VIEW, &[]),
+    }
+}
+
+fn message(community: &str, pdu_type: u8, request_id: i64, error: (i64, i64), varbinds: &[(Vec<u32>, Value)]) -> Vec<u8> {
+    let list: Vec<u8> = varbinds
+        .iter()
+        .flat_map(|(name, value)| tlv(SEQUENCE, &[oid(name), encode_value(value)].concat()))
+        .collect();
+    let pdu = [integer(INTEGER, request_id), integer(INTEGER, error.0), integer(INTEGER, error.1), tlv(SEQUENCE, &list)].concat();
+    let body = [integer(INTEGER, VERSION_2C), tlv(OCTET_STRING, community.as_bytes()), tlv(pdu_type, &pdu)].concat();
+    tlv(SEQUENCE, &body)
+}
+
+/// Split one TLV off the front of `buf`, returning its tag, value and the rest
+fn read_tlv(buf: &[u8]) -> Result<(u8, &[u8], &[u8]), String> {
+    let truncated = || "truncated message".to_string();
+    let (&tag, rest) = buf.split_first().ok_or_else(truncated)?;
+    let (&first, mut rest) = rest.split_first().ok_or_else(truncated)?;
+    let len = if first & 0x80 == 0 {
+        first as usize
+    } else {
+        let count = (first & 0x7F) as usize;
+        if count == 0 || count > 2 {
+            return Err("unsupported length".to_string());
+        }
+        let bytes = rest.get(..count).ok_or_else(truncated)?;
+        rest = &rest[count..];
+        bytes.iter().fold(0, |len, &b| len << 8 | b as usize)
+    };
+    let value = rest.get(..len).ok_or_else(truncated)?;
+    Ok((tag, value, &rest[len..]))
+}
+
+fn read_integer(buf: &[u8]) -> Result<(i64, &[u8]), String> {
+    let (tag, value, rest) = read_tlv(buf)?;
+    if tag != INTEGER || value.is_empty() || value.len() > 8 {
+        return Err("expected an integer".to_string());
+    }
+    let sign = if value[0] & 0x80 != 0 { -1i64 } else { 0 };
+    Ok((value.iter().fold(sign, |n, &b| n << 8 | b as i64), rest))
+}
+
+fn read_oid(value: &[u8]) -> Result<Vec<u32>, String> {
+    let (&first, rest) = value.split_first().ok_or("empty OID")?;
+    let mut arcs = vec![(first / 40).min(2) as u32, (first - (first / 40).min(2) * 40) as u32];
+    let mut arc = 0u32;
+    for &b in rest {
+        arc = arc.checked_mul(128).ok_or("OID arc too large")? | (b & 0x7F) as u32;
+        if b & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    Ok(arcs)
+}
+
+/// A request as the agent sees it
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    community: String,
+    pdu_type: u8,
+    request_id: i64,
+    /// Non-repeaters and max-repetitions for GETBULK, error status and index otherwise
+    fields: (i64, i64),
+    names: Vec<Vec<u32>>,
+}
+
+fn parse(buf: &[u8]) -> Result<Request, String> {
+    let (tag, body, _) = read_tlv(buf)?;
+    if tag != SEQUENCE {
+        return Err("not an SNMP message".to_string());
+    }
+    let (version, rest) = read_integer(body)?;
+    if version != VERSION_2C {
+        return Err(format!("unsupported SNMP version {}", version + 1));
+    }
+    let (tag, community, rest) = read_tlv(rest)?;
+    if tag != OCTET_STRING {
+        return Err("expected a community string".to_string());
+    }
+    let (pdu_type, pdu, _) = read_tlv(rest)?;
+    let (request_id, rest) = read_integer(pdu)?;
+    let (first, rest) = read_integer(rest)?;
+    let (second, rest) = read_integer(rest)?;
+    let (_, mut list, _) = read_tlv(rest)?;
+    let mut names = Vec::new();
+    while !list.is_empty() {
+        let (_, varbind, rest) = read_tlv(list)?;
+        let (tag, name, _) = read_tlv(varbind)?;
+        if tag != OID {
+            return Err("expected an OID".to_string());
+        }
+        names.push(read_oid(name)?);
+        list = rest;
+    }
+    Ok(Request {
+        community: String::from_utf8_lossy(community).into_owned(),
+        pdu_type,
+        request_id,
+        fields: (first, second),
+        names,
+    })
+}
+
+fn flag(on: bool) -> Value {
+    Value::Integer(on as i64)
+}
+
+fn under(arc: &[u32]) -> Vec<u32> {
+    [BASE, arc].concat()
+}
+
+/// Trap destinations plus the counters and uptime the agent reports
+pub struct Snmp {
+    pub community: String,
+    pub traps: Vec<SocketAddr>,
+    started: Instant,
+    suspends: AtomicU32,
+    failures: AtomicU32,
+}
+
+impl Snmp {
+    pub fn new(community: String, traps: Vec<SocketAddr>) -> Self {
+        Snmp { community, traps, started: Instant::now(), suspends: AtomicU32::new(0), failures: AtomicU32::new(0) }
+    }
+
+    /// Hundredths of a second since the daemon started
+    fn uptime(&self) -> u32 {
+        (self.started.elapsed().as_millis() / 10) as u32
+    }
+
+    /// The agent's objects in OID order, with `inhibited` as checked beforehand
+    fn objects(&self, control: &Control, inhibited: Option<String>) -> Vec<(Vec<u32>, Value)> {
+        let (profile, _) = control.profiles.current(&LocalTime::now());
+        let status = control.status.lock().unwrap();
+        vec![
+            (under(&[1, 0]), Value::String(status.listen.clone())),
+            (under(&[2, 0]), flag(control.maintenance.enabled())),
+            (under(&[3, 0]), flag(control.paused.is_paused())),
+            (under(&[4, 0]), Value::String(inhibited.unwrap_or_default())),
+            (under(&[5, 0]), flag(status.suspend_pending)),
+            (under(&[6, 0]), Value::String(status.last_trigger.clone().unwrap_or_default())),
+            (under(&[7, 0]), Value::String(profile)),
+            (under(&[8, 0]), Value::Counter(self.suspends.load(Ordering::Relaxed))),
+            (under(&[9, 0]), Value::Counter(self.failures.load(Ordering::Relaxed))),
+            (under(&[12, 0]), Value::Counter(status.missed_sleeps)),
+            (under(&[13, 0]), flag(status.flood)),
+            (under(&[14, 0]), Value::Counter(status.rejected_packets)),
+            (under(&[15, 0]), Value::Counter(status.kernel_drops)),
+        ]
+    }
+
+    /// Build the response to a request, or None for requests the agent does not answer
+    async fn respond(&self, request: &Request, control: &Control) -> Option<Vec<u8>> {
+        if request.community != self.community {
+            return None;
+        }
+        let objects = self.objects(control, control.inhibited().await);
+        let get = |name: &Vec<u32>| match objects.iter().find(|(oid, _)| oid == name) {
+            Some((_, value)) => value.clone(),
+            None if name.starts_with(BASE) => Value::NoSuchInstance,
+            None => Value::NoSuchObject,
+        };
+        let next = |name: &Vec<u32>| match objects.iter().find(|(oid, _)| oid > name) {
+            Some((oid, value)) => (oid.clone(), value.clone()),
+            None => (name.clone(), Value::EndOfMibView),
+        };
+
+        let mut error = (0, 0);
+        let varbinds: Vec<(Vec<u32>, Value)> = match request.pdu_type {
+            GET => request.names.iter().map(|name| (name.clone(), get(name))).collect(),
+            GET_NEXT => request.names.iter().map(next).collect(),
+            GET_BULK => {
+                let (non_repeaters, repetitions) = request.fields;
+                let split = (non_repeaters.max(0) as usize).min(request.names.len());
+                let mut varbinds: Vec<_> = request.names[..split].iter().map(next).collect();
+                let mut cursors = request.names[split..].to_vec();
+                for _ in 0..repetitions.clamp(0, MAX_REPETITIONS) {
+                    for cursor in cursors.iter_mut() {
+                        let (oid, value) = next(cursor);
+                        *cursor = oid.clone();
+                        varbinds.push((oid, value));
+                    }
+                }
+                varbinds
+            }
+            SET => {
+                error = (NOT_WRITABLE, 1);
+                request.names.iter().map(|name| (name.clone(), Value::Null)).collect()
+            }
+            _ => return None,
+        };
+        Some(message(&self.community, RESPONSE, request.request_id, error, &varbinds))
+    }
+
+    fn trap(&self, trap: Trap, detail: &str, request_id: i64) -> Vec<u8> {
+        let varbinds = [
+            (SYS_UP_TIME.to_vec(), Value::Ticks(self.uptime())),
+            (SNMP_TRAP_OID.to_vec(), Value::Oid(under(&[10, trap.arc()]))),
+            (under(&[11, 0]), Value::String(detail.to_string())),
+        ];
+        message(&self.community, TRAP, request_id, (0, 0), &varbinds)
+    }
+
+    /// Count the event and send a trap for it to every --snmp-trap destination
+    async fn notify(&self, trap: Trap, detail: &str) {
+        match trap {
+            Trap::Suspend => self.suspends.fetch_add(1, Ordering::Relaxed),
+            Trap::Failure | Trap::Missed => self.failures.fetch_add(1, Ordering::Relaxed),
+            Trap::Resume => 0,
+        };
+        if self.traps.is_empty() {
+            return;
+        }
+        let trap = self.trap(trap, detail, self.uptime() as i64);
+        let socket = match UdpSocket::bind("0.0.0.0:0").await {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("Cannot send SNMP traps: {}", e);
+                return;
+            }
+        };
+        for destination in &self.traps {
+            if let Err(e) = socket.send_to(&trap, destination).await {
+                warn!("Failed to send SNMP trap to {}: {}", destination, e);
+            }
+        }
+    }
+}
+
+impl Listener for Snmp {
+    fn handle<'a>(&'a self, event: &'a Event) -> Handled<'a> {
+        Box::pin(async move {
+            match event {
+                Event::ActionFinished { action } => self.notify(Trap::Suspend, action.as_str()).await,
+                Event::Resumed { action } => self.notify(Trap::Resume, action.as_str()).await,
+                Event::ActionFailed { detail } => self.notify(Trap::Failure, detail).await,
+                Event::MissedSleep { detail } => self.notify(Trap::Missed, detail).await,
+                Event::Verified { ok: false, detail, .. } => self.notify(Trap::Failure, detail).await,
+                _ => {}
+            }
+        })
+    }
+}
+
+/// Bind `addr` and answer SNMP requests in the background
+pub async fn listen(addr: SocketAddr, snmp: Arc<Snmp>, control: Control) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(addr).await?;
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1500];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("Receiving SNMP request failed: {}", e);
+                    return;
+                }
+            };
+            let request = match parse(&buf[..len]) {
+                Ok(request) => request,
+                Err(e) => {
+                    verbose!("Ignoring SNMP message from {}: {}", peer, e);
+                    continue;
+                }
+            };
+            let Some(response) = snmp.respond(&request, &control).await else {
+                verbose!("Ignoring SNMP request from {} with the wrong community", peer);
+                continue;
+            };
+            if let Err(e) = socket.send_to(&response, peer).await {
+                warn!("Failed to answer SNMP request from {}: {}", peer, e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bans::Bans;
+    use crate::inhibit::Inhibitors;
+    use crate::maintenance::Maintenance;
+    use crate::pause::Paused;
+    use crate::peers::Peers;
+    use crate::policy::{Policy, Profiles};
+
+    fn control() -> Control {
+        let policy = Policy {
+            quorum: 1,
+            quorum_window: 10,
+            confirm: false,
+            grace: 0,
+            warn_users: false,
+            inhibit_share_clients: false,
+            inhibit_ports: Vec::new(),
+            ignore_desktop_inhibitors: true,
+            inhibit_session_activity: false,
+            min_idle: None,
+        };
+        Control::new(
+            Maintenance::new(std::env::temp_dir().join(format!("sol-snmp-test-{}", std::process::id()))),
+            Paused::default(),
+            Arc::new(Inhibitors::default()),
+            Arc::new(Profiles::new(policy)),
+            Arc::new(Peers::default()),
+            Arc::new(Bans::new(None, None)),
+        )
+    }
+
+    fn request(pdu_type: u8, fields: (i64, i64), names: &[Vec<u32>]) -> Vec<u8> {
+        let varbinds: Vec<_> = names.iter().map(|name| (name.clone(), Value::Null)).collect();
+        message("public", pdu_type, 42, fields, &varbinds)
+    }
+
+    /// The OIDs and values of a response, decoded just enough to check them
+    fn varbinds(response: &[u8]) -> Vec<(Vec<u32>, u8, Vec<u8>)> {
+        let (_, body, _) = read_tlv(response).unwrap();
+        let (_, rest) = read_integer(body).unwrap();
+        let (_, _, rest) = read_tlv(rest).unwrap();
+        let (tag, pdu, _) = read_tlv(rest).unwrap();
+        assert_eq!(tag, RESPONSE);
+        let (_, rest) = read_integer(pdu).unwrap();
+        let (_, rest) = read_integer(rest).unwrap();
+        let (_, rest) = read_integer(rest).unwrap();
+        let (_, mut list, _) = read_tlv(rest).unwrap();
+        let mut varbinds = Vec::new();
+        while !list.is_empty() {
+            let (_, varbind, rest) = read_tlv(list).unwrap();
+            let (_, name, value) = read_tlv(varbind).unwrap();
+            let (tag, value, _) = read_tlv(value).unwrap();
+            varbinds.push((read_oid(name).unwrap(), tag, value.to_vec()));
+            list = rest;
+        }
+        varbinds
+    }
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(integer(INTEGER, 0), [0x02, 0x01, 0x00]);
+        assert_eq!(integer(INTEGER, 128), [0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(integer(INTEGER, -1), [0x02, 0x01, 0xFF]);
+        assert_eq!(oid(&[1, 3, 6, 1, 4, 1, 8072]), [0x06, 0x07, 0x2B, 0x06, 0x01, 0x04, 0x01, 0xBF, 0x08]);
+        assert_eq!(read_oid(&oid(BASE)[2..]).unwrap(), BASE);
+
+        let parsed = parse(&request(GET_BULK, (0, 5), &[BASE.to_vec()])).unwrap();
+        assert_eq!((parsed.community.as_str(), parsed.pdu_type, parsed.request_id), ("public", GET_BULK, 42));
+        assert_eq!((parsed.fields, parsed.names), ((0, 5), vec![BASE.to_vec()]));
+    }
+
+    #[tokio::test]
+    async fn test_agent() {
+        let snmp = Snmp::new("public".to_string(), Vec::new());
+        let control = control();
+        control.status.lock().unwrap().listen = "0.0.0.0:10".to_string();
+
+        let get = parse(&request(GET, (0, 0), &[under(&[1, 0]), under(&[1]), vec![1, 3, 6, 1, 2]])).unwrap();
+        let values = varbinds(&snmp.respond(&get, &control).await.unwrap());
+        assert_eq!((values[0].1, values[0].2.as_slice()), (OCTET_STRING, &b"0.0.0.0:10"[..]));
+        assert_eq!(values[1].1, NO_SUCH_INSTANCE);
+        assert_eq!(values[2].1, NO_SUCH_OBJECT);
+
+        let walk = parse(&request(GET_BULK, (0, 20), &[BASE.to_vec()])).unwrap();
+        let values = varbinds(&snmp.respond(&walk, &control).await.unwrap());
+        assert_eq!(values.len(), 20);
+        assert_eq!(values[0].0, under(&[1, 0]));
+        assert_eq!(values[8].0, under(&[9, 0]));
+        assert_eq!(values[9].0, under(&[12, 0]));
+        assert_eq!(values[11].0, under(&[14, 0]));
+        assert_eq!(values[12].0, under(&[15, 0]));
+        assert_eq!(values[13].1, END_OF_MIB_VIEW);
+
+        let wrong = Request { community: "private".to_string(), ..get };
+        assert_eq!(snmp.respond(&wrong, &control).await, None);
+    }
+}
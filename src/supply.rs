@@ -0,0 +1,203 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::process::Command;
+
+const SYSFS_POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// How long one `upsc` query may take
+const NUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the UPS is asked again
+pub const NUT_POLL: Duration = Duration::from_secs(15);
+
+/// Where the machine is drawing power from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerSource {
+    Ac,
+    /// A discharging battery or UPS
+    Battery,
+}
+
+impl FromStr for PowerSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ac" => Ok(PowerSource::Ac),
+            "battery" | "ups" => Ok(PowerSource::Battery),
+            _ => Err(format!("Invalid power source '{}' (expected ac or battery)", s)),
+        }
+    }
+}
+
+/// Reads the power source from a NUT UPS when configured, otherwise from sysfs
+#[derive(Clone, Debug)]
+pub struct Supply {
+    /// NUT UPS as `UPS[@HOST[:PORT]]`, queried with `upsc`
+    pub nut: Option<String>,
+    pub sysfs: PathBuf,
+    /// What the last poll of the UPS returned, so rules never wait on `upsc`
+    ups: Arc<Mutex<Option<Result<PowerSource, String>>>>,
+}
+
+impl Default for Supply {
+    fn default() -> Self {
+        Supply { nut: None, sysfs: PathBuf::from(SYSFS_POWER_SUPPLY), ups: Arc::default() }
+    }
+}
+
+impl Supply {
+    pub fn new(nut: Option<String>) -> Self {
+        Supply { nut, ..Supply::default() }
+    }
+
+    /// The current source: what the UPS reported at the last poll, or sysfs when there is no
+    /// UPS or it did not answer. With no information at all the machine is assumed to be on AC.
+    pub fn source(&self) -> PowerSource {
+        match *self.ups.lock().unwrap() {
+            Some(Ok(source)) => source,
+            _ => sysfs_source(&self.sysfs),
+        }
+    }
+
+    /// Ask the UPS once and keep the answer for `source`. A failure is logged when it first
+    /// happens, not at every poll.
+    pub async fn poll(&self) {
+        let Some(ups) = &self.nut else {
+            return;
+        };
+        let polled = tokio::time::timeout(NUT_TIMEOUT, nut_source(ups))
+            .await
+            .unwrap_or_else(|_| Err(format!("upsc timed out after {}s", NUT_TIMEOUT.as_secs())));
+        let mut last = self.ups.lock().unwrap();
+        if let Err(e) = &polled
+            && last.as_ref() != Some(&polled)
+        {
+            warn!("Cannot read UPS {} ({}), falling back to sysfs", ups, e);
+        }
+        *last = Some(polled);
+    }
+
+    /// Poll the UPS again every `interval`
+    pub fn refresh(&self, interval: Duration) {
+        if self.nut.is_none() {
+            return;
+        }
+        let supply = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                supply.poll().await;
+            }
+        });
+    }
+}
+
+async fn nut_source(ups: &str) -> Result<PowerSource, String> {
+    let output = Command::new("upsc")
+        .args([ups, "ups.status"])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("upsc: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("upsc failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let status = String::from_utf8_lossy(&output.stdout);
+    parse_ups_status(&status).ok_or_else(|| format!("unexpected ups.status '{}'", status.trim()))
+}
+
+/// `ups.status` is a list of flags such as `OL CHRG` or `OB LB`
+fn parse_ups_status(status: &str) -> Option<PowerSource> {
+    let flags: Vec<&str> = status.split_whitespace().collect();
+    if flags.contains(&"OB") {
+        Some(PowerSource::Battery)
+    } else if flags.contains(&"OL") {
+        Some(PowerSource::Ac)
+    } else {
+        None
+    }
+}
+
+fn read_attr(dir: &Path, name: &str) -> String {
+    fs::read_to_string(dir.join(name)).map(|s| s.trim().to_string()).unwrap_or_default()
+}
+
+/// On battery when any battery or UPS is discharging, or when every mains supply is offline
+fn sysfs_source(root: &Path) -> PowerSource {
+    let Ok(entries) = fs::read_dir(root) else {
+        return PowerSource::Ac;
+    };
+
+    let mut mains = Vec::new();
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        match read_attr(&dir, "type").as_str() {
+            "Mains" | "USB" => mains.push(read_attr(&dir, "online") == "1"),
+            "Battery" | "UPS" if read_attr(&dir, "status") == "Discharging" => return PowerSource::Battery,
+            _ => {}
+        }
+    }
+
+    if !mains.is_empty() && !mains.contains(&true) {
+        PowerSource::Battery
+    } else {
+        PowerSource::Ac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supply(root: &Path, name: &str, attrs: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (attr, value) in attrs {
+            fs::write(dir.join(attr), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_ups_status() {
+        assert_eq!(parse_ups_status("OL CHRG\n"), Some(PowerSource::Ac));
+        assert_eq!(parse_ups_status("OB LB"), Some(PowerSource::Battery));
+        assert_eq!(parse_ups_status("BYPASS"), None);
+    }
+
+    #[test]
+    fn test_sysfs_source() {
+        let root = std::env::temp_dir().join(format!("sol-supply-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(sysfs_source(&root), PowerSource::Ac);
+
+        supply(&root, "AC", &[("type", "Mains"), ("online", "1")]);
+        supply(&root, "BAT0", &[("type", "Battery"), ("status", "Charging")]);
+        assert_eq!(sysfs_source(&root), PowerSource::Ac);
+
+        supply(&root, "AC", &[("online", "0")]);
+        assert_eq!(sysfs_source(&root), PowerSource::Battery);
+
+        supply(&root, "AC", &[("online", "1")]);
+        supply(&root, "ups", &[("type", "UPS"), ("status", "Discharging")]);
+        assert_eq!(sysfs_source(&root), PowerSource::Battery);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_polled_source() {
+        let supply = Supply { sysfs: "/nonexistent".into(), ..Supply::default() };
+        supply.poll().await;
+        assert_eq!(*supply.ups.lock().unwrap(), None);
+        assert_eq!(supply.source(), PowerSource::Ac);
+
+        *supply.ups.lock().unwrap() = Some(Ok(PowerSource::Battery));
+        assert_eq!(supply.source(), PowerSource::Battery);
+        *supply.ups.lock().unwrap() = Some(Err("upsc: not found".to_string()));
+        assert_eq!(supply.source(), PowerSource::Ac);
+    }
+}
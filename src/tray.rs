@@ -0,0 +1,182 @@
+use crate::control;
+use std::path::PathBuf;
+use std::time::Duration;
+use zbus::object_server::SignalEmitter;
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const WATCHER: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+
+/// Width, height and ARGB32 data of an icon image
+type Pixmap = (i32, i32, Vec<u8>);
+
+/// Icon name, icon images, title and text
+type ToolTip = (String, Vec<Pixmap>, String, String);
+
+/// How often the daemon's status is fetched
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What the icon shows, taken from the `status` control command
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct State {
+    /// False while the control socket cannot be reached
+    pub connected: bool,
+    pub maintenance: bool,
+    pub paused: bool,
+    pub pending: bool,
+    /// The status report, shown as the tooltip
+    pub report: String,
+}
+
+impl State {
+    pub fn parse(report: &str) -> Self {
+        let value = |key: &str| {
+            report.lines().find_map(|line| line.strip_prefix(key)?.strip_prefix(": ")).unwrap_or_default()
+        };
+        State {
+            connected: true,
+            maintenance: value("maintenance") == "on",
+            paused: value("paused") == "yes",
+            pending: value("suspend pending") == "yes",
+            report: report.to_string(),
+        }
+    }
+
+    fn icon(&self) -> &'static str {
+        match self {
+            State { connected: false, .. } => "network-offline",
+            State { maintenance: true, .. } | State { paused: true, .. } => "media-playback-pause",
+            _ => "system-suspend",
+        }
+    }
+
+    /// A pending suspend asks for attention, since it can still be cancelled
+    fn status(&self) -> &'static str {
+        if self.pending { "NeedsAttention" } else { "Active" }
+    }
+}
+
+/// A StatusNotifierItem, the tray icon protocol of KDE, GNOME (with the AppIndicator
+/// extension), XFCE and most bars. Clicking it toggles maintenance mode.
+struct Item {
+    socket: PathBuf,
+    state: State,
+}
+
+#[zbus::interface(name = "org.kde.StatusNotifierItem")]
+impl Item {
+    #[zbus(property)]
+    fn category(&self) -> &str {
+        "SystemServices"
+    }
+
+    #[zbus(property)]
+    fn id(&self) -> &str {
+        "sol"
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> &str {
+        "Sleep-on-LAN"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        self.state.status()
+    }
+
+    #[zbus(property)]
+    fn icon_name(&self) -> &str {
+        self.state.icon()
+    }
+
+    #[zbus(property)]
+    fn tool_tip(&self) -> ToolTip {
+        let text = if self.state.connected { self.state.report.clone() } else { "daemon not reachable".to_string() };
+        (self.state.icon().to_string(), Vec::new(), "Sleep-on-LAN".to_string(), text)
+    }
+
+    #[zbus(property)]
+    fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    async fn activate(&self, _x: i32, _y: i32) {
+        let command = if self.state.maintenance { "maintenance off" } else { "maintenance on" };
+        if let Err(e) = control::request(&self.socket, command).await.map_err(|e| e.to_string()) {
+            eprintln!("{}", e);
+        }
+    }
+
+    async fn secondary_activate(&self, x: i32, y: i32) {
+        self.activate(x, y).await;
+    }
+
+    #[zbus(signal)]
+    async fn new_icon(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn new_tool_tip(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn new_status(emitter: &SignalEmitter<'_>, status: &str) -> zbus::Result<()>;
+}
+
+/// Show the daemon's state in the tray until interrupted
+pub async fn run(socket: PathBuf) -> Result<(), String> {
+    let name = format!("org.kde.StatusNotifierItem-{}-1", std::process::id());
+    let item = Item { socket: socket.clone(), state: State::default() };
+    let connection = zbus::connection::Builder::session()
+        .and_then(|builder| builder.name(name.as_str()))
+        .and_then(|builder| builder.serve_at(ITEM_PATH, item))
+        .map_err(|e| format!("Cannot connect to the session bus: {}", e))?
+        .build()
+        .await
+        .map_err(|e| format!("Cannot connect to the session bus: {}", e))?;
+    connection
+        .call_method(Some(WATCHER), WATCHER_PATH, Some(WATCHER), "RegisterStatusNotifierItem", &(name.as_str(),))
+        .await
+        .map_err(|e| format!("No system tray is running ({})", e))?;
+
+    let item = connection
+        .object_server()
+        .interface::<_, Item>(ITEM_PATH)
+        .await
+        .map_err(|e| e.to_string())?;
+    loop {
+        let state = match control::request(&socket, "status").await {
+            Ok(report) => State::parse(&report),
+            Err(_) => State::default(),
+        };
+        let mut current = item.get_mut().await;
+        if current.state != state {
+            current.state = state;
+            let status = current.state.status();
+            drop(current);
+            let emitter = item.signal_emitter();
+            let sent = async {
+                Item::new_icon(emitter).await?;
+                Item::new_tool_tip(emitter).await?;
+                Item::new_status(emitter, status).await
+            };
+            sent.await.map_err(|e| format!("Cannot update the tray icon: {}", e))?;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() {
+        let report = "listening: 0.0.0.0:10\nprofile: default (auto)\nmaintenance: on\npaused: no\n\
+                      inhibited: no\nsuspend pending: yes\nlast trigger: 192.168.1.5:40000";
+        let state = State::parse(report);
+        assert!(state.connected && state.maintenance && state.pending && !state.paused);
+        assert_eq!((state.icon(), state.status()), ("media-playback-pause", "NeedsAttention"));
+        assert_eq!(State::default().icon(), "network-offline");
+        assert_eq!(State::parse("maintenance: off").icon(), "system-suspend");
+    }
+}
@@ -0,0 +1,104 @@
+use crate::sha256;
+use core::fmt;
+
+/// First bytes of a v2 packet; legacy packets start with 0xFF or 0x00
+pub const PREFIX: [u8; 4] = *b"SOL2";
+
+/// Prefix plus the HMAC-SHA256 of the body
+pub const HEADER_SIZE: usize = PREFIX.len() + 32;
+
+/// Signature field of an unsigned packet
+pub const UNSIGNED: [u8; 32] = [0; 32];
+
+/// Why a datagram is not a well-formed v2 packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// No room for the header and a body; holds the actual length
+    Size(usize),
+    Prefix,
+    /// The output buffer passed to `encode` is too small
+    Buffer,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Size(len) => write!(f, "Invalid size: {} (expected more than {})", len, HEADER_SIZE),
+            Error::Prefix => write!(f, "Invalid v2 prefix"),
+            Error::Buffer => write!(f, "Buffer too small"),
+        }
+    }
+}
+
+/// A v2 packet: `SOL2`, a 32-byte HMAC-SHA256 of the body (all zero when unsigned), then a
+/// JSON body
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub signature: [u8; 32],
+    pub body: &'a [u8],
+}
+
+impl Packet<'_> {
+    pub fn is_signed(&self) -> bool {
+        self.signature != UNSIGNED
+    }
+
+    /// Whether the body was signed with `key`
+    pub fn verify(&self, key: &[u8]) -> bool {
+        sha256::equal(&self.signature, &sha256::hmac(key, self.body))
+    }
+}
+
+pub fn parse(packet: &[u8]) -> Result<Packet<'_>, Error> {
+    if packet.len() <= HEADER_SIZE {
+        return Err(Error::Size(packet.len()));
+    }
+    if packet[..PREFIX.len()] != PREFIX {
+        return Err(Error::Prefix);
+    }
+    let mut signature = [0u8; 32];
+    signature.copy_from_slice(&packet[PREFIX.len()..HEADER_SIZE]);
+    Ok(Packet { signature, body: &packet[HEADER_SIZE..] })
+}
+
+/// Write a packet carrying `body` into `out`, signed when `key` is given, and return its length
+pub fn encode(key: Option<&[u8]>, body: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let len = HEADER_SIZE + body.len();
+    if out.len() < len {
+        return Err(Error::Buffer);
+    }
+    let signature = key.map_or(UNSIGNED, |key| sha256::hmac(key, body));
+    out[..PREFIX.len()].copy_from_slice(&PREFIX);
+    out[PREFIX.len()..HEADER_SIZE].copy_from_slice(&signature);
+    out[HEADER_SIZE..len].copy_from_slice(body);
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = br#"{"action":"hibernate","mac":"aa:bb:cc:dd:ee:ff","ts":1760000000}"#;
+
+    #[test]
+    fn test_signed_round_trip() {
+        let mut buf = [0u8; 256];
+        let len = encode(Some(b"secret"), BODY, &mut buf).unwrap();
+        let packet = parse(&buf[..len]).unwrap();
+        assert_eq!(packet.body, BODY);
+        assert!(packet.is_signed());
+        assert!(packet.verify(b"secret"));
+        assert!(!packet.verify(b"other"));
+    }
+
+    #[test]
+    fn test_unsigned_and_malformed() {
+        let mut buf = [0u8; 256];
+        let len = encode(None, BODY, &mut buf).unwrap();
+        assert!(!parse(&buf[..len]).unwrap().is_signed());
+        assert_eq!(parse(&buf[..HEADER_SIZE]), Err(Error::Size(HEADER_SIZE)));
+        buf[0] = 0xFF;
+        assert_eq!(parse(&buf[..len]), Err(Error::Prefix));
+        assert_eq!(encode(None, BODY, &mut buf[..40]), Err(Error::Buffer));
+    }
+}
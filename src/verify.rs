@@ -0,0 +1,121 @@
+use crate::events::{Bus, Event};
+use crate::mac::MacAddr;
+use crate::reach::{self, Target};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How often a peer is probed while waiting for it to change state
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// A MAC and the host to probe to see whether the machine behind it is up, written as
+/// `MAC=TARGET`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub mac: MacAddr,
+    pub target: Target,
+}
+
+impl std::str::FromStr for Check {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mac, target) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid check '{}' (expected MAC=HOST[:PORT])", s))?;
+        Ok(Check { mac: mac.parse()?, target: target.parse()? })
+    }
+}
+
+/// Probes that confirm a woken machine came up, or a group member went to sleep
+#[derive(Clone)]
+pub struct Checks {
+    checks: Vec<Check>,
+    pub timeout: Duration,
+    events: Arc<Bus>,
+}
+
+impl std::fmt::Debug for Checks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Checks").field("checks", &self.checks).field("timeout", &self.timeout).finish_non_exhaustive()
+    }
+}
+
+impl Checks {
+    pub fn new(checks: Vec<Check>, timeout: Duration, events: Arc<Bus>) -> Self {
+        Checks { checks, timeout, events }
+    }
+
+    pub fn target(&self, mac: &MacAddr) -> Option<&Target> {
+        self.checks.iter().find(|check| check.mac == *mac).map(|check| &check.target)
+    }
+
+    /// Probe the machine with `mac` until it is up (or down) or the timeout passes, then log
+    /// and publish the outcome. None when there is nothing to probe for `mac`.
+    pub async fn wait(&self, name: &str, mac: &MacAddr, up: bool) -> Option<Result<String, String>> {
+        let target = self.target(mac)?.clone();
+        let state = if up { "up" } else { "down" };
+        let start = Instant::now();
+        let result = loop {
+            if reach::probe(target.clone()).await == up {
+                break Ok(format!("{} is {} after {}s ({})", name, state, start.elapsed().as_secs(), target));
+            }
+            if start.elapsed() + INTERVAL > self.timeout {
+                break Err(format!("{} is not {} after {}s ({})", name, state, self.timeout.as_secs(), target));
+            }
+            tokio::time::sleep(INTERVAL).await;
+        };
+        let (ok, detail) = match &result {
+            Ok(detail) => {
+                info!("Verified: {}", detail);
+                (true, detail.clone())
+            }
+            Err(detail) => {
+                warn!("Verification failed: {}", detail);
+                (false, detail.clone())
+            }
+        };
+        self.events.publish(Event::Verified { peer: name.to_string(), ok, detail }).await;
+        Some(result)
+    }
+
+    /// Verify in the background; false when there is nothing to probe for `mac`
+    pub fn spawn(&self, name: String, mac: MacAddr, up: bool) -> bool {
+        if self.target(&mac).is_none() {
+            return false;
+        }
+        let checks = self.clone();
+        tokio::spawn(async move { checks.wait(&name, &mac, up).await });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+    #[test]
+    fn test_parse_check() {
+        let check: Check = "aa:bb:cc:dd:ee:ff=nas.lan:22".parse().unwrap();
+        assert_eq!(check.mac, MAC);
+        assert_eq!(check.target.to_string(), "nas.lan:22");
+        assert!("aa:bb:cc:dd:ee:ff".parse::<Check>().is_err());
+        assert!("aa:bb:cc:dd:ee=nas.lan".parse::<Check>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let check = format!("aa:bb:cc:dd:ee:ff={}", target).parse().unwrap();
+        let checks = Checks::new(vec![check], Duration::ZERO, Arc::new(Bus::default()));
+
+        assert_eq!(checks.wait("nas", &MAC, true).await, Some(Ok(format!("nas is up after 0s ({})", target))));
+        assert!(checks.wait("nas", &MAC, false).await.unwrap().is_err());
+        drop(listener);
+        assert_eq!(checks.wait("nas", &MAC, true).await, Some(Err(format!("nas is not up after 0s ({})", target))));
+        assert_eq!(checks.wait("desk", &MacAddr::ZERO, true).await, None);
+    }
+}
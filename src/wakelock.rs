@@ -0,0 +1,234 @@
+use crate::inhibit::Inhibitor;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const BUS_NAME: &str = "org.sleeponlan.SleepOnLan";
+pub const OBJECT_PATH: &str = "/org/sleeponlan/WakeLock";
+
+/// Longest a single wake lock may be held before it must be renewed
+pub const MAX_LOCK_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WakeLock {
+    pub id: u32,
+    pub reason: String,
+    /// UID of the client that took the lock, the only one besides root that may release it
+    pub owner: u32,
+    pub taken: Instant,
+    pub expires: Instant,
+}
+
+/// Temporary "don't sleep" locks registered by local applications
+#[derive(Clone, Default)]
+pub struct WakeLocks {
+    inner: Arc<Mutex<Table>>,
+}
+
+#[derive(Default)]
+struct Table {
+    next_id: u32,
+    locks: Vec<WakeLock>,
+}
+
+impl WakeLocks {
+    /// Register a lock for `owner` held for `duration` (capped at MAX_LOCK_DURATION) and
+    /// return its id
+    pub fn acquire(&self, reason: String, owner: u32, duration: Duration, now: Instant) -> u32 {
+        let mut table = self.inner.lock().unwrap();
+        table.next_id = table.next_id.wrapping_add(1);
+        let id = table.next_id;
+        table.locks.push(WakeLock {
+            id,
+            reason,
+            owner,
+            taken: now,
+            expires: now + duration.min(MAX_LOCK_DURATION),
+        });
+        id
+    }
+
+    /// Release a lock early on behalf of `caller`, returning whether it was held. Only the
+    /// lock's owner and root may release it.
+    pub fn release(&self, id: u32, caller: u32) -> Result<bool, String> {
+        let mut table = self.inner.lock().unwrap();
+        match table.locks.iter().position(|lock| lock.id == id) {
+            Some(i) if caller != 0 && table.locks[i].owner != caller => {
+                Err(format!("wake lock {} belongs to uid {}", id, table.locks[i].owner))
+            }
+            Some(i) => {
+                table.locks.remove(i);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Locks that have not yet expired
+    pub fn active(&self, now: Instant) -> Vec<WakeLock> {
+        let mut table = self.inner.lock().unwrap();
+        table.locks.retain(|lock| lock.expires > now);
+        table.locks.clone()
+    }
+}
+
+impl Inhibitor for WakeLocks {
+    fn name(&self) -> &str {
+        "wake-lock"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        let locks = self.active(Instant::now());
+        if locks.is_empty() {
+            return Ok(None);
+        }
+
+        let reasons: Vec<&str> = locks.iter().map(|lock| lock.reason.as_str()).collect();
+        Ok(Some(format!("held by {}", reasons.join(", "))))
+    }
+}
+
+/// Every held lock as shown in status output, e.g.
+/// `1 "nightly backup" by uid 1000, held 5 minute(s), 115 minute(s) left`
+pub fn describe(locks: &[WakeLock], now: Instant) -> Vec<String> {
+    locks
+        .iter()
+        .map(|lock| {
+            format!(
+                "{} \"{}\" by uid {}, held {} minute(s), {} minute(s) left",
+                lock.id,
+                lock.reason,
+                lock.owner,
+                now.saturating_duration_since(lock.taken).as_secs() / 60,
+                lock.expires.saturating_duration_since(now).as_secs().div_ceil(60)
+            )
+        })
+        .collect()
+}
+
+struct WakeLockService {
+    locks: WakeLocks,
+}
+
+/// The UID of the client that sent a method call, as the bus daemon reports it
+async fn caller(connection: &zbus::Connection, header: &zbus::message::Header<'_>) -> zbus::fdo::Result<u32> {
+    let sender = header.sender().ok_or_else(|| zbus::fdo::Error::Failed("call has no sender".to_string()))?;
+    zbus::fdo::DBusProxy::new(connection).await?.get_connection_unix_user(sender.clone().into()).await
+}
+
+#[zbus::interface(name = "org.sleeponlan.WakeLock1")]
+impl WakeLockService {
+    /// Hold off sleep for `timeout_secs` seconds; returns the lock id
+    async fn acquire(
+        &self,
+        reason: String,
+        timeout_secs: u64,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+    ) -> zbus::fdo::Result<u32> {
+        let owner = caller(connection, &header).await?;
+        let id = self.locks.acquire(reason.clone(), owner, Duration::from_secs(timeout_secs), Instant::now());
+        info!("Wake lock {} acquired by uid {} for {}s: {}", id, owner, timeout_secs, reason);
+        Ok(id)
+    }
+
+    /// Release a lock before it expires; only its owner or root may
+    async fn release(
+        &self,
+        id: u32,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+    ) -> zbus::fdo::Result<bool> {
+        let uid = caller(connection, &header).await?;
+        let released = self.locks.release(id, uid).map_err(zbus::fdo::Error::AccessDenied)?;
+        if released {
+            info!("Wake lock {} released by uid {}", id, uid);
+        }
+        Ok(released)
+    }
+
+    /// Active locks as (id, reason, seconds remaining)
+    fn list(&self) -> Vec<(u32, String, u64)> {
+        let now = Instant::now();
+        self.locks
+            .active(now)
+            .into_iter()
+            .map(|lock| (lock.id, lock.reason, lock.expires.duration_since(now).as_secs()))
+            .collect()
+    }
+}
+
+/// Own the bus name on the system bus and serve the wake-lock interface.
+/// The returned connection must be kept alive for as long as the service should run.
+pub async fn serve(locks: WakeLocks) -> zbus::Result<zbus::Connection> {
+    zbus::connection::Builder::system()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, WakeLockService { locks })?
+        .build()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acquire_and_release() {
+        let locks = WakeLocks::default();
+        let now = Instant::now();
+
+        let backup = locks.acquire("backup".to_string(), 1000, Duration::from_secs(60), now);
+        let upgrade = locks.acquire("upgrade".to_string(), 1001, Duration::from_secs(60), now);
+        assert_ne!(backup, upgrade);
+        assert_eq!(locks.active(now).len(), 2);
+
+        assert_eq!(locks.release(backup, 1000), Ok(true));
+        assert_eq!(locks.release(backup, 1000), Ok(false));
+        assert_eq!(locks.active(now)[0].reason, "upgrade");
+
+        // Another user cannot release the lock, but root can
+        assert_eq!(locks.release(upgrade, 1000), Err(format!("wake lock {} belongs to uid 1001", upgrade)));
+        assert_eq!(locks.release(upgrade, 0), Ok(true));
+        assert!(locks.active(now).is_empty());
+    }
+
+    #[test]
+    fn test_locks_expire() {
+        let locks = WakeLocks::default();
+        let now = Instant::now();
+        locks.acquire("backup".to_string(), 1000, Duration::from_secs(60), now);
+
+        assert_eq!(locks.active(now + Duration::from_secs(59)).len(), 1);
+        assert!(locks.active(now + Duration::from_secs(61)).is_empty());
+    }
+
+    #[test]
+    fn test_lock_duration_is_capped() {
+        let locks = WakeLocks::default();
+        let now = Instant::now();
+        locks.acquire("forever".to_string(), 1000, Duration::from_secs(u32::MAX as u64), now);
+
+        assert_eq!(locks.active(now)[0].expires, now + MAX_LOCK_DURATION);
+    }
+
+    #[test]
+    fn test_inhibitor_lists_reasons() {
+        let locks = WakeLocks::default();
+        assert_eq!(locks.check(), Ok(None));
+
+        locks.acquire("nightly backup".to_string(), 1000, Duration::from_secs(60), Instant::now());
+        assert_eq!(locks.check(), Ok(Some("held by nightly backup".to_string())));
+    }
+
+    #[test]
+    fn test_describe_locks() {
+        let locks = WakeLocks::default();
+        let now = Instant::now();
+        locks.acquire("nightly backup".to_string(), 1000, Duration::from_secs(7200), now);
+        locks.acquire("upgrade".to_string(), 0, Duration::from_secs(90), now + Duration::from_secs(240));
+
+        assert_eq!(describe(&locks.active(now), now + Duration::from_secs(300)), [
+            "1 \"nightly backup\" by uid 1000, held 5 minute(s), 115 minute(s) left",
+            "2 \"upgrade\" by uid 0, held 1 minute(s), 1 minute(s) left",
+        ]);
+    }
+}
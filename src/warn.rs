@@ -0,0 +1,103 @@
+use crate::rules::Action;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::Instant;
+
+/// Seconds-remaining marks at which logged-in users are reminded of a pending action
+const COUNTDOWN_MARKS: [u64; 5] = [300, 120, 60, 30, 10];
+
+/// How long `wall` may take before it is killed, e.g. when writing to a stuck terminal
+const WALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait out the grace period, broadcasting a countdown to `action` to logged-in users when
+/// `warn_users` is set. `source` names what triggered it, such as the sender's address.
+pub async fn countdown(grace: Duration, action: Action, source: String, warn_users: bool) {
+    if !warn_users {
+        tokio::time::sleep(grace).await;
+        return;
+    }
+
+    // Marks are timed from the deadline, so a slow `wall` does not stretch the grace period
+    let deadline = Instant::now() + grace;
+    broadcast(&warning_message(action, grace.as_secs(), &source)).await;
+
+    for mark in countdown_marks(grace.as_secs()) {
+        tokio::time::sleep_until(deadline - Duration::from_secs(mark)).await;
+        broadcast(&warning_message(action, mark, &source)).await;
+    }
+
+    tokio::time::sleep_until(deadline).await;
+}
+
+fn countdown_marks(total: u64) -> Vec<u64> {
+    COUNTDOWN_MARKS.iter().copied().filter(|mark| *mark < total).collect()
+}
+
+fn warning_message(action: Action, remaining: u64, source: &str) -> String {
+    let verb = match action {
+        Action::Poweroff => "power off",
+        action => action.as_str(),
+    };
+    format!(
+        "Sleep-on-LAN: this system will {} in {}s (triggered by {}).\nRun `sol cancel` to abort.",
+        verb, remaining, source
+    )
+}
+
+async fn broadcast(message: &str) {
+    let sent = tokio::time::timeout(WALL_TIMEOUT, wall(message)).await;
+    if let Err(e) = sent.unwrap_or_else(|_| Err(format!("wall timed out after {}s and was killed", WALL_TIMEOUT.as_secs()).into())) {
+        error!("Failed to warn logged-in users: {}", e);
+    }
+}
+
+/// Dropping the future kills `wall`, which is how a timeout stops it
+async fn wall(message: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("wall")
+        .kill_on_drop(true)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(message.as_bytes()).await?;
+    }
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(format!("wall failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_countdown_marks() {
+        assert_eq!(countdown_marks(5), Vec::<u64>::new());
+        assert_eq!(countdown_marks(30), vec![10]);
+        assert_eq!(countdown_marks(90), vec![60, 30, 10]);
+        assert_eq!(countdown_marks(600), vec![300, 120, 60, 30, 10]);
+    }
+
+    #[test]
+    fn test_warning_message_names_source() {
+        let message = warning_message(Action::Suspend, 60, "192.168.1.20");
+        assert!(message.contains("will suspend in 60s"));
+        assert!(message.contains("192.168.1.20"));
+        assert!(message.contains("sol cancel"));
+    }
+
+    #[test]
+    fn test_warning_message_names_action() {
+        assert!(warning_message(Action::Hibernate, 30, "x").contains("will hibernate in 30s"));
+        let message = warning_message(Action::Poweroff, 10, "schedule 'nightly'");
+        assert!(message.starts_with("Sleep-on-LAN: this system will power off in 10s (triggered by schedule 'nightly')"), "{}", message);
+    }
+}
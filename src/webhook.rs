@@ -0,0 +1,64 @@
+use crate::heartbeat::post;
+use crate::json::quote;
+use sol::notifier::{Notification, Notifier, Sent, Target};
+
+/// POSTs each notification as a JSON object to `url`, for services without a dedicated backend
+pub struct Webhook {
+    pub url: String,
+}
+
+/// The notification as one line of JSON
+fn body(notification: &Notification) -> String {
+    format!(
+        "{{\"event\":{},\"host\":{},\"title\":{},\"message\":{},\"priority\":\"{}\",\"click\":{},\"time\":{}}}",
+        quote(notification.event),
+        quote(&notification.host),
+        quote(&notification.title),
+        quote(&notification.message),
+        notification.priority.as_str(),
+        notification.click.as_deref().map(quote).unwrap_or_else(|| "null".to_string()),
+        crate::protocol::unix_now()
+    )
+}
+
+/// The backend for a `type = "webhook"` table, which takes no credentials
+pub fn build(target: Target) -> Result<Box<dyn Notifier>, String> {
+    match target {
+        Target { url, token: None, user: None } => Ok(Box::new(Webhook { url })),
+        _ => Err("'token' and 'user' do not apply to webhook".to_string()),
+    }
+}
+
+impl Notifier for Webhook {
+    fn describe(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> Sent<'a> {
+        Box::pin(async move { post(&self.url, &[("Content-Type", "application/json")], &body(notification)).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::Json;
+    use sol::notifier::Priority;
+
+    #[test]
+    fn test_body() {
+        let notification = Notification {
+            event: "missed-sleep",
+            host: "nas".to_string(),
+            title: "nas: missed-sleep".to_string(),
+            message: "suspend reported success but the system did not sleep within 60s".to_string(),
+            priority: Priority::High,
+            tag: "warning",
+            click: None,
+        };
+        let json = Json::parse(&body(&notification)).unwrap();
+        assert_eq!(json.get("event").and_then(Json::as_str), Some("missed-sleep"));
+        assert_eq!(json.get("priority").and_then(Json::as_str), Some("high"));
+        assert_eq!(json.get("click"), Some(&Json::Null));
+    }
+}
@@ -0,0 +1,259 @@
+use crate::inhibit::{self, COMMAND_TIMEOUT, Inhibitor};
+use crate::presuspend::run_step;
+use clap::ValueEnum;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// How often a running scrub or resilver is checked while waiting for it
+const SCRUB_POLL: Duration = Duration::from_secs(10);
+
+/// What a running scrub or resilver does to a suspend
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScrubMode {
+    /// Refuse to suspend, like any other inhibitor
+    Inhibit,
+    /// Hold the suspend until it finishes, up to --zfs-scrub-timeout
+    Wait,
+}
+
+/// A scrub or resilver in progress, from `zpool status`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scan {
+    pub pool: String,
+    /// `scrub` or `resilver`
+    pub kind: String,
+    /// How far along it is, e.g. `17.13%`
+    pub done: Option<String>,
+}
+
+impl std::fmt::Display for Scan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} in progress on {}", self.kind, self.pool)?;
+        if let Some(done) = &self.done {
+            write!(f, " ({} done)", done)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scrubs and resilvers in progress in `zpool status` output
+fn parse_status(output: &str) -> Vec<Scan> {
+    let mut scans: Vec<Scan> = Vec::new();
+    let mut pool = None;
+    let mut scanning = false;
+    for line in output.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix("pool:") {
+            pool = Some(name.trim().to_string());
+            scanning = false;
+        } else if let Some(scan) = line.strip_prefix("scan:") {
+            let kind = scan.split_whitespace().next().unwrap_or_default();
+            scanning = scan.contains(" in progress") && (kind == "scrub" || kind == "resilver");
+            if let (true, Some(pool)) = (scanning, &pool) {
+                scans.push(Scan { pool: pool.clone(), kind: kind.to_string(), done: None });
+            }
+        } else if scanning && line.contains("% done") {
+            let done = line.split([',', ' ']).find(|word| word.ends_with('%'));
+            if let Some(scan) = scans.last_mut() {
+                scan.done = done.map(str::to_string);
+            }
+        } else if line.starts_with("config:") {
+            scanning = false;
+        }
+    }
+    scans
+}
+
+/// Scrubs and resilvers running on `pools`, or on every imported pool when empty
+pub fn scans(pools: &[String]) -> Result<Vec<Scan>, String> {
+    let output = inhibit::output(std::process::Command::new("zpool").arg("status").args(pools), COMMAND_TIMEOUT)
+        .map_err(|e| format!("zpool: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("zpool status failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(parse_status(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Blocks suspend while a pool is being scrubbed or resilvered
+pub struct ZfsScrub {
+    pub pools: Vec<String>,
+}
+
+impl Inhibitor for ZfsScrub {
+    fn name(&self) -> &str {
+        "zfs"
+    }
+
+    fn check(&self) -> Result<Option<String>, String> {
+        let scans = scans(&self.pools)?;
+        Ok((!scans.is_empty()).then(|| scans.iter().map(Scan::to_string).collect::<Vec<_>>().join(", ")))
+    }
+}
+
+/// The `sync` property of a pool's root dataset, as `(value, source)`
+fn sync_property(pool: &str) -> Result<(String, String), String> {
+    let mut cmd = std::process::Command::new("zfs");
+    cmd.args(["get", "-H", "-o", "value,source", "sync", pool]);
+    let output = inhibit::output(&mut cmd, COMMAND_TIMEOUT)
+        .map_err(|e| format!("zfs: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("zfs get sync {} failed: {}", pool, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    match text.trim_end().split_once('\t') {
+        Some((value, source)) => Ok((value.to_string(), source.to_string())),
+        None => Err(format!("Unexpected output from zfs get sync {}: {:?}", pool, text)),
+    }
+}
+
+/// Imported pools, by name
+fn imported() -> Result<Vec<String>, String> {
+    let mut cmd = std::process::Command::new("zpool");
+    cmd.args(["list", "-H", "-o", "name"]);
+    let output = inhibit::output(&mut cmd, COMMAND_TIMEOUT)
+        .map_err(|e| format!("zpool: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("zpool list failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+}
+
+/// Run one of the `zpool`/`zfs` queries above on the blocking pool
+async fn blocking<T: Send + 'static>(query: impl FnOnce() -> Result<T, String> + Send + 'static) -> Result<T, String> {
+    tokio::task::spawn_blocking(query).await.map_err(|e| e.to_string())?
+}
+
+/// ZFS handling before suspend: wait for scrubs, sync pools and pin them to sync=always
+/// until resume
+pub struct Zfs {
+    /// Pools to handle; every imported pool when empty
+    pub pools: Vec<String>,
+    /// Set to `Wait` to hold the suspend while a scrub or resilver runs
+    pub scrub: Option<ScrubMode>,
+    pub scrub_timeout: Duration,
+    pub sync: bool,
+    pub sync_always: bool,
+    pub timeout: Duration,
+    /// The `sync` settings replaced by sync=always; None where the value was inherited
+    pub saved: Mutex<Vec<(String, Option<String>)>>,
+}
+
+impl Zfs {
+    pub fn enabled(&self) -> bool {
+        self.scrub == Some(ScrubMode::Wait) || self.sync || self.sync_always
+    }
+
+    /// Wait for scrubs, sync the pools and set sync=always, in that order. On failure,
+    /// pools already set to sync=always are restored.
+    pub async fn prepare(&self) -> Result<(), String> {
+        if self.scrub == Some(ScrubMode::Wait) {
+            self.wait_for_scrubs().await?;
+        }
+        if self.sync {
+            let mut cmd = Command::new("zpool");
+            cmd.arg("sync").args(&self.pools);
+            run_step("zpool sync", cmd, self.timeout).await?;
+        }
+        if self.sync_always {
+            let pools = if self.pools.is_empty() { blocking(imported).await? } else { self.pools.clone() };
+            for pool in pools {
+                let (value, source) = blocking({
+                    let pool = pool.clone();
+                    move || sync_property(&pool)
+                })
+                .await?;
+                let previous = (source == "local").then_some(value);
+                let mut cmd = Command::new("zfs");
+                cmd.args(["set", "sync=always", &pool]);
+                if let Err(e) = run_step(&format!("zfs set sync=always {}", pool), cmd, self.timeout).await {
+                    self.restore().await;
+                    return Err(e);
+                }
+                self.saved.lock().unwrap().push((pool, previous));
+            }
+        }
+        Ok(())
+    }
+
+    async fn wait_for_scrubs(&self) -> Result<(), String> {
+        let started = Instant::now();
+        let mut logged = false;
+        loop {
+            let pools = self.pools.clone();
+            let scans = blocking(move || scans(&pools)).await?;
+            let Some(scan) = scans.first() else {
+                return Ok(());
+            };
+            if started.elapsed() >= self.scrub_timeout {
+                return Err(format!("{} after waiting {}s", scan, self.scrub_timeout.as_secs()));
+            }
+            if !logged {
+                info!("Waiting for the ZFS {} before suspending", scan);
+                logged = true;
+            }
+            tokio::time::sleep(SCRUB_POLL.min(self.scrub_timeout.saturating_sub(started.elapsed()))).await;
+        }
+    }
+
+    /// Put back the `sync` settings replaced by `prepare`, logging failures without stopping
+    pub async fn restore(&self) {
+        let saved = std::mem::take(&mut *self.saved.lock().unwrap());
+        for (pool, previous) in saved {
+            let mut cmd = Command::new("zfs");
+            let name = match &previous {
+                Some(value) => {
+                    cmd.args(["set", &format!("sync={}", value), &pool]);
+                    format!("zfs set sync={} {}", value, pool)
+                }
+                None => {
+                    cmd.args(["inherit", "sync", &pool]);
+                    format!("zfs inherit sync {}", pool)
+                }
+            };
+            if let Err(e) = run_step(&name, cmd, self.timeout).await {
+                error!("Failed to restore the ZFS sync property: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = "  pool: backup
+ state: ONLINE
+  scan: scrub repaired 0B in 02:10:44 with 0 errors on Sun Oct 12 02:34:45 2025
+config:
+
+\tNAME        STATE     READ WRITE CKSUM
+\tbackup      ONLINE       0     0     0
+
+errors: No known data errors
+
+  pool: tank
+ state: DEGRADED
+  scan: resilver in progress since Wed Oct 15 21:02:10 2025
+\t1.23T / 4.56T scanned at 512M/s, 800G / 4.56T issued at 300M/s
+\t400G resilvered, 17.13% done, 03:40:12 to go
+config:
+
+\tNAME        STATE     READ WRITE CKSUM
+\ttank        DEGRADED     0     0     0
+
+errors: No known data errors
+";
+
+    #[test]
+    fn test_parse_status() {
+        let scans = parse_status(STATUS);
+        assert_eq!(
+            scans,
+            [Scan { pool: "tank".to_string(), kind: "resilver".to_string(), done: Some("17.13%".to_string()) }]
+        );
+        assert_eq!(scans[0].to_string(), "resilver in progress on tank (17.13% done)");
+        assert_eq!(parse_status(&STATUS.replace("resilver in progress", "scrub in progress"))[0].kind, "scrub");
+        assert!(parse_status("  pool: tank\n  scan: none requested\nconfig:\n").is_empty());
+    }
+}